@@ -0,0 +1,84 @@
+//! Sparse against dense solves of the normal equations of synthetic pose graphs
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use nalgebra::{DMatrix, Isometry3, Vector3};
+use slam_rs::graph::{
+    BetweenFactor, FactorGraph, Key, LinearSolver, Optimizer, OptimizerParams, PriorFactor, Values,
+    Variable,
+};
+
+/// A robot driving back and forth along rows, with odometry between consecutive poses and loop
+/// closures to the pose next to it on the previous row
+fn pose_graph(n_poses: u64, row_length: u64) -> (FactorGraph, Values) {
+    let truth = |i: u64| {
+        let (row, col) = (i / row_length, i % row_length);
+        let x = if row % 2 == 0 {
+            col
+        } else {
+            row_length - 1 - col
+        };
+        Isometry3::new(
+            Vector3::new(x as f64, row as f64, 0.0),
+            Vector3::new(0.0, 0.0, 0.01 * i as f64),
+        )
+    };
+
+    let mut graph = FactorGraph::new();
+    let mut values = Values::new();
+    graph.insert(PriorFactor::new(
+        Key::Pose(0),
+        truth(0),
+        DMatrix::identity(6, 6),
+    ));
+    for i in 0..n_poses {
+        let noise = [0.01, -0.02, 0.01, 0.05, -0.03, 0.02];
+        values.insert(
+            Key::Pose(i),
+            Variable::Pose(truth(i)).retract(&noise).unwrap(),
+        );
+        if i > 0 {
+            graph.insert(BetweenFactor::new(
+                Key::Pose(i - 1),
+                Key::Pose(i),
+                truth(i - 1).inverse() * truth(i),
+                DMatrix::identity(6, 6),
+            ));
+        }
+        // the pose at the same x on the previous row
+        let (row, col) = (i / row_length, i % row_length);
+        if row > 0 {
+            let j = row * row_length - 1 - col;
+            graph.insert(BetweenFactor::new(
+                Key::Pose(j),
+                Key::Pose(i),
+                truth(j).inverse() * truth(i),
+                DMatrix::identity(6, 6),
+            ));
+        }
+    }
+    (graph, values)
+}
+
+fn solve_pose_graph(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pose_graph_iteration");
+    group.sample_size(10);
+    for &n_poses in &[100, 400] {
+        let (graph, values) = pose_graph(n_poses, 20);
+        for &(name, linear_solver) in &[
+            ("dense", LinearSolver::DenseCholesky),
+            ("sparse", LinearSolver::SparseCholesky),
+        ] {
+            let optimizer = Optimizer::new(OptimizerParams {
+                linear_solver,
+                max_iterations: 1,
+                ..Default::default()
+            });
+            group.bench_with_input(BenchmarkId::new(name, n_poses), &n_poses, |b, _| {
+                b.iter(|| optimizer.optimize(&graph, &mut values.clone()).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, solve_pose_graph);
+criterion_main!(benches);
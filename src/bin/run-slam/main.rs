@@ -1,10 +1,306 @@
 extern crate clap;
 extern crate slam_rs;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
+use nalgebra::{Isometry3, Translation3, Vector3};
+use slam_rs::drivers::{
+    CameraCalibration, EurocStreamGray, EurocStreamImu, ImuCalibration, Stream,
+};
 use slam_rs::errors::{SlamError, SlamErrorKind};
+use slam_rs::features::{OrbExtractor, OrbParams};
+use slam_rs::geometry::{CameraModel, PoseBearing, Triangulator};
+use slam_rs::graph::{
+    static_initialization, ImuNoise, Optimizer, OptimizerParams, PoseGraph, PoseGraphFormat,
+    StaticInitParams,
+};
+use slam_rs::mapping::{
+    KeyframeFeature, KeyframeSelector, Map, MapParams, Relocalization, Relocalizer,
+};
+use slam_rs::tracking::{KltTracker, Msckf, MsckfParams, NavState, Track};
+use slam_rs::utils::{ImuSample, MeasurementData};
 use std::error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Number of frames at the start of a sequence that are relocalised against a loaded map
+const RELOCALIZATION_FRAMES: usize = 20;
+
+/// Back-ends estimating the trajectory
+#[derive(Debug, Clone)]
+enum Backend {
+    /// Factor-graph optimisation
+    Graph,
+    /// Multi-State Constraint Kalman Filter. The calibration it estimates online is reported at
+    /// the end of the run
+    Msckf(MsckfParams),
+}
+
+fn format_vector(v: &Vector3<f64>) -> String {
+    format!("[{:.3}, {:.3}, {:.3}]", v.x, v.y, v.z)
+}
+
+/// `T_WB` of the body
+fn body_pose(state: &NavState) -> Isometry3<f64> {
+    Isometry3::from_parts(Translation3::from(state.position), state.rotation)
+}
+
+/// Optimise a pose-graph file and write the optimised poses to another one
+fn optimize_graph(matches: &ArgMatches) -> Result<(), Box<dyn error::Error>> {
+    let input = PathBuf::from(matches.value_of("input").unwrap());
+    let output = PathBuf::from(matches.value_of("output").unwrap());
+    let format = match matches.value_of("format") {
+        Some("toro") => PoseGraphFormat::Toro,
+        _ => PoseGraphFormat::G2o,
+    };
+    let mut params = OptimizerParams::default();
+    if let Some(iterations) = matches.value_of("max-iterations") {
+        params.max_iterations = iterations.parse().map_err(|_| {
+            SlamError::new(SlamErrorKind::InvalidCLI(
+                "max-iterations".into(),
+                format!("Expected a number of iterations, got [{}]", iterations),
+            ))
+        })?;
+    }
+
+    let mut pose_graph = PoseGraph::read(&input)?;
+    let graph = pose_graph.factor_graph();
+    let mut values = pose_graph.values();
+    let report = Optimizer::new(params).optimize(&graph, &mut values)?;
+    println!(
+        "Optimised {} poses and {} edges in {} iterations - cost {} -> {} ({:?})",
+        pose_graph.poses.len(),
+        pose_graph.edges.len(),
+        report.iterations.len(),
+        report.initial_cost,
+        report.final_cost,
+        report.termination
+    );
+
+    pose_graph.update(&values)?;
+    pose_graph.write(&output, format)?;
+    Ok(())
+}
+
+/// Run the MSCKF over the cam0 and imu0 streams of the dataset
+///
+/// The filter starts at the end of the stationary segment at the start of the IMU stream, and
+/// runs in the frame of the IMU. The keyframes of the sequence and their landmarks extend the map,
+/// once the trajectory is in its world - from the start for an empty map, or from the anchor on,
+/// the index of a frame and the `T_WC` of its camera in the world of the map, in a new session.
+fn run_msckf(
+    dataset_path: &Path,
+    mut params: MsckfParams,
+    map: &mut Map,
+    anchor: Option<(usize, Isometry3<f64>)>,
+) -> Result<(), Box<dyn error::Error>> {
+    let imu = ImuCalibration::from_file(&dataset_path.join("imu0").join("sensor.yaml"))?;
+    let mut camera = CameraCalibration::from_file(&dataset_path.join("cam0").join("sensor.yaml"))?;
+    camera.t_bs = imu.t_bs.inverse() * camera.t_bs;
+    params.noise = ImuNoise::from_calibration(&imu);
+
+    let mut imu_stream = EurocStreamImu::new().root_dir(dataset_path.join("imu0"));
+    imu_stream.init()?;
+    let samples: Vec<ImuSample> = imu_stream
+        .filter_map(|data| match data {
+            MeasurementData::Imu(sample) => Some(sample),
+            _ => None,
+        })
+        .collect();
+    let init = static_initialization(&samples, &StaticInitParams::default())?;
+    let start = samples[init.n_samples - 1].timestamp;
+    println!(
+        "Stationary for {:.2}s - gyroscope bias {} rad/s",
+        init.duration,
+        format_vector(&init.bias.gyro)
+    );
+    let state = NavState::at_rest(start, init.rotation);
+    let mut msckf = Msckf::from_calibration(state, init.bias, &camera, params.clone());
+
+    let mut frames = EurocStreamGray::new().root_dir(dataset_path.join("cam0"));
+    frames.init()?;
+    let timestamps = frames.timestamps().to_vec();
+    let mut imu_samples = samples[init.n_samples..].iter().peekable();
+    let mut tracker = KltTracker::default();
+    let (mut n_frames, mut n_features) = (0, 0);
+    // `T_MW` from the world of the filter to the one of the map, and the offset of the identifiers
+    // of the tracks in the map
+    let (mut t_mw, mut feature_offset) = (None, 0);
+    if map.keyframes().is_empty() {
+        t_mw = Some(Isometry3::identity());
+    }
+    let camera_model = camera.camera_model();
+    let extractor = OrbExtractor::default();
+    let selector = KeyframeSelector::default();
+    for (idx, (timestamp, data)) in timestamps.into_iter().zip(frames).enumerate() {
+        let img = match data {
+            MeasurementData::Grayscale(img) => img,
+            _ => continue,
+        };
+        // frames of the stationary segment only start the tracks, the body is at rest
+        let tracks = tracker.process_frame(&img);
+        let capture = timestamp.as_secs_f64() + msckf.calibration().time_offset;
+        if capture >= start.as_secs_f64() {
+            while let Some(sample) =
+                imu_samples.next_if(|sample| sample.timestamp.as_secs_f64() <= capture)
+            {
+                msckf.propagate(sample);
+            }
+            let update = msckf.process_frame(timestamp, tracks);
+            n_frames += 1;
+            n_features += update.n_features;
+        }
+
+        if let Some((_, t_wc)) = anchor.filter(|(frame, _)| *frame == idx) {
+            let t_wb = body_pose(msckf.state());
+            t_mw = Some(t_wc * (t_wb * msckf.calibration().t_bs).inverse());
+            feature_offset = map.start_session();
+            println!(
+                "Anchored the trajectory in the map at frame {} - session {}, features from {}",
+                idx,
+                map.n_sessions() - 1,
+                feature_offset
+            );
+        }
+
+        if let Some(t_mw) = t_mw {
+            let tracks: Vec<Track> = tracker
+                .active_tracks()
+                .map(|track| Track {
+                    id: track.id + feature_offset,
+                    ..track.clone()
+                })
+                .collect();
+            let session = map.n_sessions() - 1;
+            let last = map
+                .keyframes()
+                .latest()
+                .filter(|keyframe| map.session_of(keyframe.id) == session);
+            if selector.select(last, timestamp, &tracks).is_some() {
+                let t_wc = t_mw * body_pose(msckf.state()) * msckf.calibration().t_bs;
+                let pts: Vec<_> = tracks.iter().map(|track| track.pt).collect();
+                let descriptors = extractor.compute(&img, &pts);
+                insert_keyframe(
+                    map,
+                    camera_model.as_ref(),
+                    timestamp,
+                    t_wc,
+                    tracks
+                        .iter()
+                        .zip(descriptors)
+                        .map(|(track, descriptor)| KeyframeFeature {
+                            id: track.id,
+                            pt: track.pt,
+                            descriptor,
+                        }),
+                )?;
+            }
+        }
+    }
+
+    let state = msckf.state();
+    let (pose, velocity, frame) = match t_mw {
+        Some(t_mw) => (
+            t_mw * body_pose(state),
+            t_mw.rotation * state.velocity,
+            "map",
+        ),
+        None => (body_pose(state), state.velocity, "filter"),
+    };
+    println!(
+        "Filtered {} frames with {} features - body at {} m in the {} frame, moving at {} m/s",
+        n_frames,
+        n_features,
+        format_vector(&pose.translation.vector),
+        frame,
+        format_vector(&velocity)
+    );
+    if params.estimate_extrinsics || params.estimate_time_offset {
+        println!("Camera-IMU calibration: {}", msckf.calibration());
+    }
+    if t_mw.is_none() {
+        println!("The trajectory isn't anchored in the map, which is left as loaded");
+    }
+    Ok(())
+}
+
+/// Add a keyframe to the map, and the landmarks of its features that have no landmark yet and
+/// are triangulated from the keyframes observing them
+fn insert_keyframe(
+    map: &mut Map,
+    camera: &dyn CameraModel,
+    timestamp: Duration,
+    t_wc: Isometry3<f64>,
+    features: impl Iterator<Item = KeyframeFeature>,
+) -> Result<(), Box<dyn error::Error>> {
+    let keyframe = map.insert_keyframe(timestamp, t_wc, features.collect());
+    let new: Vec<u64> = map
+        .keyframes()
+        .get(keyframe)
+        .map_or_else(Vec::new, |keyframe| {
+            keyframe
+                .features
+                .iter()
+                .map(|feature| feature.id)
+                .filter(|&id| map.landmark(id).is_none())
+                .collect()
+        });
+
+    let triangulator = Triangulator::default();
+    for id in new {
+        let obs: Vec<PoseBearing> = map
+            .keyframes()
+            .observers(id)
+            .filter_map(|observer| {
+                let observer = map.keyframes().get(observer)?;
+                let bearing = camera.unproject(&observer.feature(id)?.pt)?;
+                Some((observer.pose.inverse(), bearing))
+            })
+            .collect();
+        if let Some(point) = triangulator.triangulate(&obs) {
+            map.insert_landmark(id, point.point)?;
+        }
+    }
+    Ok(())
+}
+
+/// Relocalise the first frames of the sequence against a map of a previous session, returning the
+/// index of the first relocalised frame along with its relocalisation
+fn relocalize_first_frames(
+    map: &Map,
+    dataset_path: &Path,
+) -> Result<Option<(usize, Relocalization)>, Box<dyn error::Error>> {
+    let stream_path = dataset_path.join("cam0");
+    let calibration = CameraCalibration::from_file(&stream_path.join("sensor.yaml"))?;
+    let camera = calibration.camera_model();
+    let mut stream = EurocStreamGray::new().root_dir(stream_path);
+    stream.init()?;
+
+    let extractor = OrbExtractor::new(OrbParams::default());
+    let relocalizer = Relocalizer::default();
+    for (idx, data) in stream.take(RELOCALIZATION_FRAMES).enumerate() {
+        let img = match data {
+            MeasurementData::Grayscale(img) => img,
+            _ => continue,
+        };
+        let (keypoints, descriptors) = extractor.detect_and_compute(&img);
+        if let Some(result) = relocalizer.relocalize(map, camera.as_ref(), &keypoints, &descriptors)
+        {
+            println!(
+                "Relocalised frame {} against keyframe {} with {} inliers - camera at {}",
+                idx,
+                result.keyframe,
+                result.inliers.len(),
+                format_vector(&result.pose.translation.vector)
+            );
+            return Ok(Some((idx, result)));
+        }
+    }
+    println!(
+        "Couldn't relocalise the first {} frames against the map",
+        RELOCALIZATION_FRAMES
+    );
+    Ok(None)
+}
 
 pub fn main() -> Result<(), Box<dyn error::Error>> {
     // --------------------------------------------------------------------------------------------
@@ -14,12 +310,13 @@ pub fn main() -> Result<(), Box<dyn error::Error>> {
     let matches = App::new("SLAM Runner")
         .version("0.1.0")
         .author("Nikos Koukis <nickkouk@gmail.com>")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("dataset")
                 .short('d')
                 .long("dataset")
                 .takes_value(true)
-                .about("Path to the dataset root directory")
+                .help("Path to the dataset root directory")
                 .required(true),
         )
         .arg(
@@ -27,10 +324,81 @@ pub fn main() -> Result<(), Box<dyn error::Error>> {
                 .short('c')
                 .long("config-file")
                 .takes_value(true)
-                .about("Path to the SLAM configuration file"),
+                .help("Path to the SLAM configuration file"),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .short('b')
+                .long("backend")
+                .takes_value(true)
+                .possible_values(["graph", "msckf"])
+                .default_value("graph")
+                .help("Estimator - factor-graph optimisation or the MSCKF filter"),
+        )
+        .arg(
+            Arg::with_name("estimate-extrinsics")
+                .long("estimate-extrinsics")
+                .help("Refine the camera-IMU extrinsics of sensor.yaml online (msckf backend)"),
+        )
+        .arg(
+            Arg::with_name("estimate-time-offset")
+                .long("estimate-time-offset")
+                .help("Estimate the camera-IMU time offset online (msckf backend)"),
+        )
+        .arg(
+            Arg::with_name("load-map")
+                .long("load-map")
+                .takes_value(true)
+                .help(
+                    "Map file of a previous session to relocalise the first frames against - the \
+                     msckf backend extends it in a new session",
+                ),
+        )
+        .arg(
+            Arg::with_name("save-map")
+                .long("save-map")
+                .takes_value(true)
+                .help("Path to write the map built by the msckf backend to at the end of the run"),
+        )
+        .subcommand(
+            App::new("optimize-graph")
+                .about("Optimise a pose graph in the g2o or TORO format")
+                .arg(
+                    Arg::with_name("input")
+                        .index(1)
+                        .help("Path to the pose-graph file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .help("Path to write the optimised pose graph to")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short('f')
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(["g2o", "toro"])
+                        .default_value("g2o")
+                        .help("Format of the written pose graph"),
+                )
+                .arg(
+                    Arg::with_name("max-iterations")
+                        .long("max-iterations")
+                        .takes_value(true)
+                        .help("Maximum number of iterations of the optimiser"),
+                ),
         )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("optimize-graph") {
+        return optimize_graph(matches);
+    }
+
     // FIXME: Do this as part of a CLI validator struct
     let dataset_path = PathBuf::from(matches.value_of("dataset").unwrap());
     if !dataset_path.exists() {
@@ -60,12 +428,60 @@ pub fn main() -> Result<(), Box<dyn error::Error>> {
 
     // Initialise Frontend and Backend
     // TODO
+    let backend = match matches.value_of("backend") {
+        Some("msckf") => Backend::Msckf(MsckfParams {
+            estimate_extrinsics: matches.is_present("estimate-extrinsics"),
+            estimate_time_offset: matches.is_present("estimate-time-offset"),
+            ..MsckfParams::default()
+        }),
+        _ => Backend::Graph,
+    };
+
+    if matches.is_present("save-map") && matches!(backend, Backend::Graph) {
+        return Err(SlamError::new(SlamErrorKind::InvalidCLI(
+            "save-map".into(),
+            "Maps are only built by the msckf backend".into(),
+        ))
+        .into());
+    }
+
+    // multi-session - the first frames are relocalised against the map of a previous sequence,
+    // whose world frame anchors the trajectory of the sequence, which extends the map
+    let (mut map, anchor) = match matches.value_of("load-map") {
+        Some(path) => {
+            let map = Map::read(Path::new(path), MapParams::default())?;
+            println!(
+                "Loaded a map of {} keyframes and {} landmarks in {} sessions",
+                map.keyframes().len(),
+                map.n_landmarks(),
+                map.n_sessions()
+            );
+            let anchor = relocalize_first_frames(&map, &dataset_path)?
+                .map(|(idx, result)| (idx, result.pose));
+            (map, anchor)
+        }
+        None => (Map::new(MapParams::default()), None),
+    };
 
     // Build SLAM Object
     // TODO
 
     // Run the SLAM Loop + Update the GUI
     // TODO Offload the former into a separate thread
-    // TODO
+    match backend {
+        Backend::Msckf(params) => run_msckf(&dataset_path, params, &mut map, anchor)?,
+        // TODO
+        Backend::Graph => {}
+    }
+
+    if let Some(path) = matches.value_of("save-map") {
+        map.write(Path::new(path))?;
+        println!(
+            "Wrote a map of {} keyframes and {} landmarks to [{}]",
+            map.keyframes().len(),
+            map.n_landmarks(),
+            path
+        );
+    }
     Ok(())
 }
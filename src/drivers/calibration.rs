@@ -0,0 +1,289 @@
+/// Sensor calibrations, as provided in the `sensor.yaml` file of each `EuRoC` stream
+///
+/// <...>/mav0/cam0/sensor.yaml
+/// <...>/mav0/imu0/sensor.yaml
+use crate::drivers::traits::DatasetDriverError;
+use crate::geometry::camera::{CameraModel, PinholeEquidistant, PinholeRadTan};
+
+use nalgebra::{Isometry3, Matrix3, Translation3, UnitQuaternion};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+// -------------------------------------------------------------------------------------------------
+// yaml representation
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+struct YamlMatrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CameraYaml {
+    #[serde(rename = "T_BS")]
+    t_bs: YamlMatrix,
+    rate_hz: f64,
+    resolution: [u32; 2],
+    camera_model: String,
+    intrinsics: [f64; 4],
+    distortion_model: String,
+    distortion_coefficients: Vec<f64>,
+    /// Written by Kalibr, missing from the `EuRoC` files
+    #[serde(default)]
+    timeshift_cam_imu: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImuYaml {
+    #[serde(rename = "T_BS")]
+    t_bs: YamlMatrix,
+    rate_hz: f64,
+    gyroscope_noise_density: f64,
+    gyroscope_random_walk: f64,
+    accelerometer_noise_density: f64,
+    accelerometer_random_walk: f64,
+}
+
+/// Parse the row-major 4x4 homogeneous transformation of the yaml file
+fn parse_transform(m: &YamlMatrix) -> Result<Isometry3<f64>, DatasetDriverError> {
+    if m.rows != 4 || m.cols != 4 || m.data.len() != 16 {
+        return Err(DatasetDriverError::InvalidCalibration(format!(
+            "Expected a 4x4 transformation, got {}x{} with {} elements",
+            m.rows,
+            m.cols,
+            m.data.len()
+        )));
+    }
+
+    let d = &m.data;
+    let rotation = Matrix3::new(d[0], d[1], d[2], d[4], d[5], d[6], d[8], d[9], d[10]);
+    if (rotation * rotation.transpose() - Matrix3::identity()).amax() > 1e-3 {
+        return Err(DatasetDriverError::InvalidCalibration(
+            "Rotation part of the transformation is not orthonormal".into(),
+        ));
+    }
+    if rotation.determinant() < 0.0 {
+        return Err(DatasetDriverError::InvalidCalibration(
+            "Rotation part of the transformation is a reflection".into(),
+        ));
+    }
+
+    Ok(Isometry3::from_parts(
+        Translation3::new(d[3], d[7], d[11]),
+        UnitQuaternion::from_matrix(&rotation),
+    ))
+}
+
+fn read_file(path: &Path) -> Result<String, DatasetDriverError> {
+    fs::read_to_string(path).map_err(|e| {
+        DatasetDriverError::InvalidCalibration(format!("Reading [{}]: {}", path.display(), e))
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+// CameraCalibration
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DistortionModel {
+    RadialTangential,
+    /// Fisheye lenses
+    Equidistant,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraCalibration {
+    /// Pose of the sensor in the body frame - transforms points from the sensor to the body
+    pub t_bs: Isometry3<f64>,
+    pub rate_hz: f64,
+    /// Width and height of the images
+    pub resolution: (u32, u32),
+    /// fu, fv, cu, cv
+    pub intrinsics: [f64; 4],
+    pub distortion_model: DistortionModel,
+    pub distortion_coefficients: Vec<f64>,
+    /// Offset of the camera timestamps to the IMU clock, `t_imu = t_cam + time_offset` (seconds)
+    pub time_offset: f64,
+}
+
+impl CameraCalibration {
+    pub fn from_yaml(conts: &str) -> Result<Self, DatasetDriverError> {
+        let yaml: CameraYaml = serde_yaml::from_str(conts)
+            .map_err(|e| DatasetDriverError::InvalidCalibration(e.to_string()))?;
+
+        if yaml.camera_model != "pinhole" {
+            return Err(DatasetDriverError::InvalidCalibration(format!(
+                "Unsupported camera model [{}]",
+                yaml.camera_model
+            )));
+        }
+        let distortion_model = match yaml.distortion_model.as_str() {
+            "radial-tangential" | "radtan" if yaml.distortion_coefficients.len() == 4 => {
+                DistortionModel::RadialTangential
+            }
+            "equidistant" if yaml.distortion_coefficients.len() == 4 => {
+                DistortionModel::Equidistant
+            }
+            other => {
+                return Err(DatasetDriverError::InvalidCalibration(format!(
+                    "Unsupported distortion model [{}] with {} coefficients",
+                    other,
+                    yaml.distortion_coefficients.len()
+                )))
+            }
+        };
+
+        Ok(Self {
+            t_bs: parse_transform(&yaml.t_bs)?,
+            rate_hz: yaml.rate_hz,
+            resolution: (yaml.resolution[0], yaml.resolution[1]),
+            intrinsics: yaml.intrinsics,
+            distortion_model,
+            distortion_coefficients: yaml.distortion_coefficients,
+            time_offset: yaml.timeshift_cam_imu,
+        })
+    }
+
+    /// Read the calibration from the given `sensor.yaml` file
+    pub fn from_file(path: &Path) -> Result<Self, DatasetDriverError> {
+        Self::from_yaml(&read_file(path)?)
+    }
+
+    /// Camera model described by this calibration
+    pub fn camera_model(&self) -> Arc<dyn CameraModel> {
+        let c = &self.distortion_coefficients;
+        let coefficients = [c[0], c[1], c[2], c[3]];
+        match self.distortion_model {
+            DistortionModel::RadialTangential => Arc::new(PinholeRadTan::new(
+                self.intrinsics,
+                coefficients,
+                self.resolution,
+            )),
+            DistortionModel::Equidistant => Arc::new(PinholeEquidistant::new(
+                self.intrinsics,
+                coefficients,
+                self.resolution,
+            )),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// ImuCalibration
+// -------------------------------------------------------------------------------------------------
+
+/// Extrinsics and continuous-time noise model of an IMU
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImuCalibration {
+    /// Pose of the sensor in the body frame
+    pub t_bs: Isometry3<f64>,
+    pub rate_hz: f64,
+    /// White noise of the gyroscope, in rad / s / sqrt(Hz)
+    pub gyroscope_noise_density: f64,
+    /// Diffusion of the gyroscope bias, in rad / s^2 / sqrt(Hz)
+    pub gyroscope_random_walk: f64,
+    /// White noise of the accelerometer, in m / s^2 / sqrt(Hz)
+    pub accelerometer_noise_density: f64,
+    /// Diffusion of the accelerometer bias, in m / s^3 / sqrt(Hz)
+    pub accelerometer_random_walk: f64,
+}
+
+impl ImuCalibration {
+    pub fn from_yaml(conts: &str) -> Result<Self, DatasetDriverError> {
+        let yaml: ImuYaml = serde_yaml::from_str(conts)
+            .map_err(|e| DatasetDriverError::InvalidCalibration(e.to_string()))?;
+        Ok(Self {
+            t_bs: parse_transform(&yaml.t_bs)?,
+            rate_hz: yaml.rate_hz,
+            gyroscope_noise_density: yaml.gyroscope_noise_density,
+            gyroscope_random_walk: yaml.gyroscope_random_walk,
+            accelerometer_noise_density: yaml.accelerometer_noise_density,
+            accelerometer_random_walk: yaml.accelerometer_random_walk,
+        })
+    }
+
+    /// Read the calibration from the given `sensor.yaml` file
+    pub fn from_file(path: &Path) -> Result<Self, DatasetDriverError> {
+        Self::from_yaml(&read_file(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn parse_camera_calibration() {
+        let conts = include_str!("../../tests/sample_dataset/cam0/sensor.yaml");
+        let calib = CameraCalibration::from_yaml(conts).expect("Valid calibration");
+
+        assert_eq!(calib.resolution, (752, 480));
+        assert_eq!(calib.rate_hz, 20.0);
+        assert_eq!(calib.intrinsics, [458.654, 457.296, 367.215, 248.375]);
+        assert_eq!(calib.distortion_model, DistortionModel::RadialTangential);
+        assert_eq!(calib.time_offset, 0.0);
+        let shifted = format!("{}\ntimeshift_cam_imu: 0.0055\n", conts);
+        let calib = CameraCalibration::from_yaml(&shifted).expect("Valid calibration");
+        assert_eq!(calib.time_offset, 0.0055);
+        assert!(
+            (calib.t_bs.translation.vector
+                - Vector3::new(-0.0216401454975, -0.064676986768, 0.00981073058949))
+            .norm()
+                < 1e-12
+        );
+        // camera z axis is (almost) aligned with the body x axis
+        let z_axis = calib.t_bs.rotation * Vector3::z();
+        assert!((z_axis - Vector3::new(0.0041403, 0.0257155, 0.9996607)).norm() < 1e-4);
+    }
+
+    #[test]
+    fn parse_imu_calibration() {
+        let conts = include_str!("../../tests/sample_dataset/imu0/sensor.yaml");
+        let calib = ImuCalibration::from_yaml(conts).expect("Valid calibration");
+        assert_eq!(calib.t_bs, Isometry3::identity());
+        assert_eq!(calib.rate_hz, 200.0);
+        assert_eq!(calib.gyroscope_noise_density, 1.6968e-04);
+        assert_eq!(calib.gyroscope_random_walk, 1.9393e-05);
+        assert_eq!(calib.accelerometer_noise_density, 2.0e-3);
+        assert_eq!(calib.accelerometer_random_walk, 3.0e-3);
+    }
+
+    #[test]
+    fn invalid_transforms() {
+        let conts = include_str!("../../tests/sample_dataset/imu0/sensor.yaml");
+        // a shear has a unit determinant but isn't a rotation
+        let sheared = conts.replace("[1.0, 0.0, 0.0, 0.0,", "[1.0, 0.5, 0.0, 0.0,");
+        assert!(matches!(
+            ImuCalibration::from_yaml(&sheared),
+            Err(DatasetDriverError::InvalidCalibration(_))
+        ));
+        let reflected = conts.replace("0.0, 0.0, 1.0, 0.0,", "0.0, 0.0, -1.0, 0.0,");
+        assert!(matches!(
+            ImuCalibration::from_yaml(&reflected),
+            Err(DatasetDriverError::InvalidCalibration(_))
+        ));
+    }
+
+    #[test]
+    fn distortion_models() {
+        let conts =
+            include_str!("../../tests/sample_dataset/cam0/sensor.yaml").replace("pinhole", "omni");
+        assert!(matches!(
+            CameraCalibration::from_yaml(&conts),
+            Err(DatasetDriverError::InvalidCalibration(_))
+        ));
+        let conts = include_str!("../../tests/sample_dataset/cam0/sensor.yaml")
+            .replace("radial-tangential", "equidistant");
+        let calib = CameraCalibration::from_yaml(&conts).expect("Fisheye calibration");
+        assert_eq!(calib.distortion_model, DistortionModel::Equidistant);
+        assert!(matches!(
+            CameraCalibration::from_file(Path::new("non-existent.yaml")),
+            Err(DatasetDriverError::InvalidCalibration(_))
+        ));
+    }
+}
@@ -6,7 +6,7 @@
 use crate::drivers::traits::{
     DatasetDriver, DatasetDriverError, DatasetDriverState, FiniteStream, Stream,
 };
-use crate::utils::{Measurement, MeasurementData, MeasurementType};
+use crate::utils::{ImuSample, Measurement, MeasurementData, MeasurementType};
 
 use image;
 use image::{load_from_memory_with_format, GrayImage, ImageFormat::Png};
@@ -16,6 +16,7 @@ use std::time::Duration;
 use csv::Reader;
 use csv::Result as CsvResult;
 use log::{info, warn};
+use nalgebra::Vector3;
 use std::collections::hash_map::DefaultHasher;
 use std::fs::File;
 use std::hash::{Hash, Hasher};
@@ -45,6 +46,8 @@ pub struct EurocStreamGray {
     freq: Option<f64>,
     /// Names of the images for the given stream (not the full path to the images, just the basename)
     img_paths: Vec<PathBuf>,
+    /// Timestamps of the images
+    img_stamps: Vec<Duration>,
     /// Points to the next camera measurements that is to be read
     stream_cursor: usize,
 }
@@ -56,6 +59,7 @@ impl EurocStreamGray {
             root_dir: PathBuf::new(),
             freq: None,
             img_paths: Vec::new(),
+            img_stamps: Vec::new(),
             stream_cursor: 0,
         }
     }
@@ -70,6 +74,11 @@ impl EurocStreamGray {
         self
     }
 
+    /// Timestamps of the images, in the order they are streamed
+    pub fn timestamps(&self) -> &[Duration] {
+        &self.img_stamps
+    }
+
     /// Get the contents of the camera csv file
     fn parse_csv(&self) -> std::io::Result<String> {
         let mut conts = String::new();
@@ -116,66 +125,155 @@ impl Stream for EurocStreamGray {
         let rdr = Reader::from_reader(csv_conts.as_bytes());
 
         let csv_iter = rdr.into_records();
-        let mut img_stamps: Vec<Duration> = vec![];
 
         // discard images that are not actually found in the dataset - inform about it
         for result in csv_iter {
             let record = result?;
             let nsecs = Duration::from_nanos(record[0].parse::<u64>()?);
 
-            let img_path = self.root_dir.join(&record[1]);
+            let img_path = self.root_dir.join("data").join(&record[1]);
             if !self.image_exists(&img_path) {
                 warn!("Image path [{}] is invalid", img_path.display());
                 continue;
             }
 
-            img_stamps.push(nsecs);
+            self.img_stamps.push(nsecs);
             self.img_paths.push(img_path);
         }
 
-        if img_stamps.is_empty() {
+        if self.img_stamps.is_empty() {
             return Err(Box::new(DatasetDriverError::StreamEmpty));
         }
 
-        // cache frequency -------------------------------------------------------------------------
-        // all frequencies
-        let mut freqs = Vec::<f64>::with_capacity(img_stamps.len() - 1);
-        for i in 1..img_stamps.len() {
-            freqs.push(1.0 / (img_stamps[i] - img_stamps[i - 1]).as_secs_f64())
+        self.freq = steady_frequency(&self.img_stamps);
+        Ok(())
+    }
+}
+
+impl FiniteStream for EurocStreamGray {
+    fn len(&self) -> usize {
+        self.img_paths.len()
+    }
+
+    fn freq_hint(&self) -> Result<f64, DatasetDriverError> {
+        match self.freq {
+            Some(f) => Ok(f),
+            None => Err(DatasetDriverError::UnsteadyFrequency),
         }
+    }
+}
+
+/// Mean frequency of the measurements with the given timestamps, if it is steady
+fn steady_frequency(stamps: &[Duration]) -> Option<f64> {
+    // all frequencies
+    let mut freqs = Vec::<f64>::with_capacity(stamps.len().saturating_sub(1));
+    for i in 1..stamps.len() {
+        freqs.push(1.0 / (stamps[i] - stamps[i - 1]).as_secs_f64())
+    }
+
+    // compute mean
+    let mean: f64 = freqs.iter().sum::<f64>() / freqs.len() as f64;
+
+    // compute stddev
+    let variance: f64 =
+        freqs.iter().map(|freq| (freq - mean).powi(2)).sum::<f64>() / freqs.len() as f64;
+    let stddev = variance.sqrt();
+
+    let count = freqs
+        .iter()
+        .filter(|&&freq| {
+            freq > 3.0f64.mul_add(stddev, mean) || freq < 3.0f64.mul_add(-stddev, mean)
+        })
+        .count() as f64;
+
+    // if most data (90%) are in the [-0.3sigma, +0.3sigma] range then mean == freq_hint
+    if count < (0.1 * freqs.len() as f64) {
+        Some(mean)
+    } else {
+        None
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// EurocStreamImu
+// -------------------------------------------------------------------------------------------------
+
+/// The IMU stream of a euroc dataset - the samples of `<...>/mav0/imu0/data.csv`
+#[derive(Debug, Default)]
+pub struct EurocStreamImu {
+    /// Path to the root directory of the stream, e.g. <...>/mav0/imu0/
+    root_dir: PathBuf,
+    /// Frequency of the samples in the stream
+    freq: Option<f64>,
+    samples: Vec<ImuSample>,
+    /// Points to the next sample that is to be read
+    stream_cursor: usize,
+}
+
+impl EurocStreamImu {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        // compute mean
-        let mean: f64 = freqs.iter().sum::<f64>() / freqs.len() as f64;
+    pub fn root_dir(mut self, root_dir: PathBuf) -> Self {
+        self.root_dir = root_dir;
+        self
+    }
+}
 
-        // compute stddev
-        let variance: f64 =
-            freqs.iter().map(|freq| (freq - mean).powi(2)).sum::<f64>() / freqs.len() as f64;
-        let stddev = variance.sqrt();
+impl Iterator for EurocStreamImu {
+    type Item = MeasurementData;
+    /// Get the next sample in the stream
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.samples.get(self.stream_cursor)?;
+        self.stream_cursor += 1;
+        Some(MeasurementData::Imu(*sample))
+    }
+}
 
-        let count = freqs
-            .iter()
-            .filter(|&&freq| freq > 3.0f64.mul_add(stddev, mean) || freq < mean - 3.0 * stddev)
-            .count() as f64;
+impl Stream for EurocStreamImu {
+    fn measurement_type(&self) -> MeasurementType {
+        MeasurementType::IMU
+    }
 
-        // if most data (90%) are in the [-0.3sigma, +0.3sigma] range then mean == freq_hint
-        if count < (0.1 * freqs.len() as f64) {
-            self.freq = Some(mean);
+    fn init(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // timestamp [ns], angular velocity [rad s^-1] and specific force [m s^-2]
+        let mut rdr = Reader::from_path(self.root_dir.join("data.csv"))?;
+        for result in rdr.records() {
+            let record = result?;
+            if record.len() != 7 {
+                return Err(Box::new(DatasetDriverError::InitDatasetError(format!(
+                    "Expected 7 fields in each IMU sample, found {}",
+                    record.len()
+                ))));
+            }
+            let mut values = [0.0; 6];
+            for (value, field) in values.iter_mut().zip(record.iter().skip(1)) {
+                *value = field.trim().parse()?;
+            }
+            self.samples.push(ImuSample {
+                timestamp: Duration::from_nanos(record[0].trim().parse()?),
+                gyro: Vector3::new(values[0], values[1], values[2]),
+                accel: Vector3::new(values[3], values[4], values[5]),
+            });
         }
 
+        if self.samples.is_empty() {
+            return Err(Box::new(DatasetDriverError::StreamEmpty));
+        }
+        let stamps: Vec<Duration> = self.samples.iter().map(|s| s.timestamp).collect();
+        self.freq = steady_frequency(&stamps);
         Ok(())
     }
 }
 
-impl FiniteStream for EurocStreamGray {
+impl FiniteStream for EurocStreamImu {
     fn len(&self) -> usize {
-        self.img_paths.len()
+        self.samples.len()
     }
 
     fn freq_hint(&self) -> Result<f64, DatasetDriverError> {
-        match self.freq {
-            Some(f) => Ok(f),
-            None => Err(DatasetDriverError::UnsteadyFrequency),
-        }
+        self.freq.ok_or(DatasetDriverError::UnsteadyFrequency)
     }
 }
 
@@ -327,6 +425,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn euroc_stream_dataset_layout() {
+        // <...>/mav0/cam0/data.csv lists the images of <...>/mav0/cam0/data/
+        let mut stream = EurocStreamGray::new().root_dir("tests/sample_dataset/cam0".into());
+        stream.init().expect("Valid stream");
+        assert_eq!(stream.len(), 5);
+        for data in stream {
+            match data {
+                MeasurementData::Grayscale(img) => assert_eq!(img.dimensions(), (752, 480)),
+                _ => panic!("Expected grayscale images"),
+            }
+        }
+    }
+
+    #[test]
+    fn euroc_imu_stream() {
+        let mut stream = EurocStreamImu::new().root_dir("tests/sample_dataset/imu0".into());
+        stream.init().expect("Valid stream");
+        assert_eq!(stream.len(), 47);
+        assert_approx_eq!(stream.freq_hint().expect("Needed a valid freq"), 200.0, 0.1);
+
+        let samples: Vec<ImuSample> = stream
+            .map(|data| match data {
+                MeasurementData::Imu(sample) => sample,
+                _ => panic!("Expected IMU samples"),
+            })
+            .collect();
+        assert_eq!(
+            samples[0].timestamp,
+            Duration::from_nanos(1_403_636_579_758_555_392)
+        );
+        assert_approx_eq!(samples[0].gyro.y, 0.147_305_788_868_321_38);
+        assert_approx_eq!(samples[0].accel.x, 8.147_691_708_333_333);
+    }
+
     #[test]
     fn euroc_stream_empty_data_csv() {
         // Read from an empty CSV file - Make sure it throws the appropriate error
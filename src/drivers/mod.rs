@@ -1,5 +1,7 @@
+mod calibration;
 mod euroc;
 mod traits;
 
+pub use self::calibration::*;
 pub use self::euroc::*;
 pub use self::traits::*;
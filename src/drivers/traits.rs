@@ -145,6 +145,8 @@ pub enum DatasetDriverError {
     EndOfStream,
     #[error("Stream doesn't contain any measurements")]
     StreamEmpty,
+    #[error("Invalid sensor calibration - Reason: {0}")]
+    InvalidCalibration(String),
     #[error("Unknown dataset-related error")]
     Unknown,
 }
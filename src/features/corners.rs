@@ -0,0 +1,455 @@
+/// Corner detection based on the structure tensor - Harris and Shi-Tomasi ("Good features to
+/// track") - with iterative sub-pixel refinement
+///
+/// For more information see:
+///
+/// - Harris & Stephens, "A combined corner and edge detector", Alvey Vision Conference 1988
+/// - Shi & Tomasi, "Good features to track", CVPR 1994
+use crate::utils::imgproc::{convolve_separable, gaussian_kernel, gradients, FloatImage};
+
+use image::GrayImage;
+use nalgebra::{Matrix2, Point2, Vector2};
+
+pub use crate::utils::imgproc::GradientKernel;
+
+// -------------------------------------------------------------------------------------------------
+// Parameters
+// -------------------------------------------------------------------------------------------------
+
+/// Weighting of the gradients inside the block that the structure tensor is accumulated over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Box,
+    /// Gaussian weighting with the given standard deviation (pixels)
+    Gaussian(f64),
+}
+
+/// Cornerness measure computed from the structure tensor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CornerScore {
+    /// `det(M) - k * trace(M)^2`
+    Harris(f64),
+    /// Smallest eigenvalue of `M` (Shi-Tomasi)
+    MinEigenvalue,
+}
+
+#[derive(Debug, Clone)]
+pub struct CornerParams {
+    /// Maximum number of corners to return. The strongest ones are kept
+    pub max_corners: usize,
+    /// Minimum accepted score, relative to the score of the strongest corner in the image
+    pub quality_level: f64,
+    /// Minimum euclidean distance between returned corners (pixels)
+    pub min_distance: f64,
+    /// Side of the (square) block that the structure tensor is accumulated over - must be odd
+    pub block_size: usize,
+    pub gradient: GradientKernel,
+    pub window: Window,
+    pub score: CornerScore,
+    /// Corners closer than this to the image border are discarded
+    pub border: u32,
+}
+
+impl Default for CornerParams {
+    fn default() -> Self {
+        Self {
+            max_corners: 300,
+            quality_level: 0.01,
+            min_distance: 10.0,
+            block_size: 5,
+            gradient: GradientKernel::Scharr,
+            window: Window::Gaussian(1.0),
+            score: CornerScore::MinEigenvalue,
+            border: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubPixParams {
+    /// Half side of the search window (pixels)
+    pub half_window: usize,
+    pub max_iterations: usize,
+    /// Stop when the corner moves less than this (pixels)
+    pub epsilon: f64,
+}
+
+impl Default for SubPixParams {
+    fn default() -> Self {
+        Self {
+            half_window: 5,
+            max_iterations: 40,
+            epsilon: 0.01,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Corner {
+    pub pt: Point2<f64>,
+    pub response: f64,
+}
+
+// -------------------------------------------------------------------------------------------------
+// StructureTensor
+// -------------------------------------------------------------------------------------------------
+
+/// Per-pixel structure tensor `M = sum_w [Ix^2, IxIy; IxIy, Iy^2]`
+#[derive(Debug, Clone)]
+pub struct StructureTensor {
+    ixx: FloatImage,
+    ixy: FloatImage,
+    iyy: FloatImage,
+}
+
+impl StructureTensor {
+    pub fn new(
+        img: &GrayImage,
+        gradient: GradientKernel,
+        window: Window,
+        block_size: usize,
+    ) -> Self {
+        assert!(block_size % 2 == 1, "Block size must be odd");
+
+        let (gx, gy) = gradients(&FloatImage::from(img), gradient);
+        let (width, height) = img.dimensions();
+        let mut ixx = FloatImage::new(width, height);
+        let mut ixy = FloatImage::new(width, height);
+        let mut iyy = FloatImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (dx, dy) = (gx.get(x, y), gy.get(x, y));
+                ixx.set(x, y, dx * dx);
+                ixy.set(x, y, dx * dy);
+                iyy.set(x, y, dy * dy);
+            }
+        }
+
+        let kernel = match window {
+            Window::Box => vec![1.0; block_size],
+            Window::Gaussian(sigma) => gaussian_kernel(sigma, block_size / 2),
+        };
+        Self {
+            ixx: convolve_separable(&ixx, &kernel, &kernel),
+            ixy: convolve_separable(&ixy, &kernel, &kernel),
+            iyy: convolve_separable(&iyy, &kernel, &kernel),
+        }
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.ixx.width()
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.ixx.height()
+    }
+
+    pub fn at(&self, x: u32, y: u32) -> Matrix2<f64> {
+        let (a, b, c) = (self.ixx.get(x, y), self.ixy.get(x, y), self.iyy.get(x, y));
+        Matrix2::new(a, b, b, c)
+    }
+
+    /// Score of the tensor at (x, y)
+    // determinant minus k times the squared trace
+    #[allow(clippy::suspicious_operation_groupings, clippy::suboptimal_flops)]
+    pub fn score(&self, x: u32, y: u32, score: CornerScore) -> f64 {
+        let (a, b, c) = (self.ixx.get(x, y), self.ixy.get(x, y), self.iyy.get(x, y));
+        match score {
+            CornerScore::Harris(k) => (a * c - b * b) - k * (a + c) * (a + c),
+            CornerScore::MinEigenvalue => {
+                let half_trace = 0.5 * (a + c);
+                let half_diff = 0.5 * (a - c);
+                half_trace - half_diff.hypot(b)
+            }
+        }
+    }
+
+    /// Map of the scores over the whole image
+    pub fn response(&self, score: CornerScore) -> FloatImage {
+        let mut out = FloatImage::new(self.width(), self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                out.set(x, y, self.score(x, y, score));
+            }
+        }
+        out
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// detection
+// -------------------------------------------------------------------------------------------------
+
+/// Detect the strongest corners of the image, at least `min_distance` apart
+pub fn detect_corners(img: &GrayImage, params: &CornerParams) -> Vec<Corner> {
+    detect_corners_excluding(img, params, &[])
+}
+
+/// Same as [`detect_corners`] but also keep the new corners at least `min_distance` away from
+/// the `occupied` locations - e.g. the features that are already being tracked
+pub fn detect_corners_excluding(
+    img: &GrayImage,
+    params: &CornerParams,
+    occupied: &[Point2<f64>],
+) -> Vec<Corner> {
+    let tensor = StructureTensor::new(img, params.gradient, params.window, params.block_size);
+    let response = tensor.response(params.score);
+    let (width, height) = img.dimensions();
+    let border = params.border.max(1);
+    if width <= 2 * border || height <= 2 * border {
+        return Vec::new();
+    }
+
+    let max_response = response.data().iter().cloned().fold(0.0, f64::max);
+    if max_response <= 0.0 {
+        return Vec::new();
+    }
+    let threshold = params.quality_level * max_response;
+
+    // local maxima in a 3x3 neighbourhood above the quality threshold
+    let mut candidates = Vec::new();
+    for y in border..height - border {
+        for x in border..width - border {
+            let value = response.get(x, y);
+            if value <= threshold {
+                continue;
+            }
+            let is_max =
+                (y - 1..=y + 1).all(|ny| (x - 1..=x + 1).all(|nx| response.get(nx, ny) <= value));
+            if is_max {
+                candidates.push(Corner {
+                    pt: Point2::new(f64::from(x), f64::from(y)),
+                    response: value,
+                });
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.response.partial_cmp(&a.response).unwrap());
+
+    suppress_close(
+        candidates,
+        occupied,
+        params.min_distance,
+        params.max_corners,
+        (width, height),
+    )
+}
+
+/// Greedily keep the strongest corners that are at least `min_distance` away from every other
+/// kept corner and from the `occupied` locations. The candidates must be sorted by decreasing
+/// response.
+fn suppress_close(
+    candidates: Vec<Corner>,
+    occupied: &[Point2<f64>],
+    min_distance: f64,
+    max_corners: usize,
+    (width, height): (u32, u32),
+) -> Vec<Corner> {
+    if min_distance <= 0.0 {
+        return candidates.into_iter().take(max_corners).collect();
+    }
+
+    // bucket the accepted corners in a grid with cells of `min_distance` side, so that only the
+    // neighbouring cells have to be checked
+    let cell = min_distance;
+    let grid_w = (f64::from(width) / cell).ceil() as usize + 1;
+    let grid_h = (f64::from(height) / cell).ceil() as usize + 1;
+    let mut grid: Vec<Vec<Point2<f64>>> = vec![Vec::new(); grid_w * grid_h];
+    let cell_of = |pt: &Point2<f64>| {
+        let cx = ((pt.x.max(0.0) / cell) as usize).min(grid_w - 1);
+        let cy = ((pt.y.max(0.0) / cell) as usize).min(grid_h - 1);
+        (cx, cy)
+    };
+    for pt in occupied {
+        let (cx, cy) = cell_of(pt);
+        grid[cy * grid_w + cx].push(*pt);
+    }
+
+    let mut kept = Vec::new();
+    for corner in candidates {
+        if kept.len() >= max_corners {
+            break;
+        }
+
+        let (cx, cy) = cell_of(&corner.pt);
+        let too_close = (cy.saturating_sub(1)..=(cy + 1).min(grid_h - 1)).any(|gy| {
+            (cx.saturating_sub(1)..=(cx + 1).min(grid_w - 1)).any(|gx| {
+                grid[gy * grid_w + gx]
+                    .iter()
+                    .any(|pt| (pt - corner.pt).norm_squared() < min_distance * min_distance)
+            })
+        });
+
+        if !too_close {
+            grid[cy * grid_w + cx].push(corner.pt);
+            kept.push(corner);
+        }
+    }
+
+    kept
+}
+
+// -------------------------------------------------------------------------------------------------
+// sub-pixel refinement
+// -------------------------------------------------------------------------------------------------
+
+/// Iteratively refine the corner locations to sub-pixel accuracy.
+///
+/// Uses the fact that the vector from the corner to any point `p` of its neighbourhood is
+/// orthogonal to the image gradient at `p`, and solves for the point minimising the (gaussian
+/// weighted) sum of these dot products. Corners that drift further than the search window are
+/// left at their original location.
+pub fn refine_subpixel(img: &GrayImage, corners: &mut [Corner], params: &SubPixParams) {
+    let img = FloatImage::from(img);
+    let half = params.half_window as i64;
+    let sigma = params.half_window as f64;
+    let weights: Vec<f64> = (-half..=half)
+        .map(|i| (-((i * i) as f64) / (sigma * sigma)).exp())
+        .collect();
+
+    for corner in corners.iter_mut() {
+        let initial = corner.pt;
+        let mut current = initial;
+
+        for _ in 0..params.max_iterations {
+            let mut a = Matrix2::zeros();
+            let mut b = Vector2::zeros();
+            for v in -half..=half {
+                for u in -half..=half {
+                    let (x, y) = (current.x + u as f64, current.y + v as f64);
+                    let sample = |dx: f64, dy: f64| img.bilinear(x + dx, y + dy);
+                    let (gx, gy) = match (
+                        sample(1.0, 0.0),
+                        sample(-1.0, 0.0),
+                        sample(0.0, 1.0),
+                        sample(0.0, -1.0),
+                    ) {
+                        (Some(r), Some(l), Some(d), Some(t)) => (0.5 * (r - l), 0.5 * (d - t)),
+                        _ => continue,
+                    };
+
+                    let w = weights[(u + half) as usize] * weights[(v + half) as usize];
+                    let g = Vector2::new(gx, gy);
+                    let ggt = w * g * g.transpose();
+                    a += ggt;
+                    b += ggt * Vector2::new(u as f64, v as f64);
+                }
+            }
+
+            let step = match a.try_inverse() {
+                Some(a_inv) => a_inv * b,
+                None => break,
+            };
+            current += step;
+            if step.norm() < params.epsilon {
+                break;
+            }
+        }
+
+        if (current - initial).abs().max() > params.half_window as f64 {
+            current = initial;
+        }
+        corner.pt = current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::imageops::blur;
+    use image::Luma;
+
+    /// Render an anti-aliased bright rectangle, with exact area coverage per pixel
+    #[allow(clippy::suboptimal_flops)]
+    fn render_rectangle(size: u32, min: (f64, f64), max: (f64, f64)) -> GrayImage {
+        let overlap = |lo: f64, hi: f64, p: u32| {
+            let (p_lo, p_hi) = (f64::from(p) - 0.5, f64::from(p) + 0.5);
+            (hi.min(p_hi) - lo.max(p_lo)).max(0.0)
+        };
+
+        GrayImage::from_fn(size, size, |x, y| {
+            let coverage = overlap(min.0, max.0, x) * overlap(min.1, max.1, y);
+            Luma([(30.0 + 180.0 * coverage).round() as u8])
+        })
+    }
+
+    #[test]
+    fn structure_tensor_of_flat_image() {
+        let img = GrayImage::from_pixel(20, 20, Luma([128]));
+        let tensor = StructureTensor::new(&img, GradientKernel::Sobel, Window::Box, 3);
+        assert_eq!(tensor.at(10, 10), Matrix2::zeros());
+        assert_eq!(tensor.score(10, 10, CornerScore::MinEigenvalue), 0.0);
+    }
+
+    #[test]
+    fn detect_rectangle_corners() {
+        let img = render_rectangle(80, (20.0, 25.0), (60.0, 55.0));
+        let expected = [(20.0, 25.0), (60.0, 25.0), (20.0, 55.0), (60.0, 55.0)];
+
+        for score in &[CornerScore::MinEigenvalue, CornerScore::Harris(0.04)] {
+            for window in &[Window::Box, Window::Gaussian(1.0)] {
+                let params = CornerParams {
+                    score: *score,
+                    window: *window,
+                    quality_level: 0.1,
+                    ..CornerParams::default()
+                };
+                let corners = detect_corners(&img, &params);
+                assert_eq!(corners.len(), 4, "{:?} / {:?}", score, window);
+                for (x, y) in &expected {
+                    // the block window shifts the maximum of the response inside the rectangle
+                    assert!(corners
+                        .iter()
+                        .any(|c| (c.pt.x - x).abs() <= 3.0 && (c.pt.y - y).abs() <= 3.0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn min_distance_is_respected() {
+        let img = GrayImage::from_fn(120, 120, |x, y| {
+            Luma([if (x / 6 + y / 6) % 2 == 0 { 40 } else { 200 }])
+        });
+        let params = CornerParams {
+            min_distance: 15.0,
+            max_corners: 1000,
+            ..CornerParams::default()
+        };
+        let corners = detect_corners(&img, &params);
+
+        assert!(!corners.is_empty());
+        for (i, a) in corners.iter().enumerate() {
+            for b in corners.iter().skip(i + 1) {
+                assert!((a.pt - b.pt).norm() >= params.min_distance);
+            }
+        }
+    }
+
+    #[test]
+    #[allow(clippy::suboptimal_flops)]
+    fn subpixel_refinement() {
+        // saddle point of a checkerboard pattern at (29.8, 40.3)
+        let corner = (29.8, 40.3);
+        let img = GrayImage::from_fn(80, 80, |x, y| {
+            let ax = (f64::from(x) + 0.5 - corner.0).clamp(0.0, 1.0);
+            let ay = (f64::from(y) + 0.5 - corner.1).clamp(0.0, 1.0);
+            let coverage = ax * ay + (1.0 - ax) * (1.0 - ay);
+            Luma([(30.0 + 180.0 * coverage).round() as u8])
+        });
+        let img = blur(&img, 1.0);
+
+        let mut corners = vec![Corner {
+            pt: Point2::new(31.0, 39.0),
+            response: 0.0,
+        }];
+        refine_subpixel(&img, &mut corners, &SubPixParams::default());
+
+        let refined = corners[0].pt;
+        assert!(
+            (refined - Point2::new(corner.0, corner.1)).norm() < 0.05,
+            "{}",
+            refined
+        );
+    }
+}
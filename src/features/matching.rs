@@ -0,0 +1,412 @@
+/// Matching of binary descriptors
+///
+/// Brute-force Hamming matching with Lowe's ratio test and mutual cross-check, plus guided
+/// variants that only consider the train keypoints inside a search region - around a predicted
+/// location or along an epipolar line.
+use crate::features::orb::{Descriptor, KeyPoint};
+
+use nalgebra::{Matrix3, Point2};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+// -------------------------------------------------------------------------------------------------
+// Match / MatcherParams
+// -------------------------------------------------------------------------------------------------
+
+/// Correspondence between the `query_idx`-th query and the `train_idx`-th train descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Match {
+    pub query_idx: usize,
+    pub train_idx: usize,
+    /// Hamming distance between the two descriptors
+    pub distance: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatcherParams {
+    /// Maximum accepted Hamming distance
+    pub max_distance: u32,
+    /// Accept a match only if `best < ratio * second_best` (Lowe's ratio test). `None` disables
+    /// the test
+    pub ratio: Option<f64>,
+    /// Accept a match only if the query is also the best match of the train descriptor
+    pub cross_check: bool,
+}
+
+impl Default for MatcherParams {
+    fn default() -> Self {
+        Self {
+            max_distance: 64,
+            ratio: Some(0.8),
+            cross_check: true,
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// SearchRegion
+// -------------------------------------------------------------------------------------------------
+
+/// Restricts the train keypoints that each query keypoint is compared against
+#[derive(Debug, Clone, Copy)]
+pub enum SearchRegion<'a> {
+    /// Within `radius` pixels of the predicted location of each query keypoint in the train image
+    Radius {
+        predicted: &'a [Point2<f64>],
+        radius: f64,
+    },
+    /// Within `band` pixels of the epipolar line `l = F * x_query` in the train image
+    EpipolarBand {
+        fundamental: &'a Matrix3<f64>,
+        band: f64,
+    },
+}
+
+// -------------------------------------------------------------------------------------------------
+// BruteForceMatcher
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default)]
+pub struct BruteForceMatcher {
+    params: MatcherParams,
+}
+
+impl BruteForceMatcher {
+    pub const fn new(params: MatcherParams) -> Self {
+        Self { params }
+    }
+
+    pub const fn params(&self) -> &MatcherParams {
+        &self.params
+    }
+
+    /// Compare every query descriptor against every train descriptor
+    pub fn match_descriptors(&self, query: &[Descriptor], train: &[Descriptor]) -> Vec<Match> {
+        let all: Vec<usize> = (0..train.len()).collect();
+        self.match_candidates(query, train, |_| all.clone())
+    }
+
+    /// Compare each query descriptor only against the train descriptors whose keypoints lie in
+    /// the given search region
+    pub fn match_guided(
+        &self,
+        query_kps: &[KeyPoint],
+        query: &[Descriptor],
+        train_kps: &[KeyPoint],
+        train: &[Descriptor],
+        region: &SearchRegion<'_>,
+    ) -> Vec<Match> {
+        assert_eq!(query_kps.len(), query.len());
+        assert_eq!(train_kps.len(), train.len());
+
+        match *region {
+            SearchRegion::Radius { predicted, radius } => {
+                assert_eq!(predicted.len(), query.len());
+                let grid = SpatialGrid::new(train_kps, radius);
+                self.match_candidates(query, train, |idx| {
+                    grid.query(&predicted[idx], radius, train_kps)
+                })
+            }
+            SearchRegion::EpipolarBand { fundamental, band } => {
+                self.match_candidates(query, train, |idx| {
+                    let pt = query_kps[idx].pt;
+                    let line = fundamental * pt.to_homogeneous();
+                    let norm = line.x.hypot(line.y);
+                    if norm < f64::EPSILON {
+                        return Vec::new();
+                    }
+
+                    train_kps
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, kp)| (line.dot(&kp.pt.to_homogeneous()) / norm).abs() <= band)
+                        .map(|(idx, _)| idx)
+                        .collect()
+                })
+            }
+        }
+    }
+
+    /// Core of the matcher - `candidates(i)` returns the train indices to compare the i-th query
+    /// descriptor against
+    fn match_candidates<F>(
+        &self,
+        query: &[Descriptor],
+        train: &[Descriptor],
+        candidates: F,
+    ) -> Vec<Match>
+    where
+        F: Fn(usize) -> Vec<usize> + Sync,
+    {
+        // distances of every query to its candidates - in parallel across the query keypoints
+        let distances: Vec<Vec<(usize, u32)>> = query
+            .par_iter()
+            .enumerate()
+            .map(|(q_idx, q)| {
+                candidates(q_idx)
+                    .into_iter()
+                    .map(|t_idx| (t_idx, q.distance(&train[t_idx])))
+                    .collect()
+            })
+            .collect();
+
+        let forward: Vec<Option<Match>> = distances
+            .par_iter()
+            .enumerate()
+            .map(|(q_idx, dists)| self.best_match(q_idx, dists))
+            .collect();
+
+        if !self.params.cross_check {
+            return forward.into_iter().flatten().collect();
+        }
+
+        // best query of every train descriptor, among the queries that considered it
+        let mut reverse: HashMap<usize, (usize, u32)> = HashMap::new();
+        for (q_idx, dists) in distances.iter().enumerate() {
+            for &(t_idx, dist) in dists {
+                let best = reverse.entry(t_idx).or_insert((q_idx, dist));
+                if dist < best.1 {
+                    *best = (q_idx, dist);
+                }
+            }
+        }
+
+        forward
+            .into_iter()
+            .flatten()
+            .filter(|m| reverse.get(&m.train_idx).map(|best| best.0) == Some(m.query_idx))
+            .collect()
+    }
+
+    /// Select the best candidate, subject to the distance threshold and the ratio test
+    fn best_match(&self, query_idx: usize, dists: &[(usize, u32)]) -> Option<Match> {
+        let mut best: Option<(usize, u32)> = None;
+        let mut second = u32::MAX;
+        for &(t_idx, dist) in dists {
+            match best {
+                Some((_, best_dist)) if dist >= best_dist => second = second.min(dist),
+                _ => {
+                    if let Some((_, best_dist)) = best {
+                        second = best_dist;
+                    }
+                    best = Some((t_idx, dist));
+                }
+            }
+        }
+
+        let (train_idx, distance) = best?;
+        if distance > self.params.max_distance {
+            return None;
+        }
+        if let Some(ratio) = self.params.ratio {
+            if second != u32::MAX && f64::from(distance) >= ratio * f64::from(second) {
+                return None;
+            }
+        }
+
+        Some(Match {
+            query_idx,
+            train_idx,
+            distance,
+        })
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// SpatialGrid
+// -------------------------------------------------------------------------------------------------
+
+/// Buckets keypoints in square cells, for fast radius queries
+#[derive(Debug)]
+struct SpatialGrid {
+    cell: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl SpatialGrid {
+    fn new(kps: &[KeyPoint], cell: f64) -> Self {
+        let cell = cell.max(1.0);
+        let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        for (idx, kp) in kps.iter().enumerate() {
+            cells
+                .entry(Self::cell_of(&kp.pt, cell))
+                .or_default()
+                .push(idx);
+        }
+        Self { cell, cells }
+    }
+
+    fn cell_of(pt: &Point2<f64>, cell: f64) -> (i64, i64) {
+        ((pt.x / cell).floor() as i64, (pt.y / cell).floor() as i64)
+    }
+
+    /// Indices of the keypoints within `radius` of `center`
+    fn query(&self, center: &Point2<f64>, radius: f64, kps: &[KeyPoint]) -> Vec<usize> {
+        let (cx, cy) = Self::cell_of(center, self.cell);
+        let reach = (radius / self.cell).ceil() as i64;
+
+        let mut found = Vec::new();
+        for gy in cy - reach..=cy + reach {
+            for gx in cx - reach..=cx + reach {
+                if let Some(indices) = self.cells.get(&(gx, gy)) {
+                    found.extend(
+                        indices
+                            .iter()
+                            .filter(|&&idx| (kps[idx].pt - center).norm() <= radius),
+                    );
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(seed: u64) -> Descriptor {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        let mut words = [0; 4];
+        for word in &mut words {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *word = state;
+        }
+        Descriptor(words)
+    }
+
+    fn keypoint(x: f64, y: f64) -> KeyPoint {
+        KeyPoint {
+            pt: Point2::new(x, y),
+            octave: 0,
+            angle: 0.0,
+            response: 1.0,
+            size: 31.0,
+        }
+    }
+
+    fn flip_bits(mut desc: Descriptor, bits: &[usize]) -> Descriptor {
+        for &bit in bits {
+            let value = desc.bit(bit);
+            desc.set_bit(bit, !value);
+        }
+        desc
+    }
+
+    #[test]
+    fn brute_force_recovers_permutation() {
+        let query: Vec<_> = (0..200).map(descriptor).collect();
+        // reversed and slightly corrupted copy
+        let train: Vec<_> = query
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, d)| flip_bits(*d, &[i % 256, (7 * i) % 256]))
+            .collect();
+
+        let matches = BruteForceMatcher::default().match_descriptors(&query, &train);
+        assert_eq!(matches.len(), query.len());
+        for m in matches {
+            assert_eq!(m.train_idx, query.len() - 1 - m.query_idx);
+            assert!(m.distance <= 2);
+        }
+    }
+
+    #[test]
+    fn ratio_test_rejects_ambiguous_matches() {
+        let query = vec![descriptor(1), descriptor(2)];
+        // two (almost) equally good candidates for the first query
+        let train = vec![
+            flip_bits(descriptor(1), &[3, 4, 5, 6, 7]),
+            flip_bits(descriptor(1), &[8, 9, 10, 11, 12, 13]),
+            descriptor(2),
+        ];
+
+        let matcher = BruteForceMatcher::new(MatcherParams {
+            cross_check: false,
+            ..MatcherParams::default()
+        });
+        let matches = matcher.match_descriptors(&query, &train);
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].query_idx, matches[0].train_idx), (1, 2));
+
+        let no_ratio = BruteForceMatcher::new(MatcherParams {
+            ratio: None,
+            cross_check: false,
+            ..MatcherParams::default()
+        });
+        assert_eq!(no_ratio.match_descriptors(&query, &train).len(), 2);
+    }
+
+    #[test]
+    fn cross_check_keeps_mutual_best() {
+        let query = vec![flip_bits(descriptor(1), &[0, 1, 2]), descriptor(1)];
+        let train = vec![descriptor(1)];
+
+        let unchecked = BruteForceMatcher::new(MatcherParams {
+            cross_check: false,
+            ..MatcherParams::default()
+        });
+        assert_eq!(unchecked.match_descriptors(&query, &train).len(), 2);
+
+        let matches = BruteForceMatcher::default().match_descriptors(&query, &train);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].query_idx, 1);
+    }
+
+    #[test]
+    fn guided_radius_matching() {
+        let query_kps = vec![keypoint(100.0, 100.0), keypoint(300.0, 50.0)];
+        let query = vec![descriptor(1), descriptor(2)];
+        // identical descriptors for the first query - only the one close to the prediction is
+        // inside the search region
+        let train_kps = vec![
+            keypoint(400.0, 400.0),
+            keypoint(112.0, 95.0),
+            keypoint(305.0, 52.0),
+        ];
+        let train = vec![descriptor(1), descriptor(1), descriptor(2)];
+        let predicted = vec![Point2::new(110.0, 98.0), Point2::new(300.0, 50.0)];
+
+        let matches = BruteForceMatcher::default().match_guided(
+            &query_kps,
+            &query,
+            &train_kps,
+            &train,
+            &SearchRegion::Radius {
+                predicted: &predicted,
+                radius: 10.0,
+            },
+        );
+
+        let pairs: Vec<_> = matches.iter().map(|m| (m.query_idx, m.train_idx)).collect();
+        assert_eq!(pairs, vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn guided_epipolar_matching() {
+        // rectified stereo pair - epipolar lines are the image rows
+        let fundamental = Matrix3::new(0.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, 1.0, 0.0);
+        let query_kps = vec![keypoint(200.0, 100.0)];
+        let query = vec![descriptor(1)];
+        let train_kps = vec![keypoint(180.0, 140.0), keypoint(170.0, 101.0)];
+        let train = vec![descriptor(1), flip_bits(descriptor(1), &[10, 20, 30])];
+
+        let matches = BruteForceMatcher::default().match_guided(
+            &query_kps,
+            &query,
+            &train_kps,
+            &train,
+            &SearchRegion::EpipolarBand {
+                fundamental: &fundamental,
+                band: 2.0,
+            },
+        );
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].train_idx, 1);
+        assert_eq!(matches[0].distance, 3);
+    }
+}
@@ -0,0 +1,13 @@
+pub mod corners;
+pub mod matching;
+pub mod orb;
+pub mod pyramid;
+pub mod stereo;
+
+pub use self::corners::{
+    detect_corners, detect_corners_excluding, refine_subpixel, Corner, CornerParams,
+};
+pub use self::matching::{BruteForceMatcher, Match, MatcherParams, SearchRegion};
+pub use self::orb::{Descriptor, KeyPoint, OrbExtractor, OrbParams};
+pub use self::pyramid::ImagePyramid;
+pub use self::stereo::{PatchCost, StereoMatch, StereoMatcher, StereoParams, StereoRig};
@@ -0,0 +1,602 @@
+/// ORB features - Oriented FAST keypoints and Rotated BRIEF descriptors
+///
+/// For more information see:
+///
+/// - [Paper](http://www.gwylab.com/download/ORB_2012.pdf) - Rublee et al., "ORB: an efficient
+///   alternative to SIFT or SURF", ICCV 2011
+use crate::features::pyramid::ImagePyramid;
+
+use image::imageops::blur;
+use image::GrayImage;
+use nalgebra::Point2;
+
+/// Radius of the circular patch used for the orientation and the descriptor
+const HALF_PATCH_SIZE: i32 = 15;
+/// Side of the (square) patch that the descriptor is computed on
+const PATCH_SIZE: i32 = 2 * HALF_PATCH_SIZE + 1;
+/// Number of contiguous pixels of the circle that have to pass the FAST segment test
+const FAST_ARC_LENGTH: usize = 9;
+/// Bresenham circle of radius 3 used by the FAST segment test
+const FAST_CIRCLE: [(i32, i32); 16] = [
+    (0, -3),
+    (1, -3),
+    (2, -2),
+    (3, -1),
+    (3, 0),
+    (3, 1),
+    (2, 2),
+    (1, 3),
+    (0, 3),
+    (-1, 3),
+    (-2, 2),
+    (-3, 1),
+    (-3, 0),
+    (-3, -1),
+    (-2, -2),
+    (-1, -3),
+];
+/// Harris constant used for ranking the FAST corners
+const HARRIS_K: f64 = 0.04;
+/// Seed of the BRIEF sampling pattern. Changing it invalidates all the stored descriptors
+const PATTERN_SEED: u64 = 0x5EED_0BB1_u64;
+
+// -------------------------------------------------------------------------------------------------
+// Descriptor
+// -------------------------------------------------------------------------------------------------
+
+/// 256-bit binary descriptor, packed in 4 words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Descriptor(pub [u64; 4]);
+
+impl Descriptor {
+    /// Number of bits in the descriptor
+    pub const BITS: usize = 256;
+
+    pub const fn bit(&self, idx: usize) -> bool {
+        (self.0[idx / 64] >> (idx % 64)) & 1 == 1
+    }
+
+    pub fn set_bit(&mut self, idx: usize, value: bool) {
+        if value {
+            self.0[idx / 64] |= 1 << (idx % 64);
+        } else {
+            self.0[idx / 64] &= !(1 << (idx % 64));
+        }
+    }
+
+    /// Hamming distance between two descriptors
+    pub fn distance(&self, other: &Self) -> u32 {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// KeyPoint
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyPoint {
+    /// Position in level-0 (full resolution) pixel coordinates
+    pub pt: Point2<f64>,
+    /// Pyramid level that the keypoint was detected in
+    pub octave: usize,
+    /// Orientation in radians, in the [-pi, pi] range
+    pub angle: f64,
+    /// Harris response of the corner - higher is better
+    pub response: f64,
+    /// Diameter of the described neighbourhood in level-0 pixels
+    pub size: f64,
+}
+
+// -------------------------------------------------------------------------------------------------
+// OrbParams
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct OrbParams {
+    /// Maximum number of features to retain across all the pyramid levels
+    pub n_features: usize,
+    /// Scale ratio between two consecutive pyramid levels
+    pub scale_factor: f64,
+    /// Number of pyramid levels
+    pub n_levels: usize,
+    /// Intensity threshold of the FAST segment test
+    pub fast_threshold: u8,
+    /// Size of the image border in which no features are detected
+    pub edge_threshold: u32,
+}
+
+impl Default for OrbParams {
+    fn default() -> Self {
+        Self {
+            n_features: 1000,
+            scale_factor: 1.2,
+            n_levels: 8,
+            fast_threshold: 20,
+            edge_threshold: 19,
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// OrbExtractor
+// -------------------------------------------------------------------------------------------------
+
+/// Detect ORB keypoints and compute their descriptors on grayscale images
+#[derive(Debug)]
+pub struct OrbExtractor {
+    params: OrbParams,
+    /// BRIEF sampling pairs (x1, y1, x2, y2), all contained in the circular patch
+    pattern: Vec<[i32; 4]>,
+    /// Half-width of each row of the circular patch
+    umax: Vec<i32>,
+}
+
+impl OrbExtractor {
+    pub fn new(params: OrbParams) -> Self {
+        assert!(
+            params.edge_threshold as i32 > HALF_PATCH_SIZE + 1,
+            "Edge threshold must leave room for the descriptor patch"
+        );
+
+        Self {
+            params,
+            pattern: sample_pattern(PATTERN_SEED),
+            umax: compute_umax(),
+        }
+    }
+
+    pub const fn params(&self) -> &OrbParams {
+        &self.params
+    }
+
+    /// Build the scale pyramid that features are detected on
+    pub fn compute_pyramid(&self, img: &GrayImage) -> ImagePyramid {
+        let min_size = 2 * self.params.edge_threshold + 1;
+        ImagePyramid::new(
+            img,
+            self.params.n_levels,
+            self.params.scale_factor,
+            min_size,
+        )
+    }
+
+    /// Detect keypoints and compute their descriptors.
+    ///
+    /// The i-th descriptor corresponds to the i-th keypoint.
+    pub fn detect_and_compute(&self, img: &GrayImage) -> (Vec<KeyPoint>, Vec<Descriptor>) {
+        let pyramid = self.compute_pyramid(img);
+        let quotas = self.features_per_level(pyramid.len());
+
+        let mut keypoints = Vec::with_capacity(self.params.n_features);
+        let mut descriptors = Vec::with_capacity(self.params.n_features);
+        for (octave, &quota) in quotas.iter().enumerate() {
+            let level = pyramid.level(octave);
+            let scale = pyramid.scale(octave);
+            let smoothed = blur(level, 2.0);
+
+            for (x, y, response) in self.detect_level(level, quota) {
+                let angle = self.intensity_centroid_angle(level, x, y);
+                descriptors.push(self.describe(&smoothed, x, y, angle));
+                keypoints.push(KeyPoint {
+                    pt: Point2::new(f64::from(x) * scale, f64::from(y) * scale),
+                    octave,
+                    angle,
+                    response,
+                    size: f64::from(PATCH_SIZE) * scale,
+                });
+            }
+        }
+
+        (keypoints, descriptors)
+    }
+
+    /// Compute the descriptors of given points, e.g. tracked features, at full resolution. `None`
+    /// for the points too close to the border of the image for their patch
+    pub fn compute(&self, img: &GrayImage, pts: &[Point2<f64>]) -> Vec<Option<Descriptor>> {
+        let (width, height) = img.dimensions();
+        let border = f64::from(self.params.edge_threshold);
+        let smoothed = blur(img, 2.0);
+        pts.iter()
+            .map(|pt| {
+                let (x, y) = (pt.x.round(), pt.y.round());
+                if x < border
+                    || y < border
+                    || x >= f64::from(width) - border
+                    || y >= f64::from(height) - border
+                {
+                    return None;
+                }
+                let (x, y) = (x as u32, y as u32);
+                let angle = self.intensity_centroid_angle(img, x, y);
+                Some(self.describe(&smoothed, x, y, angle))
+            })
+            .collect()
+    }
+
+    /// Distribute the requested number of features across the pyramid levels, proportionally to
+    /// the area of each level
+    fn features_per_level(&self, n_levels: usize) -> Vec<usize> {
+        let inv_scale = 1.0 / self.params.scale_factor;
+        let n_features = self.params.n_features as f64;
+        let mut per_level =
+            n_features * (1.0 - inv_scale) / (1.0 - inv_scale.powi(n_levels as i32));
+
+        let mut quotas = Vec::with_capacity(n_levels);
+        let mut total = 0;
+        for _ in 0..n_levels.saturating_sub(1) {
+            let quota = per_level.round() as usize;
+            quotas.push(quota);
+            total += quota;
+            per_level *= inv_scale;
+        }
+        if n_levels > 0 {
+            quotas.push(self.params.n_features.saturating_sub(total));
+        }
+
+        quotas
+    }
+
+    /// Run FAST on a single level, suppress non-maxima and keep the `quota` corners with the
+    /// highest Harris response
+    fn detect_level(&self, img: &GrayImage, quota: usize) -> Vec<(u32, u32, f64)> {
+        let (width, height) = img.dimensions();
+        let border = self.params.edge_threshold;
+        if width <= 2 * border || height <= 2 * border {
+            return Vec::new();
+        }
+
+        let mut scores = vec![0_u32; (width * height) as usize];
+        for y in border..height - border {
+            for x in border..width - border {
+                scores[(y * width + x) as usize] =
+                    fast_score(img, x, y, self.params.fast_threshold);
+            }
+        }
+
+        let mut corners = Vec::new();
+        for y in border..height - border {
+            for x in border..width - border {
+                let score = scores[(y * width + x) as usize];
+                if score == 0 {
+                    continue;
+                }
+
+                // break ties in raster order so that plateaus yield a single corner
+                let is_max = (-1_i32..=1).all(|dy| {
+                    (-1_i32..=1).all(|dx| {
+                        let idx =
+                            ((y as i32 + dy) as u32 * width + (x as i32 + dx) as u32) as usize;
+                        match (dy, dx) {
+                            (0, 0) => true,
+                            (-1, _) | (0, -1) => score > scores[idx],
+                            _ => score >= scores[idx],
+                        }
+                    })
+                });
+                if is_max {
+                    corners.push((x, y, harris_response(img, x, y)));
+                }
+            }
+        }
+
+        corners.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+        corners.truncate(quota);
+        corners
+    }
+
+    /// Orientation of the patch, computed from its intensity centroid
+    fn intensity_centroid_angle(&self, img: &GrayImage, x: u32, y: u32) -> f64 {
+        let (x, y) = (x as i32, y as i32);
+        let pixel = |u: i32, v: i32| i32::from(img.get_pixel((x + u) as u32, (y + v) as u32)[0]);
+
+        let mut m10 = 0;
+        let mut m01 = 0;
+        for u in -HALF_PATCH_SIZE..=HALF_PATCH_SIZE {
+            m10 += u * pixel(u, 0);
+        }
+        for v in 1..=HALF_PATCH_SIZE {
+            let d = self.umax[v as usize];
+            let mut v_sum = 0;
+            for u in -d..=d {
+                let (below, above) = (pixel(u, v), pixel(u, -v));
+                v_sum += below - above;
+                m10 += u * (below + above);
+            }
+            m01 += v * v_sum;
+        }
+
+        f64::from(m01).atan2(f64::from(m10))
+    }
+
+    /// Compute the steered BRIEF descriptor of the patch around (x, y)
+    #[allow(clippy::suboptimal_flops)]
+    fn describe(&self, smoothed: &GrayImage, x: u32, y: u32, angle: f64) -> Descriptor {
+        let (sin, cos) = angle.sin_cos();
+        let pixel = |px: i32, py: i32| {
+            let u = (cos * f64::from(px) - sin * f64::from(py)).round() as i32;
+            let v = (sin * f64::from(px) + cos * f64::from(py)).round() as i32;
+            smoothed.get_pixel((x as i32 + u) as u32, (y as i32 + v) as u32)[0]
+        };
+
+        let mut descriptor = Descriptor::default();
+        for (idx, [x1, y1, x2, y2]) in self.pattern.iter().enumerate() {
+            descriptor.set_bit(idx, pixel(*x1, *y1) < pixel(*x2, *y2));
+        }
+        descriptor
+    }
+}
+
+impl Default for OrbExtractor {
+    fn default() -> Self {
+        Self::new(OrbParams::default())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// helpers
+// -------------------------------------------------------------------------------------------------
+
+/// FAST-9 segment test on the pixel (x, y).
+///
+/// Returns the corner score (sum of the absolute differences that exceed the threshold on the
+/// dominant side) or 0 if the pixel is not a corner.
+fn fast_score(img: &GrayImage, x: u32, y: u32, threshold: u8) -> u32 {
+    let center = i32::from(img.get_pixel(x, y)[0]);
+    let t = i32::from(threshold);
+
+    let mut circle = [0_i32; 16];
+    for (diff, (dx, dy)) in circle.iter_mut().zip(FAST_CIRCLE.iter()) {
+        let px = img.get_pixel((x as i32 + dx) as u32, (y as i32 + dy) as u32)[0];
+        *diff = i32::from(px) - center;
+    }
+
+    // any arc of 9 pixels contains at least 2 of the 4 compass points
+    let compass = [circle[0], circle[4], circle[8], circle[12]];
+    let n_bright = compass.iter().filter(|&&d| d > t).count();
+    let n_dark = compass.iter().filter(|&&d| d < -t).count();
+    if n_bright < 2 && n_dark < 2 {
+        return 0;
+    }
+
+    if !has_arc(&circle, |d| d > t) && !has_arc(&circle, |d| d < -t) {
+        return 0;
+    }
+
+    let bright: i32 = circle.iter().filter(|&&d| d > t).map(|&d| d - t).sum();
+    let dark: i32 = circle.iter().filter(|&&d| d < -t).map(|&d| -d - t).sum();
+    bright.max(dark) as u32
+}
+
+/// Is there an arc of at least [`FAST_ARC_LENGTH`] contiguous circle pixels satisfying `pred`?
+fn has_arc(circle: &[i32; 16], pred: impl Fn(i32) -> bool) -> bool {
+    let mut run = 0;
+    for i in 0..circle.len() + FAST_ARC_LENGTH - 1 {
+        if pred(circle[i % circle.len()]) {
+            run += 1;
+            if run >= FAST_ARC_LENGTH {
+                return true;
+            }
+        } else {
+            run = 0;
+        }
+    }
+    false
+}
+
+/// Harris corner response on a 7x7 block around (x, y)
+// determinant minus k times the squared trace
+#[allow(clippy::suspicious_operation_groupings, clippy::suboptimal_flops)]
+fn harris_response(img: &GrayImage, x: u32, y: u32) -> f64 {
+    let pixel =
+        |u: i32, v: i32| f64::from(img.get_pixel((x as i32 + u) as u32, (y as i32 + v) as u32)[0]);
+
+    let (mut a, mut b, mut c) = (0.0, 0.0, 0.0);
+    for v in -3..=3 {
+        for u in -3..=3 {
+            let ix = pixel(u + 1, v) - pixel(u - 1, v);
+            let iy = pixel(u, v + 1) - pixel(u, v - 1);
+            a += ix * ix;
+            b += iy * iy;
+            c += ix * iy;
+        }
+    }
+
+    (a * b - c * c) - HARRIS_K * (a + b) * (a + b)
+}
+
+/// Half-width of each row of the circular patch, made symmetric along the diagonal
+#[allow(clippy::suboptimal_flops)]
+fn compute_umax() -> Vec<i32> {
+    let radius = f64::from(HALF_PATCH_SIZE);
+    let vmax = (radius * std::f64::consts::FRAC_1_SQRT_2 + 1.0).floor() as i32;
+    let vmin = (radius * std::f64::consts::FRAC_1_SQRT_2).ceil() as i32;
+
+    let mut umax = vec![0; HALF_PATCH_SIZE as usize + 2];
+    for v in 0..=vmax {
+        umax[v as usize] = (radius * radius - f64::from(v * v)).sqrt().round() as i32;
+    }
+
+    let mut v0 = 0;
+    for v in (vmin..=HALF_PATCH_SIZE).rev() {
+        while umax[v0] == umax[v0 + 1] {
+            v0 += 1;
+        }
+        umax[v as usize] = v0 as i32;
+        v0 += 1;
+    }
+
+    umax
+}
+
+/// Sample the BRIEF test pairs from an isotropic gaussian (sigma = patch size / 5), rejecting the
+/// points that would fall outside the circular patch.
+///
+/// The pattern is generated from a fixed seed so that it's identical across runs.
+fn sample_pattern(seed: u64) -> Vec<[i32; 4]> {
+    let sigma = f64::from(PATCH_SIZE) / 5.0;
+    let mut state = seed;
+    let mut next_uniform = move || {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        ((z >> 11) as f64 + 0.5) / (1_u64 << 53) as f64
+    };
+    let mut next_point = move || loop {
+        // Box-Muller
+        let r = sigma * (-2.0 * next_uniform().ln()).sqrt();
+        let theta = 2.0 * std::f64::consts::PI * next_uniform();
+        let (x, y) = (
+            (r * theta.cos()).round() as i32,
+            (r * theta.sin()).round() as i32,
+        );
+        if x * x + y * y <= HALF_PATCH_SIZE * HALF_PATCH_SIZE {
+            return (x, y);
+        }
+    };
+
+    (0..Descriptor::BITS)
+        .map(|_| {
+            let (x1, y1) = next_point();
+            let (x2, y2) = next_point();
+            [x1, y1, x2, y2]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::imageops::rotate90;
+    use image::{load_from_memory_with_format, ImageFormat::Png, Luma};
+
+    fn sample_image() -> GrayImage {
+        let img = include_bytes!("../../tests/sample_dataset/cam0/data/1403636579763555584.png");
+        load_from_memory_with_format(img, Png)
+            .expect("Load img from memory")
+            .into_luma8()
+    }
+
+    #[test]
+    fn descriptor_bits_and_distance() {
+        let mut a = Descriptor::default();
+        let mut b = Descriptor::default();
+        assert_eq!(a.distance(&b), 0);
+
+        a.set_bit(0, true);
+        a.set_bit(100, true);
+        a.set_bit(255, true);
+        assert!(a.bit(100) && a.bit(255) && !a.bit(1));
+        assert_eq!(a.distance(&b), 3);
+
+        b.set_bit(100, true);
+        assert_eq!(a.distance(&b), 2);
+        a.set_bit(100, false);
+        assert_eq!(a.distance(&b), 3);
+    }
+
+    #[test]
+    fn fast_detects_square_corners() {
+        let mut img = GrayImage::from_pixel(100, 100, Luma([20]));
+        for y in 40..60 {
+            for x in 40..60 {
+                img.put_pixel(x, y, Luma([220]));
+            }
+        }
+
+        let extractor = OrbExtractor::new(OrbParams {
+            n_levels: 1,
+            ..OrbParams::default()
+        });
+        let (keypoints, descriptors) = extractor.detect_and_compute(&img);
+        assert_eq!(keypoints.len(), descriptors.len());
+
+        for corner in &[(40.0, 40.0), (59.0, 40.0), (40.0, 59.0), (59.0, 59.0)] {
+            assert!(
+                keypoints.iter().any(
+                    |kp| (kp.pt.x - corner.0).abs() <= 2.0 && (kp.pt.y - corner.1).abs() <= 2.0
+                ),
+                "No keypoint detected close to corner {:?}",
+                corner
+            );
+        }
+    }
+
+    #[test]
+    fn extract_on_dataset_image() {
+        let img = sample_image();
+        let extractor = OrbExtractor::default();
+        let (keypoints, descriptors) = extractor.detect_and_compute(&img);
+
+        assert_eq!(keypoints.len(), descriptors.len());
+        assert!(keypoints.len() > 100);
+        assert!(keypoints.len() <= extractor.params().n_features);
+        assert!(keypoints.iter().any(|kp| kp.octave > 0));
+        for kp in &keypoints {
+            assert!(kp.octave < extractor.params().n_levels);
+            assert!(kp.pt.x >= 0.0 && kp.pt.x < f64::from(img.width()));
+            assert!(kp.pt.y >= 0.0 && kp.pt.y < f64::from(img.height()));
+            assert!(kp.angle.abs() <= std::f64::consts::PI);
+        }
+    }
+
+    #[test]
+    fn compute_at_given_points() {
+        let img = sample_image();
+        let extractor = OrbExtractor::default();
+        let (keypoints, descriptors) = extractor.detect_and_compute(&img);
+
+        // the keypoints of full resolution get the same descriptors
+        let (pts, expected): (Vec<_>, Vec<_>) = keypoints
+            .iter()
+            .zip(descriptors)
+            .filter(|(kp, _)| kp.octave == 0)
+            .map(|(kp, desc)| (kp.pt, Some(desc)))
+            .unzip();
+        assert!(!pts.is_empty());
+        assert_eq!(extractor.compute(&img, &pts), expected);
+
+        let border = [Point2::new(3.0, 100.0), Point2::new(100.0, 470.0)];
+        assert_eq!(extractor.compute(&img, &border), vec![None, None]);
+    }
+
+    #[test]
+    fn rotation_invariance() {
+        let img = sample_image();
+        let rotated = rotate90(&img);
+        let height = f64::from(img.height());
+
+        let extractor = OrbExtractor::new(OrbParams {
+            n_levels: 1,
+            ..OrbParams::default()
+        });
+        let (kps, descs) = extractor.detect_and_compute(&img);
+        let (kps_rot, descs_rot) = extractor.detect_and_compute(&rotated);
+
+        // rotate90 maps (x, y) to (height - 1 - y, x)
+        let mut n_pairs = 0;
+        let mut n_similar = 0;
+        for (kp, desc) in kps.iter().zip(descs.iter()) {
+            let expected = Point2::new(height - 1.0 - kp.pt.y, kp.pt.x);
+            let found = kps_rot
+                .iter()
+                .position(|kp_rot| (kp_rot.pt - expected).norm() < 0.5);
+            if let Some(idx) = found {
+                n_pairs += 1;
+                if desc.distance(&descs_rot[idx]) < 64 {
+                    n_similar += 1;
+                }
+            }
+        }
+
+        assert!(n_pairs > 50);
+        assert!(n_similar as f64 > 0.8 * n_pairs as f64);
+    }
+}
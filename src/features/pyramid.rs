@@ -0,0 +1,69 @@
+/// Multi-scale image pyramids
+///
+/// Level 0 is the original image, every subsequent level is downscaled by `scale_factor` with
+/// respect to the previous one.
+use image::imageops::{resize, FilterType};
+use image::GrayImage;
+
+// -------------------------------------------------------------------------------------------------
+// ImagePyramid
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct ImagePyramid {
+    levels: Vec<GrayImage>,
+    /// Scale of each level with respect to level 0 (>= 1.0)
+    scales: Vec<f64>,
+}
+
+impl ImagePyramid {
+    /// Build a pyramid of (at most) `n_levels` levels.
+    ///
+    /// Construction stops early if a level would become smaller than `min_size` pixels in either
+    /// dimension.
+    pub fn new(img: &GrayImage, n_levels: usize, scale_factor: f64, min_size: u32) -> Self {
+        assert!(scale_factor > 1.0, "Pyramid scale factor must be > 1.0");
+
+        let mut levels = Vec::with_capacity(n_levels);
+        let mut scales = Vec::with_capacity(n_levels);
+        levels.push(img.clone());
+        scales.push(1.0);
+
+        for i in 1..n_levels {
+            let scale = scale_factor.powi(i as i32);
+            let width = (f64::from(img.width()) / scale).round() as u32;
+            let height = (f64::from(img.height()) / scale).round() as u32;
+            if width < min_size || height < min_size {
+                break;
+            }
+
+            // downscale from the previous level - cheaper and smoother than going from level 0
+            let prev = &levels[i - 1];
+            levels.push(resize(prev, width, height, FilterType::Triangle));
+            scales.push(scale);
+        }
+
+        Self { levels, scales }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    pub fn level(&self, idx: usize) -> &GrayImage {
+        &self.levels[idx]
+    }
+
+    pub fn levels(&self) -> &[GrayImage] {
+        &self.levels
+    }
+
+    /// Scale of the given level with respect to level 0
+    pub fn scale(&self, idx: usize) -> f64 {
+        self.scales[idx]
+    }
+}
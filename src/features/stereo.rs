@@ -0,0 +1,380 @@
+/// Stereo matching and depth initialisation
+///
+/// Points of the left (cam0) image are searched along their epipolar curve in the right (cam1)
+/// image. The search is parametrised by the inverse depth of the point along its left bearing, so
+/// it works both for rectified and for calibrated (distorted, unrectified) pairs. The best patch
+/// match is refined to sub-pixel accuracy and triangulated in the cam0 frame.
+use crate::drivers::CameraCalibration;
+use crate::geometry::camera::CameraModel;
+use crate::geometry::triangulation::triangulate_midpoint;
+use crate::utils::imgproc::interpolate;
+
+use image::GrayImage;
+use nalgebra::{Isometry3, Matrix2, Point2, Point3, Unit, Vector2, Vector3};
+use rayon::prelude::*;
+use std::sync::Arc;
+
+// -------------------------------------------------------------------------------------------------
+// StereoRig
+// -------------------------------------------------------------------------------------------------
+
+/// A calibrated pair of cameras
+#[derive(Debug, Clone)]
+pub struct StereoRig {
+    pub left: Arc<dyn CameraModel>,
+    pub right: Arc<dyn CameraModel>,
+    /// Transforms points from the left (cam0) to the right (cam1) camera frame
+    pub t_rl: Isometry3<f64>,
+}
+
+impl StereoRig {
+    pub fn new(
+        left: Arc<dyn CameraModel>,
+        right: Arc<dyn CameraModel>,
+        t_rl: Isometry3<f64>,
+    ) -> Self {
+        Self { left, right, t_rl }
+    }
+
+    /// Rig described by the `EuRoC` calibrations of the two cameras
+    pub fn from_calibrations(cam0: &CameraCalibration, cam1: &CameraCalibration) -> Self {
+        Self::new(
+            cam0.camera_model(),
+            cam1.camera_model(),
+            cam1.t_bs.inverse() * cam0.t_bs,
+        )
+    }
+
+    /// Distance between the optical centres of the two cameras
+    pub fn baseline(&self) -> f64 {
+        self.t_rl.translation.vector.norm()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// StereoParams / StereoMatch
+// -------------------------------------------------------------------------------------------------
+
+/// Similarity measure used to compare image patches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchCost {
+    /// Mean absolute intensity difference
+    Sad,
+    /// `1 - NCC`, invariant to affine brightness changes between the two cameras
+    Ncc,
+}
+
+#[derive(Debug, Clone)]
+pub struct StereoParams {
+    /// The compared patches are `2 * half_patch + 1` pixels wide
+    pub half_patch: u32,
+    pub cost: PatchCost,
+    /// Matches costing more than this are rejected. Intensity levels for [`PatchCost::Sad`], in
+    /// the `[0, 2]` range for [`PatchCost::Ncc`]
+    pub max_cost: f64,
+    /// Accepted disparity range, in pixels - see [`StereoMatch::disparity`]
+    pub min_disparity: f64,
+    pub max_disparity: f64,
+}
+
+impl Default for StereoParams {
+    fn default() -> Self {
+        Self {
+            half_patch: 5,
+            cost: PatchCost::Ncc,
+            max_cost: 0.3,
+            min_disparity: 1.0,
+            max_disparity: 96.0,
+        }
+    }
+}
+
+/// Correspondence of the `left_idx`-th left point in the right image
+#[derive(Debug, Clone, PartialEq)]
+pub struct StereoMatch {
+    pub left_idx: usize,
+    pub left: Point2<f64>,
+    pub right: Point2<f64>,
+    /// Inverse depth of the point, scaled to pixels along the epipolar curve - the projection of
+    /// the point at infinity has zero disparity. Equals `left.x - right.x` for a rectified pair
+    pub disparity: f64,
+    pub cost: f64,
+    /// Triangulated point in the left (cam0) camera frame
+    pub point: Point3<f64>,
+}
+
+// -------------------------------------------------------------------------------------------------
+// StereoMatcher
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct StereoMatcher {
+    rig: StereoRig,
+    params: StereoParams,
+}
+
+impl StereoMatcher {
+    pub const fn new(rig: StereoRig, params: StereoParams) -> Self {
+        Self { rig, params }
+    }
+
+    pub const fn rig(&self) -> &StereoRig {
+        &self.rig
+    }
+
+    pub const fn params(&self) -> &StereoParams {
+        &self.params
+    }
+
+    /// Find the given left image points in the right image and triangulate them. Points that
+    /// can't be matched reliably are left out of the result
+    pub fn match_points(
+        &self,
+        left_img: &GrayImage,
+        right_img: &GrayImage,
+        pts: &[Point2<f64>],
+    ) -> Vec<StereoMatch> {
+        pts.par_iter()
+            .enumerate()
+            .filter_map(|(idx, pt)| self.match_point(left_img, right_img, idx, pt))
+            .collect()
+    }
+
+    #[allow(clippy::suboptimal_flops)]
+    fn match_point(
+        &self,
+        left_img: &GrayImage,
+        right_img: &GrayImage,
+        left_idx: usize,
+        pt: &Point2<f64>,
+    ) -> Option<StereoMatch> {
+        let half = self.params.half_patch;
+        let border = f64::from(half) + 1.0;
+        if !self.rig.left.is_in_image(pt, border) {
+            return None;
+        }
+        let left_patch = sample_patch(left_img, pt, half)?;
+        let bearing = self.rig.left.unproject(pt)?;
+
+        // A point at inverse depth `rho` along the left bearing is seen by the right camera along
+        // `R * f + rho * t`. `pixels_per_rho` converts the disparity range into inverse depths,
+        // so that consecutive samples are about one pixel apart on the right image
+        let rotated = self.rig.t_rl.rotation * bearing.into_inner();
+        let t = self.rig.t_rl.translation.vector;
+        let at_rho = |rho: f64| Point3::from(rotated + rho * t);
+        let pixels_per_rho = (self.rig.right.project_jacobian(&at_rho(0.0))? * t).norm();
+        if pixels_per_rho < f64::EPSILON {
+            return None;
+        }
+
+        let min_sample = self.params.min_disparity.max(0.0).floor();
+        let n_samples = (self.params.max_disparity - min_sample).ceil().max(0.0) as usize + 1;
+        let sample_px = |s: f64| {
+            self.rig
+                .right
+                .project(&at_rho(s / pixels_per_rho))
+                .filter(|px| self.rig.right.is_in_image(px, border))
+        };
+
+        let costs: Vec<Option<f64>> = (0..n_samples)
+            .map(|i| {
+                let px = sample_px(min_sample + i as f64)?;
+                let right_patch = sample_patch(right_img, &px, half)?;
+                Some(patch_cost(self.params.cost, &left_patch, &right_patch))
+            })
+            .collect();
+
+        let (best, best_cost) = costs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| c.map(|c| (i, c)))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())?;
+        // A minimum at the boundary of the search range is most likely outside of it
+        if best == 0 || best + 1 == n_samples || best_cost > self.params.max_cost {
+            return None;
+        }
+        let (prev, next) = (costs[best - 1]?, costs[best + 1]?);
+
+        // parabola through the three costs around the minimum
+        let curvature = prev - 2.0 * best_cost + next;
+        let offset = if curvature > f64::EPSILON {
+            (0.5 * (prev - next) / curvature).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        let disparity = min_sample + best as f64 + offset;
+        if disparity < self.params.min_disparity || disparity > self.params.max_disparity {
+            return None;
+        }
+        let right = sample_px(disparity)?;
+
+        let right_bearing = self.rig.right.unproject(&right)?;
+        let point = triangulate_midpoint(&[
+            (Isometry3::identity(), bearing),
+            (self.rig.t_rl, right_bearing),
+        ])?;
+        Some(StereoMatch {
+            left_idx,
+            left: *pt,
+            right,
+            disparity,
+            cost: best_cost,
+            point,
+        })
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// helpers
+// -------------------------------------------------------------------------------------------------
+
+/// Intensities of the square patch around `center`, row by row
+fn sample_patch(img: &GrayImage, center: &Point2<f64>, half: u32) -> Option<Vec<f64>> {
+    let half = i64::from(half);
+    let mut patch = Vec::with_capacity(((2 * half + 1) * (2 * half + 1)) as usize);
+    for dy in -half..=half {
+        for dx in -half..=half {
+            patch.push(interpolate(
+                img,
+                center.x + dx as f64,
+                center.y + dy as f64,
+            )?);
+        }
+    }
+    Some(patch)
+}
+
+fn patch_cost(cost: PatchCost, a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    match cost {
+        PatchCost::Sad => a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f64>() / n,
+        PatchCost::Ncc => {
+            let mean_a = a.iter().sum::<f64>() / n;
+            let mean_b = b.iter().sum::<f64>() / n;
+            let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+            for (a, b) in a.iter().zip(b) {
+                let (a, b) = (a - mean_a, b - mean_b);
+                ab += a * b;
+                aa += a * a;
+                bb += b * b;
+            }
+            // textureless patches can't be matched
+            if aa < f64::EPSILON || bb < f64::EPSILON {
+                return 2.0;
+            }
+            1.0 - ab / (aa * bb).sqrt()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::corners::{detect_corners, CornerParams};
+    use crate::geometry::camera::PinholeRadTan;
+    use image::{load_from_memory_with_format, ImageFormat::Png, Luma};
+    use nalgebra::Translation3;
+
+    /// Image of a textured fronto-parallel plane at depth `depth`, seen by a camera at `x_offset`
+    #[allow(clippy::suboptimal_flops)]
+    fn render_plane(camera: &PinholeRadTan, depth: f64, x_offset: f64) -> GrayImage {
+        GrayImage::from_fn(camera.width, camera.height, |u, v| {
+            let x = (f64::from(u) - camera.cx) / camera.fx * depth + x_offset;
+            let y = (f64::from(v) - camera.cy) / camera.fy * depth;
+            let value = 128.0
+                + 30.0 * (61.0 * x + 0.3).sin() * (47.0 * y).cos()
+                + 30.0 * (113.0 * x + 89.0 * y).sin()
+                + 20.0 * (37.0 * x - 151.0 * y + 1.0).sin()
+                + 20.0 * (193.0 * x + 0.5).cos() * (71.0 * y).sin();
+            Luma([value.round().clamp(0.0, 255.0) as u8])
+        })
+    }
+
+    #[test]
+    #[allow(clippy::suboptimal_flops)]
+    fn rectified_plane() {
+        let camera = PinholeRadTan::undistorted([400.0, 400.0, 160.0, 120.0], (320, 240));
+        let (baseline, depth) = (0.1, 2.0);
+        let left_img = render_plane(&camera, depth, 0.0);
+        let right_img = render_plane(&camera, depth, baseline);
+
+        let rig = StereoRig::new(
+            Arc::new(camera.clone()),
+            Arc::new(camera),
+            Isometry3::from_parts(Translation3::new(-baseline, 0.0, 0.0), Default::default()),
+        );
+        assert!((rig.baseline() - baseline).abs() < 1e-12);
+
+        let pts: Vec<_> = (0..8)
+            .flat_map(|i| {
+                (0..6).map(move |j| {
+                    Point2::new(60.3 + 30.0 * f64::from(i), 30.7 + 35.0 * f64::from(j))
+                })
+            })
+            .collect();
+        for &cost in &[PatchCost::Sad, PatchCost::Ncc] {
+            let params = StereoParams {
+                cost,
+                max_cost: if cost == PatchCost::Sad { 10.0 } else { 0.2 },
+                max_disparity: 64.0,
+                ..Default::default()
+            };
+            let matches =
+                StereoMatcher::new(rig.clone(), params).match_points(&left_img, &right_img, &pts);
+            assert!(matches.len() >= pts.len() * 9 / 10);
+            for m in &matches {
+                assert!(
+                    (m.disparity - 20.0).abs() < 0.15,
+                    "disparity {}",
+                    m.disparity
+                );
+                assert!((m.right.y - m.left.y).abs() < 1e-6);
+                assert!((m.point.z - depth).abs() < 0.02, "depth {}", m.point.z);
+            }
+        }
+    }
+
+    #[test]
+    fn euroc_stereo_pair() {
+        let load = |bytes: &[u8]| {
+            load_from_memory_with_format(bytes, Png)
+                .expect("Load img from memory")
+                .into_luma8()
+        };
+        let left_img = load(include_bytes!(
+            "../../tests/sample_dataset/cam0/data/1403636579763555584.png"
+        ));
+        let right_img = load(include_bytes!(
+            "../../tests/sample_dataset/cam1/data/1403636579763555584.png"
+        ));
+        let cam0 = CameraCalibration::from_yaml(include_str!(
+            "../../tests/sample_dataset/cam0/sensor.yaml"
+        ))
+        .unwrap();
+        let cam1 = CameraCalibration::from_yaml(include_str!(
+            "../../tests/sample_dataset/cam1/sensor.yaml"
+        ))
+        .unwrap();
+        let rig = StereoRig::from_calibrations(&cam0, &cam1);
+        assert!((rig.baseline() - 0.11).abs() < 0.005);
+
+        let pts: Vec<_> = detect_corners(&left_img, &CornerParams::default())
+            .into_iter()
+            .map(|c| c.pt)
+            .collect();
+        let matcher = StereoMatcher::new(rig.clone(), StereoParams::default());
+        let matches = matcher.match_points(&left_img, &right_img, &pts);
+        assert!(
+            matches.len() > pts.len() / 2,
+            "{} / {}",
+            matches.len(),
+            pts.len()
+        );
+
+        for m in &matches {
+            assert!(m.point.z > 0.0);
+            let reprojected = rig.right.project(&(rig.t_rl * m.point)).unwrap();
+            assert!((reprojected - m.right).norm() < 0.1);
+        }
+    }
+}
@@ -0,0 +1,356 @@
+/// Camera projection models
+///
+/// Points are expressed in the camera frame - z along the optical axis, x pointing to the right
+/// and y pointing downwards in the image.
+use nalgebra::{Matrix2, Matrix2x3, Matrix3, Point2, Point3, Unit, Vector2, Vector3};
+use std::fmt::Debug;
+
+/// Points closer than this to the image plane of the camera are not projected
+const MIN_DEPTH: f64 = 1e-6;
+
+// -------------------------------------------------------------------------------------------------
+// CameraModel
+// -------------------------------------------------------------------------------------------------
+
+/// A model that maps 3D points in the camera frame to pixels and back to bearing vectors
+pub trait CameraModel: Debug + Send + Sync {
+    /// Project a point of the camera frame onto the image. Returns `None` if the point can't be
+    /// projected (e.g. it's behind the camera)
+    fn project(&self, p: &Point3<f64>) -> Option<Point2<f64>>;
+
+    /// Unit vector pointing along the ray that goes through the given pixel
+    fn unproject(&self, px: &Point2<f64>) -> Option<Unit<Vector3<f64>>>;
+
+    /// Width and height of the image in pixels
+    fn resolution(&self) -> (u32, u32);
+
+    /// Jacobian of [`CameraModel::project`] with respect to the point.
+    ///
+    /// The default implementation uses central differences.
+    fn project_jacobian(&self, p: &Point3<f64>) -> Option<Matrix2x3<f64>> {
+        let step = 1e-6 * p.coords.norm().max(1.0);
+        let mut jacobian = Matrix2x3::zeros();
+        for axis in 0..3 {
+            let mut delta = Vector3::zeros();
+            delta[axis] = step;
+            let plus = self.project(&(p + delta))?;
+            let minus = self.project(&(p - delta))?;
+            jacobian.set_column(axis, &((plus - minus) / (2.0 * step)));
+        }
+        Some(jacobian)
+    }
+
+    /// Is the pixel inside the image, at least `border` pixels away from its edges?
+    fn is_in_image(&self, px: &Point2<f64>, border: f64) -> bool {
+        let (width, height) = self.resolution();
+        px.x >= border
+            && px.y >= border
+            && px.x <= f64::from(width) - 1.0 - border
+            && px.y <= f64::from(height) - 1.0 - border
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// PinholeRadTan
+// -------------------------------------------------------------------------------------------------
+
+/// Pinhole camera with radial-tangential (Brown-Conrady / plumb bob) distortion
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinholeRadTan {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    /// k1, k2, p1, p2
+    pub distortion: [f64; 4],
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PinholeRadTan {
+    /// Create a camera from its intrinsics `[fu, fv, cu, cv]`, distortion coefficients
+    /// `[k1, k2, p1, p2]` and resolution `(width, height)`
+    pub const fn new(intrinsics: [f64; 4], distortion: [f64; 4], resolution: (u32, u32)) -> Self {
+        Self {
+            fx: intrinsics[0],
+            fy: intrinsics[1],
+            cx: intrinsics[2],
+            cy: intrinsics[3],
+            distortion,
+            width: resolution.0,
+            height: resolution.1,
+        }
+    }
+
+    /// A camera without any lens distortion - e.g. the output of stereo rectification
+    pub const fn undistorted(intrinsics: [f64; 4], resolution: (u32, u32)) -> Self {
+        Self::new(intrinsics, [0.0; 4], resolution)
+    }
+
+    pub fn intrinsic_matrix(&self) -> Matrix3<f64> {
+        Matrix3::new(self.fx, 0.0, self.cx, 0.0, self.fy, self.cy, 0.0, 0.0, 1.0)
+    }
+
+    /// Apply the distortion to normalised image coordinates. Also returns the jacobian of the
+    /// distortion
+    #[allow(clippy::suboptimal_flops)]
+    pub fn distort(&self, xn: &Vector2<f64>) -> (Vector2<f64>, Matrix2<f64>) {
+        let [k1, k2, p1, p2] = self.distortion;
+        let (x, y) = (xn.x, xn.y);
+        let r2 = x * x + y * y;
+        let radial = 1.0 + k1 * r2 + k2 * r2 * r2;
+        let d_radial = 2.0 * k1 + 4.0 * k2 * r2; // d(radial) / d(x) = d_radial * x
+
+        let distorted = Vector2::new(
+            x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+            y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+        );
+        let jacobian = Matrix2::new(
+            radial + d_radial * x * x + 2.0 * p1 * y + 6.0 * p2 * x,
+            d_radial * x * y + 2.0 * p1 * x + 2.0 * p2 * y,
+            d_radial * x * y + 2.0 * p1 * x + 2.0 * p2 * y,
+            radial + d_radial * y * y + 6.0 * p1 * y + 2.0 * p2 * x,
+        );
+
+        (distorted, jacobian)
+    }
+
+    /// Remove the distortion from normalised image coordinates, using Gauss-Newton iterations
+    pub fn undistort(&self, xd: &Vector2<f64>) -> Option<Vector2<f64>> {
+        let mut xn = *xd;
+        for _ in 0..20 {
+            let (distorted, jacobian) = self.distort(&xn);
+            let error = distorted - xd;
+            if error.norm_squared() < 1e-24 {
+                break;
+            }
+            xn -= jacobian.try_inverse()? * error;
+        }
+
+        let (distorted, _) = self.distort(&xn);
+        if (distorted - xd).norm() < 1e-8 {
+            Some(xn)
+        } else {
+            None
+        }
+    }
+}
+
+impl CameraModel for PinholeRadTan {
+    #[allow(clippy::suboptimal_flops)]
+    fn project(&self, p: &Point3<f64>) -> Option<Point2<f64>> {
+        if p.z < MIN_DEPTH {
+            return None;
+        }
+        let (xd, _) = self.distort(&Vector2::new(p.x / p.z, p.y / p.z));
+        Some(Point2::new(
+            self.fx * xd.x + self.cx,
+            self.fy * xd.y + self.cy,
+        ))
+    }
+
+    fn unproject(&self, px: &Point2<f64>) -> Option<Unit<Vector3<f64>>> {
+        let xd = Vector2::new((px.x - self.cx) / self.fx, (px.y - self.cy) / self.fy);
+        let xn = self.undistort(&xd)?;
+        Some(Unit::new_normalize(Vector3::new(xn.x, xn.y, 1.0)))
+    }
+
+    fn resolution(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn project_jacobian(&self, p: &Point3<f64>) -> Option<Matrix2x3<f64>> {
+        if p.z < MIN_DEPTH {
+            return None;
+        }
+        let inv_z = 1.0 / p.z;
+        let (_, d_distort) = self.distort(&Vector2::new(p.x * inv_z, p.y * inv_z));
+        let d_normalised = Matrix2x3::new(
+            inv_z,
+            0.0,
+            -p.x * inv_z * inv_z,
+            0.0,
+            inv_z,
+            -p.y * inv_z * inv_z,
+        );
+        let focal = Matrix2::new(self.fx, 0.0, 0.0, self.fy);
+
+        Some(focal * d_distort * d_normalised)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// PinholeEquidistant
+// -------------------------------------------------------------------------------------------------
+
+/// Fisheye camera with the equidistant (Kannala-Brandt) distortion model. Can see points behind
+/// the image plane, up to the field of view where the distortion stops being monotonic
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinholeEquidistant {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    /// k1, k2, k3, k4
+    pub distortion: [f64; 4],
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PinholeEquidistant {
+    /// Create a camera from its intrinsics `[fu, fv, cu, cv]`, distortion coefficients
+    /// `[k1, k2, k3, k4]` and resolution `(width, height)`
+    pub const fn new(intrinsics: [f64; 4], distortion: [f64; 4], resolution: (u32, u32)) -> Self {
+        Self {
+            fx: intrinsics[0],
+            fy: intrinsics[1],
+            cx: intrinsics[2],
+            cy: intrinsics[3],
+            distortion,
+            width: resolution.0,
+            height: resolution.1,
+        }
+    }
+
+    /// Distorted angle `theta_d` of a ray at angle `theta` from the optical axis, and its
+    /// derivative
+    #[allow(clippy::suboptimal_flops)]
+    fn distort_angle(&self, theta: f64) -> (f64, f64) {
+        let [k1, k2, k3, k4] = self.distortion;
+        let t2 = theta * theta;
+        let poly = 1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4)));
+        let d_poly = t2 * (3.0 * k1 + t2 * (5.0 * k2 + t2 * (7.0 * k3 + t2 * 9.0 * k4)));
+        (theta * poly, poly + d_poly)
+    }
+}
+
+impl CameraModel for PinholeEquidistant {
+    #[allow(clippy::suboptimal_flops)]
+    fn project(&self, p: &Point3<f64>) -> Option<Point2<f64>> {
+        let r = p.x.hypot(p.y);
+        let theta = r.atan2(p.z);
+        let (theta_d, slope) = self.distort_angle(theta);
+        // past the maximum of the distortion, different angles map to the same pixel
+        if slope <= 0.0 || p.coords.norm() < MIN_DEPTH {
+            return None;
+        }
+        let scale = if r < MIN_DEPTH {
+            1.0 / p.z
+        } else {
+            theta_d / r
+        };
+        Some(Point2::new(
+            self.fx * p.x * scale + self.cx,
+            self.fy * p.y * scale + self.cy,
+        ))
+    }
+
+    fn unproject(&self, px: &Point2<f64>) -> Option<Unit<Vector3<f64>>> {
+        let xd = Vector2::new((px.x - self.cx) / self.fx, (px.y - self.cy) / self.fy);
+        let theta_d = xd.norm();
+        if theta_d < MIN_DEPTH {
+            return Some(Vector3::z_axis());
+        }
+
+        let mut theta = theta_d;
+        for _ in 0..20 {
+            let (distorted, slope) = self.distort_angle(theta);
+            if slope <= 0.0 {
+                return None;
+            }
+            let step = (distorted - theta_d) / slope;
+            theta -= step;
+            if step.abs() < 1e-12 {
+                break;
+            }
+        }
+        if (self.distort_angle(theta).0 - theta_d).abs() > 1e-8 || theta < 0.0 {
+            return None;
+        }
+
+        let direction = xd / theta_d * theta.sin();
+        Some(Unit::new_normalize(Vector3::new(
+            direction.x,
+            direction.y,
+            theta.cos(),
+        )))
+    }
+
+    fn resolution(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn euroc_cam0() -> PinholeRadTan {
+        PinholeRadTan::new(
+            [458.654, 457.296, 367.215, 248.375],
+            [-0.28340811, 0.07395907, 0.00019359, 1.76187114e-05],
+            (752, 480),
+        )
+    }
+
+    #[test]
+    fn project_unproject_roundtrip() {
+        let camera = euroc_cam0();
+        for &(u, v) in &[(0.0, 0.0), (367.2, 248.4), (751.0, 479.0), (100.0, 400.0)] {
+            let px = Point2::new(u, v);
+            let bearing = camera.unproject(&px).expect("Valid pixel");
+            let reprojected = camera
+                .project(&Point3::from(bearing.into_inner() * 3.0))
+                .unwrap();
+            assert!((reprojected - px).norm() < 1e-6);
+        }
+        assert!(camera.project(&Point3::new(0.0, 0.0, -1.0)).is_none());
+    }
+
+    #[test]
+    fn analytic_jacobian_matches_numerical() {
+        #[derive(Debug)]
+        struct Numerical(PinholeRadTan);
+        impl CameraModel for Numerical {
+            fn project(&self, p: &Point3<f64>) -> Option<Point2<f64>> {
+                self.0.project(p)
+            }
+            fn unproject(&self, px: &Point2<f64>) -> Option<Unit<Vector3<f64>>> {
+                self.0.unproject(px)
+            }
+            fn resolution(&self) -> (u32, u32) {
+                self.0.resolution()
+            }
+        }
+
+        let camera = euroc_cam0();
+        let p = Point3::new(0.4, -0.3, 2.0);
+        let analytic = camera.project_jacobian(&p).unwrap();
+        let numerical = Numerical(camera).project_jacobian(&p).unwrap();
+        assert!((analytic - numerical).norm() < 1e-4);
+    }
+
+    #[test]
+    fn equidistant_sees_behind_the_image_plane() {
+        let camera = PinholeEquidistant::new(
+            [190.98, 190.98, 254.93, 256.89],
+            [0.0034823, 0.0007150, -0.0020532, 0.0002029],
+            (512, 512),
+        );
+        for &(u, v) in &[(254.9, 256.9), (10.0, 20.0), (500.0, 256.0), (300.0, 480.0)] {
+            let px = Point2::new(u, v);
+            let bearing = camera.unproject(&px).expect("Valid pixel");
+            let reprojected = camera
+                .project(&Point3::from(bearing.into_inner() * 2.0))
+                .unwrap();
+            assert!((reprojected - px).norm() < 1e-6);
+        }
+
+        // 100 degrees away from the optical axis
+        let behind = Point3::new(100_f64.to_radians().sin(), 0.0, 100_f64.to_radians().cos());
+        let px = camera.project(&behind).expect("Inside the field of view");
+        assert!(px.x > camera.cx);
+        let bearing = camera.unproject(&px).unwrap();
+        assert!((bearing.into_inner() - behind.coords).norm() < 1e-9);
+    }
+}
@@ -0,0 +1,69 @@
+/// Helpers for the rotation group SO3
+///
+/// Rotations are perturbed on the right, `R * Exp(phi)`, with `Exp` and `Log` the maps between the
+/// rotation vectors and the rotations.
+use nalgebra::{Matrix3, UnitQuaternion, Vector3};
+
+pub use super::linalg::skew;
+
+/// Below this angle, the closed forms are replaced by their Taylor expansions
+const SMALL_ANGLE: f64 = 1e-5;
+
+pub fn exp(phi: &Vector3<f64>) -> UnitQuaternion<f64> {
+    UnitQuaternion::from_scaled_axis(*phi)
+}
+
+pub fn log(rotation: &UnitQuaternion<f64>) -> Vector3<f64> {
+    rotation.scaled_axis()
+}
+
+/// Right Jacobian of SO3, such that `Exp(phi + dphi) ~ Exp(phi) * Exp(Jr(phi) * dphi)`
+// theta2 * theta is the cube of the angle
+#[allow(clippy::suspicious_operation_groupings)]
+pub fn right_jacobian(phi: &Vector3<f64>) -> Matrix3<f64> {
+    let theta = phi.norm();
+    let k = skew(phi);
+    if theta < SMALL_ANGLE {
+        return Matrix3::identity() - k * 0.5 + k * k / 6.0;
+    }
+    let theta2 = theta * theta;
+    Matrix3::identity() - k * ((1.0 - theta.cos()) / theta2)
+        + k * k * ((theta - theta.sin()) / (theta2 * theta))
+}
+
+/// Inverse of the right Jacobian, such that `Log(Exp(phi) * Exp(dphi)) ~ phi + Jr^-1(phi) * dphi`
+pub fn right_jacobian_inverse(phi: &Vector3<f64>) -> Matrix3<f64> {
+    let theta = phi.norm();
+    let k = skew(phi);
+    if theta < SMALL_ANGLE {
+        return Matrix3::identity() + k * 0.5 + k * k / 12.0;
+    }
+    Matrix3::identity()
+        + k * 0.5
+        + k * k * (1.0 / (theta * theta) - (1.0 + theta.cos()) / (2.0 * theta * theta.sin()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn right_jacobians() {
+        for phi in &[
+            Vector3::new(0.3, -0.2, 0.5),
+            Vector3::new(1e-7, 2e-7, 0.0),
+            Vector3::new(2.0, 1.0, -1.5),
+        ] {
+            let dphi = Vector3::new(1e-6, -2e-6, 1.5e-6);
+            let perturbed = exp(phi).inverse() * exp(&(phi + dphi));
+            assert!((log(&perturbed) - right_jacobian(phi) * dphi).norm() < 1e-11);
+
+            let composed = log(&(exp(phi) * exp(&dphi)));
+            assert!((composed - phi - right_jacobian_inverse(phi) * dphi).norm() < 1e-11);
+            assert!(
+                (right_jacobian(phi) * right_jacobian_inverse(phi) - Matrix3::identity()).norm()
+                    < 1e-9
+            );
+        }
+    }
+}
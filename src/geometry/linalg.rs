@@ -0,0 +1,213 @@
+/// Small linear algebra helpers shared by the geometric solvers
+use std::cmp::Ordering;
+
+use nalgebra::{
+    DMatrix, DVector, Isometry3, Matrix2x3, Matrix3, Point3, Schur, Translation3, UnitQuaternion,
+    Vector3, SVD,
+};
+
+/// Skew-symmetric matrix `[v]x`, such that `[v]x * w = v x w`
+pub fn skew(v: &Vector3<f64>) -> Matrix3<f64> {
+    Matrix3::new(0.0, -v.z, v.y, v.z, 0.0, -v.x, -v.y, v.x, 0.0)
+}
+
+/// SVD of a 3x3 matrix with the singular values sorted in descending order. Returns `U`, the
+/// singular values and `V` (not transposed)
+pub fn svd3(m: &Matrix3<f64>) -> (Matrix3<f64>, Vector3<f64>, Matrix3<f64>) {
+    let svd = SVD::new(*m, true, true);
+    let (u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+    let mut order = [0, 1, 2];
+    // NaN singular values of a degenerate matrix keep their place
+    order.sort_by(|&a, &b| {
+        svd.singular_values[b]
+            .partial_cmp(&svd.singular_values[a])
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let mut sorted = (Matrix3::zeros(), Vector3::zeros(), Matrix3::zeros());
+    for (dst, &src) in order.iter().enumerate() {
+        sorted.0.set_column(dst, &u.column(src));
+        sorted.1[dst] = svd.singular_values[src];
+        sorted.2.set_column(dst, &v_t.row(src).transpose());
+    }
+    sorted
+}
+
+/// Orthonormal basis of the (approximate) null space of `a` - the right singular vectors of its
+/// `dim` smallest singular values, smallest first
+pub fn null_space(a: &DMatrix<f64>, dim: usize) -> Vec<DVector<f64>> {
+    let cols = a.ncols();
+    // the thin SVD only has min(rows, cols) right singular vectors
+    let padded = if a.nrows() < cols {
+        let mut padded = DMatrix::zeros(cols, cols);
+        padded.rows_mut(0, a.nrows()).copy_from(a);
+        padded
+    } else {
+        a.clone()
+    };
+
+    let svd = SVD::new(padded, false, true);
+    let singular_values = svd.singular_values;
+    let v_t = svd.v_t.unwrap();
+    let mut order: Vec<usize> = (0..singular_values.len()).collect();
+    order.sort_by(|&a, &b| {
+        singular_values[a]
+            .partial_cmp(&singular_values[b])
+            .unwrap_or(Ordering::Equal)
+    });
+    order
+        .into_iter()
+        .take(dim)
+        .map(|idx| v_t.row(idx).transpose())
+        .collect()
+}
+
+/// Orthonormal basis of the plane perpendicular to `v`, as the rows of the matrix
+pub fn tangent_basis(v: &Vector3<f64>) -> Matrix2x3<f64> {
+    let v = v.normalize();
+    // cross with the axis least aligned with v
+    let axis = if v.x.abs() < 0.6 {
+        Vector3::x()
+    } else if v.y.abs() < 0.6 {
+        Vector3::y()
+    } else {
+        Vector3::z()
+    };
+    let b1 = v.cross(&axis).normalize();
+    let b2 = v.cross(&b1);
+    Matrix2x3::from_rows(&[b1.transpose(), b2.transpose()])
+}
+
+/// Rigid transformation `T` that best aligns the points, `dst ~ T * src`, in the least-squares
+/// sense - "Least-squares estimation of transformation parameters between two point patterns",
+/// Umeyama, 1991, without the scale
+pub fn align_points(src: &[Point3<f64>], dst: &[Point3<f64>]) -> Option<Isometry3<f64>> {
+    if src.len() < 3 || src.len() != dst.len() {
+        return None;
+    }
+    let n = src.len() as f64;
+    let src_mean = src.iter().fold(Vector3::zeros(), |acc, p| acc + p.coords) / n;
+    let dst_mean = dst.iter().fold(Vector3::zeros(), |acc, p| acc + p.coords) / n;
+    let covariance = src.iter().zip(dst).fold(Matrix3::zeros(), |acc, (s, d)| {
+        acc + (d.coords - dst_mean) * (s.coords - src_mean).transpose()
+    });
+
+    let (u, _, v) = svd3(&covariance);
+    let mut correction = Matrix3::identity();
+    if (u * v.transpose()).determinant() < 0.0 {
+        correction[(2, 2)] = -1.0;
+    }
+    let rotation = u * correction * v.transpose();
+    let rotation = UnitQuaternion::from_matrix(&rotation);
+
+    Some(Isometry3::from_parts(
+        Translation3::from(dst_mean - rotation * src_mean),
+        rotation,
+    ))
+}
+
+/// Evaluate the polynomial with the given coefficients, in ascending order of degree
+pub fn polyval(coeffs: &[f64], x: f64) -> f64 {
+    coeffs.iter().rev().fold(0.0, |acc, c| acc * x + c)
+}
+
+/// Real roots of the polynomial with the given coefficients, in ascending order of degree.
+///
+/// The roots are the eigenvalues of the companion matrix, polished with a few Newton iterations.
+/// A polynomial with non-finite coefficients has none.
+pub fn real_roots(coeffs: &[f64]) -> Vec<f64> {
+    if !coeffs.iter().all(|c| c.is_finite()) {
+        return Vec::new();
+    }
+    let scale = coeffs.iter().fold(0.0_f64, |acc, c| acc.max(c.abs()));
+    let degree = match coeffs.iter().rposition(|c| c.abs() > 1e-12 * scale) {
+        Some(degree) if degree > 0 => degree,
+        _ => return Vec::new(),
+    };
+
+    let lead = coeffs[degree];
+    let mut companion = DMatrix::zeros(degree, degree);
+    for i in 0..degree {
+        if i + 1 < degree {
+            companion[(i + 1, i)] = 1.0;
+        }
+        companion[(i, degree - 1)] = -coeffs[i] / lead;
+    }
+
+    let derivative: Vec<f64> = coeffs[..=degree]
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(power, c)| power as f64 * c)
+        .collect();
+    Schur::new(companion)
+        .complex_eigenvalues()
+        .iter()
+        .filter(|root| root.im.abs() < 1e-6 * (1.0 + root.re.abs()))
+        .map(|root| {
+            let mut x = root.re;
+            for _ in 0..3 {
+                let slope = polyval(&derivative, x);
+                if slope.abs() < f64::EPSILON {
+                    break;
+                }
+                x -= polyval(&coeffs[..=degree], x) / slope;
+            }
+            x
+        })
+        .filter(|x| x.is_finite())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polynomial_roots() {
+        // (x - 1)(x + 2)(x - 3.5)(x^2 + 1)
+        let coeffs = [7.0, -5.5, 4.5, -4.5, -2.5, 1.0];
+        let mut roots = real_roots(&coeffs);
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(roots.len(), 3);
+        for (root, expected) in roots.iter().zip(&[-2.0, 1.0, 3.5]) {
+            assert!((root - expected).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn non_finite_inputs() {
+        assert!(real_roots(&[1.0, f64::NAN, 2.0]).is_empty());
+        assert!(real_roots(&[1.0, f64::INFINITY, 2.0]).is_empty());
+    }
+
+    #[test]
+    fn sorted_decompositions() {
+        let m = Matrix3::new(0.1, 2.0, 0.3, -1.0, 0.5, 0.2, 0.7, 0.1, 3.0);
+        let (u, s, v) = svd3(&m);
+        assert!(s[0] >= s[1] && s[1] >= s[2]);
+        assert!((u * Matrix3::from_diagonal(&s) * v.transpose() - m).norm() < 1e-12);
+
+        let a = DMatrix::from_row_slice(2, 3, &[1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+        let null = null_space(&a, 1);
+        assert!((&a * &null[0]).norm() < 1e-12);
+    }
+
+    #[test]
+    fn rigid_alignment() {
+        let expected = Isometry3::new(Vector3::new(1.0, -2.0, 0.5), Vector3::new(0.3, 0.2, -0.1));
+        let src = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+            Point3::new(0.3, 0.1, 1.0),
+        ];
+        let dst: Vec<_> = src.iter().map(|p| expected * p).collect();
+        let aligned = align_points(&src, &dst).unwrap();
+        assert!((aligned.to_homogeneous() - expected.to_homogeneous()).norm() < 1e-9);
+
+        let basis = tangent_basis(&Vector3::new(0.2, -0.9, 0.3));
+        assert!((basis * Vector3::new(0.2, -0.9, 0.3)).norm() < 1e-12);
+        assert!((basis * basis.transpose() - nalgebra::Matrix2::identity()).norm() < 1e-12);
+    }
+}
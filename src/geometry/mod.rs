@@ -0,0 +1,21 @@
+pub mod camera;
+pub mod lie;
+pub(crate) mod linalg;
+pub mod pnp;
+pub mod ransac;
+pub mod triangulation;
+pub mod two_view;
+
+pub use self::camera::{CameraModel, PinholeEquidistant, PinholeRadTan};
+pub use self::pnp::{
+    bearing_residual, epnp, p3p, refine_pose, solve_pnp, P3pEstimator, PnpResult, PointBearing,
+};
+pub use self::ransac::{Estimator, Ransac, RansacParams, RansacResult, Sampling, Scoring};
+pub use self::triangulation::{
+    parallax_angle, point_covariance, refine_point, triangulate_dlt, triangulate_midpoint,
+    PoseBearing, TriangulatedPoint, TriangulationMethod, TriangulationParams, Triangulator,
+};
+pub use self::two_view::{
+    decompose_essential, essential_five_point, essential_from_pose, estimate_relative_pose,
+    fundamental_eight_point, recover_pose, EssentialEstimator, FundamentalEstimator, RelativePose,
+};
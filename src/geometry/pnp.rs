@@ -0,0 +1,626 @@
+/// Camera pose from 2D-3D correspondences (Perspective-n-Point)
+///
+/// Observations are bearing vectors, so the solvers work with any [`CameraModel`], including
+/// fisheye cameras that see points behind their image plane. Poses are `T_cw`, transforming points
+/// from the world to the camera frame. Minimal P3P solutions are hypotheses of a RANSAC loop, and
+/// the inliers of the best one are used by EPnP and a Gauss-Newton refinement on SE3.
+///
+/// [`CameraModel`]: crate::geometry::camera::CameraModel
+use crate::geometry::linalg::{align_points, real_roots, skew, tangent_basis};
+use crate::geometry::ransac::{Estimator, Ransac};
+
+use nalgebra::{
+    DMatrix, DVector, Isometry3, Matrix3, Matrix3x2, Matrix3x6, Matrix6, Point3, SymmetricEigen,
+    Translation3, Unit, UnitQuaternion, Vector2, Vector3, Vector4, Vector6, U3,
+};
+
+/// A world point and the bearing vector it's observed along
+pub type PointBearing = (Point3<f64>, Unit<Vector3<f64>>);
+
+/// Maximum number of Gauss-Newton iterations of the pose refinement
+const MAX_REFINE_ITERATIONS: usize = 10;
+
+// -------------------------------------------------------------------------------------------------
+// Residuals
+// -------------------------------------------------------------------------------------------------
+
+/// Difference between the observed bearing and the direction of the transformed point
+///
+/// Expressed on the tangent plane of the observation, so it's approximately the angle between the
+/// two, in radians. `None` for points behind the observed direction.
+pub fn bearing_residual(
+    pose: &Isometry3<f64>,
+    (point, bearing): &PointBearing,
+) -> Option<Vector2<f64>> {
+    let predicted = (pose * point).coords;
+    if predicted.dot(bearing) <= 0.0 {
+        return None;
+    }
+    Some(tangent_basis(bearing) * predicted.normalize())
+}
+
+fn squared_error(pose: &Isometry3<f64>, corrs: &[PointBearing]) -> f64 {
+    corrs
+        .iter()
+        .map(|corr| bearing_residual(pose, corr).map_or(f64::INFINITY, |r| r.norm_squared()))
+        .sum()
+}
+
+// -------------------------------------------------------------------------------------------------
+// P3P
+// -------------------------------------------------------------------------------------------------
+
+/// Coefficients of the cubic `det(a + gamma * b)`, in ascending order of degree
+fn pencil_determinant(a: &Matrix3<f64>, b: &Matrix3<f64>) -> [f64; 4] {
+    let mut coeffs = [0.0; 4];
+    for mask in 0..8_usize {
+        let mixed = Matrix3::from_fn(|r, c| {
+            if mask & (1 << c) == 0 {
+                a[(r, c)]
+            } else {
+                b[(r, c)]
+            }
+        });
+        coeffs[mask.count_ones() as usize] += mixed.determinant();
+    }
+    coeffs
+}
+
+/// Poses consistent with three correspondences - up to four solutions.
+///
+/// "Lambda Twist: An Accurate Fast Robust Perspective Three Point (P3P) Solver", Persson and
+/// Nordberg, 2018. Only the first three correspondences are used.
+#[allow(clippy::suboptimal_flops)]
+pub fn p3p(corrs: &[PointBearing]) -> Vec<Isometry3<f64>> {
+    if corrs.len() < 3 {
+        return Vec::new();
+    }
+    let x = [corrs[0].0, corrs[1].0, corrs[2].0];
+    let y = [corrs[0].1, corrs[1].1, corrs[2].1];
+    let spread = (x[1] - x[0]).cross(&(x[2] - x[0])).norm();
+    if spread < 1e-12 {
+        return Vec::new();
+    }
+
+    // |l_i y_i - l_j y_j|^2 = a_ij as quadratic forms in the depths l
+    let (a12, a13, a23) = (
+        (x[0] - x[1]).norm_squared(),
+        (x[0] - x[2]).norm_squared(),
+        (x[1] - x[2]).norm_squared(),
+    );
+    let (b12, b13, b23) = (y[0].dot(&y[1]), y[0].dot(&y[2]), y[1].dot(&y[2]));
+    let m12 = Matrix3::new(1.0, -b12, 0.0, -b12, 1.0, 0.0, 0.0, 0.0, 0.0);
+    let m13 = Matrix3::new(1.0, 0.0, -b13, 0.0, 0.0, 0.0, -b13, 0.0, 1.0);
+    let m23 = Matrix3::new(0.0, 0.0, 0.0, 0.0, 1.0, -b23, 0.0, -b23, 1.0);
+    let d1 = m12 * a23 - m23 * a12;
+    let d2 = m13 * a23 - m23 * a13;
+
+    let mut poses = Vec::new();
+    for gamma in real_roots(&pencil_determinant(&d1, &d2)) {
+        // the degenerate conic d0 is a pair of planes through the origin
+        let eigen = SymmetricEigen::new(d1 + d2 * gamma);
+        let mut order = [0, 1, 2];
+        order.sort_by(|&a, &b| {
+            eigen.eigenvalues[b]
+                .abs()
+                .partial_cmp(&eigen.eigenvalues[a].abs())
+                .unwrap()
+        });
+        let (s1, s2) = (eigen.eigenvalues[order[0]], eigen.eigenvalues[order[1]]);
+        if s1 * s2 >= 0.0 {
+            continue;
+        }
+        let (e1, e2) = (
+            eigen.eigenvectors.column(order[0]).into_owned(),
+            eigen.eigenvectors.column(order[1]).into_owned(),
+        );
+        let ratio = (-s2 / s1).sqrt();
+
+        for &sign in &[1.0, -1.0] {
+            // l1 = w0 l2 + w1 l3 on the plane
+            let normal = e1 - e2 * (sign * ratio);
+            if normal.x.abs() < 1e-12 * normal.norm() {
+                continue;
+            }
+            let (w0, w1) = (-normal.y / normal.x, -normal.z / normal.x);
+            let k = Matrix3x2::new(w0, w1, 1.0, 0.0, 0.0, 1.0);
+            let q = k.transpose() * d1 * k;
+
+            // tau = l2 / l3
+            for tau in real_roots(&[q[(1, 1)], 2.0 * q[(0, 1)], q[(0, 0)]]) {
+                let norm = tau * tau - 2.0 * b23 * tau + 1.0;
+                if tau <= 0.0 || norm <= 0.0 {
+                    continue;
+                }
+                let l3 = (a23 / norm).sqrt();
+                let depths = Vector3::new(w0 * tau * l3 + w1 * l3, tau * l3, l3);
+                if depths.x <= 0.0 {
+                    continue;
+                }
+                let depths = refine_depths(depths, [a12, a13, a23], [b12, b13, b23]);
+                if let Some(pose) = pose_from_depths(&x, &y, &depths) {
+                    poses.push(pose);
+                }
+            }
+        }
+        if !poses.is_empty() {
+            break;
+        }
+    }
+    poses
+}
+
+/// Gauss-Newton iterations on the three distance constraints
+#[allow(clippy::suboptimal_flops)]
+fn refine_depths(mut l: Vector3<f64>, a: [f64; 3], b: [f64; 3]) -> Vector3<f64> {
+    let residual = |l: &Vector3<f64>| {
+        Vector3::new(
+            l.x * l.x + l.y * l.y - 2.0 * b[0] * l.x * l.y - a[0],
+            l.x * l.x + l.z * l.z - 2.0 * b[1] * l.x * l.z - a[1],
+            l.y * l.y + l.z * l.z - 2.0 * b[2] * l.y * l.z - a[2],
+        )
+    };
+    for _ in 0..5 {
+        let r = residual(&l);
+        let jacobian = Matrix3::new(
+            2.0 * (l.x - b[0] * l.y),
+            2.0 * (l.y - b[0] * l.x),
+            0.0,
+            2.0 * (l.x - b[1] * l.z),
+            0.0,
+            2.0 * (l.z - b[1] * l.x),
+            0.0,
+            2.0 * (l.y - b[2] * l.z),
+            2.0 * (l.z - b[2] * l.y),
+        );
+        let step = match jacobian.try_inverse() {
+            Some(inverse) => inverse * r,
+            None => break,
+        };
+        if residual(&(l - step)).norm() >= r.norm() {
+            break;
+        }
+        l -= step;
+    }
+    l
+}
+
+/// Pose that maps the world points onto the bearings scaled by their depths
+fn pose_from_depths(
+    x: &[Point3<f64>; 3],
+    y: &[Unit<Vector3<f64>>; 3],
+    depths: &Vector3<f64>,
+) -> Option<Isometry3<f64>> {
+    let c: Vec<Vector3<f64>> = (0..3).map(|i| y[i].into_inner() * depths[i]).collect();
+    let (dx1, dx2) = (x[0] - x[1], x[0] - x[2]);
+    let (dc1, dc2) = (c[0] - c[1], c[0] - c[2]);
+    let world = Matrix3::from_columns(&[dx1, dx2, dx1.cross(&dx2)]);
+    let camera = Matrix3::from_columns(&[dc1, dc2, dc1.cross(&dc2)]);
+
+    let rotation = UnitQuaternion::from_matrix(&(camera * world.try_inverse()?));
+    Some(Isometry3::from_parts(
+        Translation3::from(c[0] - rotation * x[0].coords),
+        rotation,
+    ))
+}
+
+/// [`Estimator`] of the camera pose with P3P. Residuals are squared angles, and the refinement
+/// runs EPnP and Gauss-Newton on the inliers
+#[derive(Debug, Clone, Copy, Default)]
+pub struct P3pEstimator;
+
+impl Estimator for P3pEstimator {
+    type Datum = PointBearing;
+    type Model = Isometry3<f64>;
+
+    const MIN_SAMPLES: usize = 3;
+
+    fn estimate(&self, data: &[PointBearing], sample: &[usize]) -> Vec<Isometry3<f64>> {
+        let corrs: Vec<_> = sample.iter().map(|&idx| data[idx]).collect();
+        p3p(&corrs)
+    }
+
+    fn residual(&self, pose: &Isometry3<f64>, corr: &PointBearing) -> f64 {
+        bearing_residual(pose, corr).map_or(f64::INFINITY, |r| r.norm_squared())
+    }
+
+    fn refine(
+        &self,
+        data: &[PointBearing],
+        inliers: &[usize],
+        pose: &Isometry3<f64>,
+    ) -> Option<Isometry3<f64>> {
+        let corrs: Vec<_> = inliers.iter().map(|&idx| data[idx]).collect();
+        let initial = match epnp(&corrs) {
+            Some(linear) if squared_error(&linear, &corrs) < squared_error(pose, &corrs) => linear,
+            _ => *pose,
+        };
+        Some(refine_pose(&initial, &corrs))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// EPnP
+// -------------------------------------------------------------------------------------------------
+
+/// Pairs of control points, in the order of the rows of the distance constraints
+const CONTROL_PAIRS: [(usize, usize); 6] = [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)];
+
+/// Non-iterative pose from four or more correspondences - "EPnP: An Accurate O(n) Solution to the
+/// PnP Problem", Lepetit et al., 2009.
+///
+/// The collinearity of each point with its bearing is expressed on the tangent plane of the
+/// bearing rather than on the image plane, so that it holds for any field of view.
+pub fn epnp(corrs: &[PointBearing]) -> Option<Isometry3<f64>> {
+    let n = corrs.len();
+    if n < 4 {
+        return None;
+    }
+
+    // control points - the centroid, and the principal directions of the world points
+    let centroid = corrs
+        .iter()
+        .fold(Vector3::zeros(), |acc, (p, _)| acc + p.coords)
+        / n as f64;
+    let covariance = corrs.iter().fold(Matrix3::zeros(), |acc, (p, _)| {
+        acc + (p.coords - centroid) * (p.coords - centroid).transpose()
+    }) / n as f64;
+    let principal = SymmetricEigen::new(covariance);
+    let max_variance = principal.eigenvalues.max();
+    if max_variance < f64::EPSILON {
+        return None;
+    }
+    let mut world_ctrl = [centroid; 4];
+    for k in 0..3 {
+        // keep planar configurations invertible
+        let spread = principal.eigenvalues[k].max(1e-8 * max_variance).sqrt();
+        world_ctrl[k + 1] += principal.eigenvectors.column(k) * spread;
+    }
+
+    // barycentric coordinates of the points with respect to the control points
+    let basis = Matrix3::from_columns(&[
+        world_ctrl[1] - centroid,
+        world_ctrl[2] - centroid,
+        world_ctrl[3] - centroid,
+    ])
+    .try_inverse()?;
+    let alphas: Vec<[f64; 4]> = corrs
+        .iter()
+        .map(|(p, _)| {
+            let a = basis * (p.coords - centroid);
+            [1.0 - a.sum(), a.x, a.y, a.z]
+        })
+        .collect();
+
+    let mut m = DMatrix::zeros(2 * n, 12);
+    for (i, ((_, bearing), alpha)) in corrs.iter().zip(&alphas).enumerate() {
+        let tangent = tangent_basis(bearing);
+        for r in 0..2 {
+            for (j, a) in alpha.iter().enumerate() {
+                for c in 0..3 {
+                    m[(2 * i + r, 3 * j + c)] = a * tangent[(r, c)];
+                }
+            }
+        }
+    }
+    let kernel = SymmetricEigen::new(m.transpose() * m);
+    let mut order: Vec<usize> = (0..12).collect();
+    order.sort_by(|&a, &b| {
+        kernel.eigenvalues[a]
+            .partial_cmp(&kernel.eigenvalues[b])
+            .unwrap()
+    });
+    let v: Vec<[Vector3<f64>; 4]> = order[..4]
+        .iter()
+        .map(|&idx| {
+            let col = kernel.eigenvectors.column(idx);
+            control_points(|j| Vector3::new(col[3 * j], col[3 * j + 1], col[3 * j + 2]))
+        })
+        .collect();
+
+    // distance constraints between the control points - l * [b11, b12, b22, b13, b23, b33, b14,
+    // b24, b34, b44] = rho, with bij = beta_i * beta_j
+    let mut l = DMatrix::zeros(6, 10);
+    let mut rho = DVector::zeros(6);
+    for (row, &(i, j)) in CONTROL_PAIRS.iter().enumerate() {
+        let dv: Vec<Vector3<f64>> = v.iter().map(|vk| vk[i] - vk[j]).collect();
+        let mut col = 0;
+        for b in 0..4 {
+            for a in 0..=b {
+                let factor = if a == b { 1.0 } else { 2.0 };
+                l[(row, col)] = factor * dv[a].dot(&dv[b]);
+                col += 1;
+            }
+        }
+        rho[row] = (world_ctrl[i] - world_ctrl[j]).norm_squared();
+    }
+
+    let approximations = [
+        betas_approx(&l, &rho, &[0, 1, 3, 6]),
+        betas_approx(&l, &rho, &[0, 1, 2]),
+        betas_approx(&l, &rho, &[0, 1, 2, 3, 4]),
+    ];
+    approximations
+        .iter()
+        .filter_map(|betas| {
+            let betas = refine_betas(&l, &rho, (*betas)?);
+            pose_from_betas(corrs, &alphas, &v, &betas)
+        })
+        .min_by(|a, b| {
+            squared_error(a, corrs)
+                .partial_cmp(&squared_error(b, corrs))
+                .unwrap()
+        })
+}
+
+fn control_points<F: Fn(usize) -> Vector3<f64>>(f: F) -> [Vector3<f64>; 4] {
+    [f(0), f(1), f(2), f(3)]
+}
+
+/// Initial betas from the least-squares solution of the distance constraints restricted to a
+/// subset of the products `bij` - the N = 1, 2 and 3 approximations of EPnP
+fn betas_approx(l: &DMatrix<f64>, rho: &DVector<f64>, columns: &[usize]) -> Option<[f64; 4]> {
+    let sub = l.select_columns(columns);
+    let products = (sub.transpose() * &sub)
+        .cholesky()?
+        .solve(&(sub.transpose() * rho));
+
+    let mut betas = [0.0; 4];
+    let b11 = products[0];
+    betas[0] = b11.abs().sqrt();
+    match columns.len() {
+        // b11, b12, b13, b14
+        4 => {
+            for k in 1..4 {
+                betas[k] = products[k] / betas[0].max(f64::EPSILON);
+            }
+            if b11 < 0.0 {
+                betas.iter_mut().skip(1).for_each(|b| *b = -*b);
+            }
+        }
+        // b11, b12, b22 [, b13, b23]
+        _ => {
+            let b22 = products[2];
+            betas[1] = if b22 * b11 > 0.0 {
+                b22.abs().sqrt()
+            } else {
+                0.0
+            };
+            if products[1] * b11.signum() < 0.0 {
+                betas[0] = -betas[0];
+            }
+            if columns.len() == 5 {
+                betas[2] = products[3] / betas[0];
+            }
+        }
+    }
+    if betas.iter().all(|b| b.is_finite()) {
+        Some(betas)
+    } else {
+        None
+    }
+}
+
+/// Gauss-Newton on the distance constraints, over all four betas
+fn refine_betas(l: &DMatrix<f64>, rho: &DVector<f64>, betas: [f64; 4]) -> [f64; 4] {
+    let mut b = Vector4::from(betas);
+    for _ in 0..5 {
+        let mut products = DVector::zeros(10);
+        let mut jacobian = DMatrix::zeros(10, 4);
+        let mut col = 0;
+        for j in 0..4 {
+            for i in 0..=j {
+                products[col] = b[i] * b[j];
+                jacobian[(col, i)] += b[j];
+                jacobian[(col, j)] += b[i];
+                col += 1;
+            }
+        }
+        let residual = l * products - rho;
+        let jacobian = l * jacobian;
+        let step = match (jacobian.transpose() * &jacobian).cholesky() {
+            Some(cholesky) => cholesky.solve(&(jacobian.transpose() * residual)),
+            None => break,
+        };
+        b -= Vector4::new(step[0], step[1], step[2], step[3]);
+    }
+    [b[0], b[1], b[2], b[3]]
+}
+
+fn pose_from_betas(
+    corrs: &[PointBearing],
+    alphas: &[[f64; 4]],
+    v: &[[Vector3<f64>; 4]],
+    betas: &[f64; 4],
+) -> Option<Isometry3<f64>> {
+    let camera_ctrl = control_points(|j| (0..4).map(|k| v[k][j] * betas[k]).sum());
+    let mut camera_pts: Vec<Point3<f64>> = alphas
+        .iter()
+        .map(|a| Point3::from((0..4).map(|j| camera_ctrl[j] * a[j]).sum::<Vector3<f64>>()))
+        .collect();
+    // the null space is only defined up to sign
+    let facing: f64 = camera_pts
+        .iter()
+        .zip(corrs)
+        .map(|(p, (_, bearing))| p.coords.dot(bearing))
+        .sum();
+    if facing < 0.0 {
+        camera_pts.iter_mut().for_each(|p| p.coords = -p.coords);
+    }
+
+    let world_pts: Vec<Point3<f64>> = corrs.iter().map(|(p, _)| *p).collect();
+    align_points(&world_pts, &camera_pts)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Refinement / RANSAC
+// -------------------------------------------------------------------------------------------------
+
+/// Gauss-Newton minimisation of the bearing residuals of the correspondences, with the pose
+/// perturbed on SE3 as `T <- Exp([dphi; dt]) * T`
+pub fn refine_pose(pose: &Isometry3<f64>, corrs: &[PointBearing]) -> Isometry3<f64> {
+    let mut pose = *pose;
+    for _ in 0..MAX_REFINE_ITERATIONS {
+        let mut hessian = Matrix6::zeros();
+        let mut gradient = Vector6::zeros();
+        for corr in corrs {
+            let residual = match bearing_residual(&pose, corr) {
+                Some(residual) => residual,
+                None => continue,
+            };
+            let p = (pose * corr.0).coords;
+            let direction = p.normalize();
+            let d_direction = (Matrix3::identity() - direction * direction.transpose()) / p.norm();
+            let mut d_point = Matrix3x6::zeros();
+            d_point.fixed_columns_mut::<U3>(0).copy_from(&(-skew(&p)));
+            d_point
+                .fixed_columns_mut::<U3>(3)
+                .copy_from(&Matrix3::identity());
+
+            let jacobian = tangent_basis(&corr.1) * d_direction * d_point;
+            hessian += jacobian.transpose() * jacobian;
+            gradient += jacobian.transpose() * residual;
+        }
+
+        let step = match hessian.cholesky() {
+            Some(cholesky) => -cholesky.solve(&gradient),
+            None => break,
+        };
+        pose = Isometry3::new(
+            Vector3::new(step[3], step[4], step[5]),
+            Vector3::new(step[0], step[1], step[2]),
+        ) * pose;
+        if step.norm() < 1e-10 {
+            break;
+        }
+    }
+    pose
+}
+
+#[derive(Debug, Clone)]
+pub struct PnpResult {
+    /// Transforms points from the world to the camera frame
+    pub pose: Isometry3<f64>,
+    /// Indices of the correspondences consistent with the pose
+    pub inliers: Vec<usize>,
+}
+
+/// Robust camera pose - P3P in RANSAC, followed by EPnP and Gauss-Newton on the inliers
+pub fn solve_pnp(corrs: &[PointBearing], ransac: &Ransac) -> Option<PnpResult> {
+    let result = ransac.run(&P3pEstimator, corrs)?;
+    Some(PnpResult {
+        pose: result.model,
+        inliers: result.inliers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::camera::{CameraModel, PinholeEquidistant};
+    use crate::geometry::ransac::RansacParams;
+    use nalgebra::Point2;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn pose_error(a: &Isometry3<f64>, b: &Isometry3<f64>) -> (f64, f64) {
+        let delta = a.inverse() * b;
+        (delta.rotation.angle(), delta.translation.vector.norm())
+    }
+
+    /// Random world points seen by a camera at `pose`, within `max_angle` of its optical axis
+    fn scene(
+        rng: &mut StdRng,
+        pose: &Isometry3<f64>,
+        n: usize,
+        max_angle: f64,
+    ) -> Vec<PointBearing> {
+        (0..n)
+            .map(|_| {
+                let theta: f64 = rng.gen_range(0.0, max_angle);
+                let phi: f64 = rng.gen_range(-std::f64::consts::PI, std::f64::consts::PI);
+                let dir = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                let camera_pt = Point3::from(dir * rng.gen_range(1.0, 8.0));
+                (pose.inverse() * camera_pt, Unit::new_normalize(dir))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn minimal_and_linear_solvers() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..20 {
+            let pose = Isometry3::new(
+                Vector3::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), 0.5),
+                Vector3::new(rng.gen_range(-0.5, 0.5), rng.gen_range(-0.5, 0.5), 0.3),
+            );
+            let corrs = scene(&mut rng, &pose, 30, 1.2);
+
+            let solutions = p3p(&corrs);
+            assert!(!solutions.is_empty() && solutions.len() <= 4);
+            assert!(solutions.iter().any(|solution| {
+                let (rotation, translation) = pose_error(solution, &pose);
+                rotation < 1e-7 && translation < 1e-7
+            }));
+
+            let linear = epnp(&corrs).expect("An EPnP pose");
+            let (rotation, translation) = pose_error(&linear, &pose);
+            assert!(rotation < 1e-6 && translation < 1e-6);
+        }
+    }
+
+    #[test]
+    fn gauss_newton_refinement() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let pose = Isometry3::new(Vector3::new(0.2, -0.1, 1.0), Vector3::new(0.1, -0.3, 0.05));
+        let corrs = scene(&mut rng, &pose, 50, 0.8);
+        let perturbed = Isometry3::new(
+            Vector3::new(0.05, 0.1, -0.1),
+            Vector3::new(0.03, 0.02, -0.04),
+        ) * pose;
+
+        let refined = refine_pose(&perturbed, &corrs);
+        let (rotation, translation) = pose_error(&refined, &pose);
+        assert!(rotation < 1e-9 && translation < 1e-9);
+        assert!(squared_error(&refined, &corrs) < 1e-18);
+    }
+
+    #[test]
+    fn robust_pose_with_fisheye() {
+        // up to 100 degrees off-axis, behind the image plane of the camera
+        let camera = PinholeEquidistant::new(
+            [380.0, 380.0, 376.0, 240.0],
+            [-0.01, 0.005, -0.002, 0.0005],
+            (752, 480),
+        );
+        let mut rng = StdRng::seed_from_u64(11);
+        let pose = Isometry3::new(Vector3::new(-0.4, 0.3, 2.0), Vector3::new(0.2, 0.1, -0.6));
+        let mut corrs = scene(&mut rng, &pose, 300, 100_f64.to_radians());
+        corrs.retain(|(p, _)| camera.project(&(pose * p)).is_some());
+        for (idx, corr) in corrs.iter_mut().enumerate() {
+            let pixel = camera.project(&(pose * corr.0)).unwrap();
+            let noisy = if idx % 4 == 0 {
+                Point2::new(rng.gen_range(0.0, 752.0), rng.gen_range(0.0, 480.0))
+            } else {
+                pixel + nalgebra::Vector2::new(rng.gen_range(-0.5, 0.5), rng.gen_range(-0.5, 0.5))
+            };
+            corr.1 = camera.unproject(&noisy).unwrap();
+        }
+
+        let ransac = Ransac::new(RansacParams {
+            // ~2 pixels
+            threshold: (2.0_f64 / 380.0).powi(2),
+            ..Default::default()
+        });
+        let result = solve_pnp(&corrs, &ransac).expect("A pose");
+        let (rotation, translation) = pose_error(&result.pose, &pose);
+        assert!(rotation < 2e-3, "rotation error {}", rotation);
+        assert!(translation < 1e-2, "translation error {}", translation);
+        assert!(result.inliers.len() >= 140);
+        assert!(result.inliers.iter().filter(|idx| *idx % 4 == 0).count() <= 3);
+    }
+}
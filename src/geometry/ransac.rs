@@ -0,0 +1,385 @@
+/// Generic robust model estimation
+///
+/// [`Ransac`] runs any [`Estimator`] on minimal samples of the data and keeps the hypothesis with
+/// the lowest cost. The cost is either the number of outliers (RANSAC) or the sum of the truncated
+/// residuals (MSAC). Samples are drawn uniformly, or progressively from the best ranked data
+/// (PROSAC). The best hypothesis is re-estimated from all of its inliers at the end.
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::{Rng, SeedableRng};
+
+// -------------------------------------------------------------------------------------------------
+// Estimator
+// -------------------------------------------------------------------------------------------------
+
+/// A model that can be estimated from a (minimal) subset of the data
+pub trait Estimator {
+    type Datum;
+    type Model: Clone;
+
+    /// Size of the minimal sample
+    const MIN_SAMPLES: usize;
+
+    /// Estimate the models that fit the `sample`-th data. Minimal solvers may return more than one
+    /// solution, or none at all for degenerate samples
+    fn estimate(&self, data: &[Self::Datum], sample: &[usize]) -> Vec<Self::Model>;
+
+    /// Error of a datum given the model
+    fn residual(&self, model: &Self::Model, datum: &Self::Datum) -> f64;
+
+    /// Re-estimate the model from all of its inliers. The default implementation keeps the model
+    /// of the minimal sample
+    fn refine(
+        &self,
+        _data: &[Self::Datum],
+        _inliers: &[usize],
+        _model: &Self::Model,
+    ) -> Option<Self::Model> {
+        None
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// RansacParams / RansacResult
+// -------------------------------------------------------------------------------------------------
+
+/// How a hypothesis is scored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scoring {
+    /// Number of outliers
+    Ransac,
+    /// Sum of the residuals, truncated at the threshold
+    Msac,
+}
+
+/// How the minimal samples are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    Uniform,
+    /// Progressive sampling - the data must be sorted by decreasing quality, e.g. by increasing
+    /// descriptor distance
+    Prosac,
+}
+
+#[derive(Debug, Clone)]
+pub struct RansacParams {
+    /// Data with a residual below the threshold are inliers. In the units of
+    /// [`Estimator::residual`]
+    pub threshold: f64,
+    /// Probability of drawing at least one outlier-free sample, used to stop early
+    pub confidence: f64,
+    pub max_iterations: usize,
+    pub scoring: Scoring,
+    pub sampling: Sampling,
+    /// Seed of the random number generator, for repeatable runs
+    pub seed: u64,
+}
+
+impl Default for RansacParams {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            confidence: 0.999,
+            max_iterations: 1000,
+            scoring: Scoring::Msac,
+            sampling: Sampling::Uniform,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RansacResult<M> {
+    pub model: M,
+    /// Indices of the data consistent with the model
+    pub inliers: Vec<usize>,
+    pub cost: f64,
+    /// Number of hypotheses generation rounds
+    pub iterations: usize,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Ransac
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default)]
+pub struct Ransac {
+    params: RansacParams,
+}
+
+impl Ransac {
+    pub const fn new(params: RansacParams) -> Self {
+        Self { params }
+    }
+
+    pub const fn params(&self) -> &RansacParams {
+        &self.params
+    }
+
+    /// Robustly fit a model to the data. Returns `None` if there's not enough data or no
+    /// hypothesis could be generated
+    pub fn run<E: Estimator>(
+        &self,
+        estimator: &E,
+        data: &[E::Datum],
+    ) -> Option<RansacResult<E::Model>> {
+        let n_data = data.len();
+        if n_data < E::MIN_SAMPLES || E::MIN_SAMPLES == 0 {
+            return None;
+        }
+
+        let mut rng = StdRng::seed_from_u64(self.params.seed);
+        let mut sampler = Sampler::new(
+            self.params.sampling,
+            n_data,
+            E::MIN_SAMPLES,
+            self.params.max_iterations,
+        );
+        let mut best: Option<(E::Model, f64)> = None;
+        let mut max_iterations = self.params.max_iterations;
+        let mut iterations = 0;
+
+        while iterations < max_iterations {
+            iterations += 1;
+            let sample = sampler.sample(&mut rng);
+            for model in estimator.estimate(data, &sample) {
+                let (cost, n_inliers) = self.score(estimator, &model, data);
+                let is_better = match &best {
+                    Some((_, best_cost)) => cost < *best_cost,
+                    None => true,
+                };
+                if is_better {
+                    let inlier_ratio = n_inliers as f64 / n_data as f64;
+                    max_iterations =
+                        max_iterations.min(self.required_iterations(inlier_ratio, E::MIN_SAMPLES));
+                    best = Some((model, cost));
+                }
+            }
+        }
+
+        // local optimisation - re-estimate from the inliers, and from the inliers of the
+        // re-estimated model, until the inlier set settles
+        let (mut model, mut cost) = best?;
+        let mut inliers = self.inliers(estimator, &model, data);
+        let (mut candidate, mut candidate_inliers) = (model.clone(), inliers.clone());
+        for _ in 0..5 {
+            let refined = match estimator.refine(data, &candidate_inliers, &candidate) {
+                Some(refined) => refined,
+                None => break,
+            };
+            let (refined_cost, _) = self.score(estimator, &refined, data);
+            let refined_inliers = self.inliers(estimator, &refined, data);
+            if refined_cost < cost {
+                model = refined.clone();
+                cost = refined_cost;
+                inliers = refined_inliers.clone();
+            }
+            if refined_inliers == candidate_inliers {
+                break;
+            }
+            candidate = refined;
+            candidate_inliers = refined_inliers;
+        }
+
+        Some(RansacResult {
+            model,
+            inliers,
+            cost,
+            iterations,
+        })
+    }
+
+    /// Cost and number of inliers of a hypothesis
+    fn score<E: Estimator>(
+        &self,
+        estimator: &E,
+        model: &E::Model,
+        data: &[E::Datum],
+    ) -> (f64, usize) {
+        let threshold = self.params.threshold;
+        data.iter()
+            .map(|datum| estimator.residual(model, datum))
+            .fold((0.0, 0), |(cost, n_inliers), residual| {
+                let is_inlier = residual < threshold;
+                let residual_cost = match (self.params.scoring, is_inlier) {
+                    (Scoring::Ransac, true) => 0.0,
+                    (Scoring::Ransac, false) => 1.0,
+                    (Scoring::Msac, true) => residual,
+                    (Scoring::Msac, false) => threshold,
+                };
+                (cost + residual_cost, n_inliers + is_inlier as usize)
+            })
+    }
+
+    fn inliers<E: Estimator>(
+        &self,
+        estimator: &E,
+        model: &E::Model,
+        data: &[E::Datum],
+    ) -> Vec<usize> {
+        data.iter()
+            .enumerate()
+            .filter(|(_, datum)| estimator.residual(model, datum) < self.params.threshold)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Number of iterations needed to draw an outlier-free sample with the configured confidence
+    #[allow(clippy::suboptimal_flops)]
+    fn required_iterations(&self, inlier_ratio: f64, sample_size: usize) -> usize {
+        let p_good_sample = inlier_ratio.powi(sample_size as i32);
+        if p_good_sample >= 1.0 - f64::EPSILON {
+            return 1;
+        }
+        if p_good_sample <= f64::EPSILON {
+            return self.params.max_iterations;
+        }
+
+        let iterations = (1.0 - self.params.confidence).ln() / (1.0 - p_good_sample).ln();
+        iterations
+            .ceil()
+            .max(1.0)
+            .min(self.params.max_iterations as f64) as usize
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Sampler
+// -------------------------------------------------------------------------------------------------
+
+/// Draws minimal samples - PROSAC after "Matching with PROSAC - Progressive Sample Consensus",
+/// Chum and Matas, 2005
+#[derive(Debug)]
+struct Sampler {
+    sampling: Sampling,
+    n_data: usize,
+    sample_size: usize,
+    /// Iteration counter `t`
+    iteration: usize,
+    /// Size of the current progressive subset `n`
+    subset_size: usize,
+    /// `T_n` and `T'_n` of the paper
+    t_n: f64,
+    t_n_prime: usize,
+}
+
+impl Sampler {
+    fn new(sampling: Sampling, n_data: usize, sample_size: usize, max_iterations: usize) -> Self {
+        let t_n = (0..sample_size).fold(max_iterations as f64, |t_n, i| {
+            t_n * (sample_size - i) as f64 / (n_data - i) as f64
+        });
+        Self {
+            sampling,
+            n_data,
+            sample_size,
+            iteration: 0,
+            subset_size: sample_size,
+            t_n,
+            t_n_prime: 1,
+        }
+    }
+
+    fn sample<R: Rng>(&mut self, rng: &mut R) -> Vec<usize> {
+        if self.sampling == Sampling::Uniform {
+            return index::sample(rng, self.n_data, self.sample_size).into_vec();
+        }
+
+        self.iteration += 1;
+        if self.iteration == self.t_n_prime && self.subset_size < self.n_data {
+            let m = self.sample_size as f64;
+            let next_t_n =
+                self.t_n * (self.subset_size as f64 + 1.0) / (self.subset_size as f64 + 1.0 - m);
+            self.t_n_prime += (next_t_n - self.t_n).ceil() as usize;
+            self.t_n = next_t_n;
+            self.subset_size += 1;
+        }
+
+        if self.t_n_prime < self.iteration {
+            index::sample(rng, self.subset_size, self.sample_size).into_vec()
+        } else {
+            // the newest datum is always part of the sample
+            let mut sample =
+                index::sample(rng, self.subset_size - 1, self.sample_size - 1).into_vec();
+            sample.push(self.subset_size - 1);
+            sample
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Point2, Vector2};
+
+    /// Line `n . p = d` through two points
+    #[derive(Debug)]
+    struct LineEstimator;
+
+    impl Estimator for LineEstimator {
+        type Datum = Point2<f64>;
+        type Model = (Vector2<f64>, f64);
+
+        const MIN_SAMPLES: usize = 2;
+
+        fn estimate(&self, data: &[Point2<f64>], sample: &[usize]) -> Vec<Self::Model> {
+            let (a, b) = (data[sample[0]], data[sample[1]]);
+            let dir = b - a;
+            if dir.norm() < 1e-12 {
+                return Vec::new();
+            }
+            let normal = Vector2::new(-dir.y, dir.x).normalize();
+            vec![(normal, normal.dot(&a.coords))]
+        }
+
+        fn residual(&self, (normal, d): &Self::Model, p: &Point2<f64>) -> f64 {
+            (normal.dot(&p.coords) - d).powi(2)
+        }
+    }
+
+    #[allow(clippy::suboptimal_flops)]
+    fn noisy_line() -> Vec<Point2<f64>> {
+        // y = 0.5 x + 1 with 30% gross outliers, best points first
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..100)
+            .map(|i| {
+                let x = f64::from(i) * 0.1;
+                if i % 10 < 7 {
+                    Point2::new(x, 0.5 * x + 1.0 + rng.gen_range(-0.01, 0.01))
+                } else {
+                    Point2::new(x, rng.gen_range(-10.0, 10.0))
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fit_line_with_outliers() {
+        let data = noisy_line();
+        for &scoring in &[Scoring::Ransac, Scoring::Msac] {
+            for &sampling in &[Sampling::Uniform, Sampling::Prosac] {
+                let ransac = Ransac::new(RansacParams {
+                    threshold: 0.05 * 0.05,
+                    scoring,
+                    sampling,
+                    ..Default::default()
+                });
+                let result = ransac.run(&LineEstimator, &data).expect("A line");
+
+                let (normal, d) = result.model;
+                let slope = -normal.x / normal.y;
+                assert!((slope - 0.5).abs() < 0.02);
+                assert!((d / normal.y - 1.0).abs() < 0.05);
+                assert!(result.inliers.iter().all(|idx| idx % 10 < 7));
+                assert!(result.inliers.len() >= 65);
+                // stopped early thanks to the adaptive number of iterations
+                assert!(result.iterations < 50);
+            }
+        }
+    }
+
+    #[test]
+    fn not_enough_data() {
+        let ransac = Ransac::default();
+        assert!(ransac.run(&LineEstimator, &[Point2::origin()]).is_none());
+    }
+}
@@ -0,0 +1,339 @@
+/// Triangulation of 3D points observed from known poses
+///
+/// Observations are bearing vectors paired with the `T_cw` pose of the camera that made them. The
+/// point is estimated linearly (DLT or midpoint), optionally refined by Gauss-Newton on the bearing
+/// residuals, and rejected if the rays are too close to parallel or don't agree with the point. Its
+/// covariance follows from the bearing noise through the Gauss-Newton information matrix.
+use crate::geometry::linalg::{null_space, tangent_basis};
+use crate::geometry::pnp::bearing_residual;
+
+use nalgebra::{DMatrix, Isometry3, Matrix2x3, Matrix3, Point3, Unit, Vector3, U3};
+
+/// Pose of a camera (`T_cw`) and the bearing a point is observed along
+pub type PoseBearing = (Isometry3<f64>, Unit<Vector3<f64>>);
+
+/// Maximum number of Gauss-Newton iterations of the point refinement
+const MAX_REFINE_ITERATIONS: usize = 10;
+
+// -------------------------------------------------------------------------------------------------
+// Linear methods
+// -------------------------------------------------------------------------------------------------
+
+/// Whether the point lies along the bearings of all the observations rather than behind them
+fn in_front(point: &Point3<f64>, obs: &[PoseBearing]) -> bool {
+    obs.iter()
+        .all(|(pose, bearing)| bearing_residual(pose, &(*point, *bearing)).is_some())
+}
+
+/// Linear triangulation - the homogeneous point whose projections are parallel to the bearings,
+/// with the constraints expressed on the tangent plane of each bearing
+pub fn triangulate_dlt(obs: &[PoseBearing]) -> Option<Point3<f64>> {
+    if obs.len() < 2 {
+        return None;
+    }
+    let mut a = DMatrix::zeros(2 * obs.len(), 4);
+    for (i, (pose, bearing)) in obs.iter().enumerate() {
+        let projection = pose.to_homogeneous().fixed_rows::<U3>(0).into_owned();
+        let rows = tangent_basis(bearing) * projection;
+        a.rows_mut(2 * i, 2).copy_from(&rows);
+    }
+
+    let homogeneous = &null_space(&a, 1)[0];
+    if homogeneous[3].abs() < f64::EPSILON {
+        return None;
+    }
+    let point = Point3::new(homogeneous[0], homogeneous[1], homogeneous[2]) / homogeneous[3];
+    Some(point).filter(|point| in_front(point, obs))
+}
+
+/// Midpoint triangulation - the point closest to all the rays in the least-squares sense. With two
+/// views, the midpoint of their common perpendicular
+pub fn triangulate_midpoint(obs: &[PoseBearing]) -> Option<Point3<f64>> {
+    if obs.len() < 2 {
+        return None;
+    }
+    let (mut a, mut b) = (Matrix3::zeros(), Vector3::zeros());
+    for (pose, bearing) in obs {
+        let center = pose.inverse_transform_point(&Point3::origin()).coords;
+        let ray = pose.inverse_transform_vector(bearing);
+        let rejection = Matrix3::identity() - ray * ray.transpose();
+        a += rejection;
+        b += rejection * center;
+    }
+
+    let point = Point3::from(a.try_inverse()? * b);
+    Some(point).filter(|point| in_front(point, obs))
+}
+
+// -------------------------------------------------------------------------------------------------
+// Refinement / uncertainty
+// -------------------------------------------------------------------------------------------------
+
+/// Jacobian of the bearing residual (see [`bearing_residual`]) with respect to the world point
+fn residual_jacobian(
+    pose: &Isometry3<f64>,
+    bearing: &Unit<Vector3<f64>>,
+    point: &Point3<f64>,
+) -> Matrix2x3<f64> {
+    let p = (pose * point).coords;
+    let direction = p.normalize();
+    let d_direction = (Matrix3::identity() - direction * direction.transpose()) / p.norm();
+    tangent_basis(bearing) * d_direction * pose.rotation.to_rotation_matrix().matrix()
+}
+
+/// Gauss-Newton minimisation of the bearing residuals of the observations
+pub fn refine_point(point: &Point3<f64>, obs: &[PoseBearing]) -> Point3<f64> {
+    let mut point = *point;
+    for _ in 0..MAX_REFINE_ITERATIONS {
+        let (mut hessian, mut gradient) = (Matrix3::zeros(), Vector3::zeros());
+        for (pose, bearing) in obs {
+            let residual = match bearing_residual(pose, &(point, *bearing)) {
+                Some(residual) => residual,
+                None => continue,
+            };
+            let jacobian = residual_jacobian(pose, bearing, &point);
+            hessian += jacobian.transpose() * jacobian;
+            gradient += jacobian.transpose() * residual;
+        }
+
+        let step = match hessian.cholesky() {
+            Some(cholesky) => -cholesky.solve(&gradient),
+            None => break,
+        };
+        point += step;
+        if step.norm() < 1e-12 * (1.0 + point.coords.norm()) {
+            break;
+        }
+    }
+    point
+}
+
+/// First-order covariance of the point, given the standard deviation of the bearing noise in
+/// radians. `None` if the point is unconstrained along some direction
+pub fn point_covariance(
+    point: &Point3<f64>,
+    obs: &[PoseBearing],
+    bearing_sigma: f64,
+) -> Option<Matrix3<f64>> {
+    let information = obs.iter().fold(Matrix3::zeros(), |acc, (pose, bearing)| {
+        let jacobian = residual_jacobian(pose, bearing, point);
+        acc + jacobian.transpose() * jacobian
+    }) / (bearing_sigma * bearing_sigma);
+    information.cholesky().map(|cholesky| cholesky.inverse())
+}
+
+/// Largest angle between the rays from the camera centres to the point, in radians
+pub fn parallax_angle(point: &Point3<f64>, obs: &[PoseBearing]) -> f64 {
+    let rays: Vec<Vector3<f64>> = obs
+        .iter()
+        .map(|(pose, _)| (point - pose.inverse_transform_point(&Point3::origin())).normalize())
+        .collect();
+    let mut max_angle = 0.0_f64;
+    for (i, a) in rays.iter().enumerate() {
+        for b in &rays[i + 1..] {
+            max_angle = max_angle.max(a.angle(b));
+        }
+    }
+    max_angle
+}
+
+// -------------------------------------------------------------------------------------------------
+// Triangulator
+// -------------------------------------------------------------------------------------------------
+
+/// Linear triangulation method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriangulationMethod {
+    Dlt,
+    Midpoint,
+}
+
+#[derive(Debug, Clone)]
+pub struct TriangulationParams {
+    pub method: TriangulationMethod,
+    /// Refine the linear estimate with Gauss-Newton
+    pub refine: bool,
+    /// Points whose rays are closer to parallel are rejected, in radians
+    pub min_parallax: f64,
+    /// Points with a larger bearing error in any of the views are rejected, in radians
+    pub max_error: f64,
+    /// Standard deviation of the bearing noise, in radians, for the covariance
+    pub bearing_sigma: f64,
+}
+
+impl Default for TriangulationParams {
+    fn default() -> Self {
+        Self {
+            method: TriangulationMethod::Midpoint,
+            refine: true,
+            min_parallax: 1_f64.to_radians(),
+            // ~2 pixels at a focal length of 450 pixels
+            max_error: 2.0 / 450.0,
+            bearing_sigma: 1.0 / 450.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TriangulatedPoint {
+    pub point: Point3<f64>,
+    pub covariance: Matrix3<f64>,
+    /// See [`parallax_angle`]
+    pub parallax: f64,
+    /// Largest bearing error among the views, in radians
+    pub max_error: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Triangulator {
+    params: TriangulationParams,
+}
+
+impl Triangulator {
+    pub const fn new(params: TriangulationParams) -> Self {
+        Self { params }
+    }
+
+    pub const fn params(&self) -> &TriangulationParams {
+        &self.params
+    }
+
+    /// Triangulate a point from two or more observations. `None` if the point is degenerate, behind
+    /// one of the cameras, or fails the parallax or error gates
+    pub fn triangulate(&self, obs: &[PoseBearing]) -> Option<TriangulatedPoint> {
+        let linear = match self.params.method {
+            TriangulationMethod::Dlt => triangulate_dlt(obs),
+            TriangulationMethod::Midpoint => triangulate_midpoint(obs),
+        }?;
+        let point = if self.params.refine {
+            refine_point(&linear, obs)
+        } else {
+            linear
+        };
+
+        let parallax = parallax_angle(&point, obs);
+        if parallax < self.params.min_parallax {
+            return None;
+        }
+        let mut max_error = 0.0_f64;
+        for (pose, bearing) in obs {
+            let residual = bearing_residual(pose, &(point, *bearing))?;
+            max_error = max_error.max(residual.norm());
+        }
+        if max_error > self.params.max_error {
+            return None;
+        }
+
+        Some(TriangulatedPoint {
+            point,
+            covariance: point_covariance(&point, obs, self.params.bearing_sigma)?,
+            parallax,
+            max_error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Cameras along the x axis, looking down z and slightly towards the centre
+    fn cameras(n: usize, spacing: f64) -> Vec<Isometry3<f64>> {
+        (0..n)
+            .map(|i| {
+                let x = (i as f64 - (n - 1) as f64 / 2.0) * spacing;
+                Isometry3::new(Vector3::zeros(), Vector3::new(0.0, -0.02 * x, 0.0))
+                    * Isometry3::translation(-x, 0.0, 0.0)
+            })
+            .collect()
+    }
+
+    fn observe(point: &Point3<f64>, poses: &[Isometry3<f64>]) -> Vec<PoseBearing> {
+        poses
+            .iter()
+            .map(|pose| (*pose, Unit::new_normalize((pose * point).coords)))
+            .collect()
+    }
+
+    #[test]
+    fn linear_methods_are_exact() {
+        let point = Point3::new(0.3, -0.4, 5.0);
+        for n in 2..6 {
+            let obs = observe(&point, &cameras(n, 0.2));
+            let dlt = triangulate_dlt(&obs).unwrap();
+            let midpoint = triangulate_midpoint(&obs).unwrap();
+            assert!((dlt - point).norm() < 1e-9);
+            assert!((midpoint - point).norm() < 1e-9);
+        }
+
+        // behind the cameras
+        let obs = observe(&Point3::new(0.3, -0.4, -5.0), &cameras(3, 0.2));
+        let flipped: Vec<_> = obs.iter().map(|(pose, b)| (*pose, -*b)).collect();
+        assert!(triangulate_midpoint(&flipped).is_none());
+        assert!(triangulate_dlt(&flipped).is_none());
+    }
+
+    #[test]
+    fn refinement_and_covariance() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let point = Point3::new(-0.2, 0.1, 4.0);
+        let sigma = 1e-3;
+        let mut obs = observe(&point, &cameras(4, 0.3));
+        for (_, bearing) in obs.iter_mut() {
+            let noise = tangent_basis(bearing).transpose()
+                * nalgebra::Vector2::new(
+                    rng.gen_range(-sigma, sigma),
+                    rng.gen_range(-sigma, sigma),
+                );
+            *bearing = Unit::new_normalize(bearing.into_inner() + noise);
+        }
+
+        let linear = triangulate_midpoint(&obs).unwrap();
+        let refined = refine_point(&linear, &obs);
+        let error = |p: &Point3<f64>| -> f64 {
+            obs.iter()
+                .map(|(pose, b)| bearing_residual(pose, &(*p, *b)).unwrap().norm_squared())
+                .sum()
+        };
+        assert!(error(&refined) <= error(&linear));
+
+        // the uncertainty is largest along the depth
+        let covariance = point_covariance(&refined, &obs, sigma).unwrap();
+        let eigen = nalgebra::SymmetricEigen::new(covariance);
+        let (largest, _) = eigen.eigenvalues.argmax();
+        assert!(eigen.eigenvectors.column(largest).z.abs() > 0.95);
+        assert!((refined - point).norm() < 5.0 * eigen.eigenvalues[largest].sqrt());
+    }
+
+    #[test]
+    fn gating() {
+        let point = Point3::new(0.1, 0.2, 3.0);
+        let triangulator = Triangulator::default();
+        let result = triangulator
+            .triangulate(&observe(&point, &cameras(3, 0.2)))
+            .expect("A point");
+        assert!((result.point - point).norm() < 1e-9);
+        assert!(result.parallax > 5_f64.to_radians());
+
+        // ~0.2 degrees of parallax
+        assert!(triangulator
+            .triangulate(&observe(&point, &cameras(2, 0.01)))
+            .is_none());
+
+        // one of the observations is an outlier
+        let mut obs = observe(&point, &cameras(3, 0.2));
+        obs[1].1 = Unit::new_normalize(obs[1].1.into_inner() + Vector3::new(0.05, 0.0, 0.0));
+        assert!(triangulator.triangulate(&obs).is_none());
+
+        let dlt = Triangulator::new(TriangulationParams {
+            method: TriangulationMethod::Dlt,
+            refine: false,
+            ..Default::default()
+        });
+        assert!(dlt
+            .triangulate(&observe(&point, &cameras(3, 0.2)))
+            .is_some());
+    }
+}
@@ -0,0 +1,753 @@
+/// Relative pose from 2D-2D correspondences
+///
+/// The essential matrix relates bearing vectors of the same point in two views - `f2' E f1 = 0`,
+/// with `E = [t]x R` for a point transforming as `X2 = R X1 + t`. It is estimated with the
+/// minimal 5-point solver, and decomposed into the four candidate poses that are disambiguated by
+/// triangulating the correspondences in front of both cameras. The fundamental matrix relates
+/// pixels of uncalibrated views and is estimated with the normalised 8-point algorithm.
+use crate::geometry::linalg::{null_space, polyval, real_roots, skew, svd3};
+use crate::geometry::ransac::{Estimator, Ransac};
+use std::cmp::Ordering;
+
+use nalgebra::{
+    DMatrix, Isometry3, Matrix2, Matrix3, Point2, Rotation3, Translation3, Unit, UnitQuaternion,
+    Vector2, Vector3,
+};
+
+/// Bearing vectors of the same point in the first and the second view
+pub type BearingPair = (Unit<Vector3<f64>>, Unit<Vector3<f64>>);
+
+/// Whether the bearings are free of NaN and infinities, which the decompositions don't survive
+fn finite_pairs(pairs: &[BearingPair]) -> bool {
+    pairs
+        .iter()
+        .flat_map(|(f1, f2)| f1.iter().chain(f2.iter()))
+        .all(|v| v.is_finite())
+}
+
+/// Pixels of the same point in the first and the second view
+pub type PixelPair = (Point2<f64>, Point2<f64>);
+
+// -------------------------------------------------------------------------------------------------
+// Essential matrix
+// -------------------------------------------------------------------------------------------------
+
+/// One row of the `A e = 0` system, for the row-major elements `e` of the essential matrix
+fn epipolar_constraints(pairs: &[BearingPair]) -> DMatrix<f64> {
+    let mut a = DMatrix::zeros(pairs.len(), 9);
+    for (row, (f1, f2)) in pairs.iter().enumerate() {
+        for i in 0..3 {
+            for j in 0..3 {
+                a[(row, 3 * i + j)] = f2[i] * f1[j];
+            }
+        }
+    }
+    a
+}
+
+/// Essential matrices consistent with five correspondences - up to 10 real solutions
+///
+/// After "An Efficient Solution to the Five-Point Relative Pose Problem", Nistér, 2004. The
+/// solutions have unit Frobenius norm. Only the first five pairs are used.
+pub fn essential_five_point(pairs: &[BearingPair]) -> Vec<Matrix3<f64>> {
+    if pairs.len() < 5 || !finite_pairs(&pairs[..5]) {
+        return Vec::new();
+    }
+
+    // E = x X + y Y + z Z + W, with X, Y, Z, W spanning the null space of the constraints
+    let basis: Vec<Matrix3<f64>> = null_space(&epipolar_constraints(&pairs[..5]), 4)
+        .iter()
+        .map(|v| Matrix3::from_row_slice(v.as_slice()))
+        .collect();
+    let mut e = [[ZERO_POLY; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            e[i][j][X] = basis[0][(i, j)];
+            e[i][j][Y] = basis[1][(i, j)];
+            e[i][j][Z] = basis[2][(i, j)];
+            e[i][j][ONE] = basis[3][(i, j)];
+        }
+    }
+
+    // det(E) = 0 and 2 E E' E - trace(E E') E = 0
+    let mut constraints = DMatrix::zeros(10, N_MONOMIALS);
+    let det = poly_sub(
+        &poly_add(
+            &poly_mul(
+                &e[0][0],
+                &poly_sub(&poly_mul(&e[1][1], &e[2][2]), &poly_mul(&e[1][2], &e[2][1])),
+            ),
+            &poly_mul(
+                &e[0][2],
+                &poly_sub(&poly_mul(&e[1][0], &e[2][1]), &poly_mul(&e[1][1], &e[2][0])),
+            ),
+        ),
+        &poly_mul(
+            &e[0][1],
+            &poly_sub(&poly_mul(&e[1][0], &e[2][2]), &poly_mul(&e[1][2], &e[2][0])),
+        ),
+    );
+    constraints.row_mut(0).copy_from_slice(&det);
+
+    let product = |a: &[[Poly; 3]; 3], b: &[[Poly; 3]; 3], transpose_b: bool| {
+        let mut out = [[ZERO_POLY; 3]; 3];
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = (0..3).fold(ZERO_POLY, |acc, k| {
+                    let b_kj = if transpose_b { &b[j][k] } else { &b[k][j] };
+                    poly_add(&acc, &poly_mul(&a[i][k], b_kj))
+                });
+            }
+        }
+        out
+    };
+    let eet = product(&e, &e, true);
+    let eete = product(&eet, &e, false);
+    let trace = poly_add(&poly_add(&eet[0][0], &eet[1][1]), &eet[2][2]);
+    for i in 0..3 {
+        for j in 0..3 {
+            let eq = poly_sub(&poly_scale(&eete[i][j], 2.0), &poly_mul(&trace, &e[i][j]));
+            constraints.row_mut(1 + 3 * i + j).copy_from_slice(&eq);
+        }
+    }
+
+    // Gauss-Jordan elimination of the 10 leading monomials. Each row then reads
+    // `leading + reduced * [xz^2, xz, x, yz^2, yz, y, z^3, z^2, z, 1] = 0`
+    let leading = constraints.columns(0, 10).into_owned();
+    let reduced = match leading
+        .lu()
+        .solve(&constraints.columns(10, 10).into_owned())
+    {
+        Some(reduced) => reduced,
+        None => return Vec::new(),
+    };
+
+    // Hide z - each row as `a(z) x + b(z) y + c(z)`
+    let hidden = |row: usize| {
+        let r = |col: usize| reduced[(row, col)];
+        [
+            vec![r(2), r(1), r(0)],
+            vec![r(5), r(4), r(3)],
+            vec![r(9), r(8), r(7), r(6)],
+        ]
+    };
+    // (x^2 z, x^2), (y^2 z, y^2) and (xyz, xy) rows have the same leading monomial after
+    // multiplying the second one by z - their difference is linear in [x, y, 1]
+    let mut b: Vec<[Vec<f64>; 3]> = Vec::with_capacity(3);
+    for &(with_z, without_z) in &[(4, 5), (6, 7), (8, 9)] {
+        let (first, second) = (hidden(with_z), hidden(without_z));
+        let mut row = first.clone();
+        for col in 0..3 {
+            let shifted: Vec<f64> = std::iter::once(0.0)
+                .chain(second[col].iter().copied())
+                .collect();
+            row[col] = coeffs_sub(&first[col], &shifted);
+        }
+        b.push(row);
+    }
+
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+        coeffs_sub(
+            &coeffs_mul(&b[r0][c0], &b[r1][c1]),
+            &coeffs_mul(&b[r0][c1], &b[r1][c0]),
+        )
+    };
+    let det_b = coeffs_add(
+        &coeffs_sub(
+            &coeffs_mul(&b[0][0], &cofactor(1, 2, 1, 2)),
+            &coeffs_mul(&b[0][1], &cofactor(1, 2, 0, 2)),
+        ),
+        &coeffs_mul(&b[0][2], &cofactor(1, 2, 0, 1)),
+    );
+
+    real_roots(&det_b)
+        .into_iter()
+        .filter_map(|z| {
+            let m = Matrix3::from_fn(|r, c| polyval(&b[r][c], z));
+            let (r0, r1, r2) = (
+                m.row(0).transpose(),
+                m.row(1).transpose(),
+                m.row(2).transpose(),
+            );
+            let xy1 = [r0.cross(&r1), r0.cross(&r2), r1.cross(&r2)]
+                .iter()
+                .max_by(|a, b| {
+                    a.norm_squared()
+                        .partial_cmp(&b.norm_squared())
+                        .unwrap_or(Ordering::Equal)
+                })
+                .copied()?;
+            if !xy1.iter().all(|v| v.is_finite()) || xy1.z.abs() < f64::EPSILON * xy1.norm() {
+                return None;
+            }
+            let (x, y) = (xy1.x / xy1.z, xy1.y / xy1.z);
+            let essential = basis[0] * x + basis[1] * y + basis[2] * z + basis[3];
+            Some(essential / essential.norm())
+        })
+        .collect()
+}
+
+/// Linear least-squares essential matrix from at least eight correspondences, projected on the
+/// essential manifold
+pub fn essential_eight_point(pairs: &[BearingPair]) -> Option<Matrix3<f64>> {
+    essential_weighted(pairs, &vec![1.0; pairs.len()])
+}
+
+/// Eight-point solution where each epipolar constraint is scaled by its weight
+fn essential_weighted(pairs: &[BearingPair], weights: &[f64]) -> Option<Matrix3<f64>> {
+    if pairs.len() < 8 || !finite_pairs(pairs) || !weights.iter().all(|w| w.is_finite()) {
+        return None;
+    }
+    let mut constraints = epipolar_constraints(pairs);
+    for (mut row, weight) in constraints.row_iter_mut().zip(weights) {
+        row *= *weight;
+    }
+
+    let e = null_space(&constraints, 1);
+    let (u, _, v) = svd3(&Matrix3::from_row_slice(e[0].as_slice()));
+    let essential = u * Matrix3::from_diagonal(&Vector3::new(1.0, 1.0, 0.0)) * v.transpose();
+    Some(essential / essential.norm())
+}
+
+/// Epipolar error `f2' E f1` and the squared norm of its gradient on the tangent planes of the
+/// two bearings
+#[allow(clippy::suboptimal_flops)]
+fn sampson_terms(e: &Matrix3<f64>, (f1, f2): &BearingPair) -> (f64, f64) {
+    let ef1 = e * f1.into_inner();
+    let etf2 = e.transpose() * f2.into_inner();
+    let error = f2.dot(&ef1);
+    (
+        error,
+        ef1.norm_squared() + etf2.norm_squared() - 2.0 * error * error,
+    )
+}
+
+/// First-order approximation of the squared angular error of a correspondence - the Sampson
+/// distance on the tangent planes of the two bearings
+pub fn essential_sampson_error(e: &Matrix3<f64>, pair: &BearingPair) -> f64 {
+    let (error, gradient) = sampson_terms(e, pair);
+    if gradient < f64::EPSILON {
+        return f64::INFINITY;
+    }
+    error * error / gradient
+}
+
+/// [`Estimator`] of the essential matrix from bearing correspondences, with residuals in squared
+/// radians
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EssentialEstimator;
+
+impl Estimator for EssentialEstimator {
+    type Datum = BearingPair;
+    type Model = Matrix3<f64>;
+
+    const MIN_SAMPLES: usize = 5;
+
+    fn estimate(&self, data: &[BearingPair], sample: &[usize]) -> Vec<Matrix3<f64>> {
+        let pairs: Vec<_> = sample.iter().map(|&idx| data[idx]).collect();
+        essential_five_point(&pairs)
+    }
+
+    fn residual(&self, e: &Matrix3<f64>, pair: &BearingPair) -> f64 {
+        essential_sampson_error(e, pair)
+    }
+
+    fn refine(
+        &self,
+        data: &[BearingPair],
+        inliers: &[usize],
+        model: &Matrix3<f64>,
+    ) -> Option<Matrix3<f64>> {
+        // iteratively reweighted eight-point - the algebraic error is normalised to approximate
+        // the Sampson error, and a Cauchy kernel scaled by the median error limits the influence
+        // of the outliers that made it under the threshold
+        let pairs: Vec<_> = inliers.iter().map(|&idx| data[idx]).collect();
+        let mut e = *model;
+        for _ in 0..3 {
+            let terms: Vec<(f64, f64)> = pairs.iter().map(|pair| sampson_terms(&e, pair)).collect();
+            let errors: Vec<f64> = terms
+                .iter()
+                .map(|(error, gradient)| error * error / gradient.max(f64::EPSILON))
+                .collect();
+            if !errors.iter().all(|error| error.is_finite()) {
+                return None;
+            }
+            let mut sorted = errors.clone();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+            let median_error = sorted[sorted.len() / 2].max(f64::EPSILON);
+
+            let weights: Vec<f64> = terms
+                .iter()
+                .zip(&errors)
+                .map(|((_, gradient), sampson)| {
+                    let scale = 1.0 / gradient.max(f64::EPSILON);
+                    (scale / (1.0 + sampson / median_error)).sqrt()
+                })
+                .collect();
+            e = essential_weighted(&pairs, &weights)?;
+        }
+        Some(e)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Relative pose
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct RelativePose {
+    /// Transforms points from the first to the second camera frame. The scale of the translation
+    /// is unobservable - it has unit norm
+    pub pose: Isometry3<f64>,
+    pub essential: Matrix3<f64>,
+    /// Indices of the correspondences consistent with the pose, in front of both cameras
+    pub inliers: Vec<usize>,
+}
+
+/// Essential matrix of a relative pose, that transforms points from the first to the second
+/// camera frame
+pub fn essential_from_pose(pose: &Isometry3<f64>) -> Matrix3<f64> {
+    skew(&pose.translation.vector) * pose.rotation.to_rotation_matrix().matrix()
+}
+
+/// The four `(R, t)` pairs of an essential matrix
+pub fn decompose_essential(e: &Matrix3<f64>) -> [(Rotation3<f64>, Vector3<f64>); 4] {
+    let (mut u, _, mut v) = svd3(e);
+    if u.determinant() < 0.0 {
+        u = -u;
+    }
+    if v.determinant() < 0.0 {
+        v = -v;
+    }
+
+    let w = Matrix3::new(0.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0);
+    let r1 = Rotation3::from_matrix_unchecked(u * w * v.transpose());
+    let r2 = Rotation3::from_matrix_unchecked(u * w.transpose() * v.transpose());
+    let t: Vector3<f64> = u.column(2).into_owned();
+    [(r1, t), (r1, -t), (r2, t), (r2, -t)]
+}
+
+/// Depths of a correspondence along its two bearings, for `X2 = R X1 + t`
+fn depths(
+    rotation: &Rotation3<f64>,
+    t: &Vector3<f64>,
+    (f1, f2): &BearingPair,
+) -> Option<Vector2<f64>> {
+    // least-squares solution of `d1 R f1 - d2 f2 = -t`
+    let a = rotation * f1.into_inner();
+    let b = -f2.into_inner();
+    let normal = Matrix2::new(a.dot(&a), a.dot(&b), a.dot(&b), b.dot(&b));
+    normal
+        .try_inverse()
+        .map(|inv| inv * Vector2::new(-a.dot(t), -b.dot(t)))
+}
+
+/// Pick the decomposition of `e` that places most of the correspondences in front of both
+/// cameras. `None` if no candidate triangulates any correspondence
+pub fn recover_pose(e: &Matrix3<f64>, pairs: &[BearingPair]) -> Option<RelativePose> {
+    let (rotation, t, inliers) = decompose_essential(e)
+        .iter()
+        .map(|(rotation, t)| {
+            let in_front: Vec<usize> = pairs
+                .iter()
+                .enumerate()
+                .filter(|(_, pair)| {
+                    matches!(depths(rotation, t, pair), Some(d) if d.x > 0.0 && d.y > 0.0)
+                })
+                .map(|(idx, _)| idx)
+                .collect();
+            (*rotation, *t, in_front)
+        })
+        .max_by_key(|(_, _, in_front)| in_front.len())?;
+    if inliers.is_empty() {
+        return None;
+    }
+
+    Some(RelativePose {
+        pose: Isometry3::from_parts(
+            Translation3::from(t),
+            UnitQuaternion::from_rotation_matrix(&rotation),
+        ),
+        essential: *e,
+        inliers,
+    })
+}
+
+/// Robust relative pose between two views - essential matrix in RANSAC, followed by the
+/// cheirality check on its inliers
+pub fn estimate_relative_pose(pairs: &[BearingPair], ransac: &Ransac) -> Option<RelativePose> {
+    let result = ransac.run(&EssentialEstimator, pairs)?;
+    let inlier_pairs: Vec<_> = result.inliers.iter().map(|&idx| pairs[idx]).collect();
+
+    let mut relative = recover_pose(&result.model, &inlier_pairs)?;
+    relative.inliers = relative
+        .inliers
+        .iter()
+        .map(|&idx| result.inliers[idx])
+        .collect();
+    Some(relative)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Fundamental matrix
+// -------------------------------------------------------------------------------------------------
+
+/// Similarity that moves the centroid of the points to the origin and scales their mean distance
+/// from it to `sqrt(2)`
+fn normalising_transform<'a>(pts: impl Iterator<Item = &'a Point2<f64>> + Clone) -> Matrix3<f64> {
+    let n = pts.clone().count() as f64;
+    let centroid = pts.clone().fold(Vector2::zeros(), |acc, p| acc + p.coords) / n;
+    let mean_distance = pts.map(|p| (p.coords - centroid).norm()).sum::<f64>() / n;
+    let scale = if mean_distance > f64::EPSILON {
+        std::f64::consts::SQRT_2 / mean_distance
+    } else {
+        1.0
+    };
+    Matrix3::new(
+        scale,
+        0.0,
+        -scale * centroid.x,
+        0.0,
+        scale,
+        -scale * centroid.y,
+        0.0,
+        0.0,
+        1.0,
+    )
+}
+
+/// Normalised 8-point algorithm - "In Defense of the Eight-Point Algorithm", Hartley, 1997.
+/// Returns a rank-2 fundamental matrix with unit Frobenius norm, such that `x2' F x1 = 0`
+pub fn fundamental_eight_point(pairs: &[PixelPair]) -> Option<Matrix3<f64>> {
+    let finite = |p: &Point2<f64>| p.x.is_finite() && p.y.is_finite();
+    if pairs.len() < 8 || !pairs.iter().all(|(p1, p2)| finite(p1) && finite(p2)) {
+        return None;
+    }
+    let t1 = normalising_transform(pairs.iter().map(|(p1, _)| p1));
+    let t2 = normalising_transform(pairs.iter().map(|(_, p2)| p2));
+
+    let normalised: Vec<BearingPair> = pairs
+        .iter()
+        .map(|(p1, p2)| {
+            (
+                Unit::new_unchecked(t1 * p1.to_homogeneous()),
+                Unit::new_unchecked(t2 * p2.to_homogeneous()),
+            )
+        })
+        .collect();
+    let f = null_space(&epipolar_constraints(&normalised), 1);
+
+    // enforce rank 2
+    let (u, s, v) = svd3(&Matrix3::from_row_slice(f[0].as_slice()));
+    let f = u * Matrix3::from_diagonal(&Vector3::new(s[0], s[1], 0.0)) * v.transpose();
+    let f = t2.transpose() * f * t1;
+    Some(f / f.norm())
+}
+
+/// Sampson distance of a pixel correspondence, in squared pixels
+#[allow(clippy::suboptimal_flops)]
+pub fn fundamental_sampson_error(f: &Matrix3<f64>, (p1, p2): &PixelPair) -> f64 {
+    let (x1, x2) = (p1.to_homogeneous(), p2.to_homogeneous());
+    let (fx1, ftx2) = (f * x1, f.transpose() * x2);
+    let error = x2.dot(&fx1);
+    let gradient = fx1.x * fx1.x + fx1.y * fx1.y + ftx2.x * ftx2.x + ftx2.y * ftx2.y;
+    if gradient < f64::EPSILON {
+        return f64::INFINITY;
+    }
+    error * error / gradient
+}
+
+/// [`Estimator`] of the fundamental matrix from pixel correspondences, with residuals in squared
+/// pixels
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FundamentalEstimator;
+
+impl Estimator for FundamentalEstimator {
+    type Datum = PixelPair;
+    type Model = Matrix3<f64>;
+
+    const MIN_SAMPLES: usize = 8;
+
+    fn estimate(&self, data: &[PixelPair], sample: &[usize]) -> Vec<Matrix3<f64>> {
+        let pairs: Vec<_> = sample.iter().map(|&idx| data[idx]).collect();
+        fundamental_eight_point(&pairs).into_iter().collect()
+    }
+
+    fn residual(&self, f: &Matrix3<f64>, pair: &PixelPair) -> f64 {
+        fundamental_sampson_error(f, pair)
+    }
+
+    fn refine(
+        &self,
+        data: &[PixelPair],
+        inliers: &[usize],
+        _model: &Matrix3<f64>,
+    ) -> Option<Matrix3<f64>> {
+        let pairs: Vec<_> = inliers.iter().map(|&idx| data[idx]).collect();
+        fundamental_eight_point(&pairs)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Polynomials in x, y, z of degree up to 3
+// -------------------------------------------------------------------------------------------------
+
+const N_MONOMIALS: usize = 20;
+
+/// Exponents of x, y and z. The first ten are the leading monomials eliminated by the 5-point
+/// solver
+const MONOMIALS: [[u8; 3]; N_MONOMIALS] = [
+    [3, 0, 0],
+    [0, 3, 0],
+    [2, 1, 0],
+    [1, 2, 0],
+    [2, 0, 1],
+    [2, 0, 0],
+    [0, 2, 1],
+    [0, 2, 0],
+    [1, 1, 1],
+    [1, 1, 0],
+    [1, 0, 2],
+    [1, 0, 1],
+    [1, 0, 0],
+    [0, 1, 2],
+    [0, 1, 1],
+    [0, 1, 0],
+    [0, 0, 3],
+    [0, 0, 2],
+    [0, 0, 1],
+    [0, 0, 0],
+];
+const X: usize = 12;
+const Y: usize = 15;
+const Z: usize = 18;
+const ONE: usize = 19;
+
+type Poly = [f64; N_MONOMIALS];
+const ZERO_POLY: Poly = [0.0; N_MONOMIALS];
+
+fn poly_add(a: &Poly, b: &Poly) -> Poly {
+    let mut out = *a;
+    out.iter_mut().zip(b).for_each(|(o, b)| *o += b);
+    out
+}
+
+fn poly_sub(a: &Poly, b: &Poly) -> Poly {
+    poly_add(a, &poly_scale(b, -1.0))
+}
+
+fn poly_scale(a: &Poly, s: f64) -> Poly {
+    let mut out = *a;
+    out.iter_mut().for_each(|o| *o *= s);
+    out
+}
+
+/// Product of two polynomials. The product must not exceed degree 3
+fn poly_mul(a: &Poly, b: &Poly) -> Poly {
+    let mut out = ZERO_POLY;
+    for (ca, ea) in a.iter().zip(&MONOMIALS).filter(|(c, _)| **c != 0.0) {
+        for (cb, eb) in b.iter().zip(&MONOMIALS).filter(|(c, _)| **c != 0.0) {
+            let exponents = [ea[0] + eb[0], ea[1] + eb[1], ea[2] + eb[2]];
+            let idx = MONOMIALS
+                .iter()
+                .position(|m| *m == exponents)
+                .expect("Product of degree at most 3");
+            out[idx] += ca * cb;
+        }
+    }
+    out
+}
+
+// univariate polynomials, with coefficients in ascending order of degree
+
+fn coeffs_add(a: &[f64], b: &[f64]) -> Vec<f64> {
+    (0..a.len().max(b.len()))
+        .map(|i| a.get(i).unwrap_or(&0.0) + b.get(i).unwrap_or(&0.0))
+        .collect()
+}
+
+fn coeffs_sub(a: &[f64], b: &[f64]) -> Vec<f64> {
+    coeffs_add(a, &b.iter().map(|c| -c).collect::<Vec<_>>())
+}
+
+fn coeffs_mul(a: &[f64], b: &[f64]) -> Vec<f64> {
+    let mut out = vec![0.0; a.len() + b.len() - 1];
+    for (i, ca) in a.iter().enumerate() {
+        for (j, cb) in b.iter().enumerate() {
+            out[i + j] += ca * cb;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::ransac::{RansacParams, Sampling};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const FOCAL: f64 = 500.0;
+
+    struct Scene {
+        rotation: Rotation3<f64>,
+        t: Vector3<f64>,
+        pairs: Vec<BearingPair>,
+    }
+
+    fn synthetic_scene(n_points: usize, seed: u64) -> Scene {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let rotation = Rotation3::from_scaled_axis(Vector3::new(0.1, -0.2, 0.05));
+        let t = Vector3::new(1.0, 0.2, 0.1);
+        let pairs = (0..n_points)
+            .map(|_| {
+                let p = Vector3::new(
+                    rng.gen_range(-2.0, 2.0),
+                    rng.gen_range(-2.0, 2.0),
+                    rng.gen_range(4.0, 8.0),
+                );
+                (
+                    Unit::new_normalize(p),
+                    Unit::new_normalize(rotation * p + t),
+                )
+            })
+            .collect();
+        Scene { rotation, t, pairs }
+    }
+
+    fn same_up_to_scale(a: &Matrix3<f64>, b: &Matrix3<f64>) -> bool {
+        let (a, b) = (a / a.norm(), b / b.norm());
+        (a - b).norm().min((a + b).norm()) < 1e-6
+    }
+
+    #[test]
+    fn five_point_solver() {
+        for seed in 0..20 {
+            let scene = synthetic_scene(5, seed);
+            let expected = skew(&scene.t) * scene.rotation.matrix();
+
+            let solutions = essential_five_point(&scene.pairs);
+            assert!(!solutions.is_empty() && solutions.len() <= 10);
+            for e in &solutions {
+                for pair in &scene.pairs {
+                    assert!(essential_sampson_error(e, pair) < 1e-12);
+                }
+            }
+            assert!(solutions.iter().any(|e| same_up_to_scale(e, &expected)));
+        }
+    }
+
+    #[test]
+    fn eight_point_fundamental() {
+        let scene = synthetic_scene(20, 1);
+        let k = Matrix3::new(FOCAL, 0.0, 320.0, 0.0, FOCAL, 240.0, 0.0, 0.0, 1.0);
+        let project =
+            |f: &Unit<Vector3<f64>>| Point2::from_homogeneous(k * f.into_inner()).unwrap();
+        let pairs: Vec<PixelPair> = scene
+            .pairs
+            .iter()
+            .map(|(f1, f2)| (project(f1), project(f2)))
+            .collect();
+
+        let f = fundamental_eight_point(&pairs).unwrap();
+        let k_inv = k.try_inverse().unwrap();
+        let expected = k_inv.transpose() * skew(&scene.t) * scene.rotation.matrix() * k_inv;
+        assert!(same_up_to_scale(&f, &expected));
+        assert!(f.determinant().abs() < 1e-12);
+        assert!(pairs
+            .iter()
+            .all(|pair| fundamental_sampson_error(&f, pair) < 1e-12));
+    }
+
+    #[test]
+    fn recover_pose_from_essential() {
+        let scene = synthetic_scene(50, 2);
+        let e = essential_from_pose(&Isometry3::from_parts(
+            Translation3::from(scene.t),
+            UnitQuaternion::from_rotation_matrix(&scene.rotation),
+        ));
+        let relative = recover_pose(&e, &scene.pairs).unwrap();
+
+        assert_eq!(relative.inliers.len(), scene.pairs.len());
+        assert!(
+            relative
+                .pose
+                .rotation
+                .angle_to(&UnitQuaternion::from_rotation_matrix(&scene.rotation))
+                < 1e-9
+        );
+        assert!((relative.pose.translation.vector - scene.t.normalize()).norm() < 1e-9);
+    }
+
+    #[test]
+    fn robust_relative_pose() {
+        let mut scene = synthetic_scene(200, 3);
+        let mut rng = StdRng::seed_from_u64(4);
+        let noise = 0.5 / FOCAL;
+        let mut perturb = |f: &Unit<Vector3<f64>>| {
+            let delta = Vector3::new(
+                rng.gen_range(-noise, noise),
+                rng.gen_range(-noise, noise),
+                0.0,
+            );
+            Unit::new_normalize(f.into_inner() + delta)
+        };
+        let is_outlier = |idx: usize| idx % 10 >= 7;
+        for (idx, pair) in scene.pairs.iter_mut().enumerate() {
+            *pair = (perturb(&pair.0), perturb(&pair.1));
+            if is_outlier(idx) {
+                pair.1 = perturb(&Unit::new_normalize(Vector3::new(
+                    (idx as f64 * 0.37).sin(),
+                    (idx as f64 * 0.71).cos(),
+                    1.0,
+                )));
+            }
+        }
+
+        for &sampling in &[Sampling::Uniform, Sampling::Prosac] {
+            let ransac = Ransac::new(RansacParams {
+                threshold: (2.0 / FOCAL).powi(2),
+                sampling,
+                ..Default::default()
+            });
+            let relative = estimate_relative_pose(&scene.pairs, &ransac).expect("A pose");
+
+            let expected_rotation = UnitQuaternion::from_rotation_matrix(&scene.rotation);
+            assert!(relative.pose.rotation.angle_to(&expected_rotation) < 0.01);
+            let t = relative.pose.translation.vector;
+            assert!(t.angle(&scene.t) < 0.05);
+
+            let n_outliers = relative
+                .inliers
+                .iter()
+                .filter(|&&idx| is_outlier(idx))
+                .count();
+            assert!(relative.inliers.len() - n_outliers >= 133);
+            assert!(n_outliers <= 2);
+        }
+    }
+    #[test]
+    fn non_finite_correspondences() {
+        let mut scene = synthetic_scene(20, 5);
+        scene.pairs[2].1 = Unit::new_unchecked(Vector3::new(f64::NAN, 0.0, 1.0));
+        assert!(essential_five_point(&scene.pairs).is_empty());
+        assert!(essential_eight_point(&scene.pairs).is_none());
+        let all: Vec<usize> = (0..scene.pairs.len()).collect();
+        let e = essential_from_pose(&Isometry3::translation(1.0, 0.0, 0.0));
+        assert!(EssentialEstimator.refine(&scene.pairs, &all, &e).is_none());
+
+        // RANSAC on the remaining ones
+        let ransac = Ransac::new(RansacParams {
+            threshold: 1e-8,
+            ..Default::default()
+        });
+        let relative = estimate_relative_pose(&scene.pairs, &ransac).expect("A pose");
+        assert!(!relative.inliers.contains(&2));
+
+        let mut pixels: Vec<PixelPair> = (0..10)
+            .map(|i| (Point2::new(i as f64, 2.0), Point2::new(3.0, i as f64)))
+            .collect();
+        pixels[4].0.x = f64::INFINITY;
+        assert!(fundamental_eight_point(&pixels).is_none());
+    }
+}
@@ -0,0 +1,287 @@
+/// Factors - the measurements that constrain the variables of a factor graph
+///
+/// A factor contributes `0.5 * r' * W * r` to the cost, with `r` its residual and `W` its
+/// information matrix. Jacobians are with respect to the tangent spaces of the variables (see
+/// [`Variable::retract`]); factors without closed-form ones fall back to central differences.
+use crate::geometry::lie;
+use crate::graph::key::Key;
+use crate::graph::values::{GraphError, Variable};
+
+use nalgebra::{DMatrix, DVector, Isometry3, Matrix3, U3};
+use std::fmt;
+
+/// Step of the central differences of [`numerical_jacobians`]
+const NUMERICAL_STEP: f64 = 1e-6;
+
+pub trait Factor: fmt::Debug + Send + Sync {
+    /// Keys of the variables the factor depends on. The variables are passed to the other methods
+    /// in the same order
+    fn keys(&self) -> &[Key];
+
+    /// Dimension of the residual
+    fn dim(&self) -> usize;
+
+    /// Inverse of the covariance of the residual
+    fn information(&self) -> &DMatrix<f64>;
+
+    fn residual(&self, vars: &[&Variable]) -> Result<DVector<f64>, GraphError>;
+
+    /// Jacobians of the residual, one `dim x variable dim` matrix per variable
+    fn jacobians(&self, vars: &[&Variable]) -> Result<Vec<DMatrix<f64>>, GraphError> {
+        numerical_jacobians(self, vars)
+    }
+
+    /// Contribution of the factor to the cost
+    fn error(&self, vars: &[&Variable]) -> Result<f64, GraphError> {
+        let residual = self.residual(vars)?;
+        Ok(0.5 * residual.dot(&(self.information() * &residual)))
+    }
+}
+
+/// Jacobians of the residual of a factor by central differences on the tangent spaces
+pub fn numerical_jacobians<F: Factor + ?Sized>(
+    factor: &F,
+    vars: &[&Variable],
+) -> Result<Vec<DMatrix<f64>>, GraphError> {
+    let mut perturbed: Vec<Variable> = vars.iter().map(|&var| var.clone()).collect();
+    let mut jacobians = Vec::with_capacity(vars.len());
+    for (i, var) in vars.iter().enumerate() {
+        let mut jacobian = DMatrix::zeros(factor.dim(), var.dim());
+        let mut delta = vec![0.0; var.dim()];
+        for k in 0..var.dim() {
+            let mut residual = |step: f64| {
+                delta[k] = step;
+                perturbed[i] = var.retract(&delta)?;
+                let refs: Vec<&Variable> = perturbed.iter().collect();
+                factor.residual(&refs)
+            };
+            let forward = residual(NUMERICAL_STEP)?;
+            let backward = residual(-NUMERICAL_STEP)?;
+            jacobian.set_column(k, &((forward - backward) / (2.0 * NUMERICAL_STEP)));
+            delta[k] = 0.0;
+        }
+        perturbed[i] = (*var).clone();
+        jacobians.push(jacobian);
+    }
+    Ok(jacobians)
+}
+
+// -------------------------------------------------------------------------------------------------
+// PriorFactor
+// -------------------------------------------------------------------------------------------------
+
+/// Prior on a variable of any type - the residual is the tangent vector from the prior to the
+/// variable
+#[derive(Debug, Clone)]
+pub struct PriorFactor {
+    keys: [Key; 1],
+    prior: Variable,
+    information: DMatrix<f64>,
+}
+
+impl PriorFactor {
+    pub fn new<V: Into<Variable>>(key: Key, prior: V, information: DMatrix<f64>) -> Self {
+        let prior = prior.into();
+        debug_assert_eq!(information.shape(), (prior.dim(), prior.dim()));
+        Self {
+            keys: [key],
+            prior,
+            information,
+        }
+    }
+
+    pub const fn prior(&self) -> &Variable {
+        &self.prior
+    }
+}
+
+impl Factor for PriorFactor {
+    fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    fn dim(&self) -> usize {
+        self.prior.dim()
+    }
+
+    fn information(&self) -> &DMatrix<f64> {
+        &self.information
+    }
+
+    fn residual(&self, vars: &[&Variable]) -> Result<DVector<f64>, GraphError> {
+        self.prior.local(vars[0])
+    }
+
+    fn jacobians(&self, vars: &[&Variable]) -> Result<Vec<DMatrix<f64>>, GraphError> {
+        let mut jacobian = DMatrix::identity(self.dim(), self.dim());
+        if let Variable::Pose(prior) = &self.prior {
+            let pose = vars[0].as_pose()?;
+            let relative = prior.rotation.inverse() * pose.rotation;
+            jacobian
+                .fixed_slice_mut::<U3, U3>(0, 0)
+                .copy_from(&lie::right_jacobian_inverse(&lie::log(&relative)));
+            jacobian
+                .fixed_slice_mut::<U3, U3>(3, 3)
+                .copy_from(relative.to_rotation_matrix().matrix());
+        }
+        Ok(vec![jacobian])
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// BetweenFactor
+// -------------------------------------------------------------------------------------------------
+
+/// Relative pose between two poses, `T_i^-1 * T_j` - e.g. odometry or a loop closure
+#[derive(Debug, Clone)]
+pub struct BetweenFactor {
+    keys: [Key; 2],
+    measured: Isometry3<f64>,
+    information: DMatrix<f64>,
+}
+
+impl BetweenFactor {
+    pub fn new(from: Key, to: Key, measured: Isometry3<f64>, information: DMatrix<f64>) -> Self {
+        debug_assert_eq!(information.shape(), (6, 6));
+        Self {
+            keys: [from, to],
+            measured,
+            information,
+        }
+    }
+
+    pub const fn measured(&self) -> &Isometry3<f64> {
+        &self.measured
+    }
+}
+
+impl Factor for BetweenFactor {
+    fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    fn dim(&self) -> usize {
+        6
+    }
+
+    fn information(&self) -> &DMatrix<f64> {
+        &self.information
+    }
+
+    fn residual(&self, vars: &[&Variable]) -> Result<DVector<f64>, GraphError> {
+        let relative = vars[0].as_pose()?.inverse() * vars[1].as_pose()?;
+        Variable::Pose(self.measured).local(&Variable::Pose(relative))
+    }
+
+    fn jacobians(&self, vars: &[&Variable]) -> Result<Vec<DMatrix<f64>>, GraphError> {
+        let (pose_i, pose_j) = (vars[0].as_pose()?, vars[1].as_pose()?);
+        let rot_i = pose_i.rotation.to_rotation_matrix().into_inner();
+        let rot_j = pose_j.rotation.to_rotation_matrix().into_inner();
+        let rot_m = self.measured.rotation.to_rotation_matrix().into_inner();
+        let rot_ij = rot_i.transpose() * rot_j;
+
+        let error_rot = lie::log(
+            &(self.measured.rotation.inverse() * pose_i.rotation.inverse() * pose_j.rotation),
+        );
+        let jr_inv = lie::right_jacobian_inverse(&error_rot);
+        let local_j = rot_i.transpose() * (pose_j.translation.vector - pose_i.translation.vector);
+
+        let blocks_i = [
+            -jr_inv * rot_ij.transpose(),
+            Matrix3::zeros(),
+            rot_m.transpose() * lie::skew(&local_j),
+            -rot_m.transpose(),
+        ];
+        let blocks_j = [
+            jr_inv,
+            Matrix3::zeros(),
+            Matrix3::zeros(),
+            rot_m.transpose() * rot_ij,
+        ];
+        Ok(vec![pose_jacobian(&blocks_i), pose_jacobian(&blocks_j)])
+    }
+}
+
+/// 6x6 Jacobian from its `[rot/rot, rot/trans, trans/rot, trans/trans]` blocks
+fn pose_jacobian(blocks: &[Matrix3<f64>; 4]) -> DMatrix<f64> {
+    let mut jacobian = DMatrix::zeros(6, 6);
+    for (idx, block) in blocks.iter().enumerate() {
+        jacobian
+            .fixed_slice_mut::<U3, U3>(3 * (idx / 2), 3 * (idx % 2))
+            .copy_from(block);
+    }
+    jacobian
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Point3, Vector3};
+
+    fn pose(x: f64, y: f64, yaw: f64) -> Isometry3<f64> {
+        Isometry3::new(
+            Vector3::new(x, y, 0.1 * x),
+            Vector3::new(0.1 * y, -0.2, yaw),
+        )
+    }
+
+    #[test]
+    fn analytic_jacobians_match_numerical() {
+        let (a, b) = (
+            Variable::Pose(pose(1.0, 2.0, 0.3)),
+            Variable::Pose(pose(-0.5, 1.0, 2.5)),
+        );
+        let factors: Vec<Box<dyn Factor>> = vec![
+            Box::new(BetweenFactor::new(
+                Key::Pose(0),
+                Key::Pose(1),
+                pose(0.3, -1.0, 1.9),
+                DMatrix::identity(6, 6),
+            )),
+            Box::new(PriorFactor::new(
+                Key::Pose(0),
+                pose(0.8, 2.1, 0.1),
+                DMatrix::identity(6, 6),
+            )),
+            Box::new(PriorFactor::new(
+                Key::Landmark(0),
+                Point3::new(1.0, 2.0, 3.0),
+                DMatrix::identity(3, 3),
+            )),
+        ];
+        let point = Variable::Point(Point3::new(0.5, 2.5, 3.5));
+
+        for factor in &factors {
+            let vars: Vec<&Variable> = match factor.keys()[0] {
+                Key::Landmark(_) => vec![&point],
+                _ => vec![&a, &b][..factor.keys().len()].to_vec(),
+            };
+            let analytic = factor.jacobians(&vars).unwrap();
+            let numerical = numerical_jacobians(factor.as_ref(), &vars).unwrap();
+            for (analytic, numerical) in analytic.iter().zip(&numerical) {
+                assert!((analytic - numerical).norm() < 1e-7, "{:?}", factor);
+            }
+        }
+    }
+
+    #[test]
+    #[allow(clippy::suboptimal_flops)]
+    fn between_residual() {
+        let (a, b) = (pose(1.0, 2.0, 0.3), pose(-0.5, 1.0, 2.5));
+        let factor = BetweenFactor::new(
+            Key::Pose(0),
+            Key::Pose(1),
+            a.inverse() * b,
+            DMatrix::identity(6, 6) * 4.0,
+        );
+        let (a, b) = (Variable::Pose(a), Variable::Pose(b));
+        assert!(factor.residual(&[&a, &b]).unwrap().norm() < 1e-12);
+
+        // 0.1 rad of rotation error about a single axis
+        let moved = b.retract(&[0.0, 0.1, 0.0, 0.0, 0.0, 0.0]).unwrap();
+        assert!((factor.error(&[&a, &moved]).unwrap() - 0.5 * 4.0 * 0.01).abs() < 1e-12);
+
+        let point = Variable::Point(Point3::origin());
+        assert!(factor.residual(&[&a, &point]).is_err());
+    }
+}
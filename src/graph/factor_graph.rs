@@ -0,0 +1,179 @@
+/// Container of the factors of a graph
+use crate::graph::factor::Factor;
+use crate::graph::key::Key;
+use crate::graph::robust::RobustKernel;
+use crate::graph::values::{GraphError, Values};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Handle of a factor in a [`FactorGraph`]. Never reused after the factor is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FactorId(usize);
+
+#[derive(Debug, Default)]
+pub struct FactorGraph {
+    factors: BTreeMap<FactorId, Box<dyn Factor>>,
+    kernels: HashMap<FactorId, RobustKernel>,
+    /// Variables held constant by the optimisers
+    fixed: BTreeSet<Key>,
+    next_id: usize,
+}
+
+impl FactorGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<F: Factor + 'static>(&mut self, factor: F) -> FactorId {
+        self.insert_boxed(Box::new(factor))
+    }
+
+    pub fn insert_boxed(&mut self, factor: Box<dyn Factor>) -> FactorId {
+        let id = FactorId(self.next_id);
+        self.next_id += 1;
+        self.factors.insert(id, factor);
+        id
+    }
+
+    pub fn remove(&mut self, id: FactorId) -> Option<Box<dyn Factor>> {
+        self.kernels.remove(&id);
+        self.factors.remove(&id)
+    }
+
+    /// Attach a robust kernel to a factor, or detach it with `None`
+    pub fn set_kernel(&mut self, id: FactorId, kernel: Option<RobustKernel>) {
+        match kernel {
+            Some(kernel) if self.factors.contains_key(&id) => {
+                self.kernels.insert(id, kernel);
+            }
+            _ => {
+                self.kernels.remove(&id);
+            }
+        }
+    }
+
+    pub fn kernel(&self, id: FactorId) -> Option<RobustKernel> {
+        self.kernels.get(&id).copied()
+    }
+
+    /// Hold a variable constant during the optimisation, e.g. to fix the gauge freedom, or release
+    /// it
+    pub fn set_fixed(&mut self, key: Key, fixed: bool) {
+        if fixed {
+            self.fixed.insert(key);
+        } else {
+            self.fixed.remove(&key);
+        }
+    }
+
+    pub fn is_fixed(&self, key: Key) -> bool {
+        self.fixed.contains(&key)
+    }
+
+    pub fn get(&self, id: FactorId) -> Option<&dyn Factor> {
+        self.factors.get(&id).map(AsRef::as_ref)
+    }
+
+    pub fn len(&self) -> usize {
+        self.factors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.factors.is_empty()
+    }
+
+    /// Factors in the order of insertion
+    pub fn iter(&self) -> impl Iterator<Item = (FactorId, &dyn Factor)> {
+        self.factors
+            .iter()
+            .map(|(id, factor)| (*id, factor.as_ref()))
+    }
+
+    /// Keys of all the variables constrained by the graph
+    pub fn keys(&self) -> BTreeSet<Key> {
+        self.factors
+            .values()
+            .flat_map(|factor| factor.keys().iter().copied())
+            .collect()
+    }
+
+    /// Factors that depend on the variable
+    pub fn factors_of(&self, key: Key) -> Vec<FactorId> {
+        self.iter()
+            .filter(|(_, factor)| factor.keys().contains(&key))
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Total cost of the graph at the given values, through the robust kernels of the factors
+    pub fn error(&self, values: &Values) -> Result<f64, GraphError> {
+        self.iter().try_fold(0.0, |cost, (id, factor)| {
+            let error = factor.error(&values.gather(factor.keys())?)?;
+            Ok(cost
+                + self
+                    .kernel(id)
+                    .map_or(error, |kernel| 0.5 * kernel.rho(2.0 * error)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::factor::{BetweenFactor, PriorFactor};
+    use nalgebra::{DMatrix, Isometry3, Vector3};
+
+    #[test]
+    fn insert_and_remove_factors() {
+        let step = Isometry3::translation(1.0, 0.0, 0.0);
+        let mut graph = FactorGraph::new();
+        let prior = graph.insert(PriorFactor::new(
+            Key::Pose(0),
+            Isometry3::identity(),
+            DMatrix::identity(6, 6),
+        ));
+        let odometry: Vec<FactorId> = (0..3)
+            .map(|i| {
+                graph.insert(BetweenFactor::new(
+                    Key::Pose(i),
+                    Key::Pose(i + 1),
+                    step,
+                    DMatrix::identity(6, 6),
+                ))
+            })
+            .collect();
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.keys().len(), 4);
+        assert_eq!(graph.factors_of(Key::Pose(1)), odometry[..2].to_vec());
+
+        let mut values = Values::new();
+        for i in 0..4 {
+            values.insert(Key::Pose(i), Isometry3::translation(i as f64, 0.0, 0.0));
+        }
+        assert!(graph.error(&values).unwrap() < 1e-20);
+        values.insert(Key::Pose(3), Isometry3::translation(3.0, 0.5, 0.0));
+        assert!((graph.error(&values).unwrap() - 0.125).abs() < 1e-12);
+        values.remove(Key::Pose(3));
+        assert_eq!(
+            graph.error(&values),
+            Err(GraphError::MissingVariable(Key::Pose(3)))
+        );
+
+        assert!(graph.remove(prior).is_some());
+        assert!(graph.remove(prior).is_none());
+        assert!(graph.get(odometry[2]).is_some());
+        let removed = graph.insert(PriorFactor::new(
+            Key::Velocity(0),
+            Vector3::zeros(),
+            DMatrix::identity(3, 3),
+        ));
+        // ids aren't reused
+        assert_ne!(removed, prior);
+        assert_eq!(graph.len(), 4);
+
+        graph.set_fixed(Key::Pose(0), true);
+        assert!(graph.is_fixed(Key::Pose(0)) && !graph.is_fixed(Key::Pose(1)));
+        graph.set_fixed(Key::Pose(0), false);
+        assert!(!graph.is_fixed(Key::Pose(0)));
+    }
+}
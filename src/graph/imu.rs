@@ -0,0 +1,571 @@
+/// IMU preintegration and the inertial factor between two keyframes
+///
+/// "On-Manifold Preintegration for Real-Time Visual-Inertial Odometry", Forster et al., 2017. The
+/// measurements between two keyframes are integrated once, in the frame of the first one, into
+/// the deltas of rotation, velocity and position. Changes of the bias estimate are applied to the
+/// deltas to first order through their bias Jacobians, without integrating again.
+///
+/// The world frame is gravity aligned, and the poses are the poses of the IMU - the body frame.
+use crate::drivers::ImuCalibration;
+use crate::geometry::lie;
+use crate::graph::factor::Factor;
+use crate::graph::key::Key;
+use crate::graph::values::{GraphError, ImuBias, Variable};
+
+use nalgebra::{DMatrix, DVector, Matrix3, MatrixN, UnitQuaternion, Vector3, U3, U9};
+
+/// Standard gravity, in m / s^2
+pub const GRAVITY: f64 = 9.81;
+
+type Matrix9 = MatrixN<f64, U9>;
+
+/// Rotation, velocity and position
+type State = (UnitQuaternion<f64>, Vector3<f64>, Vector3<f64>);
+
+/// Continuous-time noise model of an IMU, as given by the `sensor.yaml` of the `EuRoC` datasets
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImuNoise {
+    /// rad / s / sqrt(Hz)
+    pub gyro_noise_density: f64,
+    /// m / s^2 / sqrt(Hz)
+    pub accel_noise_density: f64,
+    /// rad / s^2 / sqrt(Hz)
+    pub gyro_random_walk: f64,
+    /// m / s^3 / sqrt(Hz)
+    pub accel_random_walk: f64,
+}
+
+impl Default for ImuNoise {
+    /// The ADIS16448 of the `EuRoC` datasets
+    fn default() -> Self {
+        Self {
+            gyro_noise_density: 1.6968e-4,
+            accel_noise_density: 2.0e-3,
+            gyro_random_walk: 1.9393e-5,
+            accel_random_walk: 3.0e-3,
+        }
+    }
+}
+
+impl ImuNoise {
+    pub const fn from_calibration(calibration: &ImuCalibration) -> Self {
+        Self {
+            gyro_noise_density: calibration.gyroscope_noise_density,
+            accel_noise_density: calibration.accelerometer_noise_density,
+            gyro_random_walk: calibration.gyroscope_random_walk,
+            accel_random_walk: calibration.accelerometer_random_walk,
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// PreintegratedImu
+// -------------------------------------------------------------------------------------------------
+
+/// IMU measurements integrated between two keyframes `i` and `j`
+#[derive(Debug, Clone)]
+pub struct PreintegratedImu {
+    noise: ImuNoise,
+    /// Bias the measurements were integrated with
+    bias: ImuBias,
+    delta_t: f64,
+    delta_rotation: UnitQuaternion<f64>,
+    delta_velocity: Vector3<f64>,
+    delta_position: Vector3<f64>,
+    /// Covariance of the deltas, ordered as `[rotation, velocity, position]`
+    covariance: Matrix9,
+    d_rotation_d_gyro: Matrix3<f64>,
+    d_velocity_d_gyro: Matrix3<f64>,
+    d_velocity_d_accel: Matrix3<f64>,
+    d_position_d_gyro: Matrix3<f64>,
+    d_position_d_accel: Matrix3<f64>,
+}
+
+impl PreintegratedImu {
+    /// Start integrating at keyframe `i`, with the current estimate of the bias
+    pub fn new(bias: ImuBias, noise: ImuNoise) -> Self {
+        Self {
+            noise,
+            bias,
+            delta_t: 0.0,
+            delta_rotation: UnitQuaternion::identity(),
+            delta_velocity: Vector3::zeros(),
+            delta_position: Vector3::zeros(),
+            covariance: Matrix9::zeros(),
+            d_rotation_d_gyro: Matrix3::zeros(),
+            d_velocity_d_gyro: Matrix3::zeros(),
+            d_velocity_d_accel: Matrix3::zeros(),
+            d_position_d_gyro: Matrix3::zeros(),
+            d_position_d_accel: Matrix3::zeros(),
+        }
+    }
+
+    /// Integrate a measurement of the angular velocity and of the specific force, held for `dt`
+    /// seconds
+    pub fn integrate(&mut self, gyro: &Vector3<f64>, accel: &Vector3<f64>, dt: f64) {
+        let omega = gyro - self.bias.gyro;
+        let accel = accel - self.bias.accel;
+        let step = lie::exp(&(omega * dt));
+        let step_matrix = step.to_rotation_matrix().into_inner();
+        let right_jacobian = lie::right_jacobian(&(omega * dt));
+        let rotation = self.delta_rotation.to_rotation_matrix().into_inner();
+        let rotated_skew = rotation * lie::skew(&accel);
+        let dt2 = dt * dt;
+
+        // noise propagation
+        let mut a = Matrix9::identity();
+        a.fixed_slice_mut::<U3, U3>(0, 0)
+            .copy_from(&step_matrix.transpose());
+        a.fixed_slice_mut::<U3, U3>(3, 0)
+            .copy_from(&(-rotated_skew * dt));
+        a.fixed_slice_mut::<U3, U3>(6, 0)
+            .copy_from(&(-rotated_skew * (0.5 * dt2)));
+        a.fixed_slice_mut::<U3, U3>(6, 3)
+            .copy_from(&(Matrix3::identity() * dt));
+        // discrete noise of the measurements, from the continuous densities
+        let gyro_variance = self.noise.gyro_noise_density.powi(2) / dt;
+        let accel_variance = self.noise.accel_noise_density.powi(2) / dt;
+        let b_gyro = right_jacobian * dt;
+        let (c_velocity, c_position) = (rotation * dt, rotation * (0.5 * dt2));
+        let mut noise = Matrix9::zeros();
+        noise
+            .fixed_slice_mut::<U3, U3>(0, 0)
+            .copy_from(&(b_gyro * b_gyro.transpose() * gyro_variance));
+        noise
+            .fixed_slice_mut::<U3, U3>(3, 3)
+            .copy_from(&(c_velocity * c_velocity.transpose() * accel_variance));
+        noise
+            .fixed_slice_mut::<U3, U3>(3, 6)
+            .copy_from(&(c_velocity * c_position.transpose() * accel_variance));
+        noise
+            .fixed_slice_mut::<U3, U3>(6, 3)
+            .copy_from(&(c_position * c_velocity.transpose() * accel_variance));
+        noise
+            .fixed_slice_mut::<U3, U3>(6, 6)
+            .copy_from(&(c_position * c_position.transpose() * accel_variance));
+        self.covariance = a * self.covariance * a.transpose() + noise;
+
+        // bias Jacobians, with the deltas before the update
+        self.d_position_d_accel += self.d_velocity_d_accel * dt - rotation * (0.5 * dt2);
+        self.d_position_d_gyro +=
+            self.d_velocity_d_gyro * dt - rotated_skew * self.d_rotation_d_gyro * (0.5 * dt2);
+        self.d_velocity_d_accel -= rotation * dt;
+        self.d_velocity_d_gyro -= rotated_skew * self.d_rotation_d_gyro * dt;
+        self.d_rotation_d_gyro =
+            step_matrix.transpose() * self.d_rotation_d_gyro - right_jacobian * dt;
+
+        // deltas
+        let rotated_accel = self.delta_rotation * accel;
+        self.delta_position += self.delta_velocity * dt + rotated_accel * (0.5 * dt2);
+        self.delta_velocity += rotated_accel * dt;
+        self.delta_rotation *= step;
+        self.delta_t += dt;
+    }
+
+    pub const fn bias(&self) -> &ImuBias {
+        &self.bias
+    }
+
+    pub const fn delta_t(&self) -> f64 {
+        self.delta_t
+    }
+
+    pub const fn covariance(&self) -> &Matrix9 {
+        &self.covariance
+    }
+
+    /// Jacobian of the delta rotation with respect to the gyroscope bias
+    pub const fn rotation_gyro_jacobian(&self) -> &Matrix3<f64> {
+        &self.d_rotation_d_gyro
+    }
+
+    /// Deltas of rotation, velocity and position, corrected to first order for another bias
+    pub fn deltas(&self, bias: &ImuBias) -> State {
+        let (gyro, accel) = (bias.gyro - self.bias.gyro, bias.accel - self.bias.accel);
+        (
+            self.delta_rotation * lie::exp(&(self.d_rotation_d_gyro * gyro)),
+            self.delta_velocity + self.d_velocity_d_gyro * gyro + self.d_velocity_d_accel * accel,
+            self.delta_position + self.d_position_d_gyro * gyro + self.d_position_d_accel * accel,
+        )
+    }
+
+    /// Rotation, velocity and position of the body at `j`, given its state at `i`
+    pub fn predict(
+        &self,
+        rotation: &UnitQuaternion<f64>,
+        velocity: &Vector3<f64>,
+        position: &Vector3<f64>,
+        bias: &ImuBias,
+        gravity: &Vector3<f64>,
+    ) -> State {
+        let (delta_rotation, delta_velocity, delta_position) = self.deltas(bias);
+        let t = self.delta_t;
+        (
+            rotation * delta_rotation,
+            velocity + gravity * t + rotation * delta_velocity,
+            position + velocity * t + gravity * (0.5 * t * t) + rotation * delta_position,
+        )
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// ImuFactor
+// -------------------------------------------------------------------------------------------------
+
+/// Preintegrated IMU measurements between the states of two keyframes, with the bias of the first
+///
+/// The residual `[r_rotation; r_velocity; r_position]` compares the deltas, corrected for the
+/// bias, with the ones of the states:
+///
+/// - `Log(dR' * R_i' * R_j)`
+/// - `R_i' * (v_j - v_i - g * dt) - dv`
+/// - `R_i' * (p_j - p_i - v_i * dt - g * dt^2 / 2) - dp`
+#[derive(Debug, Clone)]
+pub struct ImuFactor {
+    /// `[pose_i, velocity_i, pose_j, velocity_j, bias_i]`
+    keys: [Key; 5],
+    preintegrated: PreintegratedImu,
+    gravity: Vector3<f64>,
+    information: DMatrix<f64>,
+}
+
+impl ImuFactor {
+    /// Fails if the covariance of the preintegration isn't positive definite, e.g. when nothing
+    /// was integrated or the noise is zero
+    pub fn new(
+        pose_i: Key,
+        velocity_i: Key,
+        pose_j: Key,
+        velocity_j: Key,
+        bias_i: Key,
+        preintegrated: PreintegratedImu,
+        gravity: Vector3<f64>,
+    ) -> Result<Self, GraphError> {
+        let covariance = DMatrix::from_column_slice(9, 9, preintegrated.covariance.as_slice());
+        let information = covariance
+            .cholesky()
+            .ok_or(GraphError::IndefiniteCovariance)?
+            .inverse();
+        Ok(Self {
+            keys: [pose_i, velocity_i, pose_j, velocity_j, bias_i],
+            preintegrated,
+            gravity,
+            information,
+        })
+    }
+
+    pub const fn preintegrated(&self) -> &PreintegratedImu {
+        &self.preintegrated
+    }
+
+    /// Rotation, velocity and position errors
+    fn errors(&self, vars: &[&Variable]) -> Result<[Vector3<f64>; 3], GraphError> {
+        let (pose_i, velocity_i) = (vars[0].as_pose()?, vars[1].as_velocity()?);
+        let (pose_j, velocity_j) = (vars[2].as_pose()?, vars[3].as_velocity()?);
+        let (delta_rotation, delta_velocity, delta_position) =
+            self.preintegrated.deltas(vars[4].as_bias()?);
+        let t = self.preintegrated.delta_t;
+        let inverse_i = pose_i.rotation.inverse();
+        let (p_i, p_j) = (pose_i.translation.vector, pose_j.translation.vector);
+
+        let rotation = lie::log(&(delta_rotation.inverse() * inverse_i * pose_j.rotation));
+        let velocity = inverse_i * (velocity_j - velocity_i - self.gravity * t) - delta_velocity;
+        let position = inverse_i * (p_j - p_i - velocity_i * t - self.gravity * (0.5 * t * t))
+            - delta_position;
+        Ok([rotation, velocity, position])
+    }
+}
+
+impl Factor for ImuFactor {
+    fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    fn dim(&self) -> usize {
+        9
+    }
+
+    fn information(&self) -> &DMatrix<f64> {
+        &self.information
+    }
+
+    fn residual(&self, vars: &[&Variable]) -> Result<DVector<f64>, GraphError> {
+        let [rotation, velocity, position] = self.errors(vars)?;
+        Ok(DVector::from_iterator(
+            9,
+            rotation.iter().chain(&velocity).chain(&position).copied(),
+        ))
+    }
+
+    fn jacobians(&self, vars: &[&Variable]) -> Result<Vec<DMatrix<f64>>, GraphError> {
+        let [rotation_error, _, _] = self.errors(vars)?;
+        let (pose_i, velocity_i) = (vars[0].as_pose()?, vars[1].as_velocity()?);
+        let (pose_j, velocity_j) = (vars[2].as_pose()?, vars[3].as_velocity()?);
+        let bias = vars[4].as_bias()?;
+        let pre = &self.preintegrated;
+        let t = pre.delta_t;
+        let r_i = pose_i.rotation.to_rotation_matrix().into_inner();
+        let r_j = pose_j.rotation.to_rotation_matrix().into_inner();
+        let (p_i, p_j) = (pose_i.translation.vector, pose_j.translation.vector);
+        let jr_inv = lie::right_jacobian_inverse(&rotation_error);
+        let gyro_correction = pre.d_rotation_d_gyro * (bias.gyro - pre.bias.gyro);
+
+        let mut d_pose_i = DMatrix::zeros(9, 6);
+        let mut d_velocity_i = DMatrix::zeros(9, 3);
+        let mut d_pose_j = DMatrix::zeros(9, 6);
+        let mut d_velocity_j = DMatrix::zeros(9, 3);
+        let mut d_bias = DMatrix::zeros(9, 6);
+        let set = |jacobian: &mut DMatrix<f64>, row: usize, col: usize, block: Matrix3<f64>| {
+            jacobian
+                .fixed_slice_mut::<U3, U3>(row, col)
+                .copy_from(&block);
+        };
+
+        set(&mut d_pose_i, 0, 0, -jr_inv * r_j.transpose() * r_i);
+        set(
+            &mut d_pose_i,
+            3,
+            0,
+            lie::skew(&(r_i.transpose() * (velocity_j - velocity_i - self.gravity * t))),
+        );
+        set(
+            &mut d_pose_i,
+            6,
+            0,
+            lie::skew(
+                &(r_i.transpose() * (p_j - p_i - velocity_i * t - self.gravity * (0.5 * t * t))),
+            ),
+        );
+        set(&mut d_pose_i, 6, 3, -Matrix3::identity());
+        set(&mut d_velocity_i, 3, 0, -r_i.transpose());
+        set(&mut d_velocity_i, 6, 0, -r_i.transpose() * t);
+        set(&mut d_pose_j, 0, 0, jr_inv);
+        set(&mut d_pose_j, 6, 3, r_i.transpose() * r_j);
+        set(&mut d_velocity_j, 3, 0, r_i.transpose());
+
+        let exp_error = lie::exp(&rotation_error).to_rotation_matrix().into_inner();
+        set(
+            &mut d_bias,
+            0,
+            0,
+            -jr_inv
+                * exp_error.transpose()
+                * lie::right_jacobian(&gyro_correction)
+                * pre.d_rotation_d_gyro,
+        );
+        set(&mut d_bias, 3, 0, -pre.d_velocity_d_gyro);
+        set(&mut d_bias, 3, 3, -pre.d_velocity_d_accel);
+        set(&mut d_bias, 6, 0, -pre.d_position_d_gyro);
+        set(&mut d_bias, 6, 3, -pre.d_position_d_accel);
+
+        Ok(vec![d_pose_i, d_velocity_i, d_pose_j, d_velocity_j, d_bias])
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// BiasRandomWalkFactor
+// -------------------------------------------------------------------------------------------------
+
+/// Evolution of the IMU bias between two keyframes, modelled as a random walk
+///
+/// The residual is `b_j - b_i`, with the covariance of the walk after `dt` seconds,
+/// `sigma_rw^2 * dt`, for the gyroscope and the accelerometer.
+#[derive(Debug, Clone)]
+pub struct BiasRandomWalkFactor {
+    keys: [Key; 2],
+    information: DMatrix<f64>,
+}
+
+impl BiasRandomWalkFactor {
+    pub fn new(bias_i: Key, bias_j: Key, dt: f64, noise: &ImuNoise) -> Self {
+        let gyro = 1.0 / (noise.gyro_random_walk.powi(2) * dt);
+        let accel = 1.0 / (noise.accel_random_walk.powi(2) * dt);
+        let information = DMatrix::from_diagonal(&DVector::from_column_slice(&[
+            gyro, gyro, gyro, accel, accel, accel,
+        ]));
+        Self {
+            keys: [bias_i, bias_j],
+            information,
+        }
+    }
+}
+
+impl Factor for BiasRandomWalkFactor {
+    fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    fn dim(&self) -> usize {
+        6
+    }
+
+    fn information(&self) -> &DMatrix<f64> {
+        &self.information
+    }
+
+    fn residual(&self, vars: &[&Variable]) -> Result<DVector<f64>, GraphError> {
+        let (bias_i, bias_j) = (vars[0].as_bias()?, vars[1].as_bias()?);
+        let (gyro, accel) = (bias_j.gyro - bias_i.gyro, bias_j.accel - bias_i.accel);
+        Ok(DVector::from_iterator(
+            6,
+            gyro.iter().chain(&accel).copied(),
+        ))
+    }
+
+    fn jacobians(&self, _vars: &[&Variable]) -> Result<Vec<DMatrix<f64>>, GraphError> {
+        Ok(vec![-DMatrix::identity(6, 6), DMatrix::identity(6, 6)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::factor::numerical_jacobians;
+    use nalgebra::{Isometry3, Translation3};
+
+    const OMEGA: [f64; 3] = [0.3, -0.2, 0.5];
+
+    /// Body rotating at a constant rate while moving along a smooth curve
+    fn state(t: f64) -> (UnitQuaternion<f64>, Vector3<f64>, Vector3<f64>) {
+        let rotation = UnitQuaternion::from_euler_angles(0.1, -0.2, 0.3)
+            * lie::exp(&(Vector3::from(OMEGA) * t));
+        let position = Vector3::new(t.sin(), (2.0 * t).cos(), 0.5 * t * t);
+        let velocity = Vector3::new(t.cos(), -2.0 * (2.0 * t).sin(), t);
+        (rotation, velocity, position)
+    }
+
+    /// Noiseless measurements at `t`, for the given bias
+    fn measurements(t: f64, bias: &ImuBias) -> (Vector3<f64>, Vector3<f64>) {
+        let (rotation, _, _) = state(t);
+        let acceleration = Vector3::new(-t.sin(), -4.0 * (2.0 * t).cos(), 1.0);
+        let gravity = Vector3::new(0.0, 0.0, -GRAVITY);
+        (
+            Vector3::from(OMEGA) + bias.gyro,
+            rotation.inverse() * (acceleration - gravity) + bias.accel,
+        )
+    }
+
+    fn preintegrate(bias: &ImuBias, estimate: ImuBias, duration: f64) -> PreintegratedImu {
+        let dt = 1e-4;
+        let mut preintegrated = PreintegratedImu::new(estimate, ImuNoise::default());
+        let steps = (duration / dt).round() as usize;
+        for k in 0..steps {
+            // midpoint of the interval
+            let (gyro, accel) = measurements((k as f64 + 0.5) * dt, bias);
+            preintegrated.integrate(&gyro, &accel, dt);
+        }
+        preintegrated
+    }
+
+    fn variables(t: f64) -> (Variable, Variable) {
+        let (rotation, velocity, position) = state(t);
+        (
+            Variable::Pose(Isometry3::from_parts(
+                Translation3::from(position),
+                rotation,
+            )),
+            Variable::Velocity(velocity),
+        )
+    }
+
+    #[test]
+    fn preintegration_predicts_the_motion() {
+        let bias = ImuBias {
+            gyro: Vector3::new(0.01, -0.02, 0.005),
+            accel: Vector3::new(0.1, 0.05, -0.2),
+        };
+        let preintegrated = preintegrate(&bias, bias, 1.0);
+        let (rotation, velocity, position) = state(0.0);
+        let gravity = Vector3::new(0.0, 0.0, -GRAVITY);
+        let predicted = preintegrated.predict(&rotation, &velocity, &position, &bias, &gravity);
+        let expected = state(1.0);
+        assert!((predicted.0.inverse() * expected.0).angle() < 1e-6);
+        // the specific force is held over each step, so the error is first order in the step
+        assert!((predicted.1 - expected.1).norm() < 1e-3);
+        assert!((predicted.2 - expected.2).norm() < 1e-3);
+
+        // the uncertainty grows with the integration time
+        let covariance = preintegrated.covariance();
+        let half = preintegrate(&bias, bias, 0.5);
+        assert!(covariance.trace() > half.covariance().trace());
+        assert!(covariance.symmetric_eigenvalues().min() > 0.0);
+
+        // first-order bias correction, against integrating with the right bias
+        let estimate = ImuBias {
+            gyro: bias.gyro + Vector3::new(2e-3, 1e-3, -1e-3),
+            accel: bias.accel + Vector3::new(-0.02, 0.01, 0.02),
+        };
+        let corrected = preintegrate(&bias, estimate, 1.0).deltas(&bias);
+        let exact = preintegrated.deltas(&bias);
+        assert!((corrected.0.inverse() * exact.0).angle() < 1e-5);
+        assert!((corrected.1 - exact.1).norm() < 1e-4);
+        assert!((corrected.2 - exact.2).norm() < 1e-4);
+    }
+
+    #[test]
+    fn factor_residual_and_jacobians() {
+        let bias = ImuBias {
+            gyro: Vector3::new(0.01, -0.02, 0.005),
+            accel: Vector3::new(0.1, 0.05, -0.2),
+        };
+        let factor = ImuFactor::new(
+            Key::Pose(0),
+            Key::Velocity(0),
+            Key::Pose(1),
+            Key::Velocity(1),
+            Key::Bias(0),
+            preintegrate(&bias, ImuBias::default(), 0.5),
+            Vector3::new(0.0, 0.0, -GRAVITY),
+        )
+        .unwrap();
+        let ((pose_i, velocity_i), (pose_j, velocity_j)) = (variables(0.0), variables(0.5));
+        let bias = Variable::Bias(bias);
+        let vars = [&pose_i, &velocity_i, &pose_j, &velocity_j, &bias];
+        assert!(factor.residual(&vars).unwrap().norm() < 1e-3);
+
+        let perturbed: Vec<Variable> = vars
+            .iter()
+            .map(|var| var.retract(&[0.05, -0.1, 0.2, 0.1, 0.05, -0.03][..var.dim()]))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let vars: Vec<&Variable> = perturbed.iter().collect();
+        let analytic = factor.jacobians(&vars).unwrap();
+        let numerical = numerical_jacobians(&factor, &vars).unwrap();
+        for (a, n) in analytic.iter().zip(&numerical) {
+            assert!((a - n).norm() < 1e-6 * n.norm().max(1.0), "{} vs {}", a, n);
+        }
+        // nothing integrated - no information on the deltas
+        let empty = PreintegratedImu::new(ImuBias::default(), ImuNoise::default());
+        let factor = ImuFactor::new(
+            Key::Pose(0),
+            Key::Velocity(0),
+            Key::Pose(1),
+            Key::Velocity(1),
+            Key::Bias(0),
+            empty,
+            Vector3::zeros(),
+        );
+        assert_eq!(factor.unwrap_err(), GraphError::IndefiniteCovariance);
+    }
+
+    #[test]
+    fn bias_random_walk() {
+        let noise = ImuNoise::default();
+        let factor = BiasRandomWalkFactor::new(Key::Bias(0), Key::Bias(1), 0.25, &noise);
+        let expected = 1.0 / (noise.accel_random_walk.powi(2) * 0.25);
+        assert!((factor.information()[(5, 5)] - expected).abs() < 1e-9 * expected);
+
+        let bias_i = Variable::Bias(ImuBias::default());
+        let bias_j = Variable::Bias(ImuBias {
+            gyro: Vector3::new(1e-3, 0.0, -2e-3),
+            accel: Vector3::new(0.01, 0.02, 0.0),
+        });
+        let vars = [&bias_i, &bias_j];
+        assert_eq!(factor.residual(&vars).unwrap()[3], 0.01);
+        let analytic = factor.jacobians(&vars).unwrap();
+        let numerical = numerical_jacobians(&factor, &vars).unwrap();
+        for (a, n) in analytic.iter().zip(&numerical) {
+            assert!((a - n).norm() < 1e-6);
+        }
+    }
+}
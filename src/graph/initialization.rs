@@ -0,0 +1,541 @@
+/// Initialisation of the inertial states
+///
+/// The stationary segment at the start of an IMU stream gives the direction of gravity, and so
+/// the roll and pitch of the body, and the bias of the gyroscope. The yaw isn't observable and is
+/// set to zero.
+///
+/// A monocular visual trajectory is only known up to scale, in a frame with an arbitrary
+/// orientation. Aligning it with the preintegrated IMU between its keyframes gives the bias of the
+/// gyroscope, the metric scale, gravity and the velocities ("VINS-Mono", Qin et al., 2018).
+use crate::geometry::lie;
+use crate::geometry::linalg::tangent_basis;
+use crate::graph::imu::{PreintegratedImu, GRAVITY};
+use crate::graph::values::ImuBias;
+use crate::utils::ImuSample;
+
+use nalgebra::{
+    DMatrix, DVector, Isometry3, Matrix3, Translation3, UnitQuaternion, Vector3, U2, U3,
+};
+use thiserror::Error;
+
+/// Errors of the initialisation of the inertial states
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum InitializationError {
+    #[error("Expected at least {expected} IMU samples, found {found}")]
+    NotEnoughSamples { expected: usize, found: usize },
+    #[error("The IMU is stationary for {0:.3}s only")]
+    NotStationary(f64),
+    #[error("Not enough excitation of the accelerometer: {0:.3} m/s^2")]
+    InsufficientExcitation(f64),
+    #[error("Invalid scale of the visual trajectory: {0}")]
+    InvalidScale(f64),
+    #[error("Estimated gravity of {0:.3} m/s^2")]
+    InvalidGravity(f64),
+    #[error("Expected one preintegration per pair of poses, found {preintegrated} for {poses}")]
+    PreintegrationCount { poses: usize, preintegrated: usize },
+}
+
+// -------------------------------------------------------------------------------------------------
+// Static initialisation
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct StaticInitParams {
+    /// Length of the windows tested for motion (seconds)
+    pub window: f64,
+    /// Maximum standard deviation of the specific force in a stationary window (m / s^2)
+    pub max_accel_deviation: f64,
+    /// Maximum standard deviation of the angular velocity in a stationary window (rad / s)
+    pub max_gyro_deviation: f64,
+    /// Minimum length of the stationary segment (seconds)
+    pub min_duration: f64,
+}
+
+impl Default for StaticInitParams {
+    fn default() -> Self {
+        Self {
+            window: 0.2,
+            max_accel_deviation: 0.1,
+            max_gyro_deviation: 0.02,
+            min_duration: 1.0,
+        }
+    }
+}
+
+/// Inertial states estimated from the stationary segment at the start of the stream
+#[derive(Debug, Clone)]
+pub struct StaticInitialization {
+    /// Number of samples of the stationary segment
+    pub n_samples: usize,
+    /// Length of the stationary segment (seconds)
+    pub duration: f64,
+    /// Rotation of the body in the gravity-aligned world frame, with zero yaw
+    pub rotation: UnitQuaternion<f64>,
+    /// Gravity in the body frame
+    pub gravity: Vector3<f64>,
+    /// The accelerometer bias can't be told apart from the tilt and is left to zero
+    pub bias: ImuBias,
+}
+
+/// Standard deviation of a set of vectors, as the root mean squared distance from their mean
+fn deviation<'a>(vectors: impl Iterator<Item = &'a Vector3<f64>> + Clone) -> f64 {
+    let n = vectors.clone().count() as f64;
+    let mean = vectors.clone().sum::<Vector3<f64>>() / n;
+    (vectors.map(|v| (v - mean).norm_squared()).sum::<f64>() / n).sqrt()
+}
+
+fn seconds(samples: &[ImuSample]) -> f64 {
+    samples.last().map_or(0.0, |last| {
+        (last.timestamp - samples[0].timestamp).as_secs_f64()
+    })
+}
+
+/// Detect the stationary segment at the start of `samples` and estimate the attitude and the
+/// gyroscope bias from it
+///
+/// The samples are split in windows of `params.window` seconds, and the segment ends at the first
+/// one where either sensor varies more than its threshold.
+pub fn static_initialization(
+    samples: &[ImuSample],
+    params: &StaticInitParams,
+) -> Result<StaticInitialization, InitializationError> {
+    if samples.len() < 2 {
+        return Err(InitializationError::NotEnoughSamples {
+            expected: 2,
+            found: samples.len(),
+        });
+    }
+
+    let mut end = 0;
+    while end < samples.len() {
+        let start = samples[end].timestamp.as_secs_f64();
+        let len = samples[end..]
+            .iter()
+            .take_while(|sample| sample.timestamp.as_secs_f64() - start < params.window)
+            .count();
+        let window = &samples[end..end + len];
+        if len < 2
+            || deviation(window.iter().map(|sample| &sample.accel)) > params.max_accel_deviation
+            || deviation(window.iter().map(|sample| &sample.gyro)) > params.max_gyro_deviation
+        {
+            break;
+        }
+        end += len;
+    }
+
+    let stationary = &samples[..end];
+    let duration = seconds(stationary);
+    if duration < params.min_duration {
+        return Err(InitializationError::NotStationary(duration));
+    }
+
+    let n = stationary.len() as f64;
+    let accel = stationary
+        .iter()
+        .map(|sample| sample.accel)
+        .sum::<Vector3<f64>>()
+        / n;
+    let gyro = stationary
+        .iter()
+        .map(|sample| sample.gyro)
+        .sum::<Vector3<f64>>()
+        / n;
+    // at rest, the specific force points up, along the z axis of the world
+    let tilt = UnitQuaternion::rotation_between(&accel, &Vector3::z())
+        .unwrap_or_else(|| UnitQuaternion::from_euler_angles(std::f64::consts::PI, 0.0, 0.0));
+    let (roll, pitch, _) = tilt.euler_angles();
+    let rotation = UnitQuaternion::from_euler_angles(roll, pitch, 0.0);
+
+    Ok(StaticInitialization {
+        n_samples: stationary.len(),
+        duration,
+        rotation,
+        gravity: -accel,
+        bias: ImuBias {
+            gyro,
+            accel: Vector3::zeros(),
+        },
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+// Visual-inertial initialisation
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct VisualInertialParams {
+    /// Minimum standard deviation of the mean specific forces between the keyframes, in the
+    /// visual frame (m / s^2)
+    pub min_accel_deviation: f64,
+    /// Maximum relative error of the magnitude of the unconstrained gravity estimate
+    pub gravity_tolerance: f64,
+    pub gyro_iterations: usize,
+    /// Iterations refining the direction of gravity, with its magnitude fixed
+    pub gravity_iterations: usize,
+}
+
+impl Default for VisualInertialParams {
+    fn default() -> Self {
+        Self {
+            min_accel_deviation: 0.25,
+            gravity_tolerance: 0.1,
+            gyro_iterations: 3,
+            gravity_iterations: 4,
+        }
+    }
+}
+
+/// Metric inertial states of the keyframes of the visual trajectory
+#[derive(Debug, Clone)]
+pub struct VisualInertialInitialization {
+    /// Multiplies the translations of the visual trajectory
+    pub scale: f64,
+    /// Rotation from the visual frame to the gravity-aligned world frame, with zero yaw
+    pub rotation: UnitQuaternion<f64>,
+    /// Gravity in the visual frame
+    pub gravity: Vector3<f64>,
+    /// Poses of the body in the world frame, `T_WB`
+    pub poses: Vec<Isometry3<f64>>,
+    /// Velocities of the body in the world frame
+    pub velocities: Vec<Vector3<f64>>,
+    /// The accelerometer bias is the one of the preintegration
+    pub bias: ImuBias,
+}
+
+/// Estimate the scale, gravity, velocities and gyroscope bias of a visual trajectory
+///
+/// `poses` are the camera poses `T_VC` of the keyframes, up to scale, and `preintegrated` the IMU
+/// between consecutive keyframes. `t_bs` is the pose of the camera in the body frame, with a
+/// metric translation.
+pub fn visual_inertial_initialization(
+    poses: &[Isometry3<f64>],
+    preintegrated: &[PreintegratedImu],
+    t_bs: &Isometry3<f64>,
+    params: &VisualInertialParams,
+) -> Result<VisualInertialInitialization, InitializationError> {
+    if poses.len() != preintegrated.len() + 1 {
+        return Err(InitializationError::PreintegrationCount {
+            poses: poses.len(),
+            preintegrated: preintegrated.len(),
+        });
+    }
+    if poses.len() < 3 {
+        return Err(InitializationError::NotEnoughSamples {
+            expected: 3,
+            found: poses.len(),
+        });
+    }
+
+    let t_sb = t_bs.inverse();
+    let rotations: Vec<UnitQuaternion<f64>> = poses
+        .iter()
+        .map(|pose| pose.rotation * t_sb.rotation)
+        .collect();
+
+    // without accelerations, the specific force is gravity alone and the scale isn't observable
+    let mean_accels: Vec<Vector3<f64>> = preintegrated
+        .iter()
+        .zip(&rotations)
+        .map(|(pre, rotation)| rotation * pre.deltas(pre.bias()).1 / pre.delta_t())
+        .collect();
+    let excitation = deviation(mean_accels.iter());
+    if excitation < params.min_accel_deviation {
+        return Err(InitializationError::InsufficientExcitation(excitation));
+    }
+    let bias = gyro_bias(&rotations, preintegrated, params.gyro_iterations);
+
+    // body positions s * c + d, with the metric lever arm d of the camera
+    let centres: Vec<Vector3<f64>> = poses.iter().map(|pose| pose.translation.vector).collect();
+    let arms: Vec<Vector3<f64>> = poses
+        .iter()
+        .map(|pose| pose.rotation * t_sb.translation.vector)
+        .collect();
+    let alignment = Alignment {
+        rotations: &rotations,
+        centres: &centres,
+        arms: &arms,
+        preintegrated,
+        bias: &bias,
+    };
+
+    let (_, gravity, scale) = alignment.solve(None);
+    let magnitude = gravity.norm();
+    if (magnitude - GRAVITY).abs() > params.gravity_tolerance * GRAVITY {
+        return Err(InitializationError::InvalidGravity(magnitude));
+    }
+    let (mut velocities, mut gravity, mut scale) = (Vec::new(), gravity, scale);
+    for _ in 0..params.gravity_iterations {
+        let solution = alignment.solve(Some(&gravity));
+        velocities = solution.0;
+        gravity = solution.1.normalize() * GRAVITY;
+        scale = solution.2;
+    }
+    if scale <= 0.0 {
+        return Err(InitializationError::InvalidScale(scale));
+    }
+
+    let tilt = UnitQuaternion::rotation_between(&gravity, &-Vector3::z())
+        .unwrap_or_else(|| UnitQuaternion::from_euler_angles(std::f64::consts::PI, 0.0, 0.0));
+    let (roll, pitch, _) = tilt.euler_angles();
+    let rotation = UnitQuaternion::from_euler_angles(roll, pitch, 0.0);
+    let poses = rotations
+        .iter()
+        .zip(centres.iter().zip(&arms))
+        .map(|(body, (centre, arm))| {
+            let position = rotation * (centre * scale + arm);
+            Isometry3::from_parts(Translation3::from(position), rotation * body)
+        })
+        .collect();
+    let velocities = velocities.iter().map(|v| rotation * v).collect();
+
+    Ok(VisualInertialInitialization {
+        scale,
+        rotation,
+        gravity,
+        poses,
+        velocities,
+        bias,
+    })
+}
+
+/// Gyroscope bias aligning the delta rotations with the visual ones, by Gauss-Newton
+fn gyro_bias(
+    rotations: &[UnitQuaternion<f64>],
+    preintegrated: &[PreintegratedImu],
+    iterations: usize,
+) -> ImuBias {
+    let mut bias = *preintegrated[0].bias();
+    for _ in 0..iterations {
+        let mut hessian = Matrix3::zeros();
+        let mut rhs = Vector3::zeros();
+        for (pair, pre) in rotations.windows(2).zip(preintegrated) {
+            let delta_rotation = pre.deltas(&bias).0;
+            let error = lie::log(&(delta_rotation.inverse() * pair[0].inverse() * pair[1]));
+            // the error decreases by J * dbg for a change dbg of the bias
+            let jacobian = pre.rotation_gyro_jacobian();
+            hessian += jacobian.transpose() * jacobian;
+            rhs += jacobian.transpose() * error;
+        }
+        match hessian.cholesky() {
+            Some(cholesky) => bias.gyro += cholesky.solve(&rhs),
+            None => break,
+        }
+    }
+    bias
+}
+
+/// Linear alignment of the velocities, gravity and scale with the preintegrated IMU
+#[derive(Debug)]
+struct Alignment<'a> {
+    rotations: &'a [UnitQuaternion<f64>],
+    centres: &'a [Vector3<f64>],
+    arms: &'a [Vector3<f64>],
+    preintegrated: &'a [PreintegratedImu],
+    bias: &'a ImuBias,
+}
+
+impl Alignment<'_> {
+    /// Least squares velocities, gravity and scale. With a `gravity` estimate, its magnitude is
+    /// fixed and only its direction is refined, in the plane perpendicular to it
+    fn solve(&self, gravity: Option<&Vector3<f64>>) -> (Vec<Vector3<f64>>, Vector3<f64>, f64) {
+        let n = self.rotations.len();
+        // gravity = base + basis' * unknowns
+        let (base, basis) = gravity.map_or_else(
+            || (Vector3::zeros(), Matrix3::identity()),
+            |g| {
+                let mut basis = Matrix3::zeros();
+                basis.fixed_rows_mut::<U2>(0).copy_from(&tangent_basis(g));
+                (*g, basis)
+            },
+        );
+        let n_gravity = if gravity.is_some() { 2 } else { 3 };
+        let (gravity_col, scale_col) = (3 * n, 3 * n + n_gravity);
+        let mut a = DMatrix::zeros(6 * (n - 1), scale_col + 1);
+        let mut b = DVector::zeros(6 * (n - 1));
+
+        for (i, pre) in self.preintegrated.iter().enumerate() {
+            let (_, delta_velocity, delta_position) = pre.deltas(self.bias);
+            let t = pre.delta_t();
+            let inverse = self.rotations[i]
+                .inverse()
+                .to_rotation_matrix()
+                .into_inner();
+            let gravity_basis = (inverse * basis.transpose())
+                .columns(0, n_gravity)
+                .into_owned();
+            let row = 6 * i;
+
+            // R_i' * (v_j - v_i - g * t) = dv
+            a.slice_mut((row, 3 * i), (3, 3)).copy_from(&-inverse);
+            a.slice_mut((row, 3 * (i + 1)), (3, 3)).copy_from(&inverse);
+            a.slice_mut((row, gravity_col), (3, n_gravity))
+                .copy_from(&(&gravity_basis * -t));
+            b.fixed_rows_mut::<U3>(row)
+                .copy_from(&(delta_velocity + inverse * base * t));
+
+            // R_i' * (s * (c_j - c_i) + d_j - d_i - v_i * t - g * t^2 / 2) = dp
+            let row = row + 3;
+            a.slice_mut((row, 3 * i), (3, 3)).copy_from(&(-inverse * t));
+            a.slice_mut((row, gravity_col), (3, n_gravity))
+                .copy_from(&(&gravity_basis * (-0.5 * t * t)));
+            a.slice_mut((row, scale_col), (3, 1))
+                .copy_from(&(inverse * (self.centres[i + 1] - self.centres[i])));
+            b.fixed_rows_mut::<U3>(row).copy_from(
+                &(delta_position - inverse * (self.arms[i + 1] - self.arms[i])
+                    + inverse * base * (0.5 * t * t)),
+            );
+        }
+
+        let solution = (a.transpose() * &a).cholesky().map_or_else(
+            || DVector::zeros(scale_col + 1),
+            |c| c.solve(&(a.transpose() * b)),
+        );
+        let velocities = (0..n)
+            .map(|k| solution.fixed_rows::<U3>(3 * k).into_owned())
+            .collect();
+        let unknowns = solution.rows(gravity_col, n_gravity);
+        let gravity = base + basis.transpose().columns(0, n_gravity) * unknowns;
+        (velocities, gravity, solution[scale_col])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::time::Duration;
+
+    /// 200Hz samples of a tilted IMU, still for `still` seconds and then shaken
+    fn imu_samples(
+        still: f64,
+        rotation: &UnitQuaternion<f64>,
+        gyro_bias: &Vector3<f64>,
+    ) -> Vec<ImuSample> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut noise = |scale: f64| Vector3::from_fn(|_, _| rng.gen_range(-scale, scale));
+        (0..800)
+            .map(|k| {
+                let t = k as f64 * 0.005;
+                let shake = if t < still { 0.0 } else { (30.0 * t).sin() };
+                ImuSample {
+                    timestamp: Duration::from_secs_f64(100.0 + t),
+                    gyro: gyro_bias + Vector3::new(0.0, shake, 0.0) + noise(0.005),
+                    accel: rotation.inverse() * Vector3::new(shake, 0.0, GRAVITY) + noise(0.05),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn initialise_from_a_stationary_start() {
+        let rotation = UnitQuaternion::from_euler_angles(0.2, -0.1, 0.7);
+        let gyro_bias = Vector3::new(0.01, -0.02, 0.003);
+        let samples = imu_samples(2.0, &rotation, &gyro_bias);
+        let init = static_initialization(&samples, &StaticInitParams::default()).unwrap();
+        assert!((init.duration - 2.0).abs() < 0.21);
+        assert!((init.bias.gyro - gyro_bias).norm() < 1e-3);
+
+        // same attitude, up to the yaw
+        let up = Vector3::z();
+        assert!((init.rotation * init.gravity.normalize() + up).norm() < 1e-3);
+        assert!(((init.rotation.inverse() * up) - (rotation.inverse() * up)).norm() < 1e-3);
+        assert!(init.rotation.euler_angles().2.abs() < 1e-9);
+
+        let moving = imu_samples(0.5, &rotation, &gyro_bias);
+        assert!(matches!(
+            static_initialization(&moving, &StaticInitParams::default()),
+            Err(InitializationError::NotStationary(duration)) if duration < 0.6
+        ));
+    }
+
+    /// Body rotating at a constant rate while moving along a curve, with `motion` scaling its
+    /// accelerations
+    fn body(t: f64, motion: f64) -> (UnitQuaternion<f64>, Vector3<f64>, Vector3<f64>) {
+        let rotation = UnitQuaternion::from_euler_angles(0.1, -0.2, 0.3)
+            * lie::exp(&(Vector3::new(0.3, -0.4, 0.5) * t));
+        let position = Vector3::new(t, 0.5 * t, 0.0)
+            + Vector3::new((1.5 * t).sin(), (2.0 * t).cos(), 0.5 * (3.0 * t).sin()) * motion;
+        let acceleration = Vector3::new(
+            -2.25 * (1.5 * t).sin(),
+            -4.0 * (2.0 * t).cos(),
+            -4.5 * (3.0 * t).sin(),
+        ) * motion;
+        (rotation, position, acceleration)
+    }
+
+    /// Camera poses of 8 keyframes, up to scale and in a rotated frame, and the IMU between them
+    #[allow(clippy::suboptimal_flops)]
+    fn keyframes(
+        motion: f64,
+        gyro_bias: &Vector3<f64>,
+        t_bs: &Isometry3<f64>,
+    ) -> (Vec<Isometry3<f64>>, Vec<PreintegratedImu>) {
+        let r_vw = UnitQuaternion::from_euler_angles(0.5, 0.3, -1.0);
+        let (dt, steps) = (1e-3, 400);
+        let gravity = Vector3::new(0.0, 0.0, -GRAVITY);
+        let poses = (0..8)
+            .map(|k| {
+                let (rotation, position, _) = body(k as f64 * steps as f64 * dt, motion);
+                let t_wb = Isometry3::from_parts(Translation3::from(position), rotation);
+                let t_vc = Isometry3::from_parts(Translation3::identity(), r_vw) * t_wb * t_bs;
+                Isometry3::from_parts(
+                    Translation3::from(t_vc.translation.vector / 4.0),
+                    t_vc.rotation,
+                )
+            })
+            .collect();
+        let preintegrated = (0..7)
+            .map(|k| {
+                let mut pre = PreintegratedImu::new(ImuBias::default(), Default::default());
+                for step in 0..steps {
+                    let t = (k * steps + step) as f64 * dt + 0.5 * dt;
+                    let (rotation, _, acceleration) = body(t, motion);
+                    let gyro = Vector3::new(0.3, -0.4, 0.5) + gyro_bias;
+                    pre.integrate(&gyro, &(rotation.inverse() * (acceleration - gravity)), dt);
+                }
+                pre
+            })
+            .collect();
+        (poses, preintegrated)
+    }
+
+    #[test]
+    fn initialise_from_a_visual_trajectory() {
+        let t_bs = Isometry3::from_parts(
+            Translation3::new(0.05, -0.02, 0.01),
+            UnitQuaternion::from_euler_angles(-1.5, 0.0, -1.5),
+        );
+        let gyro_bias = Vector3::new(0.01, -0.02, 0.005);
+        let (poses, preintegrated) = keyframes(1.0, &gyro_bias, &t_bs);
+        let params = VisualInertialParams::default();
+        let init = visual_inertial_initialization(&poses, &preintegrated, &t_bs, &params).unwrap();
+        assert!((init.scale - 4.0).abs() < 0.01 * 4.0, "{}", init.scale);
+        assert!((init.bias.gyro - gyro_bias).norm() < 1e-3);
+
+        // the recovered trajectory, up to the yaw and the initial position
+        let (rotation, position, _) = body(0.0, 1.0);
+        let yaw = init.poses[0].rotation * rotation.inverse();
+        assert!((yaw * Vector3::z() - Vector3::z()).norm() < 1e-2);
+        let (_, end, _) = body(7.0 * 0.4, 1.0);
+        let travelled = init.poses[7].translation.vector - init.poses[0].translation.vector;
+        assert!((travelled - yaw * (end - position)).norm() < 0.05);
+        let velocity = yaw * Vector3::new(1.0 + 1.5, 0.5, 1.5);
+        assert!((init.velocities[0] - velocity).norm() < 0.05);
+
+        // moving at a constant velocity
+        let (poses, preintegrated) = keyframes(0.0, &gyro_bias, &t_bs);
+        assert!(matches!(
+            visual_inertial_initialization(&poses, &preintegrated, &t_bs, &params),
+            Err(InitializationError::InsufficientExcitation(_))
+        ));
+        assert_eq!(
+            visual_inertial_initialization(&poses, &preintegrated[1..], &t_bs, &params)
+                .unwrap_err(),
+            InitializationError::PreintegrationCount {
+                poses: poses.len(),
+                preintegrated: preintegrated.len() - 1
+            }
+        );
+    }
+}
@@ -0,0 +1,513 @@
+/// Pose-graph files in the g2o and TORO formats
+///
+/// The standard pose-graph datasets (Manhattan, Intel, Sphere, Garage, ...) are distributed as
+/// lists of vertices - the initial poses - and edges - the relative pose measurements with their
+/// information. Planar graphs are embedded in 3D: poses rotate about `z`, and the edges constrain
+/// the out-of-plane components with the largest of their planar information.
+///
+/// g2o measures the rotation error of its 3D edges by the vector part of the error quaternion,
+/// roughly half the rotation vector used by [`BetweenFactor`], so their information is converted
+/// when reading and writing.
+use crate::graph::factor::{BetweenFactor, PriorFactor};
+use crate::graph::factor_graph::FactorGraph;
+use crate::graph::key::Key;
+use crate::graph::values::{GraphError, Values};
+
+use nalgebra::{DMatrix, Isometry3, Quaternion, Translation3, UnitQuaternion, Vector3};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+/// Information of the priors anchoring the fixed poses
+const ANCHOR_INFORMATION: f64 = 1e8;
+
+/// Indices of `x`, `y` and `theta` of a planar pose in the tangent space of 3D poses
+const PLANAR_INDICES: [usize; 3] = [3, 4, 2];
+
+/// Errors associated with reading and writing pose-graph files
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PoseGraphFileError {
+    #[error("Accessing [{0}] failed - Reason: {1}")]
+    Io(String, String),
+    #[error("Invalid line {line} - Reason: {reason}")]
+    Parse { line: usize, reason: String },
+    #[error("The TORO format only supports planar graphs")]
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoseGraphFormat {
+    /// `VERTEX_SE2`/`EDGE_SE2` or `VERTEX_SE3:QUAT`/`EDGE_SE3:QUAT`, and `FIX`
+    G2o,
+    /// `VERTEX2`/`EDGE2`
+    Toro,
+}
+
+/// Relative pose measurement between two vertices
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoseGraphEdge {
+    pub from: u64,
+    pub to: u64,
+    /// Pose of `to` in the frame of `from`
+    pub measured: Isometry3<f64>,
+    /// 6x6, in the tangent space of [`BetweenFactor`] - rotation first
+    pub information: DMatrix<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PoseGraph {
+    /// Whether the vertices and edges are 2D
+    pub planar: bool,
+    pub poses: BTreeMap<u64, Isometry3<f64>>,
+    pub edges: Vec<PoseGraphEdge>,
+    /// Vertices held fixed by the file
+    pub fixed: BTreeSet<u64>,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Conversions
+// -------------------------------------------------------------------------------------------------
+
+fn planar_pose(x: f64, y: f64, theta: f64) -> Isometry3<f64> {
+    Isometry3::new(Vector3::new(x, y, 0.0), Vector3::new(0.0, 0.0, theta))
+}
+
+/// Full information of a planar edge, given its information on `x`, `y` and `theta`
+fn planar_information(planar: &DMatrix<f64>) -> DMatrix<f64> {
+    let mut information = DMatrix::zeros(6, 6);
+    for (r, &row) in PLANAR_INDICES.iter().enumerate() {
+        for (c, &col) in PLANAR_INDICES.iter().enumerate() {
+            information[(row, col)] = planar[(r, c)];
+        }
+    }
+    let off_plane = planar.diagonal().max();
+    for &i in &[0, 1, 5] {
+        information[(i, i)] = off_plane;
+    }
+    information
+}
+
+/// `M` with `e = M * r` for the error `e = [t; q_v]` of g2o and the residual `r = [phi; t]`, or
+/// its inverse
+fn g2o_error_map(inverse: bool) -> DMatrix<f64> {
+    let (upper, lower) = if inverse { (2.0, 1.0) } else { (1.0, 0.5) };
+    let mut map = DMatrix::zeros(6, 6);
+    for i in 0..3 {
+        map[(i, i + 3)] = upper;
+        map[(i + 3, i)] = lower;
+    }
+    map
+}
+
+/// Symmetric matrix from its upper triangle, row by row
+fn from_upper_triangle(n: usize, values: &[f64]) -> DMatrix<f64> {
+    let mut matrix = DMatrix::zeros(n, n);
+    let mut values = values.iter();
+    for r in 0..n {
+        for c in r..n {
+            let value = *values.next().unwrap();
+            matrix[(r, c)] = value;
+            matrix[(c, r)] = value;
+        }
+    }
+    matrix
+}
+
+fn write_upper_triangle(line: &mut String, matrix: &DMatrix<f64>) {
+    for r in 0..matrix.nrows() {
+        for c in r..matrix.ncols() {
+            write!(line, " {}", matrix[(r, c)]).unwrap();
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// PoseGraph
+// -------------------------------------------------------------------------------------------------
+
+impl PoseGraph {
+    /// Parse the contents of a g2o or TORO file. Unknown tags are skipped
+    pub fn parse(contents: &str) -> Result<Self, PoseGraphFileError> {
+        let mut graph = Self::default();
+        let mut dimensions = BTreeSet::new();
+        for (index, line) in contents.lines().enumerate() {
+            let error = |reason: String| PoseGraphFileError::Parse {
+                line: index + 1,
+                reason,
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let tag = match fields.first() {
+                Some(tag) if !tag.starts_with('#') => *tag,
+                _ => continue,
+            };
+            let expected = match tag {
+                "VERTEX_SE2" | "VERTEX2" => 4,
+                "EDGE_SE2" | "EDGE2" => 11,
+                "VERTEX_SE3:QUAT" => 8,
+                "EDGE_SE3:QUAT" => 30,
+                "FIX" => 1,
+                other => {
+                    log::debug!("Skipping the unsupported tag [{}]", other);
+                    continue;
+                }
+            };
+            // FIX takes any number of vertices
+            let n_fields = fields.len() - 1;
+            if n_fields < expected || (n_fields > expected && tag != "FIX") {
+                return Err(error(format!(
+                    "Expected {} fields after [{}], found {}",
+                    expected, tag, n_fields
+                )));
+            }
+            let id = |i: usize| -> Result<u64, PoseGraphFileError> {
+                fields[i]
+                    .parse()
+                    .map_err(|_| error(format!("Invalid vertex id [{}]", fields[i])))
+            };
+            let numbers = |from: usize| -> Result<Vec<f64>, PoseGraphFileError> {
+                fields[from..]
+                    .iter()
+                    .map(|field| {
+                        field
+                            .parse()
+                            .map_err(|_| error(format!("Invalid number [{}]", field)))
+                    })
+                    .collect()
+            };
+
+            match tag {
+                "VERTEX_SE2" | "VERTEX2" => {
+                    let v = numbers(2)?;
+                    graph.poses.insert(id(1)?, planar_pose(v[0], v[1], v[2]));
+                    dimensions.insert(2);
+                }
+                "EDGE_SE2" | "EDGE2" => {
+                    let v = numbers(3)?;
+                    let information = if tag == "EDGE_SE2" {
+                        from_upper_triangle(3, &v[3..])
+                    } else {
+                        // I11 I12 I22 I33 I13 I23
+                        from_upper_triangle(3, &[v[3], v[4], v[7], v[5], v[8], v[6]])
+                    };
+                    graph.edges.push(PoseGraphEdge {
+                        from: id(1)?,
+                        to: id(2)?,
+                        measured: planar_pose(v[0], v[1], v[2]),
+                        information: planar_information(&information),
+                    });
+                    dimensions.insert(2);
+                }
+                "VERTEX_SE3:QUAT" => {
+                    let v = numbers(2)?;
+                    let rotation =
+                        UnitQuaternion::from_quaternion(Quaternion::new(v[6], v[3], v[4], v[5]));
+                    let pose = Isometry3::from_parts(Translation3::new(v[0], v[1], v[2]), rotation);
+                    graph.poses.insert(id(1)?, pose);
+                    dimensions.insert(3);
+                }
+                "EDGE_SE3:QUAT" => {
+                    let v = numbers(3)?;
+                    let rotation =
+                        UnitQuaternion::from_quaternion(Quaternion::new(v[6], v[3], v[4], v[5]));
+                    let map = g2o_error_map(false);
+                    graph.edges.push(PoseGraphEdge {
+                        from: id(1)?,
+                        to: id(2)?,
+                        measured: Isometry3::from_parts(
+                            Translation3::new(v[0], v[1], v[2]),
+                            rotation,
+                        ),
+                        information: map.transpose() * from_upper_triangle(6, &v[7..]) * map,
+                    });
+                    dimensions.insert(3);
+                }
+                _ => {
+                    for i in 1..fields.len() {
+                        graph.fixed.insert(id(i)?);
+                    }
+                }
+            }
+            if dimensions.len() > 1 {
+                return Err(error("Mixing planar and 3D vertices or edges".into()));
+            }
+        }
+        graph.planar = dimensions.contains(&2);
+        Ok(graph)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, PoseGraphFileError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| PoseGraphFileError::Io(path.display().to_string(), e.to_string()))?;
+        Self::parse(&contents)
+    }
+
+    /// Contents of the file in the given format
+    pub fn format(&self, format: PoseGraphFormat) -> Result<String, PoseGraphFileError> {
+        if format == PoseGraphFormat::Toro && !self.planar {
+            return Err(PoseGraphFileError::Unsupported);
+        }
+        let mut contents = String::new();
+        for (id, pose) in &self.poses {
+            if self.planar {
+                let tag = match format {
+                    PoseGraphFormat::G2o => "VERTEX_SE2",
+                    PoseGraphFormat::Toro => "VERTEX2",
+                };
+                let t = pose.translation.vector;
+                let theta = pose.rotation.euler_angles().2;
+                writeln!(contents, "{} {} {} {} {}", tag, id, t.x, t.y, theta).unwrap();
+            } else {
+                let (t, q) = (pose.translation.vector, pose.rotation.coords);
+                writeln!(
+                    contents,
+                    "VERTEX_SE3:QUAT {} {} {} {} {} {} {} {}",
+                    id, t.x, t.y, t.z, q[0], q[1], q[2], q[3]
+                )
+                .unwrap();
+            }
+        }
+
+        for edge in &self.edges {
+            let t = edge.measured.translation.vector;
+            let mut line;
+            if self.planar {
+                let theta = edge.measured.rotation.euler_angles().2;
+                let i = DMatrix::from_fn(3, 3, |r, c| {
+                    edge.information[(PLANAR_INDICES[r], PLANAR_INDICES[c])]
+                });
+                line = match format {
+                    PoseGraphFormat::G2o => "EDGE_SE2",
+                    PoseGraphFormat::Toro => "EDGE2",
+                }
+                .to_owned();
+                write!(line, " {} {} {} {} {}", edge.from, edge.to, t.x, t.y, theta).unwrap();
+                match format {
+                    PoseGraphFormat::G2o => write_upper_triangle(&mut line, &i),
+                    PoseGraphFormat::Toro => {
+                        for &(r, c) in &[(0, 0), (0, 1), (1, 1), (2, 2), (0, 2), (1, 2)] {
+                            write!(line, " {}", i[(r, c)]).unwrap();
+                        }
+                    }
+                }
+            } else {
+                let q = edge.measured.rotation.coords;
+                line = format!(
+                    "EDGE_SE3:QUAT {} {} {} {} {} {} {} {} {}",
+                    edge.from, edge.to, t.x, t.y, t.z, q[0], q[1], q[2], q[3]
+                );
+                let map = g2o_error_map(true);
+                write_upper_triangle(&mut line, &(map.transpose() * &edge.information * map));
+            }
+            writeln!(contents, "{}", line).unwrap();
+        }
+
+        if format == PoseGraphFormat::G2o {
+            for id in &self.fixed {
+                writeln!(contents, "FIX {}", id).unwrap();
+            }
+        }
+        Ok(contents)
+    }
+
+    pub fn write(&self, path: &Path, format: PoseGraphFormat) -> Result<(), PoseGraphFileError> {
+        fs::write(path, self.format(format)?)
+            .map_err(|e| PoseGraphFileError::Io(path.display().to_string(), e.to_string()))
+    }
+
+    /// Factor graph over [`Key::Pose`] with a [`BetweenFactor`] per edge, anchored by priors on
+    /// the fixed vertices - or the first one if none is fixed
+    pub fn factor_graph(&self) -> FactorGraph {
+        let mut graph = FactorGraph::new();
+        let anchored: Vec<u64> = if self.fixed.is_empty() {
+            self.poses.keys().take(1).copied().collect()
+        } else {
+            self.fixed.iter().copied().collect()
+        };
+        for id in anchored {
+            if let Some(pose) = self.poses.get(&id) {
+                graph.insert(PriorFactor::new(
+                    Key::Pose(id),
+                    *pose,
+                    DMatrix::identity(6, 6) * ANCHOR_INFORMATION,
+                ));
+            }
+        }
+        for edge in &self.edges {
+            graph.insert(BetweenFactor::new(
+                Key::Pose(edge.from),
+                Key::Pose(edge.to),
+                edge.measured,
+                edge.information.clone(),
+            ));
+        }
+        graph
+    }
+
+    /// Initial values of the poses
+    pub fn values(&self) -> Values {
+        let mut values = Values::new();
+        for (id, pose) in &self.poses {
+            values.insert(Key::Pose(*id), *pose);
+        }
+        values
+    }
+
+    /// Replace the poses with their estimates, e.g. after optimising
+    pub fn update(&mut self, values: &Values) -> Result<(), GraphError> {
+        for (id, pose) in &mut self.poses {
+            *pose = *values.pose(Key::Pose(*id))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::optimizer::Optimizer;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const PLANAR_G2O: &str = "\
+VERTEX_SE2 0 0 0 0
+VERTEX_SE2 1 1.1 0.05 0.1
+VERTEX_SE2 2 1.9 1.1 1.6
+EDGE_SE2 0 1 1 0 0 100 1 2 200 3 1000
+EDGE_SE2 1 2 1 1 1.5 50 0 0 50 0 500
+FIX 0
+";
+
+    const PLANAR_TORO: &str = "\
+# the same graph
+VERTEX2 0 0 0 0
+VERTEX2 1 1.1 0.05 0.1
+VERTEX2 2 1.9 1.1 1.6
+EDGE2 0 1 1 0 0 100 1 200 1000 2 3
+EDGE2 1 2 1 1 1.5 50 0 50 500 0 0
+";
+
+    const SPATIAL_G2O: &str = "\
+VERTEX_SE3:QUAT 0 0 0 0 0 0 0 1
+VERTEX_SE3:QUAT 1 1 0.5 -0.25 0 0 0.6 0.8
+EDGE_SE3:QUAT 0 1 1 0.5 -0.25 0 0 0.6 0.8 10 0 0 0 0 0 10 0 0 0 0 10 0 0 0 40 0 0 40 0 40
+";
+
+    #[test]
+    fn formats_round_trip() {
+        let g2o = PoseGraph::parse(PLANAR_G2O).unwrap();
+        let mut toro = PoseGraph::parse(PLANAR_TORO).unwrap();
+        assert!(g2o.planar && toro.planar);
+        assert_eq!((g2o.poses.len(), g2o.edges.len()), (3, 2));
+        assert_eq!(g2o.edges[0].information[(3, 4)], 1.0);
+        assert_eq!(g2o.edges[0].information[(4, 2)], 3.0);
+        // TORO doesn't fix vertices
+        toro.fixed.insert(0);
+        assert_eq!(g2o, toro);
+
+        let spatial = PoseGraph::parse(SPATIAL_G2O).unwrap();
+        assert!(!spatial.planar);
+        // the information of the quaternion error is 4 times the one of the rotation vector
+        let information = &spatial.edges[0].information;
+        assert_eq!(information[(0, 0)], 10.0);
+        assert_eq!(information[(3, 3)], 10.0);
+        assert!(spatial.format(PoseGraphFormat::Toro).is_err());
+
+        for (graph, format) in &[
+            (&g2o, PoseGraphFormat::G2o),
+            (&g2o, PoseGraphFormat::Toro),
+            (&spatial, PoseGraphFormat::G2o),
+        ] {
+            let mut parsed = PoseGraph::parse(&graph.format(*format).unwrap()).unwrap();
+            parsed.fixed = graph.fixed.clone();
+            for (id, pose) in &graph.poses {
+                assert!((pose.to_homogeneous() - parsed.poses[id].to_homogeneous()).norm() < 1e-12);
+            }
+            for (edge, other) in graph.edges.iter().zip(&parsed.edges) {
+                assert_eq!((edge.from, edge.to), (other.from, other.to));
+                assert!((&edge.information - &other.information).norm() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_files() {
+        let error = PoseGraph::parse("VERTEX_SE2 0 0 0\n").unwrap_err();
+        assert!(matches!(error, PoseGraphFileError::Parse { line: 1, .. }));
+        let error = PoseGraph::parse("# comment\nVERTEX_SE2 0 0 0 x\n").unwrap_err();
+        assert!(matches!(error, PoseGraphFileError::Parse { line: 2, .. }));
+        let mixed = format!("{}{}", PLANAR_G2O, SPATIAL_G2O);
+        assert!(PoseGraph::parse(&mixed).is_err());
+        // unknown tags are skipped
+        let graph = PoseGraph::parse("PARAMS_SE3OFFSET 0 0 0 0 0 0 0 1\nVERTEX2 4 1 2 0").unwrap();
+        assert_eq!(graph.poses.len(), 1);
+        // several vertices can be fixed at once
+        let graph = PoseGraph::parse(&format!("{}FIX 1 2\n", PLANAR_G2O)).unwrap();
+        assert_eq!(
+            graph.fixed.iter().copied().collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert!(PoseGraph::parse("FIX\n").is_err());
+        assert!(PoseGraph::parse("FIX 0 x\n").is_err());
+    }
+
+    #[test]
+    fn optimise_a_planar_file() {
+        // a square loop, driven around twice, with noisy initial poses
+        let mut rng = StdRng::seed_from_u64(6);
+        let truth: Vec<(f64, f64, f64)> = (0..8)
+            .map(|i| {
+                let corner = i % 4;
+                let angle = f64::from(corner) * std::f64::consts::FRAC_PI_2;
+                let (x, y) = [(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)][corner as usize];
+                (x, y, angle)
+            })
+            .collect();
+        let mut contents = String::new();
+        for (i, &(x, y, theta)) in truth.iter().enumerate() {
+            let noise = |rng: &mut StdRng| rng.gen_range(-0.1, 0.1);
+            contents += &format!(
+                "VERTEX_SE2 {} {} {} {}\n",
+                i,
+                x + noise(&mut rng),
+                y + noise(&mut rng),
+                theta + noise(&mut rng)
+            );
+        }
+        for i in 0..truth.len() {
+            let j = (i + 1) % truth.len();
+            let (a, b) = (truth[i], truth[j]);
+            let relative = planar_pose(a.0, a.1, a.2).inverse() * planar_pose(b.0, b.1, b.2);
+            let t = relative.translation.vector;
+            contents += &format!(
+                "EDGE_SE2 {} {} {} {} {} 100 0 0 100 0 400\n",
+                i,
+                j,
+                t.x,
+                t.y,
+                relative.rotation.euler_angles().2
+            );
+        }
+
+        let mut file = PoseGraph::parse(&contents).unwrap();
+        let mut values = file.values();
+        let report = Optimizer::default()
+            .optimize(&file.factor_graph(), &mut values)
+            .unwrap();
+        assert!(report.termination.converged());
+        file.update(&values).unwrap();
+
+        let optimised = PoseGraph::parse(&file.format(PoseGraphFormat::G2o).unwrap()).unwrap();
+        let origin = optimised.poses[&0];
+        for (i, &(x, y, theta)) in truth.iter().enumerate() {
+            // relative to the first pose, which stays at its noisy estimate
+            let expected = truth[0];
+            let expected = planar_pose(expected.0, expected.1, expected.2).inverse()
+                * planar_pose(x, y, theta);
+            let estimated = origin.inverse() * optimised.poses[&(i as u64)];
+            assert!((expected.to_homogeneous() - estimated.to_homogeneous()).norm() < 1e-6);
+            assert!(estimated.translation.vector.z.abs() < 1e-9);
+        }
+    }
+}
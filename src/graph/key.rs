@@ -0,0 +1,31 @@
+/// Typed keys of the variables of a factor graph
+use std::fmt;
+
+/// Key of a variable. The variables of the same state - e.g. the pose, velocity and IMU biases of a
+/// keyframe - share the index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Key {
+    Pose(u64),
+    Landmark(u64),
+    Velocity(u64),
+    Bias(u64),
+}
+
+impl Key {
+    pub const fn index(&self) -> u64 {
+        match *self {
+            Self::Pose(idx) | Self::Landmark(idx) | Self::Velocity(idx) | Self::Bias(idx) => idx,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pose(idx) => write!(f, "x{}", idx),
+            Self::Landmark(idx) => write!(f, "l{}", idx),
+            Self::Velocity(idx) => write!(f, "v{}", idx),
+            Self::Bias(idx) => write!(f, "b{}", idx),
+        }
+    }
+}
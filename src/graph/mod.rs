@@ -0,0 +1,37 @@
+pub mod cholesky;
+pub mod factor;
+pub mod factor_graph;
+pub mod imu;
+pub mod initialization;
+pub mod io;
+pub mod key;
+pub mod optimizer;
+pub mod ordering;
+pub mod reprojection;
+pub mod robust;
+pub mod schur;
+pub mod sliding_window;
+pub mod sparse;
+pub mod values;
+
+pub use self::cholesky::{SparseCholesky, SymbolicCholesky};
+pub use self::factor::{numerical_jacobians, BetweenFactor, Factor, PriorFactor};
+pub use self::factor_graph::{FactorGraph, FactorId};
+pub use self::imu::{BiasRandomWalkFactor, ImuFactor, ImuNoise, PreintegratedImu, GRAVITY};
+pub use self::initialization::{
+    static_initialization, visual_inertial_initialization, InitializationError, StaticInitParams,
+    StaticInitialization, VisualInertialInitialization, VisualInertialParams,
+};
+pub use self::io::{PoseGraph, PoseGraphEdge, PoseGraphFileError, PoseGraphFormat};
+pub use self::key::Key;
+pub use self::optimizer::{
+    Algorithm, IterationReport, LinearSolver, OptimizationReport, Optimizer, OptimizerParams,
+    Termination,
+};
+pub use self::ordering::amd_ordering;
+pub use self::reprojection::{InverseDepthFactor, ReprojectionFactor};
+pub use self::robust::{chi_square_quantile, classify_factors, FactorClassification, RobustKernel};
+pub use self::schur::{ReducedSystem, SchurComplement};
+pub use self::sliding_window::{MarginalPriorFactor, SlidingWindow, SlidingWindowParams};
+pub use self::sparse::SparseBlockMatrix;
+pub use self::values::{GraphError, ImuBias, InverseDepthPoint, Values, Variable};
@@ -0,0 +1,565 @@
+/// Nonlinear least-squares optimisation of factor graphs
+///
+/// Every iteration linearises all the factors at the current values, assembles the normal
+/// equations `H * dx = -g` over the tangent spaces of the variables, solves them and retracts the
+/// step onto the manifold. Levenberg-Marquardt damps the system and only accepts steps that reduce
+/// the cost, adapting the damping to how well the linear model predicted the reduction.
+///
+/// The Hessian is a [`SparseBlockMatrix`], factorised by a sparse Cholesky decomposition after a
+/// fill-reducing ordering of the variables, or densely for small problems. Bundle adjustment
+/// problems first eliminate their landmarks with the [`SchurComplement`]. Factors with a
+/// [`RobustKernel`](crate::graph::robust::RobustKernel) are reweighted at every linearisation
+/// (IRLS), and the variables fixed in the graph are left out of the normal equations.
+use crate::graph::cholesky::SymbolicCholesky;
+use crate::graph::factor_graph::FactorGraph;
+use crate::graph::key::Key;
+use crate::graph::ordering::amd_ordering;
+use crate::graph::schur::SchurComplement;
+use crate::graph::sparse::SparseBlockMatrix;
+use crate::graph::values::{GraphError, Values};
+
+use log::debug;
+use nalgebra::{DMatrix, DVector};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// -------------------------------------------------------------------------------------------------
+// OptimizerParams / OptimizationReport
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    GaussNewton,
+    LevenbergMarquardt,
+}
+
+/// How the normal equations are solved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinearSolver {
+    DenseCholesky,
+    /// Sparse Cholesky after an approximate minimum degree ordering of the variables
+    SparseCholesky,
+    /// Eliminates the landmarks with the Schur complement, and solves the reduced camera system
+    /// with sparse Cholesky - for bundle adjustment
+    SparseSchur,
+}
+
+#[derive(Debug, Clone)]
+pub struct OptimizerParams {
+    pub algorithm: Algorithm,
+    pub linear_solver: LinearSolver,
+    pub max_iterations: usize,
+    /// Stop when an accepted step reduces the cost by less than this fraction
+    pub relative_tolerance: f64,
+    /// Stop when the cost falls below this value
+    pub absolute_tolerance: f64,
+    /// Stop when the norm of the step falls below this value
+    pub step_tolerance: f64,
+    /// Damping of the first Levenberg-Marquardt iteration, relative to the diagonal of the Hessian
+    pub initial_lambda: f64,
+    /// Levenberg-Marquardt gives up when the damping grows beyond this value
+    pub max_lambda: f64,
+}
+
+impl Default for OptimizerParams {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::LevenbergMarquardt,
+            linear_solver: LinearSolver::SparseCholesky,
+            max_iterations: 100,
+            relative_tolerance: 1e-6,
+            absolute_tolerance: 1e-12,
+            step_tolerance: 1e-9,
+            initial_lambda: 1e-4,
+            max_lambda: 1e10,
+        }
+    }
+}
+
+/// Why the optimisation stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// The cost decrease fell below the relative tolerance
+    RelativeDecrease,
+    /// The cost fell below the absolute tolerance
+    AbsoluteCost,
+    /// The step fell below the step tolerance
+    StepSize,
+    MaxIterations,
+    /// Levenberg-Marquardt couldn't find a step that reduces the cost
+    MaxDamping,
+    /// The normal equations couldn't be solved, e.g. an unconstrained gauge with Gauss-Newton
+    LinearSolverFailed,
+}
+
+impl Termination {
+    pub const fn converged(&self) -> bool {
+        matches!(
+            self,
+            Self::RelativeDecrease | Self::AbsoluteCost | Self::StepSize
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IterationReport {
+    pub iteration: usize,
+    /// Cost at the end of the iteration
+    pub cost: f64,
+    pub step_norm: f64,
+    /// Damping of the iteration - always zero for Gauss-Newton
+    pub lambda: f64,
+    /// Whether the step was applied
+    pub accepted: bool,
+    pub linearize_time: Duration,
+    pub solve_time: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct OptimizationReport {
+    pub initial_cost: f64,
+    pub final_cost: f64,
+    pub iterations: Vec<IterationReport>,
+    pub termination: Termination,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Normal equations
+// -------------------------------------------------------------------------------------------------
+
+/// Block of each variable in the normal equations, and its position in the stacked tangent vector
+#[derive(Debug, Clone)]
+struct Layout {
+    keys: Vec<Key>,
+    blocks: HashMap<Key, usize>,
+    offsets: Vec<usize>,
+    dims: Vec<usize>,
+}
+
+impl Layout {
+    /// The variables of the graph that aren't fixed, in the order of their keys
+    fn new(graph: &FactorGraph, values: &Values) -> Result<Self, GraphError> {
+        let keys: Vec<Key> = graph
+            .keys()
+            .into_iter()
+            .filter(|key| !graph.is_fixed(*key))
+            .collect();
+        let blocks = keys
+            .iter()
+            .enumerate()
+            .map(|(idx, key)| (*key, idx))
+            .collect();
+        let dims = keys
+            .iter()
+            .map(|key| Ok(values.get(*key)?.dim()))
+            .collect::<Result<Vec<usize>, GraphError>>()?;
+        let offsets = dims
+            .iter()
+            .scan(0, |offset, dim| {
+                let start = *offset;
+                *offset += dim;
+                Some(start)
+            })
+            .collect();
+        Ok(Self {
+            keys,
+            blocks,
+            offsets,
+            dims,
+        })
+    }
+
+    fn retract(&self, values: &Values, step: &DVector<f64>) -> Result<Values, GraphError> {
+        let mut retracted = values.clone();
+        for (idx, key) in self.keys.iter().enumerate() {
+            let (offset, dim) = (self.offsets[idx], self.dims[idx]);
+            retracted.retract(*key, &step.as_slice()[offset..offset + dim])?;
+        }
+        Ok(retracted)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct NormalEquations {
+    hessian: SparseBlockMatrix,
+    gradient: DVector<f64>,
+    cost: f64,
+}
+
+impl NormalEquations {
+    fn linearize(
+        graph: &FactorGraph,
+        values: &Values,
+        layout: &Layout,
+    ) -> Result<Self, GraphError> {
+        let mut hessian = SparseBlockMatrix::new(layout.dims.clone());
+        let mut gradient = DVector::zeros(hessian.dim());
+        let mut cost = 0.0;
+        for (id, factor) in graph.iter() {
+            let vars = values.gather(factor.keys())?;
+            let residual = factor.residual(&vars)?;
+            let jacobians = factor.jacobians(&vars)?;
+            let chi_square = residual.dot(&(factor.information() * &residual));
+
+            // robust kernels reweight the information at the current residual
+            let information = match graph.kernel(id) {
+                Some(kernel) => {
+                    cost += 0.5 * kernel.rho(chi_square);
+                    factor.information() * kernel.weight(chi_square)
+                }
+                None => {
+                    cost += 0.5 * chi_square;
+                    factor.information().clone()
+                }
+            };
+            let weighted_jacobians: Vec<DMatrix<f64>> = jacobians
+                .iter()
+                .map(|jacobian| jacobian.transpose() * &information)
+                .collect();
+            // fixed variables have no block
+            let blocks: Vec<Option<usize>> = factor
+                .keys()
+                .iter()
+                .map(|key| layout.blocks.get(key).copied())
+                .collect();
+            for (a, block_a) in blocks.iter().enumerate() {
+                let block_a = match block_a {
+                    Some(block) => *block,
+                    None => continue,
+                };
+                let mut rows = gradient.rows_mut(layout.offsets[block_a], layout.dims[block_a]);
+                rows += &weighted_jacobians[a] * &residual;
+                for (b, block_b) in blocks.iter().enumerate().skip(a) {
+                    if let Some(block_b) = block_b {
+                        let block = &weighted_jacobians[a] * &jacobians[b];
+                        hessian.add_block(block_a, *block_b, &block);
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            hessian,
+            gradient,
+            cost,
+        })
+    }
+
+    /// Solve `(H + lambda * diag(H)) * dx = -g`
+    fn solve(&self, lambda: f64, factorization: &Factorization) -> Option<DVector<f64>> {
+        let mut damped = self.hessian.clone();
+        if lambda > 0.0 {
+            damped.add_diagonal(&self.hessian.diagonal().map(|d| lambda * d.max(1e-6)));
+        }
+        let step = match factorization {
+            Factorization::Dense => -damped.to_dense().cholesky()?.solve(&self.gradient),
+            Factorization::Sparse(symbolic) => -symbolic.factorize(&damped)?.solve(&self.gradient),
+            Factorization::Schur(schur, symbolic) => {
+                let reduced = schur.reduce(&damped, &self.gradient)?;
+                let reduced_step = symbolic.factorize(&reduced.matrix)?.solve(&reduced.rhs);
+                schur.back_substitute(&damped, &self.gradient, &reduced, &reduced_step)
+            }
+        };
+        Some(step).filter(|step| step.iter().all(|x| x.is_finite()))
+    }
+
+    /// Decrease of the cost predicted by the linear model
+    #[allow(clippy::suboptimal_flops)]
+    fn predicted_decrease(&self, step: &DVector<f64>) -> f64 {
+        -(self.gradient.dot(step) + 0.5 * step.dot(&self.hessian.mul_vector(step)))
+    }
+}
+
+/// How the normal equations are solved, with the symbolic analyses of the sparse solvers - the
+/// pattern of the Hessian doesn't change between iterations
+#[derive(Debug)]
+enum Factorization {
+    Dense,
+    Sparse(SymbolicCholesky),
+    Schur(SchurComplement, SymbolicCholesky),
+}
+
+impl Factorization {
+    fn new(solver: LinearSolver, hessian: &SparseBlockMatrix, layout: &Layout) -> Self {
+        let analyze = |matrix: &SparseBlockMatrix| {
+            SymbolicCholesky::analyze(matrix, &amd_ordering(&matrix.block_pattern()))
+        };
+        match solver {
+            LinearSolver::DenseCholesky => Self::Dense,
+            LinearSolver::SparseCholesky => Self::Sparse(analyze(hessian)),
+            LinearSolver::SparseSchur => {
+                let landmarks: Vec<bool> = layout
+                    .keys
+                    .iter()
+                    .map(|key| matches!(key, Key::Landmark(_)))
+                    .collect();
+                let schur = SchurComplement::new(hessian, &landmarks);
+                let symbolic = analyze(&schur.structure(hessian));
+                Self::Schur(schur, symbolic)
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Optimizer
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default)]
+pub struct Optimizer {
+    params: OptimizerParams,
+}
+
+impl Optimizer {
+    pub const fn new(params: OptimizerParams) -> Self {
+        Self { params }
+    }
+
+    pub const fn params(&self) -> &OptimizerParams {
+        &self.params
+    }
+
+    /// Minimise the cost of the graph, starting from and updating `values`. Variables that aren't
+    /// constrained by any factor, or are fixed in the graph, are left untouched
+    #[allow(clippy::suboptimal_flops)]
+    pub fn optimize(
+        &self,
+        graph: &FactorGraph,
+        values: &mut Values,
+    ) -> Result<OptimizationReport, GraphError> {
+        let layout = Layout::new(graph, values)?;
+        let mut start = Instant::now();
+        let mut system = NormalEquations::linearize(graph, values, &layout)?;
+        let mut linearize_time = start.elapsed();
+        let initial_cost = system.cost;
+        let factorization = Factorization::new(self.params.linear_solver, &system.hessian, &layout);
+
+        let mut iterations = Vec::new();
+        let mut lambda = match self.params.algorithm {
+            Algorithm::GaussNewton => 0.0,
+            Algorithm::LevenbergMarquardt => self.params.initial_lambda,
+        };
+        // growth factor of the damping after rejected steps (Nielsen)
+        let mut nu = 2.0;
+
+        let termination = loop {
+            if system.cost < self.params.absolute_tolerance {
+                break Termination::AbsoluteCost;
+            }
+            if iterations.len() >= self.params.max_iterations {
+                break Termination::MaxIterations;
+            }
+
+            start = Instant::now();
+            let step = match system.solve(lambda, &factorization) {
+                Some(step) => step,
+                None if self.params.algorithm == Algorithm::LevenbergMarquardt => {
+                    lambda *= nu;
+                    nu *= 2.0;
+                    if lambda > self.params.max_lambda {
+                        break Termination::MaxDamping;
+                    }
+                    continue;
+                }
+                None => break Termination::LinearSolverFailed,
+            };
+            let step_norm = step.norm();
+            let candidate = layout.retract(values, &step)?;
+            let solve_time = start.elapsed();
+
+            start = Instant::now();
+            let candidate_system = match NormalEquations::linearize(graph, &candidate, &layout) {
+                Ok(candidate_system) => candidate_system,
+                // rejected like a step that increases the cost
+                Err(GraphError::BehindCamera { .. })
+                    if self.params.algorithm == Algorithm::LevenbergMarquardt =>
+                {
+                    NormalEquations {
+                        cost: f64::INFINITY,
+                        ..system.clone()
+                    }
+                }
+                Err(error) => return Err(error),
+            };
+            let cost_change = system.cost - candidate_system.cost;
+            let accepted = match self.params.algorithm {
+                Algorithm::GaussNewton => true,
+                Algorithm::LevenbergMarquardt => {
+                    let gain = cost_change / system.predicted_decrease(&step);
+                    if cost_change > 0.0 && gain > 0.0 {
+                        lambda *= (1.0 - (2.0 * gain - 1.0).powi(3)).max(1.0 / 3.0);
+                        nu = 2.0;
+                        true
+                    } else {
+                        lambda *= nu;
+                        nu *= 2.0;
+                        false
+                    }
+                }
+            };
+
+            let previous_cost = system.cost;
+            if accepted {
+                *values = candidate;
+                system = candidate_system;
+            }
+            iterations.push(IterationReport {
+                iteration: iterations.len(),
+                cost: system.cost,
+                step_norm,
+                lambda,
+                accepted,
+                linearize_time,
+                solve_time,
+            });
+            linearize_time = start.elapsed();
+            debug!(
+                "iteration {}: cost {:.6e}, step {:.3e}, lambda {:.1e}{}",
+                iterations.len() - 1,
+                system.cost,
+                step_norm,
+                lambda,
+                if accepted { "" } else { " (rejected)" }
+            );
+
+            if step_norm < self.params.step_tolerance {
+                break Termination::StepSize;
+            }
+            if accepted && cost_change.abs() <= self.params.relative_tolerance * previous_cost {
+                break Termination::RelativeDecrease;
+            }
+            if lambda > self.params.max_lambda {
+                break Termination::MaxDamping;
+            }
+        };
+
+        Ok(OptimizationReport {
+            initial_cost,
+            final_cost: system.cost,
+            iterations,
+            termination,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::factor::{BetweenFactor, PriorFactor};
+    use nalgebra::{Isometry3, Vector3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Poses on a circle with odometry and a loop closure, and a noisy initial guess
+    fn circle(n: u64, with_prior: bool) -> (FactorGraph, Values, Vec<Isometry3<f64>>) {
+        let truth: Vec<Isometry3<f64>> = (0..n)
+            .map(|i| {
+                let angle = i as f64 * std::f64::consts::PI * 2.0 / n as f64;
+                Isometry3::new(
+                    Vector3::new(5.0 * angle.cos(), 5.0 * angle.sin(), 0.1 * angle.sin()),
+                    Vector3::new(0.0, 0.0, angle + std::f64::consts::FRAC_PI_2),
+                )
+            })
+            .collect();
+
+        let mut graph = FactorGraph::new();
+        if with_prior {
+            graph.insert(PriorFactor::new(
+                Key::Pose(0),
+                truth[0],
+                DMatrix::identity(6, 6) * 1e4,
+            ));
+        }
+        for i in 0..n {
+            let j = (i + 1) % n;
+            graph.insert(BetweenFactor::new(
+                Key::Pose(i),
+                Key::Pose(j),
+                truth[i as usize].inverse() * truth[j as usize],
+                DMatrix::identity(6, 6),
+            ));
+        }
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut values = Values::new();
+        for (i, pose) in truth.iter().enumerate() {
+            let noise: Vec<f64> = (0..6).map(|_| rng.gen_range(-0.15, 0.15)).collect();
+            let noisy = crate::graph::Variable::Pose(*pose).retract(&noise).unwrap();
+            values.insert(Key::Pose(i as u64), noisy);
+        }
+        (graph, values, truth)
+    }
+
+    fn max_error(values: &Values, truth: &[Isometry3<f64>]) -> f64 {
+        truth
+            .iter()
+            .enumerate()
+            .map(|(i, pose)| {
+                let delta = pose.inverse() * values.pose(Key::Pose(i as u64)).unwrap();
+                delta.translation.vector.norm() + delta.rotation.angle()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn gauss_newton_and_levenberg_marquardt_converge() {
+        let configurations = [
+            (Algorithm::GaussNewton, LinearSolver::SparseCholesky),
+            (Algorithm::LevenbergMarquardt, LinearSolver::SparseCholesky),
+            (Algorithm::LevenbergMarquardt, LinearSolver::DenseCholesky),
+        ];
+        for &(algorithm, linear_solver) in &configurations {
+            let (graph, mut values, truth) = circle(20, true);
+            let optimizer = Optimizer::new(OptimizerParams {
+                algorithm,
+                linear_solver,
+                ..Default::default()
+            });
+            let report = optimizer.optimize(&graph, &mut values).unwrap();
+
+            assert!(report.termination.converged(), "{:?}", report.termination);
+            assert!(report.final_cost < 1e-10 * report.initial_cost);
+            assert!(report.iterations.len() < 20);
+            assert!(max_error(&values, &truth) < 1e-5);
+            if algorithm == Algorithm::LevenbergMarquardt {
+                // only descent steps are accepted
+                let costs: Vec<f64> = report.iterations.iter().map(|it| it.cost).collect();
+                assert!(costs.windows(2).all(|pair| pair[1] <= pair[0]));
+            }
+        }
+    }
+
+    #[test]
+    fn damping_handles_the_gauge_freedom() {
+        // without a prior the whole graph can move freely
+        let (graph, mut values, _) = circle(10, false);
+        let gauss_newton = Optimizer::new(OptimizerParams {
+            algorithm: Algorithm::GaussNewton,
+            ..Default::default()
+        });
+        let report = gauss_newton.optimize(&graph, &mut values.clone()).unwrap();
+        assert_eq!(report.termination, Termination::LinearSolverFailed);
+
+        let report = Optimizer::default().optimize(&graph, &mut values).unwrap();
+        assert!(report.termination.converged());
+        assert!(report.final_cost < 1e-10);
+    }
+
+    #[test]
+    fn iteration_limit_and_missing_variables() {
+        let (graph, mut values, _) = circle(10, true);
+        let optimizer = Optimizer::new(OptimizerParams {
+            max_iterations: 2,
+            ..Default::default()
+        });
+        let report = optimizer.optimize(&graph, &mut values).unwrap();
+        assert_eq!(report.termination, Termination::MaxIterations);
+        assert_eq!(report.iterations.len(), 2);
+        assert!(report.final_cost < report.initial_cost);
+
+        values.remove(Key::Pose(4));
+        assert_eq!(
+            optimizer.optimize(&graph, &mut values).unwrap_err(),
+            GraphError::MissingVariable(Key::Pose(4))
+        );
+    }
+}
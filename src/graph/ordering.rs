@@ -0,0 +1,131 @@
+/// Fill-reducing orderings for the sparse Cholesky factorisation
+///
+/// The variables are ordered at the block level - the graph of the blocks of a Hessian is much
+/// smaller than the graph of its scalar entries, and the blocks are dense anyway.
+use std::collections::BTreeSet;
+
+/// Approximate minimum degree ordering of a symmetric sparsity pattern, given as the neighbours of
+/// each node. Returns the order of elimination - `order[k]` is the `k`-th node eliminated.
+///
+/// "An Approximate Minimum Degree Ordering Algorithm", Amestoy, Davis and Duff, 1996. Eliminated
+/// nodes become elements of the quotient graph, absorbing the elements adjacent to them, and nodes
+/// are picked by their approximate external degree. Supervariables aren't detected.
+pub fn amd_ordering(adjacency: &[Vec<usize>]) -> Vec<usize> {
+    let n = adjacency.len();
+    // A_i - neighbouring variables not covered by any element
+    let mut variables: Vec<BTreeSet<usize>> = adjacency
+        .iter()
+        .enumerate()
+        .map(|(i, neighbours)| neighbours.iter().copied().filter(|&j| j != i).collect())
+        .collect();
+    // E_i - neighbouring elements, named after the node they were created from
+    let mut elements: Vec<Vec<usize>> = vec![Vec::new(); n];
+    // L_e - variables of each element
+    let mut element_vars: Vec<Vec<usize>> = vec![Vec::new(); n];
+
+    let mut degree: Vec<usize> = variables.iter().map(BTreeSet::len).collect();
+    let mut queue: BTreeSet<(usize, usize)> = degree.iter().copied().zip(0..n).collect();
+    let mut eliminated = vec![false; n];
+    let mut in_pivot = vec![false; n];
+    // |L_e \ L_p| of the elements next to the pivot element, stamped with the step
+    let (mut outside, mut stamp) = (vec![0_usize; n], vec![usize::MAX; n]);
+    let mut order = Vec::with_capacity(n);
+
+    while let Some(&(pivot_degree, pivot)) = queue.iter().next() {
+        queue.remove(&(pivot_degree, pivot));
+        eliminated[pivot] = true;
+        order.push(pivot);
+
+        // the new element L_p = (A_p U L_e for e in E_p) \ p, absorbing the elements of the pivot
+        let absorbed = std::mem::take(&mut elements[pivot]);
+        let mut pivot_vars = Vec::new();
+        let candidates = variables[pivot].iter().copied().chain(
+            absorbed
+                .iter()
+                .flat_map(|&e| element_vars[e].iter().copied()),
+        );
+        for i in candidates {
+            if !eliminated[i] && !in_pivot[i] {
+                in_pivot[i] = true;
+                pivot_vars.push(i);
+            }
+        }
+        for &e in &absorbed {
+            element_vars[e].clear();
+        }
+        variables[pivot].clear();
+
+        for &i in &pivot_vars {
+            variables[i].remove(&pivot);
+            for j in &pivot_vars {
+                variables[i].remove(j);
+            }
+            elements[i].retain(|e| !absorbed.contains(e));
+            elements[i].push(pivot);
+        }
+
+        for &i in &pivot_vars {
+            for &e in elements[i].iter().filter(|&&e| e != pivot) {
+                if stamp[e] != pivot {
+                    stamp[e] = pivot;
+                    outside[e] = element_vars[e].len();
+                }
+                outside[e] -= 1;
+            }
+        }
+
+        let remaining = n - order.len();
+        for &i in &pivot_vars {
+            let external: usize = elements[i]
+                .iter()
+                .filter(|&&e| e != pivot)
+                .map(|&e| outside[e])
+                .sum();
+            let approximate = variables[i].len() + pivot_vars.len() - 1 + external;
+            let new_degree = approximate
+                .min(degree[i] + pivot_vars.len() - 1)
+                .min(remaining - 1);
+            queue.remove(&(degree[i], i));
+            degree[i] = new_degree;
+            queue.insert((new_degree, i));
+        }
+
+        for &i in &pivot_vars {
+            in_pivot[i] = false;
+        }
+        element_vars[pivot] = pivot_vars;
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adjacency lists of an undirected graph
+    fn pattern(n: usize, edges: &[(usize, usize)]) -> Vec<Vec<usize>> {
+        let mut adjacency = vec![Vec::new(); n];
+        for &(a, b) in edges {
+            adjacency[a].push(b);
+            adjacency[b].push(a);
+        }
+        adjacency
+    }
+
+    #[test]
+    fn wheel_pattern_eliminates_the_hub_last() {
+        // node 0 is connected to all the others, which form a chain around it
+        let n = 8;
+        let edges: Vec<(usize, usize)> = (1..n)
+            .map(|i| (0, i))
+            .chain((1..n - 1).map(|i| (i, i + 1)))
+            .collect();
+        let order = amd_ordering(&pattern(n, &edges));
+
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..n).collect::<Vec<_>>());
+        // once three nodes are left they're all connected, and ties are broken by index
+        assert!(order[n - 3..].contains(&0));
+    }
+}
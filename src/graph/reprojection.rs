@@ -0,0 +1,550 @@
+/// Reprojection error of landmarks observed by the cameras of a rig - the factor of bundle
+/// adjustment
+///
+/// The pose variables are the poses of the body in the world, `T_WB`, and each camera is mounted
+/// on the body with its `T_BS` extrinsic, as in the calibrations of
+/// [`drivers::calibration`](crate::drivers::calibration). Landmarks are either Euclidean points
+/// of the world, or [`InverseDepthPoint`]s anchored in the camera of another pose.
+use crate::geometry::camera::CameraModel;
+use crate::geometry::lie;
+use crate::geometry::linalg::tangent_basis;
+use crate::graph::factor::Factor;
+use crate::graph::key::Key;
+use crate::graph::values::{GraphError, InverseDepthPoint, Variable};
+
+use nalgebra::{
+    DMatrix, DVector, Isometry3, Matrix2x3, Matrix3x2, Point2, Point3, Vector3, U2, U3,
+};
+use std::sync::Arc;
+
+/// Pixel residual `project(T_BS^-1 * T_WB^-1 * p_W) - measured` of a landmark observed from a pose
+#[derive(Debug, Clone)]
+pub struct ReprojectionFactor {
+    keys: [Key; 2],
+    measured: Point2<f64>,
+    camera: Arc<dyn CameraModel>,
+    t_bs: Isometry3<f64>,
+    information: DMatrix<f64>,
+}
+
+impl ReprojectionFactor {
+    pub fn new(
+        pose: Key,
+        landmark: Key,
+        measured: Point2<f64>,
+        camera: Arc<dyn CameraModel>,
+        t_bs: Isometry3<f64>,
+        information: DMatrix<f64>,
+    ) -> Self {
+        debug_assert_eq!(information.shape(), (2, 2));
+        Self {
+            keys: [pose, landmark],
+            measured,
+            camera,
+            t_bs,
+            information,
+        }
+    }
+
+    pub const fn measured(&self) -> &Point2<f64> {
+        &self.measured
+    }
+
+    /// The landmark in the body and the camera frames
+    fn transform(&self, vars: &[&Variable]) -> Result<(Point3<f64>, Point3<f64>), GraphError> {
+        let p_b = vars[0]
+            .as_pose()?
+            .inverse_transform_point(vars[1].as_point()?);
+        Ok((p_b, self.t_bs.inverse_transform_point(&p_b)))
+    }
+
+    const fn behind_camera(&self) -> GraphError {
+        GraphError::BehindCamera {
+            camera: self.keys[0],
+            landmark: self.keys[1],
+        }
+    }
+}
+
+impl Factor for ReprojectionFactor {
+    fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    fn dim(&self) -> usize {
+        2
+    }
+
+    fn information(&self) -> &DMatrix<f64> {
+        &self.information
+    }
+
+    fn residual(&self, vars: &[&Variable]) -> Result<DVector<f64>, GraphError> {
+        let (_, p_c) = self.transform(vars)?;
+        let projected = self
+            .camera
+            .project(&p_c)
+            .ok_or_else(|| self.behind_camera())?;
+        Ok(DVector::from_column_slice(
+            (projected - self.measured).as_slice(),
+        ))
+    }
+
+    fn jacobians(&self, vars: &[&Variable]) -> Result<Vec<DMatrix<f64>>, GraphError> {
+        let (p_b, p_c) = self.transform(vars)?;
+        let projection = self
+            .camera
+            .project_jacobian(&p_c)
+            .ok_or_else(|| self.behind_camera())?;
+        let r_sb = self
+            .t_bs
+            .rotation
+            .inverse()
+            .to_rotation_matrix()
+            .into_inner();
+        let r_bw = vars[0]
+            .as_pose()?
+            .rotation
+            .inverse()
+            .to_rotation_matrix()
+            .into_inner();
+
+        // the body point moves by skew(p_b) * dphi - dp
+        let to_camera: Matrix2x3<f64> = projection * r_sb;
+        let pose = pose_jacobian(&(to_camera * lie::skew(&p_b.coords)), &-to_camera);
+        let point = DMatrix::from_column_slice(2, 3, (to_camera * r_bw).as_slice());
+        Ok(vec![pose, point])
+    }
+}
+
+/// `2 x 6` Jacobian of a pose from its rotation and translation blocks
+fn pose_jacobian(rotation: &Matrix2x3<f64>, translation: &Matrix2x3<f64>) -> DMatrix<f64> {
+    let mut jacobian = DMatrix::zeros(2, 6);
+    jacobian.fixed_slice_mut::<U2, U3>(0, 0).copy_from(rotation);
+    jacobian
+        .fixed_slice_mut::<U2, U3>(0, 3)
+        .copy_from(translation);
+    jacobian
+}
+
+// -------------------------------------------------------------------------------------------------
+// InverseDepthFactor
+// -------------------------------------------------------------------------------------------------
+
+/// Reprojection of an [`InverseDepthPoint`] anchored in the camera of the anchor pose, observed
+/// from another pose or from the anchor itself
+///
+/// The landmark is projected through `h = R_CW * (R_WA * b + rho * (t_WA - t_WC))`, its position in
+/// the observing camera scaled by `rho` - central cameras only depend on the direction of the
+/// point. At `rho = 0` the residual still depends on the rotations.
+#[derive(Debug, Clone)]
+pub struct InverseDepthFactor {
+    /// `[anchor, pose, landmark]`, or `[pose, landmark]` when the anchor observes the landmark
+    keys: Vec<Key>,
+    measured: Point2<f64>,
+    camera: Arc<dyn CameraModel>,
+    t_bs: Isometry3<f64>,
+    information: DMatrix<f64>,
+}
+
+impl InverseDepthFactor {
+    pub fn new(
+        anchor: Key,
+        pose: Key,
+        landmark: Key,
+        measured: Point2<f64>,
+        camera: Arc<dyn CameraModel>,
+        t_bs: Isometry3<f64>,
+        information: DMatrix<f64>,
+    ) -> Self {
+        debug_assert_eq!(information.shape(), (2, 2));
+        let keys = if anchor == pose {
+            vec![pose, landmark]
+        } else {
+            vec![anchor, pose, landmark]
+        };
+        Self {
+            keys,
+            measured,
+            camera,
+            t_bs,
+            information,
+        }
+    }
+
+    pub const fn measured(&self) -> &Point2<f64> {
+        &self.measured
+    }
+
+    /// Anchor pose, observing pose and landmark
+    fn unpack<'a>(
+        &self,
+        vars: &[&'a Variable],
+    ) -> Result<
+        (
+            &'a Isometry3<f64>,
+            &'a Isometry3<f64>,
+            &'a InverseDepthPoint,
+        ),
+        GraphError,
+    > {
+        let (anchor, pose) = (vars[0].as_pose()?, vars[vars.len() - 2].as_pose()?);
+        Ok((anchor, pose, vars[vars.len() - 1].as_inverse_depth()?))
+    }
+
+    /// `R_WB^T * (R_WA * b + rho * (t_WA - t_WB))`, such that `h = R_BS^T * (u - rho * t_BS)`
+    fn body_direction(
+        &self,
+        anchor: &Isometry3<f64>,
+        pose: &Isometry3<f64>,
+        landmark: &InverseDepthPoint,
+    ) -> Vector3<f64> {
+        let t_wa = anchor * self.t_bs;
+        let direction = t_wa.rotation * landmark.bearing.into_inner()
+            + (t_wa.translation.vector - pose.translation.vector) * landmark.inverse_depth;
+        pose.rotation.inverse() * direction
+    }
+
+    fn behind_camera(&self) -> GraphError {
+        GraphError::BehindCamera {
+            camera: self.keys[self.keys.len() - 2],
+            landmark: self.keys[self.keys.len() - 1],
+        }
+    }
+}
+
+impl Factor for InverseDepthFactor {
+    fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    fn dim(&self) -> usize {
+        2
+    }
+
+    fn information(&self) -> &DMatrix<f64> {
+        &self.information
+    }
+
+    fn residual(&self, vars: &[&Variable]) -> Result<DVector<f64>, GraphError> {
+        let (anchor, pose, landmark) = self.unpack(vars)?;
+        let u = self.body_direction(anchor, pose, landmark);
+        let h = self.t_bs.rotation.inverse()
+            * (u - self.t_bs.translation.vector * landmark.inverse_depth);
+        let projected = self
+            .camera
+            .project(&Point3::from(h))
+            .ok_or_else(|| self.behind_camera())?;
+        Ok(DVector::from_column_slice(
+            (projected - self.measured).as_slice(),
+        ))
+    }
+
+    fn jacobians(&self, vars: &[&Variable]) -> Result<Vec<DMatrix<f64>>, GraphError> {
+        let (anchor, pose, landmark) = self.unpack(vars)?;
+        let rho = landmark.inverse_depth;
+        let u = self.body_direction(anchor, pose, landmark);
+        let r_sb = self
+            .t_bs
+            .rotation
+            .inverse()
+            .to_rotation_matrix()
+            .into_inner();
+        let h = r_sb * (u - self.t_bs.translation.vector * rho);
+        let projection = self
+            .camera
+            .project_jacobian(&Point3::from(h))
+            .ok_or_else(|| self.behind_camera())?;
+
+        // dh = R_SB * R_BW * dV for the changes dV of the direction in the world
+        let r_bw = pose.rotation.inverse().to_rotation_matrix().into_inner();
+        let to_world: Matrix2x3<f64> = projection * r_sb * r_bw;
+        let r_wanchor = anchor.rotation.to_rotation_matrix().into_inner();
+        let b_body = self.t_bs.rotation * landmark.bearing.into_inner();
+
+        // the observing pose moves u by skew(u) * dphi - rho * dp
+        let pose_rotation: Matrix2x3<f64> = projection * r_sb * lie::skew(&u);
+        let pose_translation: Matrix2x3<f64> = projection * r_sb * (-rho);
+        // the anchor moves V by -R_WA' * skew(R_BS * b + rho * t_BS) * dpsi + rho * R_WA' * dq
+        let anchor_rotation =
+            -to_world * r_wanchor * lie::skew(&(b_body + self.t_bs.translation.vector * rho));
+        let anchor_translation = to_world * r_wanchor * rho;
+
+        let t_wa = anchor * self.t_bs;
+        let bearing_basis: Matrix3x2<f64> = tangent_basis(&landmark.bearing).transpose();
+        let mut landmark_jacobian = DMatrix::zeros(2, 3);
+        landmark_jacobian
+            .fixed_slice_mut::<U2, U2>(0, 0)
+            .copy_from(&(to_world * t_wa.rotation.to_rotation_matrix().matrix() * bearing_basis));
+        let t_wc = pose * self.t_bs;
+        landmark_jacobian.set_column(
+            2,
+            &(to_world * (t_wa.translation.vector - t_wc.translation.vector)),
+        );
+
+        Ok(if self.keys.len() == 2 {
+            vec![
+                pose_jacobian(
+                    &(pose_rotation + anchor_rotation),
+                    &(pose_translation + anchor_translation),
+                ),
+                landmark_jacobian,
+            ]
+        } else {
+            vec![
+                pose_jacobian(&anchor_rotation, &anchor_translation),
+                pose_jacobian(&pose_rotation, &pose_translation),
+                landmark_jacobian,
+            ]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::camera::PinholeRadTan;
+    use crate::graph::factor::{numerical_jacobians, PriorFactor};
+    use crate::graph::factor_graph::FactorGraph;
+    use crate::graph::optimizer::{LinearSolver, Optimizer, OptimizerParams};
+    use crate::graph::values::Values;
+    use nalgebra::{Unit, Vector3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn camera() -> Arc<dyn CameraModel> {
+        Arc::new(PinholeRadTan::new(
+            [458.654, 457.296, 367.215, 248.375],
+            [-0.283, 0.074, 0.0002, 1.8e-5],
+            (752, 480),
+        ))
+    }
+
+    /// Camera looking along the body `x` axis
+    fn t_bs() -> Isometry3<f64> {
+        Isometry3::new(
+            Vector3::new(0.05, -0.02, 0.01),
+            Vector3::new(0.0, std::f64::consts::FRAC_PI_2, 0.0),
+        ) * Isometry3::new(
+            Vector3::zeros(),
+            Vector3::new(0.0, 0.0, -std::f64::consts::FRAC_PI_2),
+        )
+    }
+
+    #[test]
+    fn analytic_jacobians_match_numerical() {
+        let pose = Variable::Pose(Isometry3::new(
+            Vector3::new(1.0, -0.5, 0.2),
+            Vector3::new(0.1, -0.05, 0.3),
+        ));
+        let point = Variable::Point(Point3::new(5.0, 1.0, 0.5));
+        let factor = ReprojectionFactor::new(
+            Key::Pose(0),
+            Key::Landmark(0),
+            Point2::new(300.0, 200.0),
+            camera(),
+            t_bs(),
+            DMatrix::identity(2, 2),
+        );
+        let vars = [&pose, &point];
+        assert!(factor.residual(&vars).unwrap().norm() < 400.0);
+        let analytic = factor.jacobians(&vars).unwrap();
+        let numerical = numerical_jacobians(&factor, &vars).unwrap();
+        for (a, n) in analytic.iter().zip(&numerical) {
+            assert!((a - n).norm() < 1e-5 * n.norm(), "{} vs {}", a, n);
+        }
+
+        // behind the camera
+        let behind = Variable::Point(Point3::new(-5.0, 1.0, 0.5));
+        assert_eq!(
+            factor.residual(&[&pose, &behind]).unwrap_err(),
+            GraphError::BehindCamera {
+                camera: Key::Pose(0),
+                landmark: Key::Landmark(0)
+            }
+        );
+    }
+
+    #[test]
+    fn bundle_adjustment_with_schur_complement() {
+        let mut rng = StdRng::seed_from_u64(9);
+        // a rig moving sideways in front of a wall of points
+        let poses: Vec<Isometry3<f64>> = (0..6)
+            .map(|i| {
+                Isometry3::new(
+                    Vector3::new(0.0, 0.4 * f64::from(i), 0.1 * (f64::from(i)).sin()),
+                    Vector3::new(0.0, 0.0, 0.05 * f64::from(i)),
+                )
+            })
+            .collect();
+        let points: Vec<Point3<f64>> = (0..80)
+            .map(|_| {
+                Point3::new(
+                    rng.gen_range(4.0, 8.0),
+                    rng.gen_range(-1.5, 3.5),
+                    rng.gen_range(-1.5, 1.5),
+                )
+            })
+            .collect();
+
+        let (camera, t_bs) = (camera(), t_bs());
+        let mut graph = FactorGraph::new();
+        for (i, pose) in poses.iter().enumerate() {
+            for (j, point) in points.iter().enumerate() {
+                let p_c = t_bs.inverse_transform_point(&pose.inverse_transform_point(point));
+                if let Some(px) = camera
+                    .project(&p_c)
+                    .filter(|px| camera.is_in_image(px, 0.0))
+                {
+                    graph.insert(ReprojectionFactor::new(
+                        Key::Pose(i as u64),
+                        Key::Landmark(j as u64),
+                        px,
+                        camera.clone(),
+                        t_bs,
+                        DMatrix::identity(2, 2),
+                    ));
+                }
+            }
+        }
+        // the gauge - the first pose, and the scale with a prior on the second
+        graph.set_fixed(Key::Pose(0), true);
+        graph.insert(PriorFactor::new(
+            Key::Pose(1),
+            poses[1],
+            DMatrix::from_diagonal(&DVector::from_column_slice(&[0.0, 0.0, 0.0, 1e4, 1e4, 1e4])),
+        ));
+
+        let mut initial = Values::new();
+        for (i, pose) in poses.iter().enumerate() {
+            let noise: Vec<f64> = (0..6).map(|_| rng.gen_range(-0.02, 0.02)).collect();
+            let noisy = if i == 0 {
+                *pose
+            } else {
+                *Variable::Pose(*pose)
+                    .retract(&noise)
+                    .unwrap()
+                    .as_pose()
+                    .unwrap()
+            };
+            initial.insert(Key::Pose(i as u64), noisy);
+        }
+        for (j, point) in points.iter().enumerate() {
+            let noise = Vector3::new(
+                rng.gen_range(-0.1, 0.1),
+                rng.gen_range(-0.1, 0.1),
+                rng.gen_range(-0.1, 0.1),
+            );
+            initial.insert(Key::Landmark(j as u64), point + noise);
+        }
+
+        for &linear_solver in &[LinearSolver::SparseSchur, LinearSolver::SparseCholesky] {
+            let mut values = initial.clone();
+            let optimizer = Optimizer::new(OptimizerParams {
+                linear_solver,
+                ..Default::default()
+            });
+            let report = optimizer.optimize(&graph, &mut values).unwrap();
+            assert!(report.termination.converged(), "{:?}", report.termination);
+            assert!(report.final_cost < 1e-12 * report.initial_cost);
+            assert_eq!(values.pose(Key::Pose(0)).unwrap(), &poses[0]);
+            for (j, point) in points.iter().enumerate() {
+                assert!((values.point(Key::Landmark(j as u64)).unwrap() - point).norm() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_depth_jacobians_match_numerical() {
+        let anchor = Variable::Pose(Isometry3::new(
+            Vector3::new(0.5, 0.3, -0.1),
+            Vector3::new(-0.05, 0.02, 0.1),
+        ));
+        let pose = Variable::Pose(Isometry3::new(
+            Vector3::new(0.8, 1.0, 0.2),
+            Vector3::new(0.1, -0.05, 0.3),
+        ));
+        for &inverse_depth in &[0.2, 0.0] {
+            let landmark = Variable::InverseDepth(InverseDepthPoint {
+                bearing: Unit::new_normalize(Vector3::new(0.1, -0.2, 1.0)),
+                inverse_depth,
+            });
+            for &observer in &[1, 0] {
+                let factor = InverseDepthFactor::new(
+                    Key::Pose(0),
+                    Key::Pose(observer),
+                    Key::Landmark(0),
+                    Point2::new(300.0, 200.0),
+                    camera(),
+                    t_bs(),
+                    DMatrix::identity(2, 2),
+                );
+                let vars = if observer == 0 {
+                    vec![&anchor, &landmark]
+                } else {
+                    vec![&anchor, &pose, &landmark]
+                };
+                assert_eq!(factor.keys().len(), vars.len());
+                let analytic = factor.jacobians(&vars).unwrap();
+                let numerical = numerical_jacobians(&factor, &vars).unwrap();
+                for (a, n) in analytic.iter().zip(&numerical) {
+                    assert!((a - n).norm() < 1e-5 * n.norm().max(1.0), "{} vs {}", a, n);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn distant_landmarks_constrain_rotation() {
+        let t_bs = t_bs();
+        let anchor = Isometry3::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.1));
+        let pose = Isometry3::new(Vector3::new(0.3, 0.5, 0.0), Vector3::new(0.02, -0.01, 0.2));
+        let t_wa = anchor * t_bs;
+
+        // conversions to and from Euclidean points
+        let point = Point3::new(6.0, 1.0, -0.5);
+        let anchored = InverseDepthPoint::from_point(&t_wa, &point).unwrap();
+        assert!((anchored.to_point(&t_wa).unwrap() - point).norm() < 1e-12);
+        let at_infinity = InverseDepthPoint {
+            inverse_depth: 0.0,
+            ..anchored
+        };
+        assert!(at_infinity.to_point(&t_wa).is_none());
+
+        // landmarks at infinity, whose observations only depend on the rotation
+        let (camera, mut graph, mut values) = (camera(), FactorGraph::new(), Values::new());
+        let mut rng = StdRng::seed_from_u64(10);
+        for j in 0..20 {
+            let bearing = Vector3::new(rng.gen_range(-0.4, 0.4), rng.gen_range(-0.3, 0.3), 1.0);
+            let direction = t_wa.rotation * bearing;
+            let h = (pose * t_bs).rotation.inverse() * direction;
+            let landmark = Key::Landmark(j);
+            graph.insert(InverseDepthFactor::new(
+                Key::Pose(0),
+                Key::Pose(1),
+                landmark,
+                camera.project(&Point3::from(h)).unwrap(),
+                camera.clone(),
+                t_bs,
+                DMatrix::identity(2, 2),
+            ));
+            graph.set_fixed(landmark, true);
+            let landmark_point = InverseDepthPoint {
+                bearing: Unit::new_normalize(bearing),
+                inverse_depth: 0.0,
+            };
+            values.insert(landmark, landmark_point);
+        }
+        graph.set_fixed(Key::Pose(0), true);
+        values.insert(Key::Pose(0), anchor);
+        let noisy = Variable::Pose(pose)
+            .retract(&[0.03, -0.02, 0.05, 0.1, 0.1, -0.1])
+            .unwrap();
+        values.insert(Key::Pose(1), noisy);
+
+        let report = Optimizer::default().optimize(&graph, &mut values).unwrap();
+        assert!(report.final_cost < 1e-12, "{:?}", report);
+        let estimated = values.pose(Key::Pose(1)).unwrap();
+        assert!((estimated.rotation.inverse() * pose.rotation).angle() < 1e-6);
+    }
+}
@@ -0,0 +1,350 @@
+/// Robust kernels and outlier classification
+///
+/// A kernel replaces the cost `0.5 * s` of a factor, with `s = r' * W * r` its squared Mahalanobis
+/// distance, by `0.5 * rho(s)`, which grows slower for large residuals. The optimiser minimises it
+/// by iteratively reweighted least squares - the information of the factor is scaled by the
+/// weight `rho'(s)` at every linearisation.
+use crate::graph::factor_graph::{FactorGraph, FactorId};
+use crate::graph::values::{GraphError, Values};
+
+/// Robust loss, parametrised by the scale of the squared distance beyond which residuals are
+/// down-weighted (except for DCS, parametrised by its `phi`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RobustKernel {
+    /// Quadratic below `delta`, linear beyond
+    Huber(f64),
+    Cauchy(f64),
+    /// Ignores residuals beyond `c`
+    Tukey(f64),
+    GemanMcClure(f64),
+    /// Dynamic Covariance Scaling - "Robust Map Optimization using Dynamic Covariance Scaling",
+    /// Agarwal et al., 2013
+    Dcs(f64),
+}
+
+impl RobustKernel {
+    /// Robust cost of the squared distance `s`
+    #[allow(clippy::suboptimal_flops)]
+    pub fn rho(&self, s: f64) -> f64 {
+        match *self {
+            Self::Huber(delta) => {
+                if s <= delta * delta {
+                    s
+                } else {
+                    2.0 * delta * s.sqrt() - delta * delta
+                }
+            }
+            Self::Cauchy(c) => c * c * (s / (c * c)).ln_1p(),
+            Self::Tukey(c) => {
+                let c2 = c * c;
+                if s <= c2 {
+                    c2 / 3.0 * (1.0 - (1.0 - s / c2).powi(3))
+                } else {
+                    c2 / 3.0
+                }
+            }
+            Self::GemanMcClure(c) => c * c * s / (c * c + s),
+            Self::Dcs(phi) => {
+                if s <= phi {
+                    s
+                } else {
+                    phi * (3.0 * s - phi) / (phi + s)
+                }
+            }
+        }
+    }
+
+    /// Weight of the squared distance `s` in the reweighted least squares, `rho'(s)`. For DCS it's
+    /// the square of the scaling of the residual
+    // the squares of c2 and of c2 + s
+    #[allow(clippy::suspicious_operation_groupings)]
+    pub fn weight(&self, s: f64) -> f64 {
+        match *self {
+            Self::Huber(delta) => {
+                if s <= delta * delta {
+                    1.0
+                } else {
+                    delta / s.sqrt()
+                }
+            }
+            Self::Cauchy(c) => 1.0 / (1.0 + s / (c * c)),
+            Self::Tukey(c) => {
+                let c2 = c * c;
+                if s <= c2 {
+                    (1.0 - s / c2).powi(2)
+                } else {
+                    0.0
+                }
+            }
+            Self::GemanMcClure(c) => {
+                let c2 = c * c;
+                c2 * c2 / ((c2 + s) * (c2 + s))
+            }
+            Self::Dcs(phi) => (2.0 * phi / (phi + s)).min(1.0).powi(2),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Chi-square classification
+// -------------------------------------------------------------------------------------------------
+
+/// `ln(Gamma(x))` for `x > 0`, with the Lanczos approximation
+#[allow(clippy::suboptimal_flops)]
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // reflection
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let series = COEFFS
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(COEFFS[0], |acc, (i, c)| acc + c / (x + i as f64));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+/// Regularised lower incomplete gamma function `P(a, x)`, by its series or its continued fraction
+#[allow(clippy::suboptimal_flops)]
+fn lower_incomplete_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefactor = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let (mut term, mut sum) = (1.0 / a, 1.0 / a);
+        for n in 1..500 {
+            term *= x / (a + f64::from(n));
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        return sum * prefactor;
+    }
+
+    // modified Lentz for the continued fraction of Q(a, x)
+    let tiny = 1e-300;
+    let mut b = x + 1.0 - a;
+    let (mut c, mut d) = (1.0 / tiny, 1.0 / b);
+    let mut h = d;
+    for i in 1..500 {
+        let an = -f64::from(i) * (f64::from(i) - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < tiny {
+            d = tiny;
+        }
+        c = b + an / c;
+        if c.abs() < tiny {
+            c = tiny;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    1.0 - prefactor * h
+}
+
+/// Value below which a chi-square variable with `dof` degrees of freedom falls with the given
+/// probability
+pub fn chi_square_quantile(dof: usize, probability: f64) -> f64 {
+    let a = dof as f64 / 2.0;
+    let cdf = |x: f64| lower_incomplete_gamma(a, x / 2.0);
+    let (mut lower, mut upper) = (0.0, dof as f64 + 1.0);
+    for _ in 0..64 {
+        if cdf(upper) >= probability {
+            break;
+        }
+        lower = upper;
+        upper *= 2.0;
+    }
+    for _ in 0..100 {
+        let mid = 0.5 * (lower + upper);
+        if cdf(mid) < probability {
+            lower = mid;
+        } else {
+            upper = mid;
+        }
+    }
+    0.5 * (lower + upper)
+}
+
+#[derive(Debug, Clone)]
+pub struct FactorClassification {
+    pub id: FactorId,
+    /// Squared Mahalanobis distance of the residual, `r' * W * r`
+    pub chi_square: f64,
+    pub inlier: bool,
+}
+
+/// Classify the factors as inliers or outliers at the given confidence, e.g. 0.95
+///
+/// The squared distance of each residual is tested against the chi-square distribution with as
+/// many degrees of freedom as the residual. Typically run after optimising with robust kernels.
+pub fn classify_factors(
+    graph: &FactorGraph,
+    values: &Values,
+    confidence: f64,
+) -> Result<Vec<FactorClassification>, GraphError> {
+    let mut thresholds = std::collections::HashMap::new();
+    graph
+        .iter()
+        .map(|(id, factor)| {
+            let chi_square = 2.0 * factor.error(&values.gather(factor.keys())?)?;
+            let threshold = *thresholds
+                .entry(factor.dim())
+                .or_insert_with(|| chi_square_quantile(factor.dim(), confidence));
+            Ok(FactorClassification {
+                id,
+                chi_square,
+                inlier: chi_square <= threshold,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::factor::{BetweenFactor, PriorFactor};
+    use crate::graph::key::Key;
+    use crate::graph::optimizer::Optimizer;
+    use nalgebra::{DMatrix, Isometry3, Vector3};
+
+    const KERNELS: [RobustKernel; 5] = [
+        RobustKernel::Huber(1.5),
+        RobustKernel::Cauchy(1.5),
+        RobustKernel::Tukey(1.5),
+        RobustKernel::GemanMcClure(1.5),
+        RobustKernel::Dcs(1.5),
+    ];
+
+    #[test]
+    fn kernel_shapes() {
+        for kernel in &KERNELS {
+            // quadratic around zero
+            assert!((kernel.rho(1e-6) - 1e-6).abs() < 1e-9);
+            assert!((kernel.weight(0.0) - 1.0).abs() < 1e-12);
+            let mut previous = 0.0;
+            for i in 1..100 {
+                let s = f64::from(i) * 0.5;
+                assert!(kernel.rho(s) >= previous - 1e-12, "{:?}", kernel);
+                assert!(kernel.rho(s) <= s + 1e-12);
+                assert!(kernel.weight(s) <= 1.0 && kernel.weight(s) >= 0.0);
+                previous = kernel.rho(s);
+                let slope = (kernel.rho(s + 1e-6) - kernel.rho(s - 1e-6)) / 2e-6;
+                assert!((slope - kernel.weight(s)).abs() < 1e-6, "{:?}", kernel);
+            }
+            // outliers are down-weighted
+            assert!(kernel.weight(100.0) < 0.2);
+        }
+    }
+
+    #[test]
+    fn chi_square_quantiles() {
+        for &(dof, probability, expected) in &[
+            (1, 0.95, 3.841_458_820_694_124),
+            (2, 0.95, 5.991_464_547_107_979),
+            (3, 0.99, 11.344_866_730_144_373),
+            (6, 0.95, 12.591_587_243_743_977),
+            (15, 0.5, 14.338_859_510_956_18),
+        ] {
+            assert!((chi_square_quantile(dof, probability) - expected).abs() < 1e-8);
+        }
+    }
+
+    #[test]
+    fn robust_optimisation_rejects_false_loop_closures() {
+        let truth: Vec<Isometry3<f64>> = (0..12)
+            .map(|i| {
+                let angle = f64::from(i) * std::f64::consts::PI / 6.0;
+                Isometry3::new(
+                    Vector3::new(3.0 * angle.cos(), 3.0 * angle.sin(), 0.0),
+                    Vector3::new(0.0, 0.0, angle),
+                )
+            })
+            .collect();
+        let information = DMatrix::identity(6, 6) * 100.0;
+        let mut graph = FactorGraph::new();
+        graph.insert(PriorFactor::new(
+            Key::Pose(0),
+            truth[0],
+            information.clone(),
+        ));
+        for i in 0..12 {
+            let j = (i + 1) % 12;
+            graph.insert(BetweenFactor::new(
+                Key::Pose(i as u64),
+                Key::Pose(j as u64),
+                truth[i].inverse() * truth[j],
+                information.clone(),
+            ));
+        }
+        // wrong loop closures
+        let outliers: Vec<FactorId> = [(2, 8), (4, 10)]
+            .iter()
+            .map(|&(i, j)| {
+                graph.insert(BetweenFactor::new(
+                    Key::Pose(i),
+                    Key::Pose(j),
+                    Isometry3::translation(0.5, 0.0, 0.0),
+                    information.clone(),
+                ))
+            })
+            .collect();
+
+        let mut initial = Values::new();
+        for (i, pose) in truth.iter().enumerate() {
+            initial.insert(Key::Pose(i as u64), *pose);
+        }
+        let max_error = |values: &Values| -> f64 {
+            truth
+                .iter()
+                .enumerate()
+                .map(|(i, pose)| {
+                    (pose.translation.vector
+                        - values.pose(Key::Pose(i as u64)).unwrap().translation.vector)
+                        .norm()
+                })
+                .fold(0.0, f64::max)
+        };
+
+        let mut values = initial.clone();
+        Optimizer::default().optimize(&graph, &mut values).unwrap();
+        assert!(max_error(&values) > 0.5);
+
+        let ids: Vec<FactorId> = graph.iter().map(|(id, _)| id).collect();
+        for kernel in &[RobustKernel::Cauchy(1.0), RobustKernel::Dcs(1.0)] {
+            for id in &ids {
+                graph.set_kernel(*id, Some(*kernel));
+            }
+            let mut values = initial.clone();
+            Optimizer::default().optimize(&graph, &mut values).unwrap();
+            // Cauchy never fully ignores the outliers
+            assert!(max_error(&values) < 1e-2, "{:?}", kernel);
+
+            let classification = classify_factors(&graph, &values, 0.95).unwrap();
+            for factor in classification {
+                assert_eq!(factor.inlier, !outliers.contains(&factor.id));
+            }
+        }
+    }
+}
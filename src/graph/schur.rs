@@ -0,0 +1,255 @@
+/// Schur complement of the landmarks of bundle adjustment problems
+///
+/// Landmarks that don't share a factor with other landmarks have block-diagonal Hessians, so they
+/// are cheap to eliminate. What remains is the reduced camera system `S * dx_c = r`, with
+///
+/// `S = H_cc - H_cl * H_ll^-1 * H_lc` and `r = -g_c + H_cl * H_ll^-1 * g_l`,
+///
+/// much smaller than the full system. The landmark steps are recovered by back-substitution,
+/// `dx_l = H_ll^-1 * (-g_l - H_lc * dx_c)`.
+use crate::graph::sparse::SparseBlockMatrix;
+
+use nalgebra::{DMatrix, DVector};
+use std::collections::BTreeSet;
+
+/// Partition of the blocks of a Hessian into the eliminated landmarks and the reduced system
+#[derive(Debug, Clone)]
+pub struct SchurComplement {
+    /// Blocks eliminated with the Schur complement
+    eliminated: Vec<usize>,
+    /// Index of each block in the reduced system, `None` for the eliminated ones
+    reduced: Vec<Option<usize>>,
+    reduced_dims: Vec<usize>,
+    /// Blocks of the reduced system connected to each eliminated block, as block indices of the
+    /// full matrix
+    neighbours: Vec<Vec<usize>>,
+}
+
+/// The reduced camera system, and what's needed to recover the eliminated steps
+#[derive(Debug, Clone)]
+pub struct ReducedSystem {
+    pub matrix: SparseBlockMatrix,
+    pub rhs: DVector<f64>,
+    /// `H_ll^-1` of the eliminated blocks
+    inverses: Vec<DMatrix<f64>>,
+}
+
+impl SchurComplement {
+    /// Eliminate the blocks flagged in `eliminable`, except those connected to another eliminated
+    /// block, which stay in the reduced system
+    pub fn new(hessian: &SparseBlockMatrix, eliminable: &[bool]) -> Self {
+        let pattern = hessian.block_pattern();
+        let mut is_eliminated = vec![false; hessian.n_blocks()];
+        for block in 0..hessian.n_blocks() {
+            is_eliminated[block] =
+                eliminable[block] && pattern[block].iter().all(|&other| !is_eliminated[other]);
+        }
+
+        let mut reduced = vec![None; hessian.n_blocks()];
+        let mut reduced_dims = Vec::new();
+        let mut eliminated = Vec::new();
+        for block in 0..hessian.n_blocks() {
+            if is_eliminated[block] {
+                eliminated.push(block);
+            } else {
+                reduced[block] = Some(reduced_dims.len());
+                reduced_dims.push(hessian.block_dims()[block]);
+            }
+        }
+        let neighbours = eliminated
+            .iter()
+            .map(|&block| {
+                let mut neighbours = pattern[block].clone();
+                neighbours.sort_unstable();
+                neighbours.dedup();
+                neighbours
+            })
+            .collect();
+
+        Self {
+            eliminated,
+            reduced,
+            reduced_dims,
+            neighbours,
+        }
+    }
+
+    pub fn n_eliminated(&self) -> usize {
+        self.eliminated.len()
+    }
+
+    /// Block `(row, col)` of the full matrix, from its upper triangle
+    fn block(hessian: &SparseBlockMatrix, row: usize, col: usize) -> DMatrix<f64> {
+        if row <= col {
+            hessian.block(row, col).cloned()
+        } else {
+            hessian.block(col, row).map(DMatrix::transpose)
+        }
+        .unwrap_or_else(|| DMatrix::zeros(hessian.block_dims()[row], hessian.block_dims()[col]))
+    }
+
+    /// Reduced matrix with zero blocks - the sparsity pattern shared by all the reduced systems
+    pub fn structure(&self, hessian: &SparseBlockMatrix) -> SparseBlockMatrix {
+        let mut structure = SparseBlockMatrix::new(self.reduced_dims.clone());
+        let mut pairs = BTreeSet::new();
+        for (row, col, _) in hessian.blocks() {
+            if let (Some(row), Some(col)) = (self.reduced[row], self.reduced[col]) {
+                pairs.insert((row, col));
+            }
+        }
+        for neighbours in &self.neighbours {
+            for (i, a) in neighbours.iter().enumerate() {
+                for b in &neighbours[i..] {
+                    pairs.insert((self.reduced[*a].unwrap(), self.reduced[*b].unwrap()));
+                }
+            }
+        }
+        pairs.extend((0..self.reduced_dims.len()).map(|block| (block, block)));
+        for (row, col) in pairs {
+            let zeros = DMatrix::zeros(self.reduced_dims[row], self.reduced_dims[col]);
+            structure.add_block(row, col, &zeros);
+        }
+        structure
+    }
+
+    /// Eliminate the landmarks from `H * dx = -g`. `None` if the Hessian of a landmark isn't
+    /// positive definite
+    pub fn reduce(
+        &self,
+        hessian: &SparseBlockMatrix,
+        gradient: &DVector<f64>,
+    ) -> Option<ReducedSystem> {
+        let mut matrix = self.structure(hessian);
+        for (row, col, block) in hessian.blocks() {
+            if let (Some(row), Some(col)) = (self.reduced[row], self.reduced[col]) {
+                matrix.add_block(row, col, block);
+            }
+        }
+        let mut rhs = DVector::zeros(matrix.dim());
+        for (block, reduced) in self.reduced.iter().enumerate() {
+            if let Some(reduced) = reduced {
+                let dim = self.reduced_dims[*reduced];
+                rhs.rows_mut(matrix.offset(*reduced), dim)
+                    .copy_from(&-gradient.rows(hessian.offset(block), dim));
+            }
+        }
+
+        let mut inverses = Vec::with_capacity(self.eliminated.len());
+        for (&landmark, neighbours) in self.eliminated.iter().zip(&self.neighbours) {
+            let inverse = hessian
+                .block(landmark, landmark)?
+                .clone()
+                .cholesky()?
+                .inverse();
+            let g_l = gradient.rows(hessian.offset(landmark), hessian.block_dims()[landmark]);
+            // H_cl * H_ll^-1 of each neighbour
+            let products: Vec<DMatrix<f64>> = neighbours
+                .iter()
+                .map(|&camera| Self::block(hessian, camera, landmark) * &inverse)
+                .collect();
+            for (i, &a) in neighbours.iter().enumerate() {
+                let row = self.reduced[a].unwrap();
+                let mut rhs_rows = rhs.rows_mut(matrix.offset(row), self.reduced_dims[row]);
+                rhs_rows += &products[i] * g_l;
+                for &b in &neighbours[i..] {
+                    let h_lb = Self::block(hessian, landmark, b);
+                    matrix.add_block(row, self.reduced[b].unwrap(), &-(&products[i] * h_lb));
+                }
+            }
+            inverses.push(inverse);
+        }
+        Some(ReducedSystem {
+            matrix,
+            rhs,
+            inverses,
+        })
+    }
+
+    /// Full step from the step of the reduced system
+    pub fn back_substitute(
+        &self,
+        hessian: &SparseBlockMatrix,
+        gradient: &DVector<f64>,
+        system: &ReducedSystem,
+        reduced_step: &DVector<f64>,
+    ) -> DVector<f64> {
+        let mut step = DVector::zeros(hessian.dim());
+        for (block, reduced) in self.reduced.iter().enumerate() {
+            if let Some(reduced) = reduced {
+                let dim = self.reduced_dims[*reduced];
+                step.rows_mut(hessian.offset(block), dim)
+                    .copy_from(&reduced_step.rows(system.matrix.offset(*reduced), dim));
+            }
+        }
+        for ((&landmark, neighbours), inverse) in self
+            .eliminated
+            .iter()
+            .zip(&self.neighbours)
+            .zip(&system.inverses)
+        {
+            let dim = hessian.block_dims()[landmark];
+            let mut rhs = -gradient.rows(hessian.offset(landmark), dim);
+            for &camera in neighbours {
+                let camera_step = step.rows(hessian.offset(camera), hessian.block_dims()[camera]);
+                rhs -= Self::block(hessian, landmark, camera) * camera_step;
+            }
+            step.rows_mut(hessian.offset(landmark), dim)
+                .copy_from(&(inverse * rhs));
+        }
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn reduced_solution_matches_the_full_one() {
+        // 3 cameras (6) and 8 landmarks (3), each landmark seen by 2 cameras, and a weak
+        // camera-camera link. The last landmark is connected to the one before it, so stays
+        let mut rng = StdRng::seed_from_u64(8);
+        let dims: Vec<usize> = vec![6, 6, 6].into_iter().chain(vec![3; 8]).collect();
+        let mut hessian = SparseBlockMatrix::new(dims.clone());
+        let mut connect = |hessian: &mut SparseBlockMatrix, a: usize, b: usize| {
+            let jacobian = DMatrix::from_fn(dims[a] + dims[b], dims[a] + dims[b], |_, _| {
+                rng.gen_range(-1.0, 1.0)
+            });
+            let block = jacobian.transpose() * jacobian;
+            let (da, db) = (dims[a], dims[b]);
+            hessian.add_block(a, a, &block.slice((0, 0), (da, da)).into_owned());
+            hessian.add_block(a, b, &block.slice((0, da), (da, db)).into_owned());
+            hessian.add_block(b, b, &block.slice((da, da), (db, db)).into_owned());
+        };
+        connect(&mut hessian, 0, 1);
+        for landmark in 3..11 {
+            connect(&mut hessian, landmark % 3, landmark);
+            connect(&mut hessian, (landmark + 1) % 3, landmark);
+        }
+        connect(&mut hessian, 9, 10);
+        let gradient = DVector::from_fn(hessian.dim(), |_, _| rng.gen_range(-1.0, 1.0));
+
+        let eliminable: Vec<bool> = (0..11).map(|block| block >= 3).collect();
+        let schur = SchurComplement::new(&hessian, &eliminable);
+        assert_eq!(schur.n_eliminated(), 7);
+
+        let reduced = schur.reduce(&hessian, &gradient).unwrap();
+        assert_eq!(reduced.matrix.dim(), 21);
+        assert_eq!(
+            reduced.matrix.n_stored_blocks(),
+            schur.structure(&hessian).n_stored_blocks()
+        );
+        let reduced_step = reduced
+            .matrix
+            .to_dense()
+            .cholesky()
+            .unwrap()
+            .solve(&reduced.rhs);
+        let step = schur.back_substitute(&hessian, &gradient, &reduced, &reduced_step);
+
+        let expected = -hessian.to_dense().cholesky().unwrap().solve(&gradient);
+        assert!((step - expected).norm() < 1e-9);
+    }
+}
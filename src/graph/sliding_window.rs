@@ -0,0 +1,526 @@
+/// Fixed-lag smoothing - optimisation of the last keyframes only
+///
+/// The smoother keeps the variables of the last `window_size` keyframes (pose, velocity and
+/// biases, which share the index of the keyframe) in the graph. When a keyframe leaves the window
+/// its variables are marginalised: the factors that involve them are linearised, the marginalised
+/// variables are eliminated with the Schur complement, and what they knew about the remaining
+/// variables is kept as a dense [`MarginalPriorFactor`]. Landmarks left without any other factor
+/// are marginalised along with the keyframe.
+///
+/// The marginal prior is linear around the estimates of its variables at the time of the
+/// marginalisation. To keep the estimator consistent, every factor of these variables evaluates
+/// its Jacobians at the same first estimates - otherwise the optimiser gains spurious information
+/// along the unobservable directions.
+///
+/// For more information see:
+///
+/// - Leutenegger et al., "Keyframe-based visual-inertial odometry using nonlinear optimization",
+///   IJRR 2015
+/// - Huang et al., "A first-estimates Jacobian EKF for improving SLAM consistency", ISER 2008
+use crate::graph::factor::Factor;
+use crate::graph::factor_graph::FactorGraph;
+use crate::graph::key::Key;
+use crate::graph::optimizer::{OptimizationReport, Optimizer, OptimizerParams};
+use crate::graph::robust::RobustKernel;
+use crate::graph::values::{GraphError, Values, Variable};
+
+use nalgebra::{DMatrix, DVector};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, RwLock};
+
+/// Eigenvalues below this fraction of the largest one are treated as zero when inverting the
+/// marginalised block and factorising the prior
+const RANK_TOLERANCE: f64 = 1e-9;
+
+type FirstEstimates = Arc<RwLock<BTreeMap<Key, Variable>>>;
+
+// -------------------------------------------------------------------------------------------------
+// MarginalPriorFactor
+// -------------------------------------------------------------------------------------------------
+
+/// Dense Gaussian prior on several variables, left by the marginalisation of others
+///
+/// The cost `0.5 * dx' * H * dx + b' * dx`, with `dx` the stacked tangent vectors from the
+/// linearisation points to the variables, is written as `0.5 * |r0 + J * dx|^2` with `H = J' * J`
+/// and `b = J' * r0`. The Jacobian `J` stays the one of the linearisation points.
+#[derive(Debug, Clone)]
+pub struct MarginalPriorFactor {
+    keys: Vec<Key>,
+    linearization_points: Vec<Variable>,
+    jacobian: DMatrix<f64>,
+    offset: DVector<f64>,
+    information: DMatrix<f64>,
+}
+
+impl MarginalPriorFactor {
+    /// Prior of the quadratic cost with Hessian `hessian` and gradient `gradient` around the
+    /// linearisation points. Returns `None` if the Hessian carries no information
+    pub fn new(
+        keys: Vec<Key>,
+        linearization_points: Vec<Variable>,
+        hessian: &DMatrix<f64>,
+        gradient: &DVector<f64>,
+    ) -> Option<Self> {
+        let eigen = hessian.clone().symmetric_eigen();
+        let largest = eigen.eigenvalues.max();
+        if largest <= 0.0 {
+            return None;
+        }
+        let rank: Vec<usize> = (0..eigen.eigenvalues.len())
+            .filter(|&k| eigen.eigenvalues[k] > RANK_TOLERANCE * largest)
+            .collect();
+
+        let mut jacobian = DMatrix::zeros(rank.len(), hessian.ncols());
+        let mut offset = DVector::zeros(rank.len());
+        for (row, &k) in rank.iter().enumerate() {
+            let (value, vector) = (eigen.eigenvalues[k], eigen.eigenvectors.column(k));
+            jacobian.set_row(row, &(vector.transpose() * value.sqrt()));
+            offset[row] = vector.dot(gradient) / value.sqrt();
+        }
+        Some(Self {
+            keys,
+            linearization_points,
+            jacobian,
+            offset,
+            information: DMatrix::identity(rank.len(), rank.len()),
+        })
+    }
+
+    pub fn linearization_points(&self) -> &[Variable] {
+        &self.linearization_points
+    }
+}
+
+impl Factor for MarginalPriorFactor {
+    fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    fn dim(&self) -> usize {
+        self.offset.len()
+    }
+
+    fn information(&self) -> &DMatrix<f64> {
+        &self.information
+    }
+
+    fn residual(&self, vars: &[&Variable]) -> Result<DVector<f64>, GraphError> {
+        let mut residual = self.offset.clone();
+        let mut offset = 0;
+        for (point, var) in self.linearization_points.iter().zip(vars) {
+            let delta = point.local(var)?;
+            residual += self.jacobian.columns(offset, delta.len()) * delta;
+            offset += point.dim();
+        }
+        Ok(residual)
+    }
+
+    fn jacobians(&self, _vars: &[&Variable]) -> Result<Vec<DMatrix<f64>>, GraphError> {
+        let mut offset = 0;
+        Ok(self
+            .linearization_points
+            .iter()
+            .map(|point| {
+                let block = self.jacobian.columns(offset, point.dim()).into_owned();
+                offset += point.dim();
+                block
+            })
+            .collect())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FirstEstimateFactor
+// -------------------------------------------------------------------------------------------------
+
+/// Evaluates the Jacobians of a factor at the first estimates of the variables that have one
+#[derive(Debug)]
+struct FirstEstimateFactor {
+    inner: Box<dyn Factor>,
+    first_estimates: FirstEstimates,
+}
+
+impl Factor for FirstEstimateFactor {
+    fn keys(&self) -> &[Key] {
+        self.inner.keys()
+    }
+
+    fn dim(&self) -> usize {
+        self.inner.dim()
+    }
+
+    fn information(&self) -> &DMatrix<f64> {
+        self.inner.information()
+    }
+
+    fn residual(&self, vars: &[&Variable]) -> Result<DVector<f64>, GraphError> {
+        self.inner.residual(vars)
+    }
+
+    fn jacobians(&self, vars: &[&Variable]) -> Result<Vec<DMatrix<f64>>, GraphError> {
+        let first_estimates = self
+            .first_estimates
+            .read()
+            .expect("Poisoned first estimates");
+        let vars: Vec<&Variable> = self
+            .keys()
+            .iter()
+            .zip(vars)
+            .map(|(key, &var)| first_estimates.get(key).unwrap_or(var))
+            .collect();
+        self.inner.jacobians(&vars)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// SlidingWindow
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct SlidingWindowParams {
+    /// Number of keyframes kept in the graph
+    pub window_size: usize,
+    pub optimizer: OptimizerParams,
+}
+
+impl Default for SlidingWindowParams {
+    fn default() -> Self {
+        Self {
+            window_size: 10,
+            optimizer: OptimizerParams::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SlidingWindow {
+    params: SlidingWindowParams,
+    graph: FactorGraph,
+    values: Values,
+    /// Indices of the keyframes in the window, oldest first
+    keyframes: VecDeque<u64>,
+    /// Linearisation points of the variables of the marginal prior
+    first_estimates: FirstEstimates,
+}
+
+impl SlidingWindow {
+    pub fn new(params: SlidingWindowParams) -> Self {
+        Self {
+            params,
+            graph: FactorGraph::new(),
+            values: Values::new(),
+            keyframes: VecDeque::new(),
+            first_estimates: FirstEstimates::default(),
+        }
+    }
+
+    pub const fn params(&self) -> &SlidingWindowParams {
+        &self.params
+    }
+
+    pub const fn graph(&self) -> &FactorGraph {
+        &self.graph
+    }
+
+    pub const fn values(&self) -> &Values {
+        &self.values
+    }
+
+    /// Indices of the keyframes in the window, oldest first
+    pub const fn keyframes(&self) -> &VecDeque<u64> {
+        &self.keyframes
+    }
+
+    /// Add the initial estimate of a variable
+    pub fn insert_value<V: Into<Variable>>(&mut self, key: Key, variable: V) {
+        self.values.insert(key, variable);
+    }
+
+    pub fn add_factor<F: Factor + 'static>(&mut self, factor: F, kernel: Option<RobustKernel>) {
+        let id = self.graph.insert(FirstEstimateFactor {
+            inner: Box::new(factor),
+            first_estimates: Arc::clone(&self.first_estimates),
+        });
+        self.graph.set_kernel(id, kernel);
+    }
+
+    /// Open the window to a new keyframe, whose variables and factors should have been added.
+    /// Marginalises the oldest keyframe when the window is full, returning the keys of the
+    /// marginalised variables
+    pub fn add_keyframe(&mut self, index: u64) -> Result<Vec<Key>, GraphError> {
+        self.keyframes.push_back(index);
+        if self.keyframes.len() <= self.params.window_size {
+            return Ok(Vec::new());
+        }
+        self.keyframes
+            .pop_front()
+            .map_or_else(|| Ok(Vec::new()), |oldest| self.marginalize(oldest))
+    }
+
+    pub fn optimize(&mut self) -> Result<OptimizationReport, GraphError> {
+        Optimizer::new(self.params.optimizer.clone()).optimize(&self.graph, &mut self.values)
+    }
+
+    /// Replace the variables of a keyframe, and the landmarks only it constrains, by a prior on
+    /// the variables that share factors with them
+    fn marginalize(&mut self, index: u64) -> Result<Vec<Key>, GraphError> {
+        let states = [Key::Pose(index), Key::Velocity(index), Key::Bias(index)];
+        let mut marginalized: BTreeSet<Key> = states
+            .iter()
+            .copied()
+            .filter(|key| self.values.contains(*key))
+            .collect();
+
+        let removed_ids: Vec<_> = self
+            .graph
+            .iter()
+            .filter(|(_, factor)| factor.keys().iter().any(|key| marginalized.contains(key)))
+            .map(|(id, _)| id)
+            .collect();
+        let mut removed = Vec::with_capacity(removed_ids.len());
+        for id in removed_ids {
+            let kernel = self.graph.kernel(id);
+            if let Some(factor) = self.graph.remove(id) {
+                removed.push((factor, kernel));
+            }
+        }
+
+        let remaining = self.graph.keys();
+        let connected: BTreeSet<Key> = removed
+            .iter()
+            .flat_map(|(factor, _)| factor.keys().iter().copied())
+            .collect();
+        marginalized.extend(
+            connected
+                .iter()
+                .filter(|key| matches!(key, Key::Landmark(_)) && !remaining.contains(key)),
+        );
+        let separator: Vec<Key> = connected
+            .into_iter()
+            .filter(|key| !marginalized.contains(key))
+            .collect();
+
+        if !separator.is_empty() {
+            let prior = self.marginal_prior(&removed, &marginalized, &separator)?;
+            if let Some(prior) = prior {
+                self.graph.insert(prior);
+            }
+        }
+
+        let mut first_estimates = self
+            .first_estimates
+            .write()
+            .expect("Poisoned first estimates");
+        for key in &marginalized {
+            self.values.remove(*key);
+            first_estimates.remove(key);
+        }
+        drop(first_estimates);
+        Ok(marginalized.into_iter().collect())
+    }
+
+    /// Linearise the removed factors and eliminate the marginalised variables
+    fn marginal_prior(
+        &self,
+        factors: &[(Box<dyn Factor>, Option<RobustKernel>)],
+        marginalized: &BTreeSet<Key>,
+        separator: &[Key],
+    ) -> Result<Option<MarginalPriorFactor>, GraphError> {
+        // marginalised variables first
+        let keys: Vec<Key> = marginalized.iter().chain(separator).copied().collect();
+        let mut offsets = BTreeMap::new();
+        let mut dim = 0;
+        for key in &keys {
+            offsets.insert(*key, dim);
+            dim += self.values.get(*key)?.dim();
+        }
+        let n_marginalized = offsets.get(&separator[0]).copied().unwrap_or(dim);
+
+        let mut hessian = DMatrix::zeros(dim, dim);
+        let mut gradient = DVector::zeros(dim);
+        for (factor, kernel) in factors {
+            let vars = self.values.gather(factor.keys())?;
+            let residual = factor.residual(&vars)?;
+            let jacobians = factor.jacobians(&vars)?;
+            let chi_square = residual.dot(&(factor.information() * &residual));
+            let information =
+                factor.information() * kernel.map_or(1.0, |kernel| kernel.weight(chi_square));
+            for (a, key_a) in factor.keys().iter().enumerate() {
+                let weighted = jacobians[a].transpose() * &information;
+                let row = offsets[key_a];
+                let mut rows = gradient.rows_mut(row, jacobians[a].ncols());
+                rows += &weighted * &residual;
+                for (b, key_b) in factor.keys().iter().enumerate() {
+                    let block = &weighted * &jacobians[b];
+                    let mut slice =
+                        hessian.slice_mut((row, offsets[key_b]), (block.nrows(), block.ncols()));
+                    slice += block;
+                }
+            }
+        }
+
+        // Schur complement of the marginalised block, with its pseudo-inverse to cope with
+        // unobservable directions
+        let m = n_marginalized;
+        let s = dim - m;
+        let eigen = hessian.slice((0, 0), (m, m)).into_owned().symmetric_eigen();
+        let largest = eigen.eigenvalues.max();
+        let inverse_values = eigen.eigenvalues.map(|value| {
+            if value > RANK_TOLERANCE * largest {
+                1.0 / value
+            } else {
+                0.0
+            }
+        });
+        let inverse = &eigen.eigenvectors
+            * DMatrix::from_diagonal(&inverse_values)
+            * eigen.eigenvectors.transpose();
+        let coupling = hessian.slice((m, 0), (s, m)).into_owned();
+        let reduced_hessian = hessian.slice((m, m), (s, s)).into_owned()
+            - &coupling * &inverse * coupling.transpose();
+        let mut reduced_gradient =
+            gradient.rows(m, s).into_owned() - &coupling * &inverse * gradient.rows(0, m);
+
+        // the prior is linear around the first estimates, which the current values may have moved
+        // away from: b(x0) = b(x) - H * (x - x0)
+        let mut first_estimates = self
+            .first_estimates
+            .write()
+            .expect("Poisoned first estimates");
+        let mut points = Vec::with_capacity(separator.len());
+        let mut shift = DVector::zeros(s);
+        for key in separator {
+            let current = self.values.get(*key)?;
+            let point = first_estimates
+                .entry(*key)
+                .or_insert_with(|| current.clone());
+            let offset = offsets[key] - m;
+            shift
+                .rows_mut(offset, current.dim())
+                .copy_from(&point.local(current)?);
+            points.push(point.clone());
+        }
+        drop(first_estimates);
+        reduced_gradient -= &reduced_hessian * shift;
+
+        Ok(MarginalPriorFactor::new(
+            separator.to_vec(),
+            points,
+            &reduced_hessian,
+            &reduced_gradient,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::factor::{numerical_jacobians, BetweenFactor, PriorFactor};
+    use nalgebra::{Isometry3, Vector3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn marginal_prior_jacobians() {
+        let point = Variable::Pose(Isometry3::new(
+            Vector3::new(1.0, 2.0, 3.0),
+            Vector3::new(0.1, -0.2, 0.3),
+        ));
+        let hessian = DMatrix::from_fn(6, 6, |i, j| if i == j { 4.0 } else { 0.5 });
+        let gradient = DVector::from_fn(6, |i, _| i as f64);
+        let prior =
+            MarginalPriorFactor::new(vec![Key::Pose(0)], vec![point.clone()], &hessian, &gradient)
+                .unwrap();
+        assert_eq!(prior.dim(), 6);
+
+        let jacobian = &prior.jacobians(&[&point]).unwrap()[0];
+        assert!((jacobian.transpose() * jacobian - &hessian).norm() < 1e-9);
+        assert!(
+            (jacobian.transpose() * prior.residual(&[&point]).unwrap() - gradient).norm() < 1e-9
+        );
+        let numerical = numerical_jacobians(&prior, &[&point]).unwrap();
+        assert!((jacobian - &numerical[0]).norm() < 1e-6);
+    }
+
+    /// Odometry along a curve, with measurements between every pair of consecutive poses and
+    /// every other pose
+    #[test]
+    fn fixed_lag_smoother_matches_the_batch_solution() {
+        let n: usize = 20;
+        let truth: Vec<Isometry3<f64>> = (0..n)
+            .map(|i| {
+                let t = i as f64 * 0.3;
+                Isometry3::new(
+                    Vector3::new(t, t.sin(), 0.1 * t),
+                    Vector3::new(0.05 * t, 0.0, 0.2 * t),
+                )
+            })
+            .collect();
+
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut measurements = Vec::new();
+        for j in 1..n {
+            for i in j.saturating_sub(2)..j {
+                let noise: Vec<f64> = (0..6).map(|_| rng.gen_range(-0.02, 0.02)).collect();
+                let relative = Variable::Pose(truth[i].inverse() * truth[j])
+                    .retract(&noise)
+                    .unwrap();
+                measurements.push((i, j, *relative.as_pose().unwrap()));
+            }
+        }
+        let information = DMatrix::identity(6, 6) * 100.0;
+        let prior = || PriorFactor::new(Key::Pose(0), truth[0], DMatrix::identity(6, 6) * 1e4);
+        let between = |(i, j, relative): (usize, usize, Isometry3<f64>)| {
+            BetweenFactor::new(
+                Key::Pose(i as u64),
+                Key::Pose(j as u64),
+                relative,
+                information.clone(),
+            )
+        };
+
+        let mut graph = FactorGraph::new();
+        let mut batch = Values::new();
+        graph.insert(prior());
+        for (k, pose) in truth.iter().enumerate() {
+            batch.insert(Key::Pose(k as u64), *pose);
+        }
+        for &measurement in &measurements {
+            graph.insert(between(measurement));
+        }
+        Optimizer::default().optimize(&graph, &mut batch).unwrap();
+
+        let mut window = SlidingWindow::new(SlidingWindowParams {
+            window_size: 5,
+            ..SlidingWindowParams::default()
+        });
+        window.insert_value(Key::Pose(0), truth[0]);
+        window.add_factor(prior(), None);
+        window.add_keyframe(0).unwrap();
+        for j in 1..n {
+            let previous = *window.values().pose(Key::Pose(j as u64 - 1)).unwrap();
+            let odometry = measurements
+                .iter()
+                .find(|m| m.1 == j && m.0 == j - 1)
+                .unwrap();
+            window.insert_value(Key::Pose(j as u64), previous * odometry.2);
+            for &measurement in measurements.iter().filter(|m| m.1 == j) {
+                window.add_factor(between(measurement), None);
+            }
+            let marginalized = window.add_keyframe(j as u64).unwrap();
+            if j >= 5 {
+                assert_eq!(marginalized, vec![Key::Pose(j as u64 - 5)]);
+            }
+            assert!(window.optimize().unwrap().termination.converged());
+        }
+
+        assert_eq!(window.keyframes(), &[15, 16, 17, 18, 19]);
+        assert_eq!(window.values().len(), 5);
+        for k in 15..n {
+            let key = Key::Pose(k as u64);
+            let delta = batch.pose(key).unwrap().inverse() * window.values().pose(key).unwrap();
+            assert!(delta.translation.vector.norm() < 5e-4, "{}", delta);
+            assert!(delta.rotation.angle() < 5e-4, "{}", delta);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+/// Sparse symmetric block matrices - the Hessians of the normal equations
+///
+/// Block rows and columns correspond to the variables of the graph, and a block is only stored if
+/// some factor connects the two variables. Only the upper triangle is stored.
+use nalgebra::{DMatrix, DVector};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseBlockMatrix {
+    block_dims: Vec<usize>,
+    /// Scalar index of the first row/column of each block
+    offsets: Vec<usize>,
+    /// Upper triangle, by block column - `columns[col][&row]` with `row <= col`
+    columns: Vec<BTreeMap<usize, DMatrix<f64>>>,
+}
+
+impl SparseBlockMatrix {
+    /// Empty matrix with the given size of the diagonal blocks
+    pub fn new(block_dims: Vec<usize>) -> Self {
+        let offsets = block_dims
+            .iter()
+            .scan(0, |offset, dim| {
+                let start = *offset;
+                *offset += dim;
+                Some(start)
+            })
+            .collect();
+        let columns = vec![BTreeMap::new(); block_dims.len()];
+        Self {
+            block_dims,
+            offsets,
+            columns,
+        }
+    }
+
+    /// Number of scalar rows and columns
+    pub fn dim(&self) -> usize {
+        self.block_dims.iter().sum()
+    }
+
+    /// Number of block rows and columns
+    pub fn n_blocks(&self) -> usize {
+        self.block_dims.len()
+    }
+
+    pub fn block_dims(&self) -> &[usize] {
+        &self.block_dims
+    }
+
+    pub fn offset(&self, block: usize) -> usize {
+        self.offsets[block]
+    }
+
+    /// Number of stored blocks, in the upper triangle
+    pub fn n_stored_blocks(&self) -> usize {
+        self.columns.iter().map(BTreeMap::len).sum()
+    }
+
+    /// Accumulate a block. Blocks of the lower triangle are transposed into the upper one
+    pub fn add_block(&mut self, row: usize, col: usize, block: &DMatrix<f64>) {
+        let (row, col, block) = if row <= col {
+            (row, col, block.clone())
+        } else {
+            (col, row, block.transpose())
+        };
+        debug_assert_eq!(block.shape(), (self.block_dims[row], self.block_dims[col]));
+        self.columns[col]
+            .entry(row)
+            .and_modify(|stored| *stored += &block)
+            .or_insert(block);
+    }
+
+    /// Stored block of the upper triangle, `row <= col`
+    pub fn block(&self, row: usize, col: usize) -> Option<&DMatrix<f64>> {
+        self.columns.get(col)?.get(&row)
+    }
+
+    /// Stored blocks of the upper triangle, as `(row, col, block)`
+    pub fn blocks(&self) -> impl Iterator<Item = (usize, usize, &DMatrix<f64>)> {
+        self.columns
+            .iter()
+            .enumerate()
+            .flat_map(|(col, blocks)| blocks.iter().map(move |(row, block)| (*row, col, block)))
+    }
+
+    pub fn diagonal(&self) -> DVector<f64> {
+        let mut diagonal = DVector::zeros(self.dim());
+        for (block, offset) in self.offsets.iter().enumerate() {
+            if let Some(stored) = self.block(block, block) {
+                diagonal
+                    .rows_mut(*offset, self.block_dims[block])
+                    .copy_from(&stored.diagonal());
+            }
+        }
+        diagonal
+    }
+
+    /// Add to the diagonal, creating the diagonal blocks that aren't stored
+    pub fn add_diagonal(&mut self, diagonal: &DVector<f64>) {
+        for block in 0..self.n_blocks() {
+            let values = diagonal.rows(self.offsets[block], self.block_dims[block]);
+            self.add_block(block, block, &DMatrix::from_diagonal(&values));
+        }
+    }
+
+    pub fn mul_vector(&self, x: &DVector<f64>) -> DVector<f64> {
+        let mut y = DVector::zeros(self.dim());
+        for (row, col, block) in self.blocks() {
+            let (row_offset, col_offset) = (self.offsets[row], self.offsets[col]);
+            let (rows, cols) = block.shape();
+            let mut y_row = y.rows_mut(row_offset, rows);
+            y_row += block * x.rows(col_offset, cols);
+            if row != col {
+                let mut y_col = y.rows_mut(col_offset, cols);
+                y_col += block.transpose() * x.rows(row_offset, rows);
+            }
+        }
+        y
+    }
+
+    pub fn to_dense(&self) -> DMatrix<f64> {
+        let mut dense = DMatrix::zeros(self.dim(), self.dim());
+        for (row, col, block) in self.blocks() {
+            let (row_offset, col_offset) = (self.offsets[row], self.offsets[col]);
+            dense
+                .slice_mut((row_offset, col_offset), block.shape())
+                .copy_from(block);
+            if row != col {
+                dense
+                    .slice_mut((col_offset, row_offset), (block.ncols(), block.nrows()))
+                    .copy_from(&block.transpose());
+            }
+        }
+        dense
+    }
+
+    /// Blocks connected to each block, excluding itself - the graph of the sparsity pattern
+    pub fn block_pattern(&self) -> Vec<Vec<usize>> {
+        let mut pattern = vec![Vec::new(); self.n_blocks()];
+        for (row, col, _) in self.blocks() {
+            if row != col {
+                pattern[row].push(col);
+                pattern[col].push(row);
+            }
+        }
+        pattern
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_operations_match_dense() {
+        let mut matrix = SparseBlockMatrix::new(vec![2, 3, 1]);
+        let a = DMatrix::from_row_slice(2, 2, &[4.0, 1.0, 1.0, 3.0]);
+        let b = DMatrix::from_row_slice(2, 3, &[0.5, -1.0, 2.0, 0.0, 1.5, -0.5]);
+        let c = DMatrix::from_row_slice(1, 2, &[0.25, -0.75]);
+        matrix.add_block(0, 0, &a);
+        matrix.add_block(0, 1, &b);
+        // lower triangle, stored transposed
+        matrix.add_block(2, 0, &c);
+        matrix.add_block(0, 0, &a);
+        assert_eq!(matrix.dim(), 6);
+        assert_eq!(matrix.n_stored_blocks(), 3);
+        assert_eq!(matrix.block(0, 0), Some(&(&a * 2.0)));
+        assert_eq!(matrix.block(0, 2), Some(&c.transpose()));
+        assert_eq!(matrix.block_pattern(), vec![vec![1, 2], vec![0], vec![0]]);
+
+        let dense = matrix.to_dense();
+        assert_eq!(dense, dense.transpose());
+        assert_eq!(dense[(5, 1)], -0.75);
+
+        let x = DVector::from_column_slice(&[1.0, -2.0, 0.5, 3.0, -1.0, 2.0]);
+        assert!((matrix.mul_vector(&x) - &dense * &x).norm() < 1e-12);
+
+        matrix.add_diagonal(&DVector::from_element(6, 1.0));
+        assert_eq!(matrix.diagonal(), dense.diagonal().add_scalar(1.0));
+        assert_eq!(matrix.n_stored_blocks(), 5);
+    }
+}
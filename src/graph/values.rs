@@ -0,0 +1,421 @@
+/// Variables of a factor graph and the store of their current estimates
+///
+/// Every variable type lives on a manifold with a vector tangent space. The optimisers compute
+/// steps in the tangent space and apply them with [`Variable::retract`]; [`Variable::local`] is
+/// its inverse.
+use crate::geometry::lie;
+use crate::geometry::linalg::tangent_basis;
+use crate::graph::key::Key;
+
+use nalgebra::{DVector, Isometry3, Point3, Unit, Vector3};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Errors associated with building and evaluating factor graphs
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    #[error("Variable {0} is missing from the values")]
+    MissingVariable(Key),
+    #[error("Expected a {expected} variable, found a {found}")]
+    VariableType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("Expected a tangent vector of dimension {expected}, found {found}")]
+    Dimension { expected: usize, found: usize },
+    #[error("Landmark {landmark} is behind camera {camera}")]
+    BehindCamera { camera: Key, landmark: Key },
+    #[error("The covariance of the measurement isn't positive definite")]
+    IndefiniteCovariance,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Variable
+// -------------------------------------------------------------------------------------------------
+
+/// Biases of the gyroscope and the accelerometer of an IMU
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuBias {
+    pub gyro: Vector3<f64>,
+    pub accel: Vector3<f64>,
+}
+
+impl Default for ImuBias {
+    fn default() -> Self {
+        Self {
+            gyro: Vector3::zeros(),
+            accel: Vector3::zeros(),
+        }
+    }
+}
+
+/// Landmark anchored in the frame of a camera, by its bearing and inverse distance. Well
+/// conditioned for distant landmarks - at zero inverse distance it lies at infinity and only
+/// constrains rotations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InverseDepthPoint {
+    /// Direction of the landmark in the anchor camera frame
+    pub bearing: Unit<Vector3<f64>>,
+    /// Inverse of the distance from the anchor camera
+    pub inverse_depth: f64,
+}
+
+impl InverseDepthPoint {
+    /// Anchor a point of the world, given the pose of the anchor camera `T_WA`. `None` if the
+    /// point is at the centre of the camera
+    pub fn from_point(t_wa: &Isometry3<f64>, point: &Point3<f64>) -> Option<Self> {
+        let p_a = t_wa.inverse_transform_point(point).coords;
+        let distance = p_a.norm();
+        if distance < f64::EPSILON {
+            return None;
+        }
+        Some(Self {
+            bearing: Unit::new_unchecked(p_a / distance),
+            inverse_depth: 1.0 / distance,
+        })
+    }
+
+    /// The point in the world, given the pose of the anchor camera `T_WA`. `None` at infinity or
+    /// for a negative inverse depth
+    pub fn to_point(&self, t_wa: &Isometry3<f64>) -> Option<Point3<f64>> {
+        if self.inverse_depth <= 0.0 {
+            return None;
+        }
+        Some(t_wa * Point3::from(self.bearing.into_inner() / self.inverse_depth))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Variable {
+    /// Tangent `[dphi; dp]`, retracted as `(R * Exp(dphi), p + R * dp)`
+    Pose(Isometry3<f64>),
+    Point(Point3<f64>),
+    Velocity(Vector3<f64>),
+    /// Tangent `[dgyro; daccel]`
+    Bias(ImuBias),
+    /// Tangent `[db; drho]`, with the bearing retracted in its tangent plane as
+    /// `normalize(b + B' * db)`, for the basis `B` of [`tangent_basis`]
+    InverseDepth(InverseDepthPoint),
+}
+
+impl Variable {
+    /// Dimension of the tangent space
+    pub const fn dim(&self) -> usize {
+        match self {
+            Self::Pose(_) | Self::Bias(_) => 6,
+            Self::Point(_) | Self::Velocity(_) | Self::InverseDepth(_) => 3,
+        }
+    }
+
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Pose(_) => "pose",
+            Self::Point(_) => "point",
+            Self::Velocity(_) => "velocity",
+            Self::Bias(_) => "bias",
+            Self::InverseDepth(_) => "inverse depth point",
+        }
+    }
+
+    /// Move the variable along the tangent vector `delta`
+    pub fn retract(&self, delta: &[f64]) -> Result<Self, GraphError> {
+        if delta.len() != self.dim() {
+            return Err(GraphError::Dimension {
+                expected: self.dim(),
+                found: delta.len(),
+            });
+        }
+        let head = Vector3::new(delta[0], delta[1], delta[2]);
+        Ok(match self {
+            Self::Pose(pose) => {
+                let tail = Vector3::new(delta[3], delta[4], delta[5]);
+                Self::Pose(pose * Isometry3::from_parts(tail.into(), lie::exp(&head)))
+            }
+            Self::Point(point) => Self::Point(point + head),
+            Self::Velocity(velocity) => Self::Velocity(velocity + head),
+            Self::Bias(bias) => Self::Bias(ImuBias {
+                gyro: bias.gyro + head,
+                accel: bias.accel + Vector3::new(delta[3], delta[4], delta[5]),
+            }),
+            Self::InverseDepth(point) => {
+                let basis = tangent_basis(&point.bearing);
+                let moved = point.bearing.into_inner() + basis.transpose() * head.xy();
+                Self::InverseDepth(InverseDepthPoint {
+                    bearing: Unit::new_normalize(moved),
+                    inverse_depth: point.inverse_depth + head.z,
+                })
+            }
+        })
+    }
+
+    /// Tangent vector from this variable to `other`, such that `self.retract(self.local(other))`
+    /// is `other`
+    pub fn local(&self, other: &Self) -> Result<DVector<f64>, GraphError> {
+        let mismatch = || GraphError::VariableType {
+            expected: self.kind(),
+            found: other.kind(),
+        };
+        Ok(match (self, other) {
+            (Self::Pose(a), Self::Pose(b)) => {
+                let rotation = lie::log(&(a.rotation.inverse() * b.rotation));
+                let translation =
+                    a.rotation.inverse() * (b.translation.vector - a.translation.vector);
+                DVector::from_iterator(6, rotation.iter().chain(translation.iter()).copied())
+            }
+            (Self::Point(a), Self::Point(b)) => DVector::from_column_slice((b - a).as_slice()),
+            (Self::Velocity(a), Self::Velocity(b)) => {
+                DVector::from_column_slice((b - a).as_slice())
+            }
+            (Self::Bias(a), Self::Bias(b)) => {
+                let (gyro, accel) = (b.gyro - a.gyro, b.accel - a.accel);
+                DVector::from_iterator(6, gyro.iter().chain(accel.iter()).copied())
+            }
+            (Self::InverseDepth(a), Self::InverseDepth(b)) => {
+                // the inverse of the retraction, for bearings less than 90 degrees apart
+                let projected = b.bearing.into_inner() / a.bearing.dot(&b.bearing);
+                let bearing = tangent_basis(&a.bearing) * projected;
+                DVector::from_column_slice(&[
+                    bearing.x,
+                    bearing.y,
+                    b.inverse_depth - a.inverse_depth,
+                ])
+            }
+            _ => return Err(mismatch()),
+        })
+    }
+
+    pub const fn as_pose(&self) -> Result<&Isometry3<f64>, GraphError> {
+        match self {
+            Self::Pose(pose) => Ok(pose),
+            _ => Err(self.type_error("pose")),
+        }
+    }
+
+    pub const fn as_point(&self) -> Result<&Point3<f64>, GraphError> {
+        match self {
+            Self::Point(point) => Ok(point),
+            _ => Err(self.type_error("point")),
+        }
+    }
+
+    pub const fn as_velocity(&self) -> Result<&Vector3<f64>, GraphError> {
+        match self {
+            Self::Velocity(velocity) => Ok(velocity),
+            _ => Err(self.type_error("velocity")),
+        }
+    }
+
+    pub const fn as_bias(&self) -> Result<&ImuBias, GraphError> {
+        match self {
+            Self::Bias(bias) => Ok(bias),
+            _ => Err(self.type_error("bias")),
+        }
+    }
+
+    pub const fn as_inverse_depth(&self) -> Result<&InverseDepthPoint, GraphError> {
+        match self {
+            Self::InverseDepth(point) => Ok(point),
+            _ => Err(self.type_error("inverse depth point")),
+        }
+    }
+
+    const fn type_error(&self, expected: &'static str) -> GraphError {
+        GraphError::VariableType {
+            expected,
+            found: self.kind(),
+        }
+    }
+}
+
+impl From<Isometry3<f64>> for Variable {
+    fn from(pose: Isometry3<f64>) -> Self {
+        Self::Pose(pose)
+    }
+}
+
+impl From<Point3<f64>> for Variable {
+    fn from(point: Point3<f64>) -> Self {
+        Self::Point(point)
+    }
+}
+
+impl From<Vector3<f64>> for Variable {
+    fn from(velocity: Vector3<f64>) -> Self {
+        Self::Velocity(velocity)
+    }
+}
+
+impl From<ImuBias> for Variable {
+    fn from(bias: ImuBias) -> Self {
+        Self::Bias(bias)
+    }
+}
+
+impl From<InverseDepthPoint> for Variable {
+    fn from(point: InverseDepthPoint) -> Self {
+        Self::InverseDepth(point)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Values
+// -------------------------------------------------------------------------------------------------
+
+/// Current estimate of the variables - the linearisation point of the optimisers. Iterates in the
+/// order of the keys
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Values {
+    variables: BTreeMap<Key, Variable>,
+}
+
+impl Values {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert or replace a variable, returning the previous one
+    pub fn insert<V: Into<Variable>>(&mut self, key: Key, variable: V) -> Option<Variable> {
+        self.variables.insert(key, variable.into())
+    }
+
+    pub fn remove(&mut self, key: Key) -> Option<Variable> {
+        self.variables.remove(&key)
+    }
+
+    pub fn get(&self, key: Key) -> Result<&Variable, GraphError> {
+        self.variables
+            .get(&key)
+            .ok_or(GraphError::MissingVariable(key))
+    }
+
+    pub fn contains(&self, key: Key) -> bool {
+        self.variables.contains_key(&key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.variables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Key, &Variable)> {
+        self.variables
+            .iter()
+            .map(|(key, variable)| (*key, variable))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = Key> + '_ {
+        self.variables.keys().copied()
+    }
+
+    /// Sum of the tangent dimensions of all the variables
+    pub fn dim(&self) -> usize {
+        self.variables.values().map(Variable::dim).sum()
+    }
+
+    pub fn pose(&self, key: Key) -> Result<&Isometry3<f64>, GraphError> {
+        self.get(key)?.as_pose()
+    }
+
+    pub fn point(&self, key: Key) -> Result<&Point3<f64>, GraphError> {
+        self.get(key)?.as_point()
+    }
+
+    pub fn velocity(&self, key: Key) -> Result<&Vector3<f64>, GraphError> {
+        self.get(key)?.as_velocity()
+    }
+
+    pub fn bias(&self, key: Key) -> Result<&ImuBias, GraphError> {
+        self.get(key)?.as_bias()
+    }
+
+    pub fn inverse_depth(&self, key: Key) -> Result<&InverseDepthPoint, GraphError> {
+        self.get(key)?.as_inverse_depth()
+    }
+
+    /// The variables of the keys, in the same order
+    pub fn gather(&self, keys: &[Key]) -> Result<Vec<&Variable>, GraphError> {
+        keys.iter().map(|key| self.get(*key)).collect()
+    }
+
+    /// Move a variable along the tangent vector `delta`
+    pub fn retract(&mut self, key: Key, delta: &[f64]) -> Result<(), GraphError> {
+        let retracted = self.get(key)?.retract(delta)?;
+        self.variables.insert(key, retracted);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retract_and_local_are_inverse() {
+        let variables = [
+            Variable::Pose(Isometry3::new(
+                Vector3::new(1.0, -2.0, 0.3),
+                Vector3::new(0.4, 0.1, -1.2),
+            )),
+            Variable::Point(Point3::new(0.5, 1.5, -3.0)),
+            Variable::Velocity(Vector3::new(0.2, 0.0, -0.1)),
+            Variable::Bias(ImuBias {
+                gyro: Vector3::new(0.01, 0.0, -0.02),
+                accel: Vector3::new(0.1, 0.2, 0.0),
+            }),
+            Variable::InverseDepth(InverseDepthPoint {
+                bearing: Unit::new_normalize(Vector3::new(0.2, -0.1, 1.0)),
+                inverse_depth: 0.25,
+            }),
+        ];
+        let delta = [0.1, -0.2, 0.05, 0.3, -0.1, 0.7];
+        for variable in &variables {
+            let delta = &delta[..variable.dim()];
+            let retracted = variable.retract(delta).unwrap();
+            let local = variable.local(&retracted).unwrap();
+            assert!((local - DVector::from_column_slice(delta)).norm() < 1e-12);
+            assert!(variable.retract(&[0.0; 2]).is_err());
+        }
+
+        // poses are perturbed on the right
+        let pose = *variables[0].as_pose().unwrap();
+        let retracted = variables[0]
+            .retract(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0])
+            .unwrap();
+        let moved = retracted.as_pose().unwrap().translation.vector - pose.translation.vector;
+        assert!((moved - pose.rotation * Vector3::x()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn typed_access() {
+        let mut values = Values::new();
+        values.insert(Key::Pose(0), Isometry3::identity());
+        values.insert(Key::Landmark(3), Point3::new(1.0, 2.0, 3.0));
+        values.insert(Key::Bias(0), ImuBias::default());
+        assert_eq!(values.len(), 3);
+        assert_eq!(values.dim(), 15);
+
+        assert!(values.pose(Key::Pose(0)).is_ok());
+        assert_eq!(
+            values.pose(Key::Landmark(3)),
+            Err(GraphError::VariableType {
+                expected: "pose",
+                found: "point"
+            })
+        );
+        assert_eq!(
+            values.velocity(Key::Velocity(0)),
+            Err(GraphError::MissingVariable(Key::Velocity(0)))
+        );
+
+        values.retract(Key::Landmark(3), &[1.0, 0.0, -1.0]).unwrap();
+        assert_eq!(
+            values.point(Key::Landmark(3)).unwrap(),
+            &Point3::new(2.0, 2.0, 2.0)
+        );
+        assert!(values.remove(Key::Pose(0)).is_some());
+        assert!(!values.contains(Key::Pose(0)));
+    }
+}
@@ -13,9 +13,15 @@
 #![cfg_attr(test, feature(proc_macro_hygiene))]
 
 pub mod drivers;
+pub mod features;
+pub mod geometry;
+pub mod graph;
+pub mod mapping;
+pub mod tracking;
 pub mod utils;
 
 pub use self::drivers::{
-    DatasetDriver, DatasetDriverState, EurocDriver, EurocStreamGray, FiniteStream, Stream,
+    DatasetDriver, DatasetDriverState, EurocDriver, EurocStreamGray, EurocStreamImu, FiniteStream,
+    Stream,
 };
 pub use self::utils::errors;
@@ -0,0 +1,488 @@
+/// Map files - the keyframes, landmarks and covisibility graph of a [`Map`], in a versioned binary
+/// format
+///
+/// All the numbers are little-endian. The file starts with the magic bytes `SLAMMAP\0` and the
+/// version of the format, followed by:
+///
+/// - the sessions: their number minus one (`u32`), and the first keyframe of each one after the
+///   first (`u64`)
+/// - the keyframes (`u64` count): identifier (`u64`), timestamp (`u64` seconds, `u32`
+///   nanoseconds), pose (translation and quaternion `i, j, k, w`, `f64`), and the features (`u32`
+///   count) - identifier (`u64`), pixel (`f64`), and optional descriptor (`u8` flag, 4 `u64`)
+/// - the landmarks (`u64` count): identifier (`u64`), position (`f64`), statistics (`u32`
+///   visible, `u32` found) and first keyframe (`u64`)
+/// - the edges of the covisibility graph (`u64` count): keyframes and weight (`u64`)
+///
+/// The observations of the landmarks and the covisibility graph are implied by the features of
+/// the keyframes and are rebuilt on reading - the stored edges are checked against them.
+use crate::features::orb::Descriptor;
+use crate::mapping::keyframes::{Keyframe, KeyframeFeature};
+use crate::mapping::map::{LandmarkStats, Map, MapParams};
+
+use nalgebra::{Isometry3, Point2, Point3, Quaternion, Translation3, UnitQuaternion};
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+const MAGIC: &[u8; 8] = b"SLAMMAP\0";
+/// Version of the format written - files of older versions remain readable
+pub const MAP_FILE_VERSION: u32 = 1;
+
+/// Errors associated with reading and writing map files
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MapFileError {
+    #[error("Accessing [{0}] failed - Reason: {1}")]
+    Io(String, String),
+    #[error("Not a map file")]
+    InvalidMagic,
+    #[error(
+        "Unsupported map file version {0} - the latest supported is {}",
+        MAP_FILE_VERSION
+    )]
+    UnsupportedVersion(u32),
+    #[error("The map file ends unexpectedly")]
+    Truncated,
+    #[error("Corrupted map file - Reason: {0}")]
+    Corrupted(String),
+}
+
+// -------------------------------------------------------------------------------------------------
+// Encoding
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn count(&mut self, len: usize) {
+        self.u64(len as u64);
+    }
+}
+
+#[derive(Debug)]
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], MapFileError> {
+        if self.bytes.len() < n {
+            return Err(MapFileError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, MapFileError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, MapFileError> {
+        let bytes = self
+            .take(4)?
+            .try_into()
+            .map_err(|_| MapFileError::Truncated)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, MapFileError> {
+        let bytes = self
+            .take(8)?
+            .try_into()
+            .map_err(|_| MapFileError::Truncated)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn f64(&mut self) -> Result<f64, MapFileError> {
+        let bytes = self
+            .take(8)?
+            .try_into()
+            .map_err(|_| MapFileError::Truncated)?;
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    /// Number of items that follow, each of at least `min_size` bytes - checked against the
+    /// remaining bytes before anything is allocated for them
+    fn count(&mut self, min_size: usize) -> Result<usize, MapFileError> {
+        let len = self.u64()? as usize;
+        if len.saturating_mul(min_size) > self.bytes.len() {
+            return Err(MapFileError::Truncated);
+        }
+        Ok(len)
+    }
+}
+
+fn write_keyframe(writer: &mut Writer, keyframe: &Keyframe) {
+    writer.u64(keyframe.id);
+    writer.u64(keyframe.timestamp.as_secs());
+    writer.u32(keyframe.timestamp.subsec_nanos());
+    let translation = &keyframe.pose.translation.vector;
+    let rotation = keyframe.pose.rotation.quaternion();
+    for &value in translation.iter().chain(rotation.coords.iter()) {
+        writer.f64(value);
+    }
+    writer.u32(keyframe.features.len() as u32);
+    for feature in &keyframe.features {
+        writer.u64(feature.id);
+        writer.f64(feature.pt.x);
+        writer.f64(feature.pt.y);
+        match &feature.descriptor {
+            Some(descriptor) => {
+                writer.u8(1);
+                for &word in &descriptor.0 {
+                    writer.u64(word);
+                }
+            }
+            None => writer.u8(0),
+        }
+    }
+}
+
+fn read_keyframe(reader: &mut Reader<'_>) -> Result<Keyframe, MapFileError> {
+    let id = reader.u64()?;
+    let (secs, nanos) = (reader.u64()?, reader.u32()?);
+    if nanos >= 1_000_000_000 {
+        return Err(MapFileError::Corrupted(format!(
+            "Invalid timestamp of keyframe {}",
+            id
+        )));
+    }
+    let mut pose = [0.0; 7];
+    for value in &mut pose {
+        *value = reader.f64()?;
+    }
+    if !pose.iter().all(|value| value.is_finite()) {
+        return Err(MapFileError::Corrupted(format!(
+            "Non-finite pose of keyframe {}",
+            id
+        )));
+    }
+    let rotation = Quaternion::new(pose[6], pose[3], pose[4], pose[5]);
+    // stored normalised - any other rotation is a corruption
+    if (rotation.norm() - 1.0).abs() > 1e-6 {
+        return Err(MapFileError::Corrupted(format!(
+            "The rotation of keyframe {} isn't a unit quaternion",
+            id
+        )));
+    }
+
+    let n_features = reader.u32()?;
+    let mut features = Vec::with_capacity((n_features as usize).min(reader.bytes.len() / 25));
+    for _ in 0..n_features {
+        let (feature, x, y) = (reader.u64()?, reader.f64()?, reader.f64()?);
+        if !x.is_finite() || !y.is_finite() {
+            return Err(MapFileError::Corrupted(format!(
+                "Non-finite pixel of feature {} in keyframe {}",
+                feature, id
+            )));
+        }
+        let descriptor = match reader.u8()? {
+            0 => None,
+            1 => Some(Descriptor([
+                reader.u64()?,
+                reader.u64()?,
+                reader.u64()?,
+                reader.u64()?,
+            ])),
+            flag => {
+                return Err(MapFileError::Corrupted(format!(
+                    "Invalid descriptor flag {} in keyframe {}",
+                    flag, id
+                )))
+            }
+        };
+        features.push(KeyframeFeature {
+            id: feature,
+            pt: Point2::new(x, y),
+            descriptor,
+        });
+    }
+
+    Ok(Keyframe {
+        id,
+        timestamp: Duration::new(secs, nanos),
+        pose: Isometry3::from_parts(
+            Translation3::new(pose[0], pose[1], pose[2]),
+            UnitQuaternion::new_unchecked(rotation),
+        ),
+        features,
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+// Map files
+// -------------------------------------------------------------------------------------------------
+
+impl Map {
+    /// Contents of the map file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.u32(MAP_FILE_VERSION);
+
+        writer.u32(self.session_starts().len() as u32);
+        for &first in self.session_starts() {
+            writer.u64(first);
+        }
+
+        writer.count(self.keyframes().len());
+        for keyframe in self.keyframes().iter() {
+            write_keyframe(&mut writer, keyframe);
+        }
+
+        writer.count(self.n_landmarks());
+        for landmark in self.landmarks() {
+            writer.u64(landmark.id);
+            for &value in landmark.position.coords.iter() {
+                writer.f64(value);
+            }
+            writer.u32(landmark.stats.visible);
+            writer.u32(landmark.stats.found);
+            writer.u64(landmark.first_keyframe);
+        }
+
+        let edges: Vec<(u64, u64, usize)> = self.keyframes().covisibility_edges().collect();
+        writer.count(edges.len());
+        for (a, b, weight) in edges {
+            writer.u64(a);
+            writer.u64(b);
+            writer.u64(weight as u64);
+        }
+        writer.bytes
+    }
+
+    /// Map of the contents of a map file
+    pub fn from_bytes(bytes: &[u8], params: MapParams) -> Result<Self, MapFileError> {
+        let mut reader = Reader { bytes };
+        if reader
+            .take(MAGIC.len())
+            .map_err(|_| MapFileError::InvalidMagic)?
+            != MAGIC
+        {
+            return Err(MapFileError::InvalidMagic);
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > MAP_FILE_VERSION {
+            return Err(MapFileError::UnsupportedVersion(version));
+        }
+
+        let mut map = Self::new(params);
+        let n_sessions = reader.u32()?;
+        let sessions = (0..n_sessions)
+            .map(|_| reader.u64())
+            .collect::<Result<Vec<u64>, MapFileError>>()?;
+        map.restore_sessions(sessions);
+
+        // identifier, timestamp, pose and number of features
+        let n_keyframes = reader.count(80)?;
+        for _ in 0..n_keyframes {
+            map.restore_keyframe(read_keyframe(&mut reader)?);
+        }
+
+        let n_landmarks = reader.count(48)?;
+        for _ in 0..n_landmarks {
+            let id = reader.u64()?;
+            let position = Point3::new(reader.f64()?, reader.f64()?, reader.f64()?);
+            if !position.iter().all(|value| value.is_finite()) {
+                return Err(MapFileError::Corrupted(format!(
+                    "Non-finite position of landmark {}",
+                    id
+                )));
+            }
+            let stats = LandmarkStats {
+                visible: reader.u32()?,
+                found: reader.u32()?,
+            };
+            let first_keyframe = reader.u64()?;
+            map.restore_landmark(id, position, stats, first_keyframe)
+                .map_err(|e| MapFileError::Corrupted(e.to_string()))?;
+        }
+
+        let n_edges = reader.count(24)?;
+        let edges = (0..n_edges)
+            .map(|_| Ok((reader.u64()?, reader.u64()?, reader.u64()? as usize)))
+            .collect::<Result<Vec<(u64, u64, usize)>, MapFileError>>()?;
+        if !map.keyframes().covisibility_edges().eq(edges) {
+            return Err(MapFileError::Corrupted(
+                "The covisibility graph doesn't match the features of the keyframes".into(),
+            ));
+        }
+        if !reader.bytes.is_empty() {
+            return Err(MapFileError::Corrupted(format!(
+                "{} unexpected bytes at the end of the file",
+                reader.bytes.len()
+            )));
+        }
+        Ok(map)
+    }
+
+    pub fn read(path: &Path, params: MapParams) -> Result<Self, MapFileError> {
+        let bytes = fs::read(path)
+            .map_err(|e| MapFileError::Io(path.display().to_string(), e.to_string()))?;
+        Self::from_bytes(&bytes, params)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), MapFileError> {
+        fs::write(path, self.to_bytes())
+            .map_err(|e| MapFileError::Io(path.display().to_string(), e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+
+    fn map() -> Map {
+        let mut map = Map::default();
+        for k in 0..4_u64 {
+            let features = (k..k + 6)
+                .map(|id| KeyframeFeature {
+                    id,
+                    pt: Point2::new(10.0 * id as f64, 20.5),
+                    descriptor: if id % 3 == 0 {
+                        None
+                    } else {
+                        Some(Descriptor([id, !id, id << 7, 42]))
+                    },
+                })
+                .collect();
+            let pose = Isometry3::new(Vector3::new(k as f64, 0.5, -1.0), Vector3::z() * 0.1);
+            map.insert_keyframe(Duration::new(k, 123_456_789), pose, features);
+        }
+        for id in 0..9 {
+            map.insert_landmark(id, Point3::new(id as f64, 1.0, 5.0))
+                .unwrap();
+        }
+        map.record_visible(&[2, 3]);
+        map.record_found(&[3]);
+        map
+    }
+
+    #[test]
+    fn map_file_roundtrip() {
+        let mut original = map();
+        let offset = original.start_session();
+        assert_eq!(offset, 9);
+        let features = vec![KeyframeFeature {
+            id: offset + 1,
+            pt: Point2::new(1.0, 2.0),
+            descriptor: None,
+        }];
+        original.insert_keyframe(Duration::from_secs(10), Isometry3::identity(), features);
+        original.remove_keyframe(1).unwrap();
+
+        let bytes = original.to_bytes();
+        let read = Map::from_bytes(&bytes, MapParams::default()).unwrap();
+        assert_eq!(read.n_sessions(), 2);
+        assert_eq!(read.session_of(3), 0);
+        assert_eq!(read.session_of(4), 1);
+        assert!(read.keyframes().iter().eq(original.keyframes().iter()));
+        assert!(read.landmarks().eq(original.landmarks()));
+        assert!(read
+            .keyframes()
+            .covisibility_edges()
+            .eq(original.keyframes().covisibility_edges()));
+        assert_eq!(read.keyframes().next_id(), 5);
+        assert_eq!(read.to_bytes(), bytes);
+
+        let path = std::env::temp_dir().join("slam-rs-map-file-roundtrip.map");
+        original.write(&path).unwrap();
+        assert_eq!(
+            Map::read(&path, MapParams::default()).unwrap().to_bytes(),
+            bytes
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn non_finite_map_files() {
+        let bytes = map().to_bytes();
+        let read = |bytes: &[u8]| Map::from_bytes(bytes, MapParams::default()).err();
+
+        // the pose of the first keyframe follows the header, the number of keyframes, and the
+        // identifier and timestamp of the keyframe
+        let pose = 8 + 4 + 4 + 8 + 8 + 12;
+        for (offset, value) in [
+            (0, f64::NAN),
+            (2, f64::INFINITY),
+            (3, f64::NAN),
+            (6, f64::NAN),
+        ] {
+            let mut tampered = bytes.clone();
+            let start = pose + 8 * offset;
+            tampered[start..start + 8].copy_from_slice(&value.to_le_bytes());
+            assert!(
+                matches!(read(&tampered), Some(MapFileError::Corrupted(_))),
+                "{}",
+                offset
+            );
+            // truncated within the non-finite value
+            assert_eq!(read(&tampered[..start + 4]), Some(MapFileError::Truncated));
+        }
+
+        let mut map = map();
+        map.insert_landmark(42, Point3::new(1.0, f64::NAN, 2.0))
+            .unwrap();
+        assert!(matches!(
+            read(&map.to_bytes()),
+            Some(MapFileError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_map_files() {
+        let bytes = map().to_bytes();
+        let read = |bytes: &[u8]| Map::from_bytes(bytes, MapParams::default()).err();
+
+        assert_eq!(read(b"SLAM"), Some(MapFileError::InvalidMagic));
+        assert_eq!(read(&bytes[1..]), Some(MapFileError::InvalidMagic));
+        let mut newer = bytes.clone();
+        newer[8..12].copy_from_slice(&(MAP_FILE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            read(&newer),
+            Some(MapFileError::UnsupportedVersion(MAP_FILE_VERSION + 1))
+        );
+        for len in (12..bytes.len()).step_by(7) {
+            assert_eq!(
+                read(&bytes[..len]),
+                Some(MapFileError::Truncated),
+                "{}",
+                len
+            );
+        }
+        // the weight of the last covisibility edge
+        let mut tampered = bytes.clone();
+        let last = tampered.len() - 8;
+        tampered[last] += 1;
+        assert!(matches!(read(&tampered), Some(MapFileError::Corrupted(_))));
+        let mut extended = bytes;
+        extended.push(0);
+        assert!(matches!(read(&extended), Some(MapFileError::Corrupted(_))));
+        assert!(matches!(
+            Map::read(Path::new("non-existent.map"), MapParams::default()),
+            Err(MapFileError::Io(_, _))
+        ));
+    }
+}
@@ -0,0 +1,501 @@
+/// Keyframe selection and the database of the keyframes
+///
+/// A frame becomes a keyframe when the tracks of the last keyframe are getting lost, when the
+/// image moved enough since the last keyframe, or when too much time has passed. The database
+/// keeps the poses, features and descriptors of the keyframes, and the covisibility graph - two
+/// keyframes are connected with a weight equal to the number of features that they share.
+///
+/// For more information see:
+///
+/// - Mur-Artal et al., "ORB-SLAM: a versatile and accurate monocular SLAM system", T-RO 2015
+use crate::features::orb::Descriptor;
+use crate::tracking::klt::Track;
+
+use nalgebra::{Isometry3, Point2};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+// -------------------------------------------------------------------------------------------------
+// Keyframe
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyframeFeature {
+    /// Identifier of the feature track (and of its landmark)
+    pub id: u64,
+    pub pt: Point2<f64>,
+    pub descriptor: Option<Descriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyframe {
+    pub id: u64,
+    pub timestamp: Duration,
+    /// Pose of the camera in the world - transforms points from the camera to the world
+    pub pose: Isometry3<f64>,
+    pub features: Vec<KeyframeFeature>,
+}
+
+impl Keyframe {
+    pub fn feature(&self, id: u64) -> Option<&KeyframeFeature> {
+        self.features.iter().find(|f| f.id == id)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// KeyframeSelector
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct KeyframeParams {
+    /// No keyframe is inserted sooner than this after the last one
+    pub min_interval: Duration,
+    /// A keyframe is always inserted this long after the last one
+    pub max_interval: Duration,
+    /// Insert a keyframe when fewer of the features of the last keyframe are still tracked
+    pub min_tracked_ratio: f64,
+    /// Insert a keyframe when the median displacement of the tracked features since the last
+    /// keyframe exceeds this (pixels)
+    pub min_parallax: f64,
+}
+
+impl Default for KeyframeParams {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_millis(50),
+            max_interval: Duration::from_secs(1),
+            min_tracked_ratio: 0.7,
+            min_parallax: 20.0,
+        }
+    }
+}
+
+/// Why a frame was selected as a keyframe
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyframeReason {
+    /// There is no keyframe yet
+    First,
+    /// Fraction of the features of the last keyframe that are still tracked
+    TrackedRatio(f64),
+    /// Median displacement of the tracked features (pixels)
+    Parallax(f64),
+    /// Time since the last keyframe
+    Interval(Duration),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KeyframeSelector {
+    params: KeyframeParams,
+}
+
+impl KeyframeSelector {
+    pub const fn new(params: KeyframeParams) -> Self {
+        Self { params }
+    }
+
+    pub const fn params(&self) -> &KeyframeParams {
+        &self.params
+    }
+
+    /// Should the frame at `timestamp`, with the given tracks, become a keyframe?
+    pub fn select(
+        &self,
+        last: Option<&Keyframe>,
+        timestamp: Duration,
+        tracks: &[Track],
+    ) -> Option<KeyframeReason> {
+        let last = match last {
+            Some(last) => last,
+            None => return Some(KeyframeReason::First),
+        };
+        let elapsed = timestamp.checked_sub(last.timestamp).unwrap_or_default();
+        if elapsed < self.params.min_interval {
+            return None;
+        }
+        if elapsed >= self.params.max_interval {
+            return Some(KeyframeReason::Interval(elapsed));
+        }
+
+        let mut displacements: Vec<f64> = tracks
+            .iter()
+            .filter(|track| track.status.is_active())
+            .filter_map(|track| last.feature(track.id).map(|f| (track.pt - f.pt).norm()))
+            .filter(|displacement| displacement.is_finite())
+            .collect();
+        let ratio = if last.features.is_empty() {
+            0.0
+        } else {
+            displacements.len() as f64 / last.features.len() as f64
+        };
+        if ratio < self.params.min_tracked_ratio {
+            return Some(KeyframeReason::TrackedRatio(ratio));
+        }
+
+        // nothing tracked, with a tracked ratio allowed to be zero
+        if displacements.is_empty() {
+            return None;
+        }
+        displacements.sort_by(|a, b| a.partial_cmp(b).expect("Finite displacements"));
+        let parallax = displacements[displacements.len() / 2];
+        if parallax > self.params.min_parallax {
+            return Some(KeyframeReason::Parallax(parallax));
+        }
+        None
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// KeyframeDatabase
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct CullingParams {
+    /// A keyframe is redundant when this fraction of its features is observed by other keyframes
+    pub redundancy: f64,
+    /// Number of other keyframes that must observe a feature for it to count as redundant
+    pub min_observers: usize,
+}
+
+impl Default for CullingParams {
+    fn default() -> Self {
+        Self {
+            redundancy: 0.9,
+            min_observers: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KeyframeDatabase {
+    keyframes: BTreeMap<u64, Keyframe>,
+    next_id: u64,
+    /// Keyframes observing each feature
+    observers: HashMap<u64, BTreeSet<u64>>,
+    /// Number of features shared by each pair of keyframes, stored in both directions
+    covisibility: BTreeMap<u64, BTreeMap<u64, usize>>,
+}
+
+impl KeyframeDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Keyframe> {
+        self.keyframes.get(&id)
+    }
+
+    /// Most recent keyframe
+    pub fn latest(&self) -> Option<&Keyframe> {
+        self.keyframes.values().next_back()
+    }
+
+    /// Keyframes in order of insertion
+    pub fn iter(&self) -> impl Iterator<Item = &Keyframe> {
+        self.keyframes.values()
+    }
+
+    /// Add a keyframe, returning its identifier
+    pub fn insert(
+        &mut self,
+        timestamp: Duration,
+        pose: Isometry3<f64>,
+        features: Vec<KeyframeFeature>,
+    ) -> u64 {
+        let id = self.next_id;
+        self.restore(Keyframe {
+            id,
+            timestamp,
+            pose,
+            features,
+        });
+        id
+    }
+
+    /// Add a keyframe that keeps its identifier, e.g. read from a map file. Replaces the keyframe
+    /// with the same identifier, if any
+    pub(crate) fn restore(&mut self, keyframe: Keyframe) {
+        let id = keyframe.id;
+        self.remove(id);
+        self.next_id = self.next_id.max(id + 1);
+
+        let mut weights = BTreeMap::new();
+        for feature in &keyframe.features {
+            let observers = self.observers.entry(feature.id).or_default();
+            for &other in observers.iter() {
+                *weights.entry(other).or_insert(0) += 1;
+            }
+            observers.insert(id);
+        }
+        for (&other, &weight) in &weights {
+            self.covisibility
+                .entry(other)
+                .or_default()
+                .insert(id, weight);
+        }
+        self.covisibility.insert(id, weights);
+        self.keyframes.insert(id, keyframe);
+    }
+
+    /// Identifier of the next inserted keyframe
+    pub const fn next_id(&self) -> u64 {
+        self.next_id
+    }
+
+    /// Update the pose of a keyframe, e.g. after an optimisation. Returns false if it doesn't exist
+    pub fn set_pose(&mut self, id: u64, pose: Isometry3<f64>) -> bool {
+        self.keyframes
+            .get_mut(&id)
+            .map(|keyframe| keyframe.pose = pose)
+            .is_some()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Keyframe> {
+        let keyframe = self.keyframes.remove(&id)?;
+        for feature in &keyframe.features {
+            if let Some(observers) = self.observers.get_mut(&feature.id) {
+                observers.remove(&id);
+                if observers.is_empty() {
+                    self.observers.remove(&feature.id);
+                }
+            }
+        }
+        for other in self.covisibility.remove(&id).unwrap_or_default().keys() {
+            if let Some(weights) = self.covisibility.get_mut(other) {
+                weights.remove(&id);
+            }
+        }
+        Some(keyframe)
+    }
+
+    /// Remove a feature from all the keyframes observing it
+    pub fn remove_feature(&mut self, feature: u64) {
+        let observers = self.observers.remove(&feature).unwrap_or_default();
+        self.link(&observers, false);
+        for id in &observers {
+            if let Some(keyframe) = self.keyframes.get_mut(id) {
+                keyframe.features.retain(|f| f.id != feature);
+            }
+        }
+    }
+
+    /// Relabel the observations of the feature `remove` as observations of `keep`, e.g. when two
+    /// tracks turn out to be the same landmark. Keyframes observing both keep their `keep` feature
+    pub fn merge_features(&mut self, keep: u64, remove: u64) {
+        if keep == remove {
+            return;
+        }
+        let removed = self.observers.remove(&remove).unwrap_or_default();
+        let kept = self.observers.remove(&keep).unwrap_or_default();
+        self.link(&removed, false);
+        self.link(&kept, false);
+        for id in &removed {
+            if let Some(keyframe) = self.keyframes.get_mut(id) {
+                if kept.contains(id) {
+                    keyframe.features.retain(|f| f.id != remove);
+                } else {
+                    for feature in keyframe.features.iter_mut().filter(|f| f.id == remove) {
+                        feature.id = keep;
+                    }
+                }
+            }
+        }
+        let merged: BTreeSet<u64> = kept.union(&removed).copied().collect();
+        self.link(&merged, true);
+        if !merged.is_empty() {
+            self.observers.insert(keep, merged);
+        }
+    }
+
+    /// Add (or subtract) the contribution of a feature seen by `observers` to the covisibility
+    fn link(&mut self, observers: &BTreeSet<u64>, add: bool) {
+        for &a in observers {
+            for &b in observers.iter().filter(|&&b| b != a) {
+                let weights = self.covisibility.entry(a).or_default();
+                let weight = weights.entry(b).or_insert(0);
+                if add {
+                    *weight += 1;
+                } else {
+                    *weight = weight.saturating_sub(1);
+                    if *weight == 0 {
+                        weights.remove(&b);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keyframes observing the feature
+    pub fn observers(&self, feature: u64) -> impl Iterator<Item = u64> + '_ {
+        self.observers.get(&feature).into_iter().flatten().copied()
+    }
+
+    /// Edges `(a, b, weight)` of the covisibility graph, with `a < b`
+    pub fn covisibility_edges(&self) -> impl Iterator<Item = (u64, u64, usize)> + '_ {
+        self.covisibility.iter().flat_map(|(&a, weights)| {
+            weights
+                .range(a + 1..)
+                .map(move |(&b, &weight)| (a, b, weight))
+        })
+    }
+
+    /// Keyframes sharing at least `min_shared` features with the keyframe, most connected first
+    pub fn covisible(&self, id: u64, min_shared: usize) -> Vec<(u64, usize)> {
+        let mut neighbours: Vec<(u64, usize)> = self
+            .covisibility
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .filter(|(_, &weight)| weight >= min_shared)
+            .map(|(&other, &weight)| (other, weight))
+            .collect();
+        neighbours.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        neighbours
+    }
+
+    /// Remove the keyframes whose features are mostly observed by enough other keyframes. The
+    /// first keyframe, which anchors the map, and the latest one are always kept. Returns the
+    /// identifiers of the removed keyframes
+    pub fn cull_redundant(&mut self, params: &CullingParams) -> Vec<u64> {
+        let ids: Vec<u64> = self.keyframes.keys().copied().collect();
+        if ids.len() < 3 {
+            return Vec::new();
+        }
+
+        let mut culled = Vec::new();
+        for &id in &ids[1..ids.len() - 1] {
+            let keyframe = &self.keyframes[&id];
+            if keyframe.features.is_empty() {
+                continue;
+            }
+            let redundant = keyframe
+                .features
+                .iter()
+                .filter(|f| self.observers[&f.id].len() > params.min_observers)
+                .count();
+            if redundant as f64 >= params.redundancy * keyframe.features.len() as f64 {
+                self.remove(id);
+                culled.push(id);
+            }
+        }
+        culled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracking::klt::TrackStatus;
+    use nalgebra::Vector2;
+
+    fn features(ids: std::ops::Range<u64>) -> Vec<KeyframeFeature> {
+        ids.map(|id| KeyframeFeature {
+            id,
+            pt: Point2::new(id as f64, 2.0 * id as f64),
+            descriptor: Some(Descriptor([id; 4])),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn keyframe_selection() {
+        let selector = KeyframeSelector::default();
+        let at = Duration::from_millis;
+        assert_eq!(
+            selector.select(None, at(0), &[]),
+            Some(KeyframeReason::First)
+        );
+
+        let last = Keyframe {
+            id: 0,
+            timestamp: at(0),
+            pose: Isometry3::identity(),
+            features: features(0..100),
+        };
+        let tracks = |n: u64, shift: f64| -> Vec<Track> {
+            features(0..n)
+                .into_iter()
+                .map(|f| Track {
+                    id: f.id,
+                    pt: f.pt + Vector2::new(shift, 0.0),
+                    status: TrackStatus::Tracked,
+                    age: 1,
+                })
+                .collect()
+        };
+        assert_eq!(
+            selector.select(Some(&last), at(20), &tracks(10, 50.0)),
+            None
+        );
+        assert_eq!(
+            selector.select(Some(&last), at(200), &tracks(90, 5.0)),
+            None
+        );
+        assert_eq!(
+            selector.select(Some(&last), at(200), &tracks(50, 5.0)),
+            Some(KeyframeReason::TrackedRatio(0.5))
+        );
+        assert_eq!(
+            selector.select(Some(&last), at(200), &tracks(90, 25.0)),
+            Some(KeyframeReason::Parallax(25.0))
+        );
+        assert_eq!(
+            selector.select(Some(&last), at(1500), &tracks(90, 5.0)),
+            Some(KeyframeReason::Interval(at(1500)))
+        );
+
+        // diverged tracks aren't tracked, and a frame may track nothing
+        let mut diverged = tracks(90, 5.0);
+        diverged[0].pt.x = f64::NAN;
+        assert_eq!(selector.select(Some(&last), at(200), &diverged), None);
+        let selector = KeyframeSelector::new(KeyframeParams {
+            min_tracked_ratio: 0.0,
+            ..KeyframeParams::default()
+        });
+        assert_eq!(selector.select(Some(&last), at(200), &[]), None);
+    }
+
+    #[test]
+    fn covisibility_and_culling() {
+        let mut database = KeyframeDatabase::new();
+        let pose = Isometry3::identity();
+        let ids: Vec<u64> = [0..50, 10..60, 20..70, 20..70, 20..70, 25..75]
+            .iter()
+            .enumerate()
+            .map(|(k, range)| {
+                database.insert(Duration::from_secs(k as u64), pose, features(range.clone()))
+            })
+            .collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(database.latest().map(|k| k.id), Some(5));
+        assert_eq!(
+            database.covisible(0, 30),
+            vec![(1, 40), (2, 30), (3, 30), (4, 30)]
+        );
+        assert_eq!(database.covisible(2, 0)[0], (3, 50));
+        assert_eq!(
+            database.observers(22).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+
+        // the three identical keyframes are redundant, but not all of them can be removed
+        let culled = database.cull_redundant(&CullingParams::default());
+        assert_eq!(culled, vec![2]);
+        assert_eq!(database.len(), 5);
+        assert!(database.get(2).is_none());
+        assert!(database.covisible(3, 0).iter().all(|&(id, _)| id != 2));
+        assert_eq!(database.covisible(3, 0)[0], (4, 50));
+
+        assert!(database.set_pose(5, Isometry3::translation(1.0, 0.0, 0.0)));
+        assert!(!database.set_pose(2, pose));
+        assert!(database.remove(5).is_some());
+        assert_eq!(database.observers(72).count(), 0);
+    }
+}
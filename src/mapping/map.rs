@@ -0,0 +1,575 @@
+/// The map shared by the front-end and the back-end - keyframes and the landmarks they observe
+///
+/// Landmarks share the identifier of the feature tracks that observe them in the keyframes, so
+/// the observations of a landmark and the covisibility graph of the [`KeyframeDatabase`] always
+/// agree. Every landmark keeps a representative descriptor - the one of its observations with the
+/// least median distance to the others - and its mean viewing direction.
+///
+/// Landmarks are unstable when the tracker rarely finds them where they are predicted to be
+/// visible, or when few keyframes observe them a while after their creation.
+use crate::features::orb::Descriptor;
+use crate::geometry::camera::CameraModel;
+use crate::mapping::keyframes::{Keyframe, KeyframeDatabase, KeyframeFeature};
+
+use nalgebra::{Isometry3, Point2, Point3, Unit, Vector3};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MapError {
+    #[error("Keyframe {0} is not in the map")]
+    MissingKeyframe(u64),
+    #[error("Landmark {0} is not in the map")]
+    MissingLandmark(u64),
+    #[error("Landmark {0} is already in the map")]
+    DuplicateLandmark(u64),
+}
+
+// -------------------------------------------------------------------------------------------------
+// Landmark
+// -------------------------------------------------------------------------------------------------
+
+/// How reliably the tracker finds a landmark
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LandmarkStats {
+    /// Number of frames in which the landmark was predicted to be visible
+    pub visible: u32,
+    /// Number of frames in which it was actually matched
+    pub found: u32,
+}
+
+impl LandmarkStats {
+    pub fn found_ratio(&self) -> f64 {
+        if self.visible == 0 {
+            1.0
+        } else {
+            f64::from(self.found) / f64::from(self.visible)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Landmark {
+    pub id: u64,
+    /// Position in the world
+    pub position: Point3<f64>,
+    /// Representative descriptor of the observations
+    pub descriptor: Option<Descriptor>,
+    /// Location of the landmark in each keyframe observing it
+    pub observations: BTreeMap<u64, Point2<f64>>,
+    /// Mean direction from the observing cameras to the landmark
+    pub normal: Option<Unit<Vector3<f64>>>,
+    pub stats: LandmarkStats,
+    /// Latest keyframe when the landmark was created
+    pub first_keyframe: u64,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Map
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct MapParams {
+    /// Landmarks found in fewer of the frames where they should be visible are culled
+    pub min_found_ratio: f64,
+    /// Landmarks observed by fewer keyframes are culled...
+    pub min_observations: usize,
+    /// ...once this many keyframes have been inserted after their creation
+    pub culling_delay: u64,
+    /// Landmarks aren't visible from viewpoints further than this from their mean viewing
+    /// direction (radians)
+    pub max_viewing_angle: f64,
+}
+
+impl Default for MapParams {
+    fn default() -> Self {
+        Self {
+            min_found_ratio: 0.25,
+            min_observations: 3,
+            culling_delay: 3,
+            max_viewing_angle: 60_f64.to_radians(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    params: MapParams,
+    keyframes: KeyframeDatabase,
+    landmarks: BTreeMap<u64, Landmark>,
+    /// First keyframe of every session after the first one
+    sessions: Vec<u64>,
+}
+
+impl Map {
+    pub fn new(params: MapParams) -> Self {
+        Self {
+            params,
+            keyframes: KeyframeDatabase::new(),
+            landmarks: BTreeMap::new(),
+            sessions: Vec::new(),
+        }
+    }
+
+    pub const fn params(&self) -> &MapParams {
+        &self.params
+    }
+
+    pub const fn keyframes(&self) -> &KeyframeDatabase {
+        &self.keyframes
+    }
+
+    pub fn landmark(&self, id: u64) -> Option<&Landmark> {
+        self.landmarks.get(&id)
+    }
+
+    pub fn landmarks(&self) -> impl Iterator<Item = &Landmark> {
+        self.landmarks.values()
+    }
+
+    pub fn n_landmarks(&self) -> usize {
+        self.landmarks.len()
+    }
+
+    pub fn n_sessions(&self) -> usize {
+        self.sessions.len() + 1
+    }
+
+    /// First keyframe of every session after the first one
+    pub fn session_starts(&self) -> &[u64] {
+        &self.sessions
+    }
+
+    /// Session that the keyframe was inserted in, counting from zero
+    pub fn session_of(&self, keyframe: u64) -> usize {
+        self.sessions
+            .iter()
+            .filter(|&&first| first <= keyframe)
+            .count()
+    }
+
+    /// Start a new session, e.g. of another sequence relocalised against a loaded map. Returns
+    /// the offset to add to the feature identifiers of the new session, so that they don't collide
+    /// with the ones of the map
+    pub fn start_session(&mut self) -> u64 {
+        self.sessions.push(self.keyframes.next_id());
+        self.next_feature_id()
+    }
+
+    /// Smallest identifier above the ones of all the features and landmarks of the map
+    pub fn next_feature_id(&self) -> u64 {
+        let features = self
+            .keyframes
+            .iter()
+            .flat_map(|kf| kf.features.iter().map(|f| f.id));
+        self.landmarks
+            .keys()
+            .copied()
+            .chain(features)
+            .max()
+            .map_or(0, |id| id + 1)
+    }
+
+    /// Add a keyframe, which becomes an observation of the landmarks of its features
+    pub fn insert_keyframe(
+        &mut self,
+        timestamp: Duration,
+        pose: Isometry3<f64>,
+        features: Vec<KeyframeFeature>,
+    ) -> u64 {
+        let id = self.keyframes.insert(timestamp, pose, features);
+        let observed: Vec<(u64, Point2<f64>)> =
+            self.keyframes.get(id).map_or_else(Vec::new, |kf| {
+                kf.features
+                    .iter()
+                    .filter(|f| self.landmarks.contains_key(&f.id))
+                    .map(|f| (f.id, f.pt))
+                    .collect()
+            });
+        for (landmark, pt) in observed {
+            if let Some(landmark) = self.landmarks.get_mut(&landmark) {
+                landmark.observations.insert(id, pt);
+            }
+            self.refresh(landmark);
+        }
+        id
+    }
+
+    /// Add the landmark of the feature `id`, observed by the keyframes that have that feature
+    pub fn insert_landmark(&mut self, id: u64, position: Point3<f64>) -> Result<(), MapError> {
+        if self.landmarks.contains_key(&id) {
+            return Err(MapError::DuplicateLandmark(id));
+        }
+        let observations = self
+            .keyframes
+            .observers(id)
+            .filter_map(|kf| Some((kf, self.keyframes.get(kf)?.feature(id)?.pt)))
+            .collect();
+        self.landmarks.insert(
+            id,
+            Landmark {
+                id,
+                position,
+                descriptor: None,
+                observations,
+                normal: None,
+                stats: LandmarkStats::default(),
+                first_keyframe: self.keyframes.latest().map_or(0, |kf| kf.id),
+            },
+        );
+        self.refresh(id);
+        Ok(())
+    }
+
+    /// Update the position of a landmark, e.g. after an optimisation
+    pub fn set_landmark_position(
+        &mut self,
+        id: u64,
+        position: Point3<f64>,
+    ) -> Result<(), MapError> {
+        self.landmarks
+            .get_mut(&id)
+            .ok_or(MapError::MissingLandmark(id))?
+            .position = position;
+        self.refresh(id);
+        Ok(())
+    }
+
+    /// Update the pose of a keyframe, e.g. after an optimisation
+    pub fn set_keyframe_pose(&mut self, id: u64, pose: Isometry3<f64>) -> Result<(), MapError> {
+        if !self.keyframes.set_pose(id, pose) {
+            return Err(MapError::MissingKeyframe(id));
+        }
+        for landmark in self.observed_landmarks(id) {
+            self.refresh(landmark);
+        }
+        Ok(())
+    }
+
+    /// Remove a keyframe, and the landmarks that no other keyframe observes
+    pub fn remove_keyframe(&mut self, id: u64) -> Result<Keyframe, MapError> {
+        let observed = self.observed_landmarks(id);
+        let keyframe = self
+            .keyframes
+            .remove(id)
+            .ok_or(MapError::MissingKeyframe(id))?;
+        for landmark in observed {
+            let orphan = match self.landmarks.get_mut(&landmark) {
+                Some(entry) => {
+                    entry.observations.remove(&id);
+                    entry.observations.is_empty()
+                }
+                None => continue,
+            };
+            if orphan {
+                self.landmarks.remove(&landmark);
+            } else {
+                self.refresh(landmark);
+            }
+        }
+        Ok(keyframe)
+    }
+
+    pub fn remove_landmark(&mut self, id: u64) -> Result<Landmark, MapError> {
+        let landmark = self
+            .landmarks
+            .remove(&id)
+            .ok_or(MapError::MissingLandmark(id))?;
+        self.keyframes.remove_feature(id);
+        Ok(landmark)
+    }
+
+    /// Add a keyframe read from a map file
+    pub(crate) fn restore_keyframe(&mut self, keyframe: Keyframe) {
+        self.keyframes.restore(keyframe);
+    }
+
+    /// Add a landmark read from a map file, after its keyframes
+    pub(crate) fn restore_landmark(
+        &mut self,
+        id: u64,
+        position: Point3<f64>,
+        stats: LandmarkStats,
+        first_keyframe: u64,
+    ) -> Result<(), MapError> {
+        self.insert_landmark(id, position)?;
+        if let Some(landmark) = self.landmarks.get_mut(&id) {
+            landmark.stats = stats;
+            landmark.first_keyframe = first_keyframe;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn restore_sessions(&mut self, sessions: Vec<u64>) {
+        self.sessions = sessions;
+    }
+
+    /// The landmarks were predicted to be visible in the current frame
+    pub fn record_visible(&mut self, ids: &[u64]) {
+        for id in ids {
+            if let Some(landmark) = self.landmarks.get_mut(id) {
+                landmark.stats.visible += 1;
+            }
+        }
+    }
+
+    /// The landmarks were matched in the current frame
+    pub fn record_found(&mut self, ids: &[u64]) {
+        for id in ids {
+            if let Some(landmark) = self.landmarks.get_mut(id) {
+                landmark.stats.found += 1;
+            }
+        }
+    }
+
+    /// Merge the duplicate landmark `remove` into `keep`, e.g. when a track was lost and the
+    /// landmark was detected again as a new feature
+    pub fn merge_landmarks(&mut self, keep: u64, remove: u64) -> Result<(), MapError> {
+        if !self.landmarks.contains_key(&keep) {
+            return Err(MapError::MissingLandmark(keep));
+        }
+        if keep == remove {
+            return Ok(());
+        }
+        let removed = self
+            .landmarks
+            .remove(&remove)
+            .ok_or(MapError::MissingLandmark(remove))?;
+        self.keyframes.merge_features(keep, remove);
+        if let Some(landmark) = self.landmarks.get_mut(&keep) {
+            for (kf, pt) in removed.observations {
+                landmark.observations.entry(kf).or_insert(pt);
+            }
+            landmark.stats.visible += removed.stats.visible;
+            landmark.stats.found += removed.stats.found;
+            landmark.first_keyframe = landmark.first_keyframe.min(removed.first_keyframe);
+        }
+        self.refresh(keep);
+        Ok(())
+    }
+
+    /// Remove the unstable landmarks, returning their identifiers
+    pub fn cull_landmarks(&mut self) -> Vec<u64> {
+        let latest = self.keyframes.latest().map_or(0, |kf| kf.id);
+        let unstable: Vec<u64> = self
+            .landmarks
+            .values()
+            .filter(|landmark| {
+                landmark.stats.found_ratio() < self.params.min_found_ratio
+                    || (latest >= landmark.first_keyframe + self.params.culling_delay
+                        && landmark.observations.len() < self.params.min_observations)
+            })
+            .map(|landmark| landmark.id)
+            .collect();
+        for &id in &unstable {
+            self.landmarks.remove(&id);
+            self.keyframes.remove_feature(id);
+        }
+        unstable
+    }
+
+    /// Landmarks inside the frustum of a camera with pose `t_wc`, seen from less than the maximum
+    /// viewing angle, with their projections
+    pub fn visible_landmarks(
+        &self,
+        t_wc: &Isometry3<f64>,
+        camera: &dyn CameraModel,
+    ) -> Vec<(u64, Point2<f64>)> {
+        let t_cw = t_wc.inverse();
+        let min_cos = self.params.max_viewing_angle.cos();
+        self.landmarks
+            .values()
+            .filter_map(|landmark| {
+                let p_c = t_cw * landmark.position;
+                if p_c.z <= 0.0 {
+                    return None;
+                }
+                let px = camera.project(&p_c)?;
+                if !camera.is_in_image(&px, 0.0) {
+                    return None;
+                }
+                let ray = landmark.position.coords - t_wc.translation.vector;
+                let viewing_cos = landmark
+                    .normal
+                    .map_or(1.0, |normal| normal.dot(&ray) / ray.norm());
+                if viewing_cos < min_cos {
+                    return None;
+                }
+                Some((landmark.id, px))
+            })
+            .collect()
+    }
+
+    /// Landmarks observed by the keyframe and by its `n_neighbours` most covisible keyframes -
+    /// the local map to track against
+    pub fn local_landmarks(&self, keyframe: u64, n_neighbours: usize) -> BTreeSet<u64> {
+        let mut keyframes = vec![keyframe];
+        keyframes.extend(
+            self.keyframes
+                .covisible(keyframe, 1)
+                .into_iter()
+                .take(n_neighbours)
+                .map(|(id, _)| id),
+        );
+        keyframes
+            .into_iter()
+            .flat_map(|id| self.observed_landmarks(id))
+            .collect()
+    }
+
+    fn observed_landmarks(&self, keyframe: u64) -> Vec<u64> {
+        self.keyframes.get(keyframe).map_or_else(Vec::new, |kf| {
+            kf.features
+                .iter()
+                .map(|f| f.id)
+                .filter(|id| self.landmarks.contains_key(id))
+                .collect()
+        })
+    }
+
+    /// Recompute the representative descriptor and the viewing direction of a landmark
+    fn refresh(&mut self, id: u64) {
+        let landmark = match self.landmarks.get_mut(&id) {
+            Some(landmark) => landmark,
+            None => return,
+        };
+        let mut descriptors = Vec::new();
+        let mut normal = Vector3::zeros();
+        for kf in landmark.observations.keys() {
+            if let Some(keyframe) = self.keyframes.get(*kf) {
+                let ray = landmark.position.coords - keyframe.pose.translation.vector;
+                normal += ray.normalize();
+                descriptors.extend(keyframe.feature(id).and_then(|f| f.descriptor));
+            }
+        }
+        landmark.normal = Unit::try_new(normal, 1e-9);
+
+        // the descriptor with the least median distance to the other ones
+        landmark.descriptor = descriptors
+            .iter()
+            .min_by_key(|a| {
+                let mut distances: Vec<u32> = descriptors.iter().map(|b| a.distance(b)).collect();
+                distances.sort_unstable();
+                distances[distances.len() / 2]
+            })
+            .copied();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::camera::PinholeRadTan;
+
+    fn feature(id: u64, descriptor: u64) -> KeyframeFeature {
+        KeyframeFeature {
+            id,
+            pt: Point2::new(id as f64, 0.0),
+            descriptor: Some(Descriptor([descriptor, 0, 0, 0])),
+        }
+    }
+
+    /// Three keyframes along the x axis looking along z, observing landmarks 0 to 9
+    #[allow(clippy::suboptimal_flops)]
+    fn map() -> Map {
+        let mut map = Map::default();
+        for k in 0..3 {
+            let features = (0..10)
+                .filter(|&id| id >= 2 * k)
+                .map(|id| feature(id, [0b1, 0b1, 0b1110][k as usize]))
+                .collect();
+            map.insert_keyframe(
+                Duration::from_secs(k),
+                Isometry3::translation(k as f64, 0.0, 0.0),
+                features,
+            );
+        }
+        for id in 0..10 {
+            let position = Point3::new(id as f64 * 0.4 - 2.0, 0.5, 5.0);
+            map.insert_landmark(id, position).unwrap();
+        }
+        map
+    }
+
+    #[test]
+    fn observations_merging_and_culling() {
+        let mut map = map();
+        assert_eq!(map.n_landmarks(), 10);
+        let landmark = map.landmark(5).unwrap();
+        assert_eq!(
+            landmark.observations.keys().copied().collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        // the outlying descriptor of the last keyframe isn't representative
+        assert_eq!(landmark.descriptor, Some(Descriptor([1, 0, 0, 0])));
+        assert!(landmark.normal.unwrap().z > 0.9);
+        assert_eq!(
+            map.insert_landmark(5, Point3::origin()),
+            Err(MapError::DuplicateLandmark(5))
+        );
+
+        // landmark 0 is only observed by the first keyframe - merging it into 4 adds nothing to
+        // the covisibility of the first keyframe, which already sees landmark 4 as well
+        assert_eq!(map.keyframes().covisible(0, 0), vec![(1, 8), (2, 6)]);
+        map.merge_landmarks(4, 0).unwrap();
+        assert!(map.landmark(0).is_none());
+        assert_eq!(map.landmark(4).unwrap().observations.len(), 3);
+        assert_eq!(map.keyframes().get(0).unwrap().features.len(), 9);
+        assert_eq!(map.keyframes().covisible(0, 0), vec![(1, 8), (2, 6)]);
+        // landmark 1 takes over the observation of landmark 2 in the second keyframe
+        map.merge_landmarks(1, 2).unwrap();
+        assert_eq!(map.landmark(1).unwrap().observations.len(), 2);
+        assert_eq!(map.keyframes().covisible(0, 0), vec![(1, 8), (2, 6)]);
+        assert_eq!(map.merge_landmarks(1, 2), Err(MapError::MissingLandmark(2)));
+
+        // rarely found
+        map.record_visible(&[7, 8]);
+        map.record_visible(&[7, 8]);
+        map.record_found(&[8]);
+        map.record_visible(&[7]);
+        map.record_visible(&[7, 8]);
+        // not observed by enough keyframes, but too recent to be culled
+        assert_eq!(map.cull_landmarks(), vec![7]);
+        assert!(map.landmark(1).is_some());
+        assert_eq!(map.keyframes().covisible(0, 0), vec![(1, 7), (2, 5)]);
+
+        map.remove_keyframe(0).unwrap();
+        assert!(map.landmark(1).is_some());
+        assert!(map.landmark(3).is_some());
+        assert_eq!(map.remove_keyframe(0), Err(MapError::MissingKeyframe(0)));
+    }
+
+    #[test]
+    fn landmarks_in_the_frustum() {
+        let map = map();
+        let camera = PinholeRadTan::undistorted([300.0, 300.0, 320.0, 240.0], (640, 480));
+        let ids = |pose: Isometry3<f64>| -> Vec<u64> {
+            map.visible_landmarks(&pose, &camera)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect()
+        };
+        // the landmarks span x in [-2, 1.6] at depth 5 - the field of view at that depth is
+        // x in [-5.3, 5.3]
+        assert_eq!(ids(Isometry3::identity()), (0..10).collect::<Vec<_>>());
+        // a camera moved to the right only sees the landmarks on its left half
+        let moved = ids(Isometry3::translation(6.0, 0.0, 0.0));
+        assert!(!moved.is_empty() && moved.iter().all(|&id| id >= 3));
+        // behind the landmarks, looking away
+        assert!(ids(Isometry3::translation(0.0, 0.0, 10.0)).is_empty());
+        // looking back at them from behind is beyond the maximum viewing angle
+        let behind = Isometry3::new(
+            Vector3::new(0.0, 0.5, 10.0),
+            Vector3::y() * std::f64::consts::PI,
+        );
+        assert!(ids(behind).is_empty());
+
+        let local = map.local_landmarks(2, 1);
+        assert_eq!(local, (0..10).collect::<BTreeSet<u64>>());
+        assert_eq!(
+            map.local_landmarks(2, 0),
+            (4..10).collect::<BTreeSet<u64>>()
+        );
+    }
+}
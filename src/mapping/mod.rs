@@ -0,0 +1,12 @@
+pub mod io;
+pub mod keyframes;
+pub mod map;
+pub mod relocalization;
+
+pub use self::io::{MapFileError, MAP_FILE_VERSION};
+pub use self::keyframes::{
+    CullingParams, Keyframe, KeyframeDatabase, KeyframeFeature, KeyframeParams, KeyframeReason,
+    KeyframeSelector,
+};
+pub use self::map::{Landmark, LandmarkStats, Map, MapError, MapParams};
+pub use self::relocalization::{Relocalization, RelocalizationParams, Relocalizer};
@@ -0,0 +1,306 @@
+/// Relocalisation of a frame against a map, e.g. after the tracking was lost or at the start of a
+/// new session over a loaded map
+///
+/// The descriptors of the frame are matched against the features of every keyframe, and the
+/// keyframes with the most matches are the candidates. For each one, the frame is matched against
+/// the landmarks of the candidate and of its covisible keyframes, and the camera pose is computed
+/// from these 2D-3D matches with P3P in RANSAC. The first candidate with enough inliers wins.
+use crate::features::matching::{BruteForceMatcher, MatcherParams};
+use crate::features::orb::{Descriptor, KeyPoint};
+use crate::geometry::camera::CameraModel;
+use crate::geometry::pnp::{solve_pnp, PointBearing};
+use crate::geometry::ransac::{Ransac, RansacParams};
+use crate::mapping::map::Map;
+
+use log::debug;
+use nalgebra::{Isometry3, Point3};
+
+#[derive(Debug, Clone)]
+pub struct RelocalizationParams {
+    pub matcher: MatcherParams,
+    /// The threshold is on the squared angle between the bearings and the landmarks
+    pub ransac: RansacParams,
+    /// Number of keyframes, with the most matches, that the frame is relocalised against
+    pub n_candidates: usize,
+    /// Number of covisible keyframes whose landmarks are matched along with the ones of a
+    /// candidate
+    pub n_neighbours: usize,
+    /// Candidates with fewer matches are skipped
+    pub min_matches: usize,
+    /// Poses with fewer inliers are rejected
+    pub min_inliers: usize,
+}
+
+impl Default for RelocalizationParams {
+    fn default() -> Self {
+        Self {
+            matcher: MatcherParams::default(),
+            ransac: RansacParams {
+                // about 0.2 degrees
+                threshold: 1e-5,
+                ..RansacParams::default()
+            },
+            n_candidates: 5,
+            n_neighbours: 10,
+            min_matches: 20,
+            min_inliers: 15,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Relocalization {
+    /// Keyframe that the frame was relocalised against
+    pub keyframe: u64,
+    /// Pose of the camera in the world of the map
+    pub pose: Isometry3<f64>,
+    /// Keypoints consistent with the pose, and their landmarks
+    pub inliers: Vec<(usize, u64)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Relocalizer {
+    params: RelocalizationParams,
+}
+
+impl Relocalizer {
+    pub const fn new(params: RelocalizationParams) -> Self {
+        Self { params }
+    }
+
+    pub const fn params(&self) -> &RelocalizationParams {
+        &self.params
+    }
+
+    /// Pose of a frame with the given keypoints and descriptors in the map. `None` if no
+    /// candidate keyframe leads to a pose with enough inliers
+    pub fn relocalize(
+        &self,
+        map: &Map,
+        camera: &dyn CameraModel,
+        keypoints: &[KeyPoint],
+        descriptors: &[Descriptor],
+    ) -> Option<Relocalization> {
+        let matcher = BruteForceMatcher::new(self.params.matcher.clone());
+        let mut candidates: Vec<(u64, usize)> = map
+            .keyframes()
+            .iter()
+            .map(|keyframe| {
+                let train: Vec<Descriptor> = keyframe
+                    .features
+                    .iter()
+                    .filter(|f| map.landmark(f.id).is_some())
+                    .filter_map(|f| f.descriptor)
+                    .collect();
+                (
+                    keyframe.id,
+                    matcher.match_descriptors(descriptors, &train).len(),
+                )
+            })
+            .filter(|&(_, n_matches)| n_matches >= self.params.min_matches)
+            .collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let ransac = Ransac::new(self.params.ransac.clone());
+        for &(keyframe, n_matches) in candidates.iter().take(self.params.n_candidates) {
+            let landmarks: Vec<(u64, Point3<f64>, Descriptor)> = map
+                .local_landmarks(keyframe, self.params.n_neighbours)
+                .into_iter()
+                .filter_map(|id| {
+                    let landmark = map.landmark(id)?;
+                    Some((id, landmark.position, landmark.descriptor?))
+                })
+                .collect();
+            let train: Vec<Descriptor> = landmarks.iter().map(|l| l.2).collect();
+
+            let (matched, corrs): (Vec<(usize, u64)>, Vec<PointBearing>) = matcher
+                .match_descriptors(descriptors, &train)
+                .into_iter()
+                .filter_map(|m| {
+                    let (id, position, _) = landmarks[m.train_idx];
+                    let bearing = camera.unproject(&keypoints[m.query_idx].pt)?;
+                    Some(((m.query_idx, id), (position, bearing)))
+                })
+                .unzip();
+            if corrs.len() < self.params.min_matches {
+                continue;
+            }
+
+            let result = match solve_pnp(&corrs, &ransac) {
+                Some(result) => result,
+                None => continue,
+            };
+            debug!(
+                "relocalisation against keyframe {}: {} keyframe matches, {} of {} inliers",
+                keyframe,
+                n_matches,
+                result.inliers.len(),
+                corrs.len()
+            );
+            if result.inliers.len() >= self.params.min_inliers {
+                return Some(Relocalization {
+                    keyframe,
+                    pose: result.pose.inverse(),
+                    inliers: result.inliers.iter().map(|&idx| matched[idx]).collect(),
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::camera::PinholeRadTan;
+    use crate::mapping::keyframes::KeyframeFeature;
+    use crate::mapping::map::MapParams;
+    use nalgebra::{Point2, Vector3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::time::Duration;
+
+    fn keypoint(pt: Point2<f64>) -> KeyPoint {
+        KeyPoint {
+            pt,
+            octave: 0,
+            angle: 0.0,
+            response: 1.0,
+            size: 31.0,
+        }
+    }
+
+    fn random_descriptor(rng: &mut StdRng) -> Descriptor {
+        Descriptor([rng.gen(), rng.gen(), rng.gen(), rng.gen()])
+    }
+
+    /// Visible landmarks and their projections
+    fn project(
+        camera: &PinholeRadTan,
+        landmarks: &[(Point3<f64>, Descriptor)],
+        t_wc: &Isometry3<f64>,
+    ) -> Vec<(u64, Point2<f64>)> {
+        landmarks
+            .iter()
+            .enumerate()
+            .filter_map(|(id, (position, _))| {
+                let px = camera.project(&(t_wc.inverse() * position))?;
+                Some((id as u64, px)).filter(|_| camera.is_in_image(&px, 0.0))
+            })
+            .collect()
+    }
+
+    /// Map of 5 keyframes observing 300 random landmarks, and the landmarks
+    fn scene(rng: &mut StdRng, camera: &PinholeRadTan) -> (Map, Vec<(Point3<f64>, Descriptor)>) {
+        let landmarks: Vec<(Point3<f64>, Descriptor)> = (0..300)
+            .map(|_| {
+                let position = Point3::new(
+                    rng.gen_range(-6.0, 8.0),
+                    rng.gen_range(-3.0, 3.0),
+                    rng.gen_range(4.0, 10.0),
+                );
+                (position, random_descriptor(rng))
+            })
+            .collect();
+
+        let mut map = Map::default();
+        for k in 0..5 {
+            let pose = Isometry3::translation(k as f64 * 0.5, 0.0, 0.0);
+            let features = project(camera, &landmarks, &pose)
+                .into_iter()
+                .map(|(id, pt)| KeyframeFeature {
+                    id,
+                    pt,
+                    descriptor: Some(landmarks[id as usize].1),
+                })
+                .collect();
+            map.insert_keyframe(Duration::from_secs(k), pose, features);
+        }
+        for (id, (position, _)) in landmarks.iter().enumerate() {
+            map.insert_landmark(id as u64, *position).unwrap();
+        }
+        (map, landmarks)
+    }
+
+    /// A frame at `t_wc`, seeing most of the landmarks with slightly different descriptors, and
+    /// clutter that isn't in the map. Also returns the keypoints of the landmarks
+    fn frame(
+        rng: &mut StdRng,
+        camera: &PinholeRadTan,
+        landmarks: &[(Point3<f64>, Descriptor)],
+        t_wc: &Isometry3<f64>,
+    ) -> (Vec<KeyPoint>, Vec<Descriptor>, Vec<(usize, u64)>) {
+        let mut truth = Vec::new();
+        let mut keypoints = Vec::new();
+        let mut descriptors = Vec::new();
+        for (id, pt) in project(camera, landmarks, t_wc) {
+            if rng.gen_bool(0.8) {
+                let mut descriptor = landmarks[id as usize].1;
+                for _ in 0..5 {
+                    let bit = rng.gen_range(0, Descriptor::BITS);
+                    descriptor.set_bit(bit, !descriptor.bit(bit));
+                }
+                truth.push((keypoints.len(), id));
+                keypoints.push(keypoint(pt));
+                descriptors.push(descriptor);
+            }
+        }
+        for _ in 0..50 {
+            let pt = Point2::new(rng.gen_range(0.0, 639.0), rng.gen_range(0.0, 479.0));
+            keypoints.push(keypoint(pt));
+            descriptors.push(random_descriptor(rng));
+        }
+        (keypoints, descriptors, truth)
+    }
+
+    #[test]
+    fn relocalise_against_a_map() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let camera = PinholeRadTan::undistorted([400.0, 400.0, 320.0, 240.0], (640, 480));
+        let (map, landmarks) = scene(&mut rng, &camera);
+        // between the keyframes
+        let t_wc = Isometry3::new(Vector3::new(1.2, 0.1, 0.3), Vector3::new(0.02, -0.05, 0.03));
+        let (keypoints, descriptors, truth) = frame(&mut rng, &camera, &landmarks, &t_wc);
+
+        let relocalizer = Relocalizer::default();
+        let result = relocalizer
+            .relocalize(&map, &camera, &keypoints, &descriptors)
+            .expect("Relocalised");
+        let delta = result.pose.inverse() * t_wc;
+        assert!(delta.translation.vector.norm() < 1e-6, "{}", delta);
+        assert!(delta.rotation.angle() < 1e-6, "{}", delta);
+        assert!(result.inliers.len() as f64 > 0.9 * truth.len() as f64);
+        assert!(result.inliers.iter().all(|inlier| truth.contains(inlier)));
+
+        // nothing in common with the map
+        let clutter: Vec<Descriptor> = (0..keypoints.len())
+            .map(|_| random_descriptor(&mut rng))
+            .collect();
+        assert!(relocalizer
+            .relocalize(&map, &camera, &keypoints, &clutter)
+            .is_none());
+    }
+
+    #[test]
+    fn relocalise_against_a_saved_map() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let camera = PinholeRadTan::undistorted([400.0, 400.0, 320.0, 240.0], (640, 480));
+        let (map, landmarks) = scene(&mut rng, &camera);
+        let path = std::env::temp_dir().join("slam-rs-relocalise-saved-map.map");
+        map.write(&path).unwrap();
+        let loaded = Map::read(&path, MapParams::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.keyframes().len(), 5);
+        assert_eq!(loaded.n_landmarks(), landmarks.len());
+
+        let t_wc = Isometry3::new(Vector3::new(0.4, -0.2, 0.1), Vector3::new(-0.03, 0.04, 0.0));
+        let (keypoints, descriptors, truth) = frame(&mut rng, &camera, &landmarks, &t_wc);
+        let result = Relocalizer::default()
+            .relocalize(&loaded, &camera, &keypoints, &descriptors)
+            .expect("Relocalised");
+        let delta = result.pose.inverse() * t_wc;
+        assert!(delta.translation.vector.norm() < 1e-6, "{}", delta);
+        assert!(delta.rotation.angle() < 1e-6, "{}", delta);
+        assert!(result.inliers.iter().all(|inlier| truth.contains(inlier)));
+    }
+}
@@ -0,0 +1,466 @@
+/// Pyramidal Lucas-Kanade feature tracker, using the inverse compositional formulation
+///
+/// For more information see:
+///
+/// - Bouguet, "Pyramidal implementation of the Lucas Kanade feature tracker", Intel 2001
+/// - Baker & Matthews, "Lucas-Kanade 20 years on: a unifying framework", IJCV 2004
+use crate::features::corners::{detect_corners_excluding, CornerParams};
+use crate::features::pyramid::ImagePyramid;
+use crate::utils::imgproc::{gradients, FloatImage, GradientKernel};
+
+use image::GrayImage;
+use nalgebra::{Matrix2, Point2, Vector2};
+
+// -------------------------------------------------------------------------------------------------
+// TrackStatus / Track
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackStatus {
+    /// Detected in the current frame
+    New,
+    /// Successfully tracked from the previous frame
+    Tracked,
+    /// The feature (or its patch) left the image
+    OutOfBounds,
+    /// The patch doesn't have enough texture to be tracked reliably
+    LowTexture,
+    /// The iterations didn't converge
+    Diverged,
+    /// Tracking back to the previous frame didn't lead to the original location
+    FbInconsistent,
+}
+
+impl TrackStatus {
+    /// Is the track still alive after the current frame?
+    pub const fn is_active(self) -> bool {
+        matches!(self, Self::New | Self::Tracked)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    /// Identifier of the track, unique across the lifetime of the tracker
+    pub id: u64,
+    /// Location in the current frame (last known location for lost tracks)
+    pub pt: Point2<f64>,
+    pub status: TrackStatus,
+    /// Number of frames that the feature has been tracked for
+    pub age: usize,
+}
+
+// -------------------------------------------------------------------------------------------------
+// KltParams
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct KltParams {
+    /// Number of pyramid levels - each one half the size of the previous one
+    pub n_levels: usize,
+    /// Half side of the tracked patch (pixels)
+    pub half_window: usize,
+    pub max_iterations: usize,
+    /// Stop iterating when the update is smaller than this (pixels)
+    pub epsilon: f64,
+    /// Minimum eigenvalue of the (per-pixel normalised) patch hessian
+    pub min_eigenvalue: f64,
+    /// Maximum distance between the original location and the one obtained by tracking forward
+    /// and then backward (pixels). Set to `None` to disable the check
+    pub fb_threshold: Option<f64>,
+    pub gradient: GradientKernel,
+    /// Detect new features whenever the number of active tracks drops below this
+    pub min_tracks: usize,
+    /// Parameters of the detector used for replenishing the tracks. `max_corners` is the target
+    /// number of active tracks
+    pub detector: CornerParams,
+}
+
+impl Default for KltParams {
+    fn default() -> Self {
+        Self {
+            n_levels: 4,
+            half_window: 7,
+            max_iterations: 30,
+            epsilon: 0.01,
+            min_eigenvalue: 0.1,
+            fb_threshold: Some(0.5),
+            gradient: GradientKernel::Scharr,
+            min_tracks: 150,
+            detector: CornerParams::default(),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// KltPyramid
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+struct KltLevel {
+    img: FloatImage,
+    gx: FloatImage,
+    gy: FloatImage,
+    /// Scale of the level with respect to level 0
+    scale: f64,
+}
+
+/// Image pyramid along with the gradients of each level
+#[derive(Debug, Clone)]
+pub struct KltPyramid {
+    levels: Vec<KltLevel>,
+}
+
+impl KltPyramid {
+    pub fn new(img: &GrayImage, n_levels: usize, gradient: GradientKernel) -> Self {
+        let pyramid = ImagePyramid::new(img, n_levels, 2.0, 16);
+        let levels = (0..pyramid.len())
+            .map(|idx| {
+                let img = FloatImage::from(pyramid.level(idx));
+                let (gx, gy) = gradients(&img, gradient);
+                KltLevel {
+                    img,
+                    gx,
+                    gy,
+                    scale: pyramid.scale(idx),
+                }
+            })
+            .collect();
+
+        Self { levels }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+}
+
+/// Track the given points from `prev` to `next`.
+///
+/// `guesses` optionally provides an initial estimate of the location of each point in `next`,
+/// otherwise the original locations are used.
+pub fn track_points(
+    prev: &KltPyramid,
+    next: &KltPyramid,
+    pts: &[Point2<f64>],
+    guesses: Option<&[Point2<f64>]>,
+    params: &KltParams,
+) -> Vec<Result<Point2<f64>, TrackStatus>> {
+    pts.iter()
+        .enumerate()
+        .map(|(idx, pt)| {
+            let guess = guesses.map_or(*pt, |g| g[idx]);
+            let tracked = track_point(prev, next, pt, guess, params)?;
+
+            match params.fb_threshold {
+                Some(threshold) => {
+                    let back = track_point(next, prev, &tracked, *pt, params)
+                        .map_err(|_| TrackStatus::FbInconsistent)?;
+                    if (back - pt).norm() > threshold {
+                        Err(TrackStatus::FbInconsistent)
+                    } else {
+                        Ok(tracked)
+                    }
+                }
+                None => Ok(tracked),
+            }
+        })
+        .collect()
+}
+
+/// Coarse-to-fine inverse compositional alignment of the patch around `pt` (in `prev`)
+#[allow(clippy::suboptimal_flops)]
+fn track_point(
+    prev: &KltPyramid,
+    next: &KltPyramid,
+    pt: &Point2<f64>,
+    guess: Point2<f64>,
+    params: &KltParams,
+) -> Result<Point2<f64>, TrackStatus> {
+    let half = params.half_window as i64;
+    let n_levels = prev.len().min(next.len());
+    let mut estimate = guess;
+
+    'levels: for level_idx in (0..n_levels).rev() {
+        let is_finest = level_idx == 0;
+        let (tmpl_level, search_level) = (&prev.levels[level_idx], &next.levels[level_idx]);
+        let scale = tmpl_level.scale;
+        let center = pt / scale;
+
+        // template values and gradients - fixed across iterations
+        let mut template = Vec::with_capacity(((2 * half + 1) * (2 * half + 1)) as usize);
+        for v in -half..=half {
+            for u in -half..=half {
+                let (x, y) = (center.x + u as f64, center.y + v as f64);
+                match (
+                    tmpl_level.img.bilinear(x, y),
+                    tmpl_level.gx.bilinear(x, y),
+                    tmpl_level.gy.bilinear(x, y),
+                ) {
+                    (Some(value), Some(gx), Some(gy)) => {
+                        template.push((u as f64, v as f64, value, Vector2::new(gx, gy)))
+                    }
+                    _ => break,
+                }
+            }
+        }
+        if template.len() != ((2 * half + 1) * (2 * half + 1)) as usize {
+            if is_finest {
+                return Err(TrackStatus::OutOfBounds);
+            }
+            continue;
+        }
+
+        let hessian: Matrix2<f64> = template.iter().map(|(_, _, _, g)| g * g.transpose()).sum();
+        let normalised = hessian / template.len() as f64;
+        let min_eigenvalue = 0.5 * (normalised.trace())
+            - (0.25 * (normalised[(0, 0)] - normalised[(1, 1)]).powi(2)
+                + normalised[(0, 1)].powi(2))
+            .sqrt();
+        let hessian_inv = match hessian.try_inverse() {
+            Some(inv) if min_eigenvalue >= params.min_eigenvalue => inv,
+            _ if is_finest => return Err(TrackStatus::LowTexture),
+            _ => continue,
+        };
+
+        let mut position = estimate / scale;
+        let mut converged = false;
+        for _ in 0..params.max_iterations {
+            let mut b = Vector2::zeros();
+            for (u, v, value, g) in &template {
+                match search_level.img.bilinear(position.x + u, position.y + v) {
+                    Some(warped) => b += g * (warped - value),
+                    None if is_finest => return Err(TrackStatus::OutOfBounds),
+                    // the mismatch is incomplete - keep the estimate of the coarser levels
+                    None => continue 'levels,
+                }
+            }
+
+            let delta = hessian_inv * b;
+            position -= delta;
+            if delta.norm() < params.epsilon {
+                converged = true;
+                break;
+            }
+        }
+
+        if !position.x.is_finite() || !position.y.is_finite() {
+            return Err(TrackStatus::Diverged);
+        }
+        if is_finest && !converged {
+            return Err(TrackStatus::Diverged);
+        }
+        estimate = position * scale;
+    }
+
+    Ok(estimate)
+}
+
+// -------------------------------------------------------------------------------------------------
+// KltTracker
+// -------------------------------------------------------------------------------------------------
+
+/// Track features across consecutive frames of an image stream.
+///
+/// Lost tracks are reported (with the reason in their status) for the frame that they were lost
+/// in and are dropped afterwards. New features are detected whenever the number of active tracks
+/// drops too low.
+#[derive(Debug)]
+pub struct KltTracker {
+    params: KltParams,
+    prev: Option<KltPyramid>,
+    tracks: Vec<Track>,
+    next_id: u64,
+}
+
+impl KltTracker {
+    pub const fn new(params: KltParams) -> Self {
+        Self {
+            params,
+            prev: None,
+            tracks: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub const fn params(&self) -> &KltParams {
+        &self.params
+    }
+
+    /// Tracks of the last processed frame, including the ones that were lost in it
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// Tracks that are still alive after the last processed frame
+    pub fn active_tracks(&self) -> impl Iterator<Item = &Track> {
+        self.tracks.iter().filter(|t| t.status.is_active())
+    }
+
+    /// Track the features of the previous frame to `img` and replenish them if needed
+    pub fn process_frame(&mut self, img: &GrayImage) -> &[Track] {
+        let pyramid = KltPyramid::new(img, self.params.n_levels, self.params.gradient);
+        self.tracks.retain(|t| t.status.is_active());
+
+        if let Some(prev) = &self.prev {
+            let pts: Vec<_> = self.tracks.iter().map(|t| t.pt).collect();
+            let results = track_points(prev, &pyramid, &pts, None, &self.params);
+            for (track, result) in self.tracks.iter_mut().zip(results) {
+                match result {
+                    Ok(pt) => {
+                        track.pt = pt;
+                        track.status = TrackStatus::Tracked;
+                        track.age += 1;
+                    }
+                    Err(status) => track.status = status,
+                }
+            }
+        }
+
+        let n_active = self.active_tracks().count();
+        if n_active < self.params.min_tracks {
+            let occupied: Vec<_> = self.active_tracks().map(|t| t.pt).collect();
+            let detector = CornerParams {
+                max_corners: self.params.detector.max_corners.saturating_sub(n_active),
+                border: self
+                    .params
+                    .detector
+                    .border
+                    .max(self.params.half_window as u32 + 1),
+                ..self.params.detector.clone()
+            };
+
+            for corner in detect_corners_excluding(img, &detector, &occupied) {
+                self.tracks.push(Track {
+                    id: self.next_id,
+                    pt: corner.pt,
+                    status: TrackStatus::New,
+                    age: 0,
+                });
+                self.next_id += 1;
+            }
+        }
+
+        self.prev = Some(pyramid);
+        &self.tracks
+    }
+}
+
+impl Default for KltTracker {
+    fn default() -> Self {
+        Self::new(KltParams::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::imgproc::interpolate;
+    use image::{load_from_memory_with_format, ImageFormat::Png, Luma};
+    use std::collections::HashMap;
+
+    fn sample_image() -> GrayImage {
+        let img = include_bytes!("../../tests/sample_dataset/cam0/data/1403636579763555584.png");
+        load_from_memory_with_format(img, Png)
+            .expect("Load img from memory")
+            .into_luma8()
+    }
+
+    /// Shift the image content by (dx, dy) pixels
+    fn shift(img: &GrayImage, dx: f64, dy: f64) -> GrayImage {
+        GrayImage::from_fn(img.width(), img.height(), |x, y| {
+            let value = interpolate(img, f64::from(x) - dx, f64::from(y) - dy).unwrap_or(0.0);
+            Luma([value.round() as u8])
+        })
+    }
+
+    #[test]
+    fn track_known_translation() {
+        let img = sample_image();
+        let (dx, dy) = (3.4, -2.7);
+        let shifted = shift(&img, dx, dy);
+
+        let mut tracker = KltTracker::default();
+        let initial: HashMap<u64, Point2<f64>> = tracker
+            .process_frame(&img)
+            .iter()
+            .map(|t| (t.id, t.pt))
+            .collect();
+        assert!(initial.len() >= tracker.params().min_tracks);
+
+        tracker.process_frame(&shifted);
+        let tracked: Vec<_> = tracker
+            .tracks()
+            .iter()
+            .filter(|t| t.status == TrackStatus::Tracked)
+            .collect();
+        assert!(tracked.len() as f64 > 0.9 * initial.len() as f64);
+
+        // resampling the shifted image blurs it slightly - allow for some outliers
+        let errors: Vec<f64> = tracked
+            .iter()
+            .map(|t| (t.pt - initial[&t.id] - Vector2::new(dx, dy)).norm())
+            .collect();
+        let mean_error = errors.iter().sum::<f64>() / errors.len() as f64;
+        assert!(mean_error < 0.05, "{}", mean_error);
+        let n_accurate = errors.iter().filter(|&&e| e < 0.15).count();
+        assert!(n_accurate as f64 > 0.95 * errors.len() as f64);
+        assert!(tracked.iter().all(|t| t.age == 1));
+    }
+
+    #[test]
+    fn lost_tracks_are_flagged_and_dropped() {
+        let img = sample_image();
+        let unrelated = GrayImage::from_fn(img.width(), img.height(), |x, y| {
+            Luma([((x * 7919 + y * 104_729) % 251) as u8])
+        });
+
+        let mut tracker = KltTracker::default();
+        let n_initial = tracker.process_frame(&img).len();
+        let n_lost = tracker
+            .process_frame(&unrelated)
+            .iter()
+            .filter(|t| !t.status.is_active() && t.id < n_initial as u64)
+            .count();
+        assert!(n_lost as f64 > 0.9 * n_initial as f64);
+
+        // lost tracks are only reported once
+        tracker.process_frame(&unrelated);
+        assert!(tracker
+            .tracks()
+            .iter()
+            .all(|t| t.status.is_active() || t.id >= n_initial as u64));
+    }
+
+    #[test]
+    fn track_ids_persist_across_stream() {
+        let mut paths: Vec<_> = std::fs::read_dir("tests/sample_dataset/cam0/data")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+
+        let mut tracker = KltTracker::default();
+        let mut prev_ids: Vec<u64> = Vec::new();
+        for path in paths {
+            let img = image::open(path).unwrap().into_luma8();
+            let tracks = tracker.process_frame(&img);
+
+            // every track that was active in the previous frame is reported with the same id
+            for id in &prev_ids {
+                assert!(tracks.iter().any(|t| t.id == *id));
+            }
+            let ids: Vec<_> = tracks.iter().map(|t| t.id).collect();
+            let mut unique = ids.clone();
+            unique.dedup();
+            assert_eq!(ids.len(), unique.len());
+
+            prev_ids = tracker.active_tracks().map(|t| t.id).collect();
+        }
+
+        assert!(tracker.active_tracks().any(|t| t.age == 4));
+    }
+}
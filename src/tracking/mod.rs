@@ -0,0 +1,7 @@
+pub mod klt;
+pub mod msckf;
+pub mod strapdown;
+
+pub use self::klt::{KltParams, KltTracker, Track, TrackStatus};
+pub use self::msckf::{Msckf, MsckfParams, MsckfUpdate};
+pub use self::strapdown::{Integration, NavState, StrapdownParams, StrapdownPropagator};
@@ -0,0 +1,845 @@
+/// Multi-State Constraint Kalman Filter - filter-based visual-inertial odometry
+///
+/// "A Multi-State Constraint Kalman Filter for Vision-aided Inertial Navigation", Mourikis &
+/// Roumeliotis, ICRA 2007. The error state of the IMU, `[dtheta, dv, dp, dbg, dba]`, is propagated
+/// with the IMU samples, and a clone of the pose of the body `[dtheta, dp]` is appended at each
+/// frame. When a feature track ends, the feature is triangulated from the clones that observed it
+/// and its bearing residuals update all of them at once. The residuals are projected on the left
+/// null space of their Jacobian with respect to the feature, so the feature is never part of the
+/// state.
+///
+/// Optionally, the pose of the camera in the body frame `[dtheta, dp]` and the offset of the camera
+/// timestamps to the IMU clock are estimated as well, as in "Online temporal calibration for
+/// camera-assisted inertial navigation", Li & Mourikis, 2014. A frame stamped `t` is captured at
+/// `t + time_offset` in the IMU clock, so its clone depends on the offset through the angular and
+/// linear velocities of the body.
+///
+/// Rotations are perturbed on the right, `R * Exp(dtheta)`, and positions and velocities in the
+/// world frame.
+use crate::drivers::CameraCalibration;
+use crate::geometry::camera::CameraModel;
+use crate::geometry::lie;
+use crate::geometry::linalg::{null_space, skew, tangent_basis};
+use crate::geometry::pnp::bearing_residual;
+use crate::geometry::triangulation::{
+    parallax_angle, refine_point, triangulate_dlt, triangulate_midpoint, PoseBearing,
+};
+use crate::graph::imu::{ImuNoise, GRAVITY};
+use crate::graph::robust::chi_square_quantile;
+use crate::graph::values::ImuBias;
+use crate::tracking::klt::Track;
+use crate::tracking::strapdown::{Integration, NavState, StrapdownParams, StrapdownPropagator};
+use crate::utils::ImuSample;
+
+use nalgebra::{
+    DMatrix, DVector, Isometry3, Matrix3, Translation3, Unit, UnitQuaternion, Vector3, U3,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Dimension of the error state of the IMU
+const IMU_DIM: usize = 15;
+/// Dimension of the error state of a clone
+const CLONE_DIM: usize = 6;
+
+/// Bearings of a feature, with the frames they were observed in
+type Observations = Vec<(u64, Unit<Vector3<f64>>)>;
+
+#[derive(Debug, Clone)]
+pub struct MsckfParams {
+    pub noise: ImuNoise,
+    /// Gravity in the world frame (m / s^2)
+    pub gravity: Vector3<f64>,
+    /// Standard deviation of the bearing noise of the features (rad)
+    pub bearing_sigma: f64,
+    /// Clones kept in the sliding window - the oldest one is marginalised beyond that
+    pub max_clones: usize,
+    /// Minimum number of observations of a feature used in an update
+    pub min_observations: usize,
+    /// Minimum parallax of a feature used in an update (rad)
+    pub min_parallax: f64,
+    /// Confidence of the chi-square test gating the features, e.g. 0.95
+    pub confidence: f64,
+    /// Initial standard deviations of the attitude (rad), velocity (m / s) and biases. The initial
+    /// position defines the world frame and is certain
+    pub initial_attitude_sigma: f64,
+    pub initial_velocity_sigma: f64,
+    pub initial_gyro_bias_sigma: f64,
+    pub initial_accel_bias_sigma: f64,
+    /// Estimate the pose of the camera in the body frame
+    pub estimate_extrinsics: bool,
+    /// Estimate the offset of the camera timestamps to the IMU clock
+    pub estimate_time_offset: bool,
+    /// Initial standard deviations of the extrinsics (rad and m) and of the time offset (s)
+    pub initial_extrinsic_rotation_sigma: f64,
+    pub initial_extrinsic_translation_sigma: f64,
+    pub initial_time_offset_sigma: f64,
+}
+
+impl Default for MsckfParams {
+    fn default() -> Self {
+        Self {
+            noise: ImuNoise::default(),
+            gravity: Vector3::new(0.0, 0.0, -GRAVITY),
+            bearing_sigma: 2e-3,
+            max_clones: 20,
+            min_observations: 3,
+            min_parallax: 0.5_f64.to_radians(),
+            confidence: 0.95,
+            initial_attitude_sigma: 0.02,
+            initial_velocity_sigma: 0.1,
+            initial_gyro_bias_sigma: 0.01,
+            initial_accel_bias_sigma: 0.1,
+            estimate_extrinsics: false,
+            estimate_time_offset: false,
+            initial_extrinsic_rotation_sigma: 0.01,
+            initial_extrinsic_translation_sigma: 0.02,
+            initial_time_offset_sigma: 0.01,
+        }
+    }
+}
+
+/// Pose of the body when a frame was captured
+#[derive(Debug, Clone)]
+struct PoseClone {
+    frame: u64,
+    rotation: UnitQuaternion<f64>,
+    position: Vector3<f64>,
+}
+
+impl PoseClone {
+    /// `T_CW` of the camera
+    fn camera_pose(&self, t_bs: &Isometry3<f64>) -> Isometry3<f64> {
+        (Isometry3::from_parts(Translation3::from(self.position), self.rotation) * t_bs).inverse()
+    }
+}
+
+/// Outcome of the update of a frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MsckfUpdate {
+    /// Features whose observations updated the state
+    pub n_features: usize,
+    /// Features rejected by the chi-square test
+    pub n_rejected: usize,
+}
+
+/// Camera-IMU calibration of the filter, with the standard deviations of the estimated parts
+#[derive(Debug, Clone)]
+pub struct CameraImuCalibration {
+    pub t_bs: Isometry3<f64>,
+    /// `t_imu = t_cam + time_offset` (seconds)
+    pub time_offset: f64,
+    /// Of the rotation (rad) and translation (m) of the extrinsics
+    pub extrinsic_sigmas: Option<(Vector3<f64>, Vector3<f64>)>,
+    pub time_offset_sigma: Option<f64>,
+}
+
+impl fmt::Display for CameraImuCalibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = self.t_bs.translation.vector;
+        let r = self.t_bs.rotation.scaled_axis();
+        write!(
+            f,
+            "T_BS translation [{:.4}, {:.4}, {:.4}] m, rotation [{:.4}, {:.4}, {:.4}] rad",
+            t.x, t.y, t.z, r.x, r.y, r.z
+        )?;
+        if let Some((rotation, translation)) = &self.extrinsic_sigmas {
+            write!(
+                f,
+                " (sigmas {:.4} m, {:.4} rad)",
+                translation.max(),
+                rotation.max()
+            )?;
+        }
+        write!(f, ", time offset {:.2} ms", self.time_offset * 1e3)?;
+        if let Some(sigma) = self.time_offset_sigma {
+            write!(f, " (sigma {:.2} ms)", sigma * 1e3)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Msckf {
+    params: MsckfParams,
+    camera: Arc<dyn CameraModel>,
+    t_bs: Isometry3<f64>,
+    /// `t_imu = t_cam + time_offset` (seconds)
+    time_offset: f64,
+    /// Nominal state of the IMU, with the bias estimate in its parameters
+    propagator: StrapdownPropagator,
+    last_sample: Option<ImuSample>,
+    /// `[imu, extrinsics, time offset, clones...]`, the calibration states when estimated
+    covariance: DMatrix<f64>,
+    extrinsic_index: Option<usize>,
+    time_offset_index: Option<usize>,
+    clones_start: usize,
+    clones: VecDeque<PoseClone>,
+    /// Bearings of the tracked features in each frame, by track id
+    features: BTreeMap<u64, Observations>,
+    next_frame: u64,
+}
+
+impl Msckf {
+    /// Start from an initialised state, e.g. of the static or the visual-inertial initialisation
+    pub fn new(
+        state: NavState,
+        bias: ImuBias,
+        camera: Arc<dyn CameraModel>,
+        t_bs: Isometry3<f64>,
+        time_offset: f64,
+        params: MsckfParams,
+    ) -> Self {
+        let mut sigmas = vec![
+            params.initial_attitude_sigma,
+            params.initial_velocity_sigma,
+            0.0,
+            params.initial_gyro_bias_sigma,
+            params.initial_accel_bias_sigma,
+        ];
+        let mut clones_start = IMU_DIM;
+        let extrinsic_index = if params.estimate_extrinsics {
+            sigmas.push(params.initial_extrinsic_rotation_sigma);
+            sigmas.push(params.initial_extrinsic_translation_sigma);
+            clones_start += CLONE_DIM;
+            Some(IMU_DIM)
+        } else {
+            None
+        };
+        let variances: Vec<f64> = sigmas
+            .iter()
+            .flat_map(|sigma| vec![sigma * sigma; 3])
+            .collect();
+        let mut covariance = DMatrix::from_diagonal(&DVector::from_vec(variances));
+        let time_offset_index = if params.estimate_time_offset {
+            covariance = covariance
+                .insert_row(clones_start, 0.0)
+                .insert_column(clones_start, 0.0);
+            covariance[(clones_start, clones_start)] = params.initial_time_offset_sigma.powi(2);
+            clones_start += 1;
+            Some(clones_start - 1)
+        } else {
+            None
+        };
+        let strapdown = StrapdownParams {
+            integration: Integration::Midpoint,
+            gravity: params.gravity,
+            bias,
+        };
+        Self {
+            params,
+            camera,
+            t_bs,
+            time_offset,
+            propagator: StrapdownPropagator::new(state, strapdown),
+            last_sample: None,
+            covariance,
+            extrinsic_index,
+            time_offset_index,
+            clones_start,
+            clones: VecDeque::new(),
+            features: BTreeMap::new(),
+            next_frame: 0,
+        }
+    }
+
+    /// Start from the extrinsics and the time offset of the calibration of the camera
+    pub fn from_calibration(
+        state: NavState,
+        bias: ImuBias,
+        calibration: &CameraCalibration,
+        params: MsckfParams,
+    ) -> Self {
+        Self::new(
+            state,
+            bias,
+            calibration.camera_model(),
+            calibration.t_bs,
+            calibration.time_offset,
+            params,
+        )
+    }
+
+    pub const fn state(&self) -> &NavState {
+        self.propagator.state()
+    }
+
+    /// Current estimate of the camera-IMU calibration
+    pub fn calibration(&self) -> CameraImuCalibration {
+        let sigma = |index: usize| self.covariance[(index, index)].sqrt();
+        let sigmas = |start: usize| Vector3::from_fn(|i, _| sigma(start + i));
+        CameraImuCalibration {
+            t_bs: self.t_bs,
+            time_offset: self.time_offset,
+            extrinsic_sigmas: self
+                .extrinsic_index
+                .map(|index| (sigmas(index), sigmas(index + 3))),
+            time_offset_sigma: self.time_offset_index.map(sigma),
+        }
+    }
+
+    pub const fn bias(&self) -> &ImuBias {
+        &self.propagator.params().bias
+    }
+
+    pub const fn covariance(&self) -> &DMatrix<f64> {
+        &self.covariance
+    }
+
+    pub fn n_clones(&self) -> usize {
+        self.clones.len()
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Propagation
+    // ---------------------------------------------------------------------------------------------
+
+    /// Propagate the nominal state and the covariance to the time of the sample
+    pub fn propagate(&mut self, sample: &ImuSample) {
+        let before = self.state().clone();
+        let bias = *self.bias();
+        let previous = self.last_sample.replace(*sample).unwrap_or(*sample);
+        self.propagator.propagate(sample);
+        if sample.timestamp <= before.timestamp {
+            return;
+        }
+
+        let dt = (sample.timestamp - before.timestamp).as_secs_f64();
+        let omega = (previous.gyro + sample.gyro) * 0.5 - bias.gyro;
+        let accel = (previous.accel + sample.accel) * 0.5 - bias.accel;
+        let rotation = before.rotation.to_rotation_matrix().into_inner();
+        let rotated_skew = rotation * skew(&accel);
+
+        let mut transition = DMatrix::<f64>::identity(IMU_DIM, IMU_DIM);
+        let mut set = |row: usize, col: usize, block: Matrix3<f64>| {
+            transition
+                .fixed_slice_mut::<U3, U3>(row, col)
+                .copy_from(&block);
+        };
+        set(
+            0,
+            0,
+            lie::exp(&(omega * dt))
+                .to_rotation_matrix()
+                .into_inner()
+                .transpose(),
+        );
+        set(0, 9, -lie::right_jacobian(&(omega * dt)) * dt);
+        set(3, 0, -rotated_skew * dt);
+        set(3, 12, -rotation * dt);
+        set(6, 0, -rotated_skew * (0.5 * dt * dt));
+        set(6, 3, Matrix3::identity() * dt);
+        set(6, 12, -rotation * (0.5 * dt * dt));
+
+        let noise = &self.params.noise;
+        let densities = [
+            noise.gyro_noise_density,
+            noise.accel_noise_density,
+            0.0,
+            noise.gyro_random_walk,
+            noise.accel_random_walk,
+        ];
+        let process_noise = DMatrix::from_diagonal(&DVector::from_iterator(
+            IMU_DIM,
+            densities
+                .iter()
+                .flat_map(|sigma| vec![sigma * sigma * dt; 3]),
+        ));
+
+        let dim = self.covariance.nrows();
+        let imu = self
+            .covariance
+            .slice((0, 0), (IMU_DIM, IMU_DIM))
+            .into_owned();
+        let imu = &transition * imu * transition.transpose() + process_noise;
+        self.covariance
+            .slice_mut((0, 0), (IMU_DIM, IMU_DIM))
+            .copy_from(&imu);
+        if dim > IMU_DIM {
+            let cross = &transition
+                * self
+                    .covariance
+                    .slice((0, IMU_DIM), (IMU_DIM, dim - IMU_DIM));
+            self.covariance
+                .slice_mut((0, IMU_DIM), (IMU_DIM, dim - IMU_DIM))
+                .copy_from(&cross);
+            self.covariance
+                .slice_mut((IMU_DIM, 0), (dim - IMU_DIM, IMU_DIM))
+                .copy_from(&cross.transpose());
+        }
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Update
+    // ---------------------------------------------------------------------------------------------
+
+    /// Clone the pose of the body for a new frame, record the observations of the active tracks
+    /// and update the state with the features that can't be tracked any further
+    ///
+    /// `timestamp` is the time of the frame in the clock of the camera. The IMU samples up to
+    /// that time should have been propagated already.
+    pub fn process_frame(&mut self, timestamp: Duration, tracks: &[Track]) -> MsckfUpdate {
+        let frame = self.next_frame;
+        self.next_frame += 1;
+        self.augment(frame, timestamp);
+
+        let mut observed = Vec::new();
+        for track in tracks.iter().filter(|track| track.status.is_active()) {
+            if let Some(bearing) = self.camera.unproject(&track.pt) {
+                self.features
+                    .entry(track.id)
+                    .or_default()
+                    .push((frame, bearing));
+                observed.push(track.id);
+            }
+        }
+
+        // lost features, and the ones observed in the clone about to be marginalised
+        let oldest = if self.clones.len() > self.params.max_clones {
+            Some(self.clones[0].frame)
+        } else {
+            None
+        };
+        let (finished, active): (BTreeMap<_, _>, BTreeMap<_, _>) =
+            std::mem::take(&mut self.features)
+                .into_iter()
+                .partition(|(id, observations)| {
+                    !observed.contains(id) || oldest == Some(observations[0].0)
+                });
+        self.features = active;
+        let finished: Vec<Observations> = finished
+            .into_values()
+            .filter(|observations| observations.len() >= self.params.min_observations)
+            .collect();
+
+        let update = self.update(&finished);
+        if oldest.is_some() {
+            self.marginalize_oldest();
+        }
+        update
+    }
+
+    /// Append a clone of the pose at the time of the frame, extrapolated from the current state
+    fn augment(&mut self, frame: u64, timestamp: Duration) {
+        let state = self.state();
+        let interval = timestamp.as_secs_f64() + self.time_offset - state.timestamp.as_secs_f64();
+        let omega = self
+            .last_sample
+            .map_or_else(Vector3::zeros, |sample| sample.gyro - self.bias().gyro);
+        let step = lie::exp(&(omega * interval));
+        let velocity = state.velocity;
+        self.clones.push_back(PoseClone {
+            frame,
+            rotation: state.rotation * step,
+            position: state.position + velocity * interval,
+        });
+
+        // the clone errors follow the attitude, velocity and position errors of the IMU, and the
+        // error of the time offset through the motion of the body
+        let dim = self.covariance.nrows();
+        let mut jacobian = DMatrix::zeros(CLONE_DIM, dim);
+        jacobian
+            .slice_mut((0, 0), (3, 3))
+            .copy_from(&step.to_rotation_matrix().into_inner().transpose());
+        jacobian
+            .slice_mut((3, 3), (3, 3))
+            .copy_from(&(Matrix3::identity() * interval));
+        jacobian
+            .slice_mut((3, 6), (3, 3))
+            .copy_from(&Matrix3::identity());
+        if let Some(index) = self.time_offset_index {
+            jacobian.slice_mut((0, index), (3, 1)).copy_from(&omega);
+            jacobian.slice_mut((3, index), (3, 1)).copy_from(&velocity);
+        }
+        let cross = &jacobian * &self.covariance;
+        let mut covariance = DMatrix::zeros(dim + CLONE_DIM, dim + CLONE_DIM);
+        covariance
+            .slice_mut((0, 0), (dim, dim))
+            .copy_from(&self.covariance);
+        covariance
+            .slice_mut((dim, 0), (CLONE_DIM, dim))
+            .copy_from(&cross);
+        covariance
+            .slice_mut((0, dim), (dim, CLONE_DIM))
+            .copy_from(&cross.transpose());
+        covariance
+            .slice_mut((dim, dim), (CLONE_DIM, CLONE_DIM))
+            .copy_from(&(&cross * jacobian.transpose()));
+        self.covariance = covariance;
+    }
+
+    fn marginalize_oldest(&mut self) {
+        let removed = self.clones.pop_front().map(|clone| clone.frame);
+        let dim = self.covariance.nrows();
+        self.covariance = self
+            .covariance
+            .clone()
+            .remove_rows(self.clones_start, CLONE_DIM)
+            .remove_columns(self.clones_start, CLONE_DIM);
+        debug_assert_eq!(self.covariance.nrows(), dim - CLONE_DIM);
+        for observations in self.features.values_mut() {
+            observations.retain(|(frame, _)| Some(*frame) != removed);
+        }
+    }
+
+    /// Residual and Jacobian of the observations of a feature, projected on the left null space
+    /// of the Jacobian of the feature. `None` if the feature can't be triangulated reliably
+    fn feature_residual(
+        &self,
+        observations: &[(u64, Unit<Vector3<f64>>)],
+    ) -> Option<(DVector<f64>, DMatrix<f64>)> {
+        let clones: Vec<(usize, &PoseClone)> = observations
+            .iter()
+            .filter_map(|(frame, _)| {
+                self.clones
+                    .iter()
+                    .enumerate()
+                    .find(|(_, clone)| clone.frame == *frame)
+            })
+            .collect();
+        if clones.len() != observations.len() {
+            return None;
+        }
+        let obs: Vec<PoseBearing> = clones
+            .iter()
+            .zip(observations)
+            .map(|((_, clone), (_, bearing))| (clone.camera_pose(&self.t_bs), *bearing))
+            .collect();
+        let point = triangulate_dlt(&obs).or_else(|| triangulate_midpoint(&obs))?;
+        let point = refine_point(&point, &obs);
+        if parallax_angle(&point, &obs) < self.params.min_parallax {
+            return None;
+        }
+
+        let rows = 2 * obs.len();
+        let mut residual = DVector::zeros(rows);
+        let mut d_state = DMatrix::zeros(rows, self.covariance.nrows());
+        let mut d_point = DMatrix::zeros(rows, 3);
+        let r_sb = self
+            .t_bs
+            .rotation
+            .inverse()
+            .to_rotation_matrix()
+            .into_inner();
+        for (j, ((index, clone), (pose, bearing))) in clones.iter().zip(&obs).enumerate() {
+            residual
+                .rows_mut(2 * j, 2)
+                .copy_from(&-bearing_residual(pose, &(point, *bearing))?);
+
+            let p_c = (pose * point).coords;
+            let direction = p_c.normalize();
+            let d_direction = tangent_basis(bearing)
+                * (Matrix3::identity() - direction * direction.transpose())
+                / p_c.norm();
+            let r_bw = clone.rotation.inverse().to_rotation_matrix().into_inner();
+            let in_body = r_bw * (point.coords - clone.position);
+            let col = self.clones_start + CLONE_DIM * index;
+            d_state
+                .slice_mut((2 * j, col), (2, 3))
+                .copy_from(&(d_direction * r_sb * skew(&in_body)));
+            d_state
+                .slice_mut((2 * j, col + 3), (2, 3))
+                .copy_from(&(-d_direction * r_sb * r_bw));
+            if let Some(col) = self.extrinsic_index {
+                d_state
+                    .slice_mut((2 * j, col), (2, 3))
+                    .copy_from(&(d_direction * skew(&p_c)));
+                d_state
+                    .slice_mut((2 * j, col + 3), (2, 3))
+                    .copy_from(&(-d_direction * r_sb));
+            }
+            d_point
+                .slice_mut((2 * j, 0), (2, 3))
+                .copy_from(&(d_direction * r_sb * r_bw));
+        }
+
+        let basis = null_space(&d_point.transpose(), rows - 3);
+        let projection = DMatrix::from_columns(&basis);
+        Some((
+            projection.transpose() * residual,
+            projection.transpose() * d_state,
+        ))
+    }
+
+    /// EKF update with the observations of the finished features
+    fn update(&mut self, features: &[Observations]) -> MsckfUpdate {
+        let variance = self.params.bearing_sigma.powi(2);
+        let mut report = MsckfUpdate::default();
+        let mut residuals = Vec::new();
+        let mut jacobians = Vec::new();
+        for observations in features {
+            let (residual, jacobian) = match self.feature_residual(observations) {
+                Some(projected) => projected,
+                None => continue,
+            };
+            // Mahalanobis test of the feature on its own
+            let innovation = &jacobian * &self.covariance * jacobian.transpose()
+                + DMatrix::identity(residual.len(), residual.len()) * variance;
+            let chi_square = innovation.cholesky().map_or(f64::INFINITY, |cholesky| {
+                residual.dot(&cholesky.solve(&residual))
+            });
+            if chi_square > chi_square_quantile(residual.len(), self.params.confidence) {
+                report.n_rejected += 1;
+                continue;
+            }
+            report.n_features += 1;
+            residuals.push(residual);
+            jacobians.push(jacobian);
+        }
+        if residuals.is_empty() {
+            return report;
+        }
+
+        let rows: usize = residuals.iter().map(DVector::len).sum();
+        let dim = self.covariance.nrows();
+        let mut residual = DVector::zeros(rows);
+        let mut jacobian = DMatrix::zeros(rows, dim);
+        let mut row = 0;
+        for (r, h) in residuals.iter().zip(&jacobians) {
+            residual.rows_mut(row, r.len()).copy_from(r);
+            jacobian.rows_mut(row, r.len()).copy_from(h);
+            row += r.len();
+        }
+
+        let innovation = &jacobian * &self.covariance * jacobian.transpose()
+            + DMatrix::identity(rows, rows) * variance;
+        let cholesky = match innovation.cholesky() {
+            Some(cholesky) => cholesky,
+            None => return MsckfUpdate::default(),
+        };
+        // K = P * H' * S^-1
+        let gain = cholesky.solve(&(&jacobian * &self.covariance)).transpose();
+        let correction = &gain * residual;
+        // Joseph form, for a covariance that stays symmetric and positive
+        let reduction = DMatrix::identity(dim, dim) - &gain * &jacobian;
+        let covariance = &reduction * &self.covariance * reduction.transpose()
+            + &gain * gain.transpose() * variance;
+        self.covariance = (&covariance + covariance.transpose()) * 0.5;
+        self.inject(&correction);
+        report
+    }
+
+    /// Apply the estimated errors to the nominal states
+    fn inject(&mut self, correction: &DVector<f64>) {
+        let block = |offset: usize| correction.fixed_rows::<U3>(offset).into_owned();
+        let mut state = self.state().clone();
+        state.rotation *= lie::exp(&block(0));
+        state.velocity += block(3);
+        state.position += block(6);
+        let bias = ImuBias {
+            gyro: self.bias().gyro + block(9),
+            accel: self.bias().accel + block(12),
+        };
+        self.propagator.reset(state);
+        self.propagator.set_bias(bias);
+
+        if let Some(offset) = self.extrinsic_index {
+            self.t_bs.rotation *= lie::exp(&block(offset));
+            self.t_bs.translation.vector += block(offset + 3);
+        }
+        if let Some(index) = self.time_offset_index {
+            self.time_offset += correction[index];
+        }
+        for (index, clone) in self.clones.iter_mut().enumerate() {
+            let offset = self.clones_start + CLONE_DIM * index;
+            clone.rotation *= lie::exp(&block(offset));
+            clone.position += block(offset + 3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::camera::PinholeRadTan;
+    use crate::tracking::klt::TrackStatus;
+    use nalgebra::Point3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::time::Duration;
+
+    fn attitude(t: f64) -> UnitQuaternion<f64> {
+        UnitQuaternion::from_euler_angles(
+            0.3 * (1.5 * t).sin(),
+            0.25 * (1.1 * t).cos(),
+            0.3 * (0.9 * t).sin(),
+        )
+    }
+
+    /// Body swaying in front of the landmarks
+    fn truth(t: f64) -> NavState {
+        NavState {
+            timestamp: Duration::from_secs_f64(t),
+            rotation: attitude(t),
+            velocity: Vector3::new(
+                0.39 * (1.3 * t).cos(),
+                0.28 * (0.7 * t).cos(),
+                -0.2 * t.sin(),
+            ),
+            position: Vector3::new(0.3 * (1.3 * t).sin(), 0.4 * (0.7 * t).sin(), 0.2 * t.cos()),
+        }
+    }
+
+    fn sample(t: f64, bias: &ImuBias) -> ImuSample {
+        let h = 1e-6;
+        let rotation = attitude(t);
+        let omega = (rotation.inverse() * attitude(t + h)).scaled_axis()
+            - (rotation.inverse() * attitude(t - h)).scaled_axis();
+        let acceleration = Vector3::new(
+            -0.507 * (1.3 * t).sin(),
+            -0.196 * (0.7 * t).sin(),
+            -0.2 * t.cos(),
+        );
+        ImuSample {
+            timestamp: Duration::from_secs_f64(t),
+            gyro: omega / (2.0 * h) + bias.gyro,
+            accel: rotation.inverse() * (acceleration + Vector3::new(0.0, 0.0, GRAVITY))
+                + bias.accel,
+        }
+    }
+
+    /// Camera looking along the x axis of the body
+    fn extrinsics() -> Isometry3<f64> {
+        Isometry3::from_parts(
+            Translation3::new(0.05, 0.0, 0.0),
+            UnitQuaternion::from_matrix(&Matrix3::new(
+                0.0, 0.0, 1.0, -1.0, 0.0, 0.0, 0.0, -1.0, 0.0,
+            )),
+        )
+    }
+
+    /// Run the filter for 4s with an unknown accelerometer bias and a wrong initial velocity, and
+    /// dead reckoning alongside it. The frames are captured `time_offset` seconds after their
+    /// timestamps
+    fn simulate(
+        params: MsckfParams,
+        initial_t_bs: Isometry3<f64>,
+        time_offset: f64,
+    ) -> (Msckf, StrapdownPropagator, ImuBias) {
+        let mut rng = StdRng::seed_from_u64(3);
+        let camera: Arc<dyn CameraModel> = Arc::new(PinholeRadTan::new(
+            [450.0, 450.0, 376.0, 240.0],
+            [0.0; 4],
+            (752, 480),
+        ));
+        let t_bs = extrinsics();
+        let landmarks: Vec<Point3<f64>> = (0..150)
+            .map(|_| {
+                Point3::new(
+                    rng.gen_range(4.0, 8.0),
+                    rng.gen_range(-3.0, 3.0),
+                    rng.gen_range(-2.0, 2.0),
+                )
+            })
+            .collect();
+
+        let bias = ImuBias {
+            gyro: Vector3::zeros(),
+            accel: Vector3::new(0.05, -0.04, 0.03),
+        };
+        let mut initial = truth(0.0);
+        initial.velocity += Vector3::new(0.05, -0.05, 0.02);
+        let mut filter = Msckf::new(
+            initial.clone(),
+            ImuBias::default(),
+            camera.clone(),
+            initial_t_bs,
+            0.0,
+            params,
+        );
+        let mut dead_reckoning = StrapdownPropagator::new(initial, StrapdownParams::default());
+
+        let mut updated = 0;
+        for k in 0..=800 {
+            let t = k as f64 * 0.005;
+            let imu = sample(t, &bias);
+            filter.propagate(&imu);
+            dead_reckoning.propagate(&imu);
+            if k % 10 != 0 || t < time_offset {
+                continue;
+            }
+
+            // a frame at 20Hz, with every other landmark lost for a frame now and then
+            let t_cw =
+                (Isometry3::from_parts(Translation3::from(truth(t).position), truth(t).rotation)
+                    * t_bs)
+                    .inverse();
+            let tracks: Vec<Track> = landmarks
+                .iter()
+                .enumerate()
+                .filter(|(id, _)| (id + k / 10) % 7 != 0)
+                .filter_map(|(id, landmark)| {
+                    let pt = camera.project(&(t_cw * landmark))?;
+                    Some(Track {
+                        id: id as u64,
+                        pt,
+                        status: TrackStatus::Tracked,
+                        age: 1,
+                    })
+                    .filter(|track| camera.is_in_image(&track.pt, 0.0))
+                })
+                .collect();
+            let stamp = Duration::from_secs_f64(t - time_offset);
+            let update = filter.process_frame(stamp, &tracks);
+            assert_eq!(update.n_rejected, 0);
+            updated += update.n_features;
+            assert!(filter.n_clones() <= filter.params.max_clones);
+        }
+        assert!(updated > 100);
+        (filter, dead_reckoning, bias)
+    }
+
+    #[test]
+    fn msckf_corrects_the_dead_reckoning() {
+        let params = MsckfParams {
+            max_clones: 10,
+            ..MsckfParams::default()
+        };
+        let (filter, dead_reckoning, bias) = simulate(params, extrinsics(), 0.0);
+
+        let expected = truth(4.0);
+        let error = (filter.state().position - expected.position).norm();
+        let drift = (dead_reckoning.state().position - expected.position).norm();
+        assert!(error < 0.1 * drift, "{} vs {}", error, drift);
+        assert!((filter.state().velocity - expected.velocity).norm() < 0.02);
+        // barely rotating, the accelerometer bias is only partially observable
+        assert!((filter.bias().accel - bias.accel).norm() < 0.7 * bias.accel.norm());
+
+        let covariance = filter.covariance();
+        assert!((covariance - covariance.transpose()).norm() < 1e-12);
+        assert!(covariance.clone().symmetric_eigenvalues().min() > -1e-12);
+    }
+
+    #[test]
+    fn online_camera_imu_calibration() {
+        let params = MsckfParams {
+            max_clones: 10,
+            estimate_extrinsics: true,
+            estimate_time_offset: true,
+            // noiseless tracks
+            bearing_sigma: 2e-4,
+            ..MsckfParams::default()
+        };
+        let t_bs = extrinsics();
+        let initial = Isometry3::from_parts(
+            Translation3::new(0.01, -0.01, 0.01),
+            UnitQuaternion::from_euler_angles(0.005, -0.005, 0.005),
+        ) * t_bs;
+        let (filter, _, _) = simulate(params, initial, 0.02);
+
+        let calibration = filter.calibration();
+        let time_offset_error = (calibration.time_offset - 0.02).abs();
+        let translation_error =
+            (calibration.t_bs.translation.vector - t_bs.translation.vector).norm();
+        let rotation_error = (calibration.t_bs.rotation.inverse() * t_bs.rotation).angle();
+        assert!(time_offset_error < 0.003, "{}", calibration);
+        assert!(rotation_error < 0.004, "{}", calibration);
+        assert!(calibration.time_offset_sigma.unwrap() < 0.002);
+        // the lever arm needs larger rotations than these to be observable within a few seconds
+        assert!(translation_error < 0.02, "{}", calibration);
+        assert!(format!("{}", calibration).contains("time offset"));
+        assert!((filter.state().position - truth(4.0).position).norm() < 0.05);
+    }
+}
@@ -0,0 +1,273 @@
+/// Strapdown integration of the IMU samples - dead reckoning of the orientation, velocity and
+/// position of the body
+///
+/// The measurements are interpolated linearly between consecutive samples and the equations of
+/// motion
+///
+/// `dR/dt = R * [w - b_g]x`, `dv/dt = R * (a - b_a) + g`, `dp/dt = v`
+///
+/// are integrated with the midpoint rule or with 4th order Runge-Kutta.
+use crate::graph::imu::GRAVITY;
+use crate::graph::values::ImuBias;
+use crate::utils::{ImuSample, MeasurementData};
+
+use nalgebra::{Quaternion, UnitQuaternion, Vector3};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integration {
+    Midpoint,
+    RungeKutta4,
+}
+
+#[derive(Debug, Clone)]
+pub struct StrapdownParams {
+    pub integration: Integration,
+    /// Gravity in the world frame (m / s^2)
+    pub gravity: Vector3<f64>,
+    /// Subtracted from the measurements
+    pub bias: ImuBias,
+}
+
+impl Default for StrapdownParams {
+    fn default() -> Self {
+        Self {
+            integration: Integration::RungeKutta4,
+            gravity: Vector3::new(0.0, 0.0, -GRAVITY),
+            bias: ImuBias::default(),
+        }
+    }
+}
+
+/// Orientation, velocity and position of the body in the world frame at a given time
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavState {
+    pub timestamp: Duration,
+    pub rotation: UnitQuaternion<f64>,
+    pub velocity: Vector3<f64>,
+    pub position: Vector3<f64>,
+}
+
+impl NavState {
+    /// At rest at the origin
+    pub fn at_rest(timestamp: Duration, rotation: UnitQuaternion<f64>) -> Self {
+        Self {
+            timestamp,
+            rotation,
+            velocity: Vector3::zeros(),
+            position: Vector3::zeros(),
+        }
+    }
+}
+
+/// Time derivatives of the orientation (as a quaternion), of the velocity and of the position
+type Derivative = (Quaternion<f64>, Vector3<f64>, Vector3<f64>);
+
+#[derive(Debug, Clone)]
+pub struct StrapdownPropagator {
+    params: StrapdownParams,
+    state: NavState,
+    /// Last sample, with the bias removed
+    last: Option<ImuSample>,
+}
+
+impl StrapdownPropagator {
+    pub const fn new(state: NavState, params: StrapdownParams) -> Self {
+        Self {
+            params,
+            state,
+            last: None,
+        }
+    }
+
+    pub const fn state(&self) -> &NavState {
+        &self.state
+    }
+
+    pub const fn params(&self) -> &StrapdownParams {
+        &self.params
+    }
+
+    /// Restart from another state, e.g. an estimate of the optimiser
+    pub fn reset(&mut self, state: NavState) {
+        self.state = state;
+    }
+
+    pub fn set_bias(&mut self, bias: ImuBias) {
+        self.params.bias = bias;
+    }
+
+    /// Propagate with the IMU samples of a stream of measurements, ignoring the other ones
+    pub fn process(&mut self, data: &MeasurementData) -> Option<&NavState> {
+        match data {
+            MeasurementData::Imu(sample) => Some(self.propagate(sample)),
+            _ => None,
+        }
+    }
+
+    /// Propagate the state to the time of `sample`. Samples older than the state are ignored and
+    /// the first one is held constant since the time of the state
+    pub fn propagate(&mut self, sample: &ImuSample) -> &NavState {
+        let current = ImuSample {
+            gyro: sample.gyro - self.params.bias.gyro,
+            accel: sample.accel - self.params.bias.accel,
+            ..*sample
+        };
+        if current.timestamp <= self.state.timestamp {
+            self.last = Some(current);
+            return &self.state;
+        }
+
+        let previous = self
+            .last
+            .filter(|last| last.timestamp <= self.state.timestamp);
+        let start = previous.map_or(
+            ImuSample {
+                timestamp: self.state.timestamp,
+                ..current
+            },
+            |previous| {
+                // interpolated at the time of the state
+                let span = (current.timestamp - previous.timestamp).as_secs_f64();
+                let s = (self.state.timestamp - previous.timestamp).as_secs_f64() / span;
+                ImuSample {
+                    timestamp: self.state.timestamp,
+                    gyro: previous.gyro.lerp(&current.gyro, s),
+                    accel: previous.accel.lerp(&current.accel, s),
+                }
+            },
+        );
+        match self.params.integration {
+            Integration::Midpoint => self.midpoint(&start, &current),
+            Integration::RungeKutta4 => self.runge_kutta(&start, &current),
+        }
+        self.state.timestamp = current.timestamp;
+        self.last = Some(current);
+        &self.state
+    }
+
+    fn midpoint(&mut self, start: &ImuSample, end: &ImuSample) {
+        let dt = (end.timestamp - start.timestamp).as_secs_f64();
+        let omega = (start.gyro + end.gyro) * 0.5;
+        let rotation = self.state.rotation * UnitQuaternion::from_scaled_axis(omega * dt);
+        let accel =
+            (self.state.rotation * start.accel + rotation * end.accel) * 0.5 + self.params.gravity;
+        self.state.position += self.state.velocity * dt + accel * (0.5 * dt * dt);
+        self.state.velocity += accel * dt;
+        self.state.rotation = rotation;
+    }
+
+    fn derivative(
+        &self,
+        rotation: &Quaternion<f64>,
+        velocity: &Vector3<f64>,
+        gyro: &Vector3<f64>,
+        accel: &Vector3<f64>,
+    ) -> Derivative {
+        let unit = UnitQuaternion::new_normalize(*rotation);
+        (
+            rotation * Quaternion::from_imag(*gyro) * 0.5,
+            unit * accel + self.params.gravity,
+            *velocity,
+        )
+    }
+
+    fn runge_kutta(&mut self, start: &ImuSample, end: &ImuSample) {
+        let dt = (end.timestamp - start.timestamp).as_secs_f64();
+        let (gyro_mid, accel_mid) = (
+            (start.gyro + end.gyro) * 0.5,
+            (start.accel + end.accel) * 0.5,
+        );
+        let (q, v) = (*self.state.rotation.quaternion(), self.state.velocity);
+
+        let k1 = self.derivative(&q, &v, &start.gyro, &start.accel);
+        let k2 = self.derivative(
+            &(q + k1.0 * (0.5 * dt)),
+            &(v + k1.1 * (0.5 * dt)),
+            &gyro_mid,
+            &accel_mid,
+        );
+        let k3 = self.derivative(
+            &(q + k2.0 * (0.5 * dt)),
+            &(v + k2.1 * (0.5 * dt)),
+            &gyro_mid,
+            &accel_mid,
+        );
+        let k4 = self.derivative(&(q + k3.0 * dt), &(v + k3.1 * dt), &end.gyro, &end.accel);
+
+        let sixth = dt / 6.0;
+        let rotation = q + (k1.0 + k2.0 * 2.0 + k3.0 * 2.0 + k4.0) * sixth;
+        self.state.rotation = UnitQuaternion::new_normalize(rotation);
+        self.state.velocity += (k1.1 + k2.1 * 2.0 + k3.1 * 2.0 + k4.1) * sixth;
+        self.state.position += (k1.2 + k2.2 * 2.0 + k3.2 * 2.0 + k4.2) * sixth;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GrayImage;
+
+    fn attitude(t: f64) -> UnitQuaternion<f64> {
+        UnitQuaternion::from_euler_angles(0.3 * t, 0.2 * t * t, (2.0 * t).sin())
+    }
+
+    /// Body rotating at a varying rate, moving along a curve
+    fn truth(t: f64) -> NavState {
+        NavState {
+            timestamp: Duration::from_secs_f64(t),
+            rotation: attitude(t),
+            velocity: Vector3::new(t.cos(), -2.0 * (2.0 * t).sin(), t),
+            position: Vector3::new(t.sin(), (2.0 * t).cos(), 0.5 * t * t),
+        }
+    }
+
+    /// Noiseless sample at `t`, with the angular velocity by differences
+    fn sample(t: f64, bias: &ImuBias) -> ImuSample {
+        let h = 1e-6;
+        let rotation = attitude(t);
+        let omega = (rotation.inverse() * attitude(t + h)).scaled_axis()
+            - (rotation.inverse() * attitude(t - h)).scaled_axis();
+        let acceleration = Vector3::new(-t.sin(), -4.0 * (2.0 * t).cos(), 1.0);
+        ImuSample {
+            timestamp: Duration::from_secs_f64(t),
+            gyro: omega / (2.0 * h) + bias.gyro,
+            accel: rotation.inverse() * (acceleration + Vector3::new(0.0, 0.0, GRAVITY))
+                + bias.accel,
+        }
+    }
+
+    #[test]
+    fn dead_reckoning_follows_the_trajectory() {
+        let bias = ImuBias {
+            gyro: Vector3::new(0.01, -0.02, 0.03),
+            accel: Vector3::new(-0.1, 0.2, 0.05),
+        };
+        let expected = truth(2.0);
+        let error = |integration| {
+            let params = StrapdownParams {
+                integration,
+                bias,
+                ..StrapdownParams::default()
+            };
+            let mut propagator = StrapdownPropagator::new(truth(0.0), params);
+            for k in 0..=400 {
+                let data = MeasurementData::Imu(sample(k as f64 * 0.005, &bias));
+                assert!(propagator.process(&data).is_some());
+            }
+            assert!(propagator
+                .process(&MeasurementData::Grayscale(GrayImage::new(2, 2)))
+                .is_none());
+            let state = propagator.state();
+            assert_eq!(state.timestamp, expected.timestamp);
+            assert!((state.rotation.inverse() * expected.rotation).angle() < 1e-4);
+            (state.position - expected.position).norm()
+        };
+        let (midpoint, runge_kutta) = (
+            error(Integration::Midpoint),
+            error(Integration::RungeKutta4),
+        );
+        assert!(midpoint < 1e-3, "{}", midpoint);
+        assert!(runge_kutta < 1e-3, "{}", runge_kutta);
+    }
+}
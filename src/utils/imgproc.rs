@@ -0,0 +1,258 @@
+/// Low-level image processing helpers shared by the feature detectors and trackers
+use image::GrayImage;
+
+// -------------------------------------------------------------------------------------------------
+// GradientKernel
+// -------------------------------------------------------------------------------------------------
+
+/// 3x3 derivative kernels used for computing the image gradients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GradientKernel {
+    Sobel,
+    /// More rotationally symmetric than Sobel - preferable for the structure tensor
+    Scharr,
+}
+
+impl GradientKernel {
+    /// The 1D (smoothing, derivative) kernels whose outer product is the 3x3 kernel.
+    ///
+    /// Both are normalised so that the gradients are in intensity units per pixel.
+    fn separable(self) -> ([f64; 3], [f64; 3]) {
+        let derivative = [-0.5, 0.0, 0.5];
+        match self {
+            Self::Sobel => ([0.25, 0.5, 0.25], derivative),
+            Self::Scharr => ([3.0 / 16.0, 10.0 / 16.0, 3.0 / 16.0], derivative),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FloatImage
+// -------------------------------------------------------------------------------------------------
+
+/// Single channel image of floating point values, stored in row-major order
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatImage {
+    width: u32,
+    height: u32,
+    data: Vec<f64>,
+}
+
+impl FloatImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0.0; (width * height) as usize],
+        }
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn data(&self) -> &[f64] {
+        &self.data
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> f64 {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, value: f64) {
+        self.data[(y * self.width + x) as usize] = value;
+    }
+
+    /// Value at the given (possibly out of bounds) integer coordinates, replicating the border
+    pub fn get_clamped(&self, x: i64, y: i64) -> f64 {
+        let x = x.max(0).min(i64::from(self.width) - 1) as u32;
+        let y = y.max(0).min(i64::from(self.height) - 1) as u32;
+        self.get(x, y)
+    }
+
+    /// Is the point inside the area where bilinear interpolation is defined? Never for an empty
+    /// image
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        in_bounds(self.width, self.height, x, y)
+    }
+
+    /// Bilinearly interpolated value at (x, y). Returns `None` outside the image
+    #[allow(clippy::suboptimal_flops)]
+    pub fn bilinear(&self, x: f64, y: f64) -> Option<f64> {
+        if !self.contains(x, y) {
+            return None;
+        }
+
+        let x0 = (x.floor() as u32).min(self.width.saturating_sub(2));
+        let y0 = (y.floor() as u32).min(self.height.saturating_sub(2));
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y1 = (y0 + 1).min(self.height - 1);
+        let (ax, ay) = (x - f64::from(x0), y - f64::from(y0));
+
+        let top = (1.0 - ax) * self.get(x0, y0) + ax * self.get(x1, y0);
+        let bottom = (1.0 - ax) * self.get(x0, y1) + ax * self.get(x1, y1);
+        Some((1.0 - ay) * top + ay * bottom)
+    }
+}
+
+impl From<&GrayImage> for FloatImage {
+    fn from(img: &GrayImage) -> Self {
+        Self {
+            width: img.width(),
+            height: img.height(),
+            data: img.as_raw().iter().map(|&p| f64::from(p)).collect(),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// filtering
+// -------------------------------------------------------------------------------------------------
+
+/// Convolve the image with the separable kernel `kx` (along x) and `ky` (along y).
+///
+/// Both kernels must have an odd length. Borders are replicated.
+pub fn convolve_separable(img: &FloatImage, kx: &[f64], ky: &[f64]) -> FloatImage {
+    assert!(
+        kx.len() % 2 == 1 && ky.len() % 2 == 1,
+        "Kernels must have an odd length"
+    );
+    let (rx, ry) = ((kx.len() / 2) as i64, (ky.len() / 2) as i64);
+
+    let mut tmp = FloatImage::new(img.width, img.height);
+    for y in 0..img.height {
+        for x in 0..img.width {
+            let value = kx
+                .iter()
+                .enumerate()
+                .map(|(i, k)| k * img.get_clamped(i64::from(x) + i as i64 - rx, i64::from(y)))
+                .sum();
+            tmp.set(x, y, value);
+        }
+    }
+
+    let mut out = FloatImage::new(img.width, img.height);
+    for y in 0..img.height {
+        for x in 0..img.width {
+            let value = ky
+                .iter()
+                .enumerate()
+                .map(|(i, k)| k * tmp.get_clamped(i64::from(x), i64::from(y) + i as i64 - ry))
+                .sum();
+            out.set(x, y, value);
+        }
+    }
+
+    out
+}
+
+/// Horizontal and vertical image gradients
+pub fn gradients(img: &FloatImage, kernel: GradientKernel) -> (FloatImage, FloatImage) {
+    let (smooth, derivative) = kernel.separable();
+    let gx = convolve_separable(img, &derivative, &smooth);
+    let gy = convolve_separable(img, &smooth, &derivative);
+    (gx, gy)
+}
+
+/// Normalised 1D gaussian kernel of length `2 * radius + 1`
+pub fn gaussian_kernel(sigma: f64, radius: usize) -> Vec<f64> {
+    let radius = radius as i64;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.into_iter().map(|k| k / sum).collect()
+}
+
+/// Is (x, y) within the pixel centres of an image of the given size?
+fn in_bounds(width: u32, height: u32, x: f64, y: f64) -> bool {
+    width > 0
+        && height > 0
+        && x >= 0.0
+        && y >= 0.0
+        && x <= f64::from(width - 1)
+        && y <= f64::from(height - 1)
+}
+
+/// Bilinearly interpolated intensity of a grayscale image. Returns `None` outside the image
+#[allow(clippy::suboptimal_flops)]
+pub fn interpolate(img: &GrayImage, x: f64, y: f64) -> Option<f64> {
+    let (width, height) = img.dimensions();
+    if !in_bounds(width, height, x, y) {
+        return None;
+    }
+
+    let x0 = (x.floor() as u32).min(width.saturating_sub(2));
+    let y0 = (y.floor() as u32).min(height.saturating_sub(2));
+    let x1 = (x0 + 1).min(width - 1);
+    let y1 = (y0 + 1).min(height - 1);
+    let (ax, ay) = (x - f64::from(x0), y - f64::from(y0));
+    let pixel = |x: u32, y: u32| f64::from(img.get_pixel(x, y)[0]);
+
+    let top = (1.0 - ax) * pixel(x0, y0) + ax * pixel(x1, y0);
+    let bottom = (1.0 - ax) * pixel(x0, y1) + ax * pixel(x1, y1);
+    Some((1.0 - ay) * top + ay * bottom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// 3x2 image whose value is `10 x + 100 y`
+    fn ramp() -> GrayImage {
+        GrayImage::from_fn(3, 2, |x, y| Luma([(10 * x + 100 * y) as u8]))
+    }
+
+    #[test]
+    fn bilinear_interpolation() {
+        let img = ramp();
+        let float = FloatImage::from(&img);
+        // pixel centres, sub-pixel positions, and the last row and column
+        for &(x, y, expected) in &[
+            (0.0, 0.0, 0.0),
+            (2.0, 1.0, 120.0),
+            (1.0, 0.0, 10.0),
+            (0.25, 0.5, 52.5),
+            (1.5, 0.75, 90.0),
+            (2.0, 0.5, 70.0),
+            (0.5, 1.0, 105.0),
+        ] {
+            assert!((interpolate(&img, x, y).unwrap() - expected).abs() < 1e-12);
+            assert!((float.bilinear(x, y).unwrap() - expected).abs() < 1e-12);
+        }
+        // past the last pixel centres
+        for &(x, y) in &[
+            (-0.1, 0.0),
+            (0.0, -0.1),
+            (2.1, 0.0),
+            (0.0, 1.1),
+            (f64::NAN, 0.0),
+        ] {
+            assert_eq!(interpolate(&img, x, y), None);
+            assert_eq!(float.bilinear(x, y), None);
+        }
+    }
+
+    #[test]
+    fn single_pixel_and_empty_images() {
+        let single = GrayImage::from_pixel(1, 1, Luma([42]));
+        assert_eq!(interpolate(&single, 0.0, 0.0), Some(42.0));
+        assert_eq!(interpolate(&single, 0.5, 0.0), None);
+        assert_eq!(FloatImage::from(&single).bilinear(0.0, 0.0), Some(42.0));
+
+        for &(width, height) in &[(0, 4), (4, 0), (0, 0)] {
+            let img = GrayImage::new(width, height);
+            let float = FloatImage::new(width, height);
+            for &(x, y) in &[(0.0, 0.0), (1.0, 1.0), (1e9, 1e9)] {
+                assert!(!float.contains(x, y));
+                assert_eq!(float.bilinear(x, y), None);
+                assert_eq!(interpolate(&img, x, y), None);
+            }
+        }
+    }
+}
@@ -1,14 +1,18 @@
 pub mod errors;
+pub mod imgproc;
 pub use self::errors::*;
 
 use image::GrayImage;
+use nalgebra::Vector3;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 
 /// Types of measurements that we can use to run SLAM with.
 #[derive(Debug)]
 pub enum MeasurementType {
     Grayscale,
+    IMU,
     RGB,      // not implemented
-    IMU,      // not implemented
     GPS,      // not implemented
     Odometry, // not implemented
 }
@@ -16,9 +20,30 @@ pub enum MeasurementType {
 #[derive(Debug, PartialEq, Hash, Clone)]
 pub enum MeasurementData {
     Grayscale(GrayImage),
+    Imu(ImuSample),
     // --- rest not implemented yet
 }
 
+/// Sample of an IMU, in its own frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuSample {
+    pub timestamp: Duration,
+    /// Angular velocity (rad / s)
+    pub gyro: Vector3<f64>,
+    /// Specific force (m / s^2)
+    pub accel: Vector3<f64>,
+}
+
+impl Hash for ImuSample {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.timestamp.hash(state);
+        for value in self.gyro.iter().chain(&self.accel) {
+            // 0.0 and -0.0 are equal and must hash the same
+            (value + 0.0).to_bits().hash(state);
+        }
+    }
+}
+
 /// Implementation for the actual measurements
 #[derive(Debug)]
 pub struct Measurement {